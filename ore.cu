@@ -1,6 +1,8 @@
 #include <stdlib.h>
 #include <stdio.h>
 #include <stdint.h>
+#include <string.h>
+#include <time.h>
 
 void gpu_init();
 int gcd(int a, int b);
@@ -178,7 +180,7 @@ void gpu_init()
 
 	cudaError_t cudaerr = cudaGetDeviceProperties(&device_prop, 0);
     if (cudaerr != cudaSuccess) {
-		printf("getting properties for device failed with error \"%s\".\n", cudaGetErrorString(cudaerr));
+		fprintf(stderr, "getting properties for device failed with error \"%s\".\n", cudaGetErrorString(cudaerr));
         exit(EXIT_FAILURE);
     }
 
@@ -195,14 +197,86 @@ int gcd(int a, int b) {
     return (a == 0) ? b : gcd(b % a, a);
 }
 
+// header: 2 bytes CPU worker thread count (ignored here), 4 bytes little endian deadline in
+// milliseconds (0 for none), 32 bytes difficulty
+#define HEADER_SIZE 38
+
+uint32_t read_u32_le(const uint8_t *bytes)
+{
+    return bytes[0] | (bytes[1] << 8) | (bytes[2] << 16) | ((uint32_t)bytes[3] << 24);
+}
+
+uint64_t now_ms()
+{
+    struct timespec ts;
+    clock_gettime(CLOCK_MONOTONIC, &ts);
+    return (uint64_t)ts.tv_sec * 1000 + ts.tv_nsec / 1000000;
+}
+
+// absolute deadline in `now_ms()` time from the header, 0 for none
+uint64_t parse_deadline(const uint8_t *header)
+{
+    uint32_t deadline_ms = read_u32_le(header + 2);
+    return deadline_ms == 0 ? 0 : now_ms() + deadline_ms;
+}
+
+// mine a single 64 bytes preimage, leaving the 32 bytes hash and 8 bytes nonce in `preimage`.
+// returns 0 if the deadline passed before a nonce was found
+int solve(uint8_t *d_diff, uint8_t *d_preimage, int *d_done, uint8_t *preimage, uint64_t *starting_tid, uint64_t deadline)
+{
+    if (deadline != 0 && now_ms() >= deadline) {
+        return 0;
+    }
+
+    int h_done[1] = {0};
+    cudaMemcpy(d_done, h_done, sizeof(int), cudaMemcpyHostToDevice);
+    cudaMemcpy(d_preimage, preimage, 64, cudaMemcpyHostToDevice);
+
+    while (!h_done[0]) {
+        brute_force_single<<<number_blocks, number_threads>>>(d_diff, d_preimage, d_done, *starting_tid);
+        *starting_tid += number_blocks * number_threads;
+        cudaMemcpy(h_done, d_done, sizeof(int), cudaMemcpyDeviceToHost);
+        cudaError_t cudaerr = cudaDeviceSynchronize();
+        if (cudaerr != cudaSuccess) {
+            h_done[0] = 1;
+            fprintf(stderr, "kernel launch failed with error \"%s\".\n", cudaGetErrorString(cudaerr));
+        }
+        if (!h_done[0] && deadline != 0 && now_ms() >= deadline) {
+            return 0;
+        }
+    }
+
+    cudaMemcpy(preimage, d_preimage, 64, cudaMemcpyDeviceToHost);
+    return 1;
+}
+
+void write_u32_le(uint32_t value)
+{
+    uint8_t bytes[4] = {
+        (uint8_t)(value & 0xff),
+        (uint8_t)((value >> 8) & 0xff),
+        (uint8_t)((value >> 16) & 0xff),
+        (uint8_t)((value >> 24) & 0xff),
+    };
+    fwrite(bytes, 1, 4, stdout);
+}
+
+// 44 bytes result record: 4 bytes little endian input index, 32 bytes hash and 8 bytes nonce
+void encode_result(uint8_t *out, uint32_t index, const uint8_t *solved)
+{
+    out[0] = index & 0xff;
+    out[1] = (index >> 8) & 0xff;
+    out[2] = (index >> 16) & 0xff;
+    out[3] = (index >> 24) & 0xff;
+    memcpy(out + 4, solved, 40);
+}
+
 void find_message()
 {
-    uint8_t* data = (uint8_t*)malloc(33 * sizeof(uint8_t));
-    // read 33 bytes from stdin
-	// first byte is reserved for compatibility with the CPU worker
-	// rest are the difficulty
-    fread(data, 1, 33, stdin);
-    uint8_t* diff = data + 1;
+    uint8_t* data = (uint8_t*)malloc(HEADER_SIZE * sizeof(uint8_t));
+    fread(data, 1, HEADER_SIZE, stdin);
+    uint8_t* diff = data + 6;
+    uint64_t deadline = parse_deadline(data);
 
 	uint64_t starting_tid = 0;
 
@@ -215,40 +289,107 @@ void find_message()
 	cudaMalloc((void**) &d_preimage, 64);
 	cudaMemcpy(d_diff, diff, 32, cudaMemcpyHostToDevice);
 
+    uint8_t preimage[64];
+    uint8_t record[44];
+    uint32_t index = 0;
+
 	// keep reading proof.hash and pubkey, in total 64 bytes
-    while (1) {
-        int h_done[1] = {0};
-	    cudaMemcpy(d_done, h_done, sizeof(int), cudaMemcpyHostToDevice);
-        uint8_t* preimage = (uint8_t*)malloc(64);
-        const size_t ret_code = fread(preimage, 1, 64, stdin);
-        if (ret_code != 64) {
+    while (fread(preimage, 1, 64, stdin) == 64) {
+        if (solve(d_diff, d_preimage, d_done, preimage, &starting_tid, deadline)) {
+            encode_result(record, index, preimage);
+            fwrite(record, 1, 44, stdout);
+        }
+        index++;
+    }
+
+    fflush(stdout);
+    free(data);
+}
+
+// persistent mode: each job is a 4 bytes little endian length followed by the same payload as the
+// one-shot mode, each response is a 4 bytes little endian length followed by the result records.
+// in streaming mode each record is written as soon as it is found and the job ends with a
+// 0xffffffff index. inputs left unsolved at the deadline have no record
+void serve_jobs(int stream)
+{
+	uint64_t starting_tid = 0;
+
+	int *d_done;
+	uint8_t *d_diff;
+	uint8_t *d_preimage;
+
+	cudaMalloc((void**) &d_done, sizeof(int));
+	cudaMalloc((void**) &d_diff, 32);
+	cudaMalloc((void**) &d_preimage, 64);
+
+    uint8_t len_bytes[4];
+
+    while (fread(len_bytes, 1, 4, stdin) == 4) {
+        uint32_t len = read_u32_le(len_bytes);
+        if (len < HEADER_SIZE || (len - HEADER_SIZE) % 64 != 0) {
+            fprintf(stderr, "invalid job length %u\n", len);
+            exit(EXIT_FAILURE);
+        }
+
+        uint8_t *job = (uint8_t*)malloc(len);
+        if (fread(job, 1, len, stdin) != len) {
+            free(job);
             break;
         }
 
-        cudaMemcpy(d_preimage, preimage, 64, cudaMemcpyHostToDevice);
-        int index = 0;
-        while (!h_done[0]) {
-            index++;
-            brute_force_single<<<number_blocks, number_threads>>>(d_diff, d_preimage, d_done, starting_tid);
-            starting_tid += number_blocks * number_threads;
-            cudaMemcpy(h_done, d_done, sizeof(int), cudaMemcpyDeviceToHost);
-            cudaError_t cudaerr = cudaDeviceSynchronize();
-            if (cudaerr != cudaSuccess) {
-                h_done[0] = 1;
-                printf("kernel launch failed with error \"%s\".\n", cudaGetErrorString(cudaerr));
+        uint32_t count = (len - HEADER_SIZE) / 64;
+        uint32_t out_len = 0;
+        uint8_t *out = (uint8_t*)malloc(count > 0 ? count * 44 : 1);
+        uint64_t deadline = parse_deadline(job);
+
+        cudaMemcpy(d_diff, job + 6, 32, cudaMemcpyHostToDevice);
+
+        for (uint32_t i = 0; i < count; i++) {
+            uint8_t *preimage = job + HEADER_SIZE + i * 64;
+            if (!solve(d_diff, d_preimage, d_done, preimage, &starting_tid, deadline)) {
+                continue;
+            }
+
+            encode_result(out + out_len, i, preimage);
+
+            if (stream) {
+                fwrite(out + out_len, 1, 44, stdout);
+                fflush(stdout);
+            } else {
+                out_len += 44;
             }
         }
-        cudaMemcpy(preimage, d_preimage, 64, cudaMemcpyDeviceToHost);
-        for (int i = 0; i < 40; i++)
-        {
-            printf("%c", preimage[i]);
+
+        if (stream) {
+            write_u32_le(0xffffffff);
+        } else {
+            write_u32_le(out_len);
+            fwrite(out, 1, out_len, stdout);
         }
+        fflush(stdout);
+
+        free(out);
+        free(job);
     }
 }
 
 int main(int argc, char **argv)
 {
     gpu_init();
-	find_message();
+
+    int persistent = 0;
+    int stream = 0;
+
+    for (int i = 1; i < argc; i++) {
+        if (strcmp(argv[i], "--persistent") == 0) persistent = 1;
+        if (strcmp(argv[i], "--stream") == 0) stream = 1;
+    }
+
+    if (persistent) {
+        serve_jobs(stream);
+    } else {
+        find_message();
+    }
+
     return EXIT_SUCCESS;
-}
\ No newline at end of file
+}
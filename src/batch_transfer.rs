@@ -1,59 +1,176 @@
-use clap::Parser;
-use solana_client::rpc_config::RpcSendTransactionConfig;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    str::FromStr,
+};
+
+use clap::{Parser, ValueEnum};
+use solana_client::{nonblocking::rpc_client::RpcClient, rpc_config::RpcSendTransactionConfig};
 use solana_sdk::{
-    commitment_config::{CommitmentConfig, CommitmentLevel},
+    commitment_config::CommitmentConfig,
+    compute_budget::ComputeBudgetInstruction,
     pubkey::Pubkey,
     signature::{Keypair, Signature},
-    signer::{EncodableKey, Signer},
+    signer::Signer,
     transaction::Transaction,
 };
 use solana_transaction_status::UiTransactionEncoding;
-use tracing::{error, info};
+use tracing::{error, info, warn};
+
+use crate::{
+    blockhash::{self, BlockhashCache},
+    constant,
+    jito,
+    register::LandingFee,
+    utils,
+    Miner,
+};
+
+/// Sends of a batch that failed, on top of the first one.
+const TRANSFER_RETRIES: usize = 3;
 
-use crate::{constant, Miner};
+/// Transfers of a transaction paying a fee, the compute budget or bribe
+/// instructions take the room of two.
+const FEE_TRANSFER_BATCH_SIZE: usize = constant::TRANSFER_BATCH_SIZE - 2;
+
+/// Compute units of a transaction of `FEE_TRANSFER_BATCH_SIZE` transfers and
+/// its compute budget instructions.
+const TRANSFER_COMPUTE_UNIT_LIMIT: u32 = 200 * (FEE_TRANSFER_BATCH_SIZE as u32 + 2);
 
 #[derive(Parser, Debug, Clone)]
 pub struct BatchTransferArgs {
     #[arg(long)]
     pub keypair: String,
 
-    #[arg(long)]
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Top each address up to this amount of SOL, or send it with --mode fixed, unless it has a row in \
+                --amounts-file"
+    )]
     pub max_value: f64,
 
+    #[arg(long, value_enum, default_value = "top-up", help = "How --max-value is sent")]
+    pub mode: TransferMode,
+
     #[arg(long = "address", value_delimiter = ',')]
     pub addresses: Vec<Pubkey>,
+
+    #[arg(long, help = "Also send to the wallets of the keypairs in this folder")]
+    pub key_folder: Option<String>,
+
+    #[arg(
+        long,
+        help = "Also send to the addresses in this file, one per line, # starts a comment"
+    )]
+    pub address_file: Option<String>,
+
+    #[arg(
+        long,
+        help = "Send the amount of each `pubkey,amount` row of this CSV file instead of topping up to --max-value, in \
+                lamports or in SOL when it has a decimal point"
+    )]
+    pub amounts_file: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Send the transactions as jito bundles tipped with --priority-fee instead of paying it as the compute \
+                unit price"
+    )]
+    pub jito: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Print the amount sent to each address, the fees and whether the fee payer covers them without sending \
+                anything"
+    )]
+    pub dry_run: bool,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferMode {
+    /// Send what each address lacks to hold --max-value, nothing to those
+    /// already holding it or more
+    TopUp,
+    /// Send --max-value to every address whatever it holds
+    Fixed,
 }
 
 impl Miner {
     pub async fn batch_transfer(&self, args: &BatchTransferArgs) {
+        let fee = match (args.jito, self.current_priority_fee()) {
+            (true, Some(tip)) => Some(LandingFee::JitoTip(tip)),
+            (true, None) => {
+                error!("--jito needs the tip in lamports, give it with --priority-fee");
+                return;
+            }
+            (false, Some(price)) => Some(LandingFee::PriorityFee(price)),
+            (false, None) => None,
+        };
+
         let client = Self::get_client_confirmed(&self.rpc);
 
-        let signer = Keypair::read_from_file(&args.keypair).unwrap();
+        let signer = utils::read_keypair(&args.keypair).unwrap();
         let balance = client.get_balance(&signer.pubkey()).await.unwrap();
 
         info!("fee payer: {}", signer.pubkey());
         info!("balance: {}", spl_token::amount_to_ui_amount(balance, 9));
 
-        info!("accounts to distribute: {}", args.addresses.len());
+        let (addresses, fixed_amounts) = match Self::read_transfer_addresses(args, self.recursive)
+            .and_then(|addresses| Ok((addresses, Self::read_transfer_amounts(args)?)))
+        {
+            Ok(value) => value,
+            Err(err) => {
+                error!("{err:#}");
+                return;
+            }
+        };
+
+        let mut amount_to_filled: Vec<(Pubkey, u64)> = vec![];
+
+        // The rows of the amounts file are sent as is, the other addresses get
+        // --max-value.
+        let by_max_value = addresses
+            .iter()
+            .filter(|address| !fixed_amounts.iter().any(|(fixed, _)| fixed == *address))
+            .copied()
+            .collect::<Vec<_>>();
+
+        let added = fixed_amounts
+            .iter()
+            .filter(|(address, _)| !addresses.contains(address))
+            .count();
+
+        info!(
+            from_max_value = by_max_value.len(),
+            from_amounts_file = fixed_amounts.len(),
+            added_from_amounts_file = added,
+            "accounts to distribute: {}",
+            by_max_value.len() + fixed_amounts.len()
+        );
+
+        amount_to_filled.extend(fixed_amounts.iter().filter(|(_, amount)| *amount > 0));
 
         let max_lamports = spl_token::ui_amount_to_amount(args.max_value, 9);
-        let mut amount_to_filled: Vec<(Pubkey, u64)> = vec![];
+        let mut current_balances = HashMap::new();
 
-        for batch in args.addresses.chunks(constant::FETCH_ACCOUNT_LIMIT) {
+        if args.mode == TransferMode::Fixed {
+            amount_to_filled.extend(by_max_value.iter().map(|address| (*address, max_lamports)));
+        }
+
+        for batch in by_max_value
+            .chunks(constant::FETCH_ACCOUNT_LIMIT)
+            .filter(|_| args.mode == TransferMode::TopUp)
+        {
             let account_data = client.get_multiple_accounts(batch).await.unwrap();
             info!(batch_size = batch.len(), "fetched accounts");
 
             for (address, account) in batch.iter().zip(account_data.iter()) {
-                let amount = match account {
-                    None => max_lamports,
-                    Some(acc) => {
-                        if acc.lamports > max_lamports {
-                            0
-                        } else {
-                            max_lamports - acc.lamports
-                        }
-                    }
-                };
+                current_balances.insert(*address, account.as_ref().map(|acc| acc.lamports).unwrap_or_default());
+
+                let amount = top_up_amount(max_lamports, account.as_ref().map(|acc| acc.lamports));
 
                 if amount > 0 {
                     amount_to_filled.push((*address, amount));
@@ -73,13 +190,272 @@ impl Miner {
             spl_token::amount_to_ui_amount(total_amount, 9)
         );
 
-        let mut batch_and_txs = amount_to_filled
-            .chunks(constant::TRANSFER_BATCH_SIZE)
-            .map(|batch| (batch.to_vec(), Signature::default()))
+        let txs = amount_to_filled.chunks(transfer_batch_size(fee)).len() as u64;
+        let fee_per_tx = match fee {
+            Some(LandingFee::PriorityFee(price)) => price * TRANSFER_COMPUTE_UNIT_LIMIT as u64 / 1_000_000,
+            _ => 0,
+        };
+        let tips = match fee {
+            Some(LandingFee::JitoTip(tip)) => tip * txs.div_ceil(5),
+            _ => 0,
+        };
+        let fees = txs * (constant::FEE_PER_SIGNER + fee_per_tx) + tips;
+        let required = total_amount + fees;
+
+        if args.dry_run {
+            println!("{:<44} {:>14} {:>14} source", "address", "balance", "amount");
+
+            for (address, amount) in &amount_to_filled {
+                let current = match current_balances.get(address) {
+                    Some(current) => spl_token::amount_to_ui_amount(*current, 9).to_string(),
+                    None => "-".to_string(),
+                };
+                let source = match fixed_amounts.iter().any(|(fixed, _)| fixed == address) {
+                    true => "amounts file",
+                    false if args.mode == TransferMode::Fixed => "fixed",
+                    false => "top-up",
+                };
+
+                println!(
+                    "{:<44} {:>14} {:>14} {}",
+                    address.to_string(),
+                    current,
+                    spl_token::amount_to_ui_amount(*amount, 9),
+                    source
+                );
+            }
+
+            println!(
+                "{:<44} {:>14} {:>14}",
+                "total",
+                "",
+                spl_token::amount_to_ui_amount(total_amount, 9)
+            );
+            println!(
+                "{:<44} {:>14} {:>14} {txs} transactions",
+                "fees",
+                "",
+                spl_token::amount_to_ui_amount(fees, 9)
+            );
+            println!(
+                "{:<44} {:>14} {:>14}",
+                "fee payer balance",
+                "",
+                spl_token::amount_to_ui_amount(balance, 9)
+            );
+        }
+
+        if required > balance {
+            error!(
+                "fee payer can't cover the transfers and fees of {} SOL, short of {} SOL",
+                spl_token::amount_to_ui_amount(required, 9),
+                spl_token::amount_to_ui_amount(required - balance, 9)
+            );
+            return;
+        }
+
+        if args.dry_run {
+            info!("fee payer covers the transfers and fees, not sending with --dry-run");
+            return;
+        }
+
+        let blockhash_cache = BlockhashCache::spawn(client.clone(), &self.rpc);
+
+        let outcomes = Self::transfer_and_confirm(&client, &blockhash_cache, &signer, &amount_to_filled, fee).await;
+        print_transfer_outcomes(&outcomes);
+    }
+
+    /// The union of `--address`, `--key-folder` and `--address-file`, in that
+    /// order and without duplicates.
+    fn read_transfer_addresses(args: &BatchTransferArgs, recursive: bool) -> eyre::Result<Vec<Pubkey>> {
+        let mut seen = HashSet::new();
+        let mut addresses = vec![];
+
+        let mut add = |source: &str, candidates: Vec<Pubkey>| {
+            let total = candidates.len();
+            let mut added = 0;
+
+            for address in candidates {
+                if seen.insert(address) {
+                    addresses.push(address);
+                    added += 1;
+                }
+            }
+
+            info!(source, total, added, "addresses loaded");
+        };
+
+        add("--address", args.addresses.clone());
+
+        if let Some(key_folder) = &args.key_folder {
+            add(
+                "--key-folder",
+                Self::read_keys(key_folder, recursive)?
+                    .iter()
+                    .map(|key| key.pubkey())
+                    .collect(),
+            );
+        }
+
+        if let Some(path) = &args.address_file {
+            let content = fs::read_to_string(path).map_err(|err| eyre::eyre!("fail to read {path}: {err}"))?;
+            let mut in_file = HashSet::new();
+            let mut from_file = vec![];
+
+            for (i, line) in content.lines().enumerate() {
+                let line = line.split('#').next().unwrap_or_default().trim();
+
+                if line.is_empty() {
+                    continue;
+                }
+
+                let address = Pubkey::from_str(line)
+                    .map_err(|err| eyre::eyre!("{path}:{}: invalid address {line}: {err}", i + 1))?;
+
+                if !in_file.insert(address) {
+                    warn!(%address, line = i + 1, "duplicate address in {path}, skipping");
+                    continue;
+                }
+
+                from_file.push(address);
+            }
+
+            add("--address-file", from_file);
+        }
+
+        Ok(addresses)
+    }
+
+    /// The `pubkey,amount` rows of `--amounts-file` in lamports, `#` starts a
+    /// comment and a `pubkey,amount` header is skipped.
+    fn read_transfer_amounts(args: &BatchTransferArgs) -> eyre::Result<Vec<(Pubkey, u64)>> {
+        let path = match &args.amounts_file {
+            Some(path) => path,
+            None => return Ok(vec![]),
+        };
+
+        let content = fs::read_to_string(path).map_err(|err| eyre::eyre!("fail to read {path}: {err}"))?;
+        let mut amounts = HashMap::new();
+        let mut rows = vec![];
+
+        for (i, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+
+            if line.is_empty() || (i == 0 && line.starts_with("pubkey")) {
+                continue;
+            }
+
+            let (address, amount) = line
+                .split_once(',')
+                .ok_or_else(|| eyre::eyre!("{path}:{}: expected `pubkey,amount`: {line}", i + 1))?;
+
+            let address = Pubkey::from_str(address.trim())
+                .map_err(|err| eyre::eyre!("{path}:{}: invalid address {address}: {err}", i + 1))?;
+
+            let amount = amount.trim();
+            let lamports = match amount.contains('.') {
+                true => amount
+                    .parse::<f64>()
+                    .map(|sol| spl_token::ui_amount_to_amount(sol, 9))
+                    .ok(),
+                false => amount.parse::<u64>().ok(),
+            }
+            .ok_or_else(|| eyre::eyre!("{path}:{}: invalid amount {amount}", i + 1))?;
+
+            if amounts.insert(address, lamports).is_some() {
+                eyre::bail!("{path}:{}: duplicate address {address}", i + 1);
+            }
+
+            rows.push((address, lamports));
+        }
+
+        info!(source = "--amounts-file", total = rows.len(), "amounts loaded");
+
+        Ok(rows)
+    }
+
+    /// Sends the transfers in batches of `TRANSFER_BATCH_SIZE` and waits until
+    /// all of them are confirmed. Expired and failed batches are rebuilt with a
+    /// fresh blockhash up to `TRANSFER_RETRIES` times in all. A batch whose
+    /// send errored is only rebuilt once its blockhash expired
+    /// without the transaction landing. Without a fee the transactions are sent
+    /// as is over RPC. Returns the outcome of every batch.
+    pub async fn transfer_and_confirm(
+        client: &RpcClient,
+        blockhash_cache: &BlockhashCache,
+        signer: &Keypair,
+        amounts: &[(Pubkey, u64)],
+        fee: Option<LandingFee>,
+    ) -> Vec<TransferOutcome> {
+        let mut batches = amounts
+            .chunks(transfer_batch_size(fee))
+            .map(|batch| TransferBatch {
+                transfers: batch.to_vec(),
+                signature: Signature::default(),
+                state: TransferState::Pending,
+                failures: 0,
+                unsettled_since: None,
+            })
             .collect::<Vec<_>>();
 
-        while !batch_and_txs.is_empty() {
-            let (slot, blockhash) = match Self::get_latest_blockhash_and_slot(&client).await {
+        loop {
+            // A transaction may land right before its blockhash expires, or
+            // after its send errored, check once more before sending it again.
+            let expired = batches
+                .iter()
+                .filter(|batch| batch.state == TransferState::Expired || batch.unsettled_since.is_some())
+                .map(|batch| batch.signature)
+                .collect::<Vec<_>>();
+
+            if !expired.is_empty() {
+                match Self::get_signature_statuses(client, &expired).await {
+                    Ok((statuses, slot)) => {
+                        for (status, sig) in statuses.iter().zip(expired.iter()) {
+                            let batch = batches.iter_mut().find(|batch| batch.signature == *sig).unwrap();
+
+                            match status
+                                .as_ref()
+                                .filter(|s| s.satisfies_commitment(utils::commitment_or(CommitmentConfig::confirmed())))
+                            {
+                                Some(status) => batch.confirmed(status.err.as_ref().map(|err| err.to_string())),
+                                None if batch.state == TransferState::Expired => {
+                                    if batch.failures > TRANSFER_RETRIES {
+                                        error!(tx = %sig, failures = batch.failures, "transaction expired, giving up");
+                                        batch.state = TransferState::Failed("blockhash expired".to_string());
+                                    }
+                                }
+                                None => {
+                                    if batch
+                                        .unsettled_since
+                                        .is_some_and(|since| slot > since + constant::SLOT_EXPIRATION)
+                                    {
+                                        info!(tx = %sig, "unsent transaction expired, rebuilding it");
+                                        batch.unsettled_since = None;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        error!("failed to get signature statuses: {:#}", err);
+                        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                        continue;
+                    }
+                }
+            }
+
+            if !batches.iter().any(TransferBatch::should_send) {
+                if batches.iter().all(|batch| batch.unsettled_since.is_none()) {
+                    break;
+                }
+
+                // Only the batches whose send errored are left, they may still
+                // land until their blockhash expires.
+                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                continue;
+            }
+
+            let (slot, blockhash) = match blockhash_cache.get(blockhash::MAX_AGE).await {
                 Ok(r) => r,
                 Err(err) => {
                     error!("failed to get latest blockhash: {:#}", err);
@@ -88,53 +464,114 @@ impl Miner {
                 }
             };
 
-            for (batch, sig) in batch_and_txs.iter_mut() {
-                let mut addresses = vec![];
-                let instructions = batch
-                    .iter()
-                    .map(|(address, amount)| {
-                        addresses.push(address.to_string());
-                        solana_sdk::system_instruction::transfer(&signer.pubkey(), address, *amount)
-                    })
-                    .collect::<Vec<_>>();
+            let mut sending = vec![];
+
+            for (i, batch) in batches.iter_mut().filter(|batch| batch.should_send()).enumerate() {
+                let mut instructions = vec![];
+
+                match fee {
+                    Some(LandingFee::PriorityFee(price)) => {
+                        instructions.push(ComputeBudgetInstruction::set_compute_unit_limit(
+                            TRANSFER_COMPUTE_UNIT_LIMIT,
+                        ));
+                        instructions.push(ComputeBudgetInstruction::set_compute_unit_price(price));
+                    }
+                    // On the first transaction of each bundle.
+                    Some(LandingFee::JitoTip(tip)) if i % 5 == 0 => {
+                        instructions.extend(jito::build_bribe_ix(&signer.pubkey(), tip));
+                    }
+                    _ => {}
+                }
+
+                instructions.extend(batch.transfers.iter().map(|(address, amount)| {
+                    solana_sdk::system_instruction::transfer(&signer.pubkey(), address, *amount)
+                }));
 
                 let tx =
-                    Transaction::new_signed_with_payer(&instructions, Some(&signer.pubkey()), &[&signer], blockhash);
+                    Transaction::new_signed_with_payer(&instructions, Some(&signer.pubkey()), &[signer], blockhash);
+
+                batch.signature = *tx.signatures.first().unwrap();
+                sending.push((batch, tx));
+            }
+
+            if let Some(LandingFee::JitoTip(tip)) = fee {
+                for bundle in sending.chunks_mut(5) {
+                    let txs = bundle.iter().map(|(_, tx)| tx.clone()).collect::<Vec<_>>();
 
-                let calculated_sig = tx.signatures.first().unwrap();
-                *sig = *calculated_sig;
+                    match jito::send_bundle(txs).await {
+                        Ok((tx, bundle_id)) => {
+                            info!(first_tx = %tx, %bundle_id, tip, txs = bundle.len(), "bundle sent");
+
+                            for (batch, _) in bundle.iter_mut() {
+                                batch.state = TransferState::Sent;
+                            }
+                        }
+                        Err(err) => {
+                            error!("failed to send bundle: {err:#}");
+
+                            for (batch, _) in bundle.iter_mut() {
+                                batch.send_failed(format!("{err:#}"), slot);
+                            }
+                        }
+                    }
+                }
+
+                sending.clear();
+            }
+
+            for (batch, tx) in sending {
+                let addresses = batch
+                    .transfers
+                    .iter()
+                    .map(|(address, _)| address.to_string())
+                    .collect::<Vec<_>>();
 
                 let send_cfg = RpcSendTransactionConfig {
                     skip_preflight: false,
-                    preflight_commitment: Some(CommitmentLevel::Confirmed),
+                    preflight_commitment: Some(utils::commitment_or(CommitmentConfig::confirmed()).commitment),
                     encoding: Some(UiTransactionEncoding::Base58),
                     max_retries: Some(5),
                     min_context_slot: Some(slot),
                 };
 
                 let send_result = client.send_transaction_with_config(&tx, send_cfg).await;
-                let total_amount = batch.iter().map(|(_, amount)| amount).sum::<u64>();
+                let total_amount = batch.transfers.iter().map(|(_, amount)| amount).sum::<u64>();
 
                 match send_result {
-                    Ok(sig) => info!(
-                        "transaction sent: {sig}, amount: {}, addresses: {addresses:?}",
-                        spl_token::amount_to_ui_amount(total_amount, 9)
-                    ),
-                    Err(err) => error!(tx = %calculated_sig, "failed to send tx: {err:#}"),
+                    Ok(sig) => {
+                        info!(
+                            "transaction sent: {sig}, amount: {}, addresses: {addresses:?}",
+                            spl_token::amount_to_ui_amount(total_amount, 9)
+                        );
+                        batch.state = TransferState::Sent;
+                    }
+                    Err(err) => {
+                        error!(tx = %batch.signature, "failed to send tx: {err:#}");
+                        batch.send_failed(format!("{err:#}"), slot);
+                    }
                 }
             }
 
             let mut latest_slot = slot;
-            let mut signatures = batch_and_txs.iter().map(|(_, sig)| *sig).collect::<Vec<_>>();
 
-            while !signatures.is_empty() && latest_slot <= slot + constant::SLOT_EXPIRATION {
+            while latest_slot <= slot + constant::SLOT_EXPIRATION {
+                let signatures = batches
+                    .iter()
+                    .filter(|batch| batch.state == TransferState::Sent || batch.unsettled_since.is_some())
+                    .map(|batch| batch.signature)
+                    .collect::<Vec<_>>();
+
+                if signatures.is_empty() {
+                    break;
+                }
+
                 tokio::time::sleep(std::time::Duration::from_secs(5)).await;
                 info!(
                     remaining_tx = signatures.len(),
                     "waiting for all transactions to be confirmed"
                 );
 
-                let response = match client.get_signature_statuses(&signatures).await {
+                let (statuses, slot) = match Self::get_signature_statuses(client, &signatures).await {
                     Ok(r) => r,
                     Err(err) => {
                         error!("failed to get signature statuses: {:#}", err);
@@ -143,10 +580,7 @@ impl Miner {
                     }
                 };
 
-                latest_slot = response.context.slot;
-                let statuses = response.value;
-
-                let mut sig_to_purge_in_query = vec![];
+                latest_slot = slot;
 
                 for (status, sig) in statuses.iter().zip(signatures.iter()) {
                     let status = match status {
@@ -154,25 +588,234 @@ impl Miner {
                         Some(s) => s,
                     };
 
-                    if !status.satisfies_commitment(CommitmentConfig::confirmed()) {
+                    if !status.satisfies_commitment(utils::commitment_or(CommitmentConfig::confirmed())) {
                         continue;
                     }
 
-                    sig_to_purge_in_query.push(*sig);
+                    let batch = batches.iter_mut().find(|batch| batch.signature == *sig).unwrap();
 
                     match &status.err {
-                        None => {
-                            info!(tx = %sig, "transaction confirmed: {sig}");
-                            batch_and_txs.retain(|(_, s)| !s.eq(sig));
-                        }
-                        Some(err) => {
-                            error!(tx = %sig, "transaction failed: {err:#}");
-                        }
+                        None => info!(tx = %sig, "transaction confirmed: {sig}"),
+                        Some(err) => error!(tx = %sig, "transaction failed: {err:#}"),
                     }
+
+                    batch.confirmed(status.err.as_ref().map(|err| err.to_string()));
                 }
+            }
 
-                signatures.retain(|s| !sig_to_purge_in_query.contains(s));
+            for batch in batches.iter_mut().filter(|batch| batch.state == TransferState::Sent) {
+                info!(tx = %batch.signature, "transaction expired, checking it once more");
+                batch.expired();
             }
         }
+
+        batches
+            .into_iter()
+            .map(|batch| TransferOutcome {
+                err: match batch.state {
+                    TransferState::Confirmed => None,
+                    TransferState::Failed(err) => Some(err),
+                    state => Some(format!("{state:?}")),
+                },
+                transfers: batch.transfers,
+                signature: batch.signature,
+            })
+            .collect()
+    }
+}
+
+/// A batch of transfers sent by `Miner::transfer_and_confirm`.
+#[derive(Debug, Clone)]
+pub struct TransferOutcome {
+    pub transfers: Vec<(Pubkey, u64)>,
+    /// Of the last transaction sent.
+    pub signature: Signature,
+    /// Why the batch didn't confirm, `None` once it did.
+    pub err: Option<String>,
+}
+
+impl TransferOutcome {
+    pub fn confirmed(&self) -> bool {
+        self.err.is_none()
+    }
+}
+
+/// Prints the amount, signature and status sent to every address.
+pub fn print_transfer_outcomes(outcomes: &[TransferOutcome]) {
+    println!("{:<44} {:>14} {:<88} status", "address", "amount", "signature");
+
+    for outcome in outcomes {
+        let status = match &outcome.err {
+            None => "confirmed".to_string(),
+            Some(err) => format!("failed: {err}"),
+        };
+
+        for (address, amount) in &outcome.transfers {
+            println!(
+                "{:<44} {:>14} {:<88} {}",
+                address.to_string(),
+                spl_token::amount_to_ui_amount(*amount, 9),
+                outcome.signature.to_string(),
+                status
+            );
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TransferState {
+    /// Not sent yet.
+    Pending,
+    Sent,
+    /// Sent but not confirmed before its blockhash expired.
+    Expired,
+    Confirmed,
+    /// The last send or the transaction failed.
+    Failed(String),
+}
+
+struct TransferBatch {
+    transfers: Vec<(Pubkey, u64)>,
+    /// Of the last transaction sent.
+    signature: Signature,
+    state: TransferState,
+    failures: usize,
+    /// Slot of the blockhash of a transaction whose send errored, it may still
+    /// land until that blockhash expires.
+    unsettled_since: Option<u64>,
+}
+
+impl TransferBatch {
+    fn should_send(&self) -> bool {
+        match self.state {
+            TransferState::Pending => true,
+            TransferState::Expired => self.failures <= TRANSFER_RETRIES,
+            TransferState::Failed(_) => self.unsettled_since.is_none() && self.failures <= TRANSFER_RETRIES,
+            TransferState::Sent | TransferState::Confirmed => false,
+        }
+    }
+
+    fn failed(&mut self, err: String) {
+        self.failures += 1;
+        self.state = TransferState::Failed(err);
+    }
+
+    /// Counts against the retries like a failure, a fee payer that can't pay
+    /// would get the batch resent forever otherwise.
+    fn expired(&mut self) {
+        self.failures += 1;
+        self.state = TransferState::Expired;
+    }
+
+    fn send_failed(&mut self, err: String, slot: u64) {
+        self.failed(err);
+        self.unsettled_since = Some(slot);
+    }
+
+    fn confirmed(&mut self, err: Option<String>) {
+        // The errored send or the expiry was already counted as a failure.
+        let counted = self.unsettled_since.take().is_some() || self.state == TransferState::Expired;
+
+        match err {
+            None => self.state = TransferState::Confirmed,
+            Some(err) if counted => self.state = TransferState::Failed(err),
+            Some(err) => self.failed(err),
+        }
+    }
+}
+
+fn transfer_batch_size(fee: Option<LandingFee>) -> usize {
+    match fee {
+        Some(_) => FEE_TRANSFER_BATCH_SIZE,
+        None => constant::TRANSFER_BATCH_SIZE,
+    }
+}
+
+/// Lamports an address holding `balance` lacks to hold `max_lamports`, 0 when
+/// it holds exactly that or more. A missing account holds nothing.
+fn top_up_amount(max_lamports: u64, balance: Option<u64>) -> u64 {
+    max_lamports.saturating_sub(balance.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_LAMPORTS: u64 = 50_000_000;
+
+    fn batch() -> TransferBatch {
+        TransferBatch {
+            transfers: vec![(Pubkey::new_unique(), MAX_LAMPORTS)],
+            signature: Signature::new_unique(),
+            state: TransferState::Pending,
+            failures: 0,
+            unsettled_since: None,
+        }
+    }
+
+    #[test]
+    fn send_failed_batch_is_not_rebuilt_before_its_blockhash_expires() {
+        let mut batch = batch();
+        batch.send_failed("timeout".to_string(), 100);
+
+        assert!(!batch.should_send());
+
+        batch.unsettled_since = None;
+
+        assert!(batch.should_send());
+    }
+
+    #[test]
+    fn send_failed_batch_that_landed_is_confirmed() {
+        let mut batch = batch();
+        batch.send_failed("timeout".to_string(), 100);
+        batch.confirmed(None);
+
+        assert_eq!(batch.state, TransferState::Confirmed);
+        assert!(!batch.should_send());
+    }
+
+    #[test]
+    fn send_failed_batch_that_landed_failing_counts_one_failure() {
+        let mut batch = batch();
+        batch.send_failed("timeout".to_string(), 100);
+        batch.confirmed(Some("insufficient funds".to_string()));
+
+        assert_eq!(batch.failures, 1);
+        assert!(batch.should_send());
+    }
+
+    #[test]
+    fn expired_batch_is_resent_up_to_the_retries() {
+        let mut batch = batch();
+
+        for _ in 0..TRANSFER_RETRIES {
+            batch.expired();
+            assert!(batch.should_send());
+        }
+
+        batch.expired();
+
+        assert!(!batch.should_send());
+    }
+
+    #[test]
+    fn top_up_amount_fills_a_missing_account() {
+        assert_eq!(top_up_amount(MAX_LAMPORTS, None), MAX_LAMPORTS);
+    }
+
+    #[test]
+    fn top_up_amount_fills_the_missing_part_below_max_value() {
+        assert_eq!(top_up_amount(MAX_LAMPORTS, Some(20_000_000)), 30_000_000);
+    }
+
+    #[test]
+    fn top_up_amount_is_zero_at_max_value() {
+        assert_eq!(top_up_amount(MAX_LAMPORTS, Some(MAX_LAMPORTS)), 0);
+    }
+
+    #[test]
+    fn top_up_amount_is_zero_above_max_value() {
+        assert_eq!(top_up_amount(MAX_LAMPORTS, Some(MAX_LAMPORTS + 1)), 0);
     }
 }
@@ -0,0 +1,145 @@
+use std::time::Duration;
+
+use clap::Parser;
+use itertools::Itertools;
+use solana_sdk::{keccak::Hash, pubkey::Pubkey};
+use tracing::{error, info};
+
+use crate::{format_duration, utils, worker::WorkerSpec, Miner};
+
+/// Roughly 2^8 hashes per nonce.
+const EASY_DIFFICULTY: [u8; 32] = {
+    let mut difficulty = [u8::MAX; 32];
+    difficulty[0] = 0;
+    difficulty
+};
+
+/// Roughly 2^24 hashes per nonce.
+const HARD_DIFFICULTY: [u8; 32] = {
+    let mut difficulty = [u8::MAX; 32];
+    difficulty[0] = 0;
+    difficulty[1] = 0;
+    difficulty[2] = 0;
+    difficulty
+};
+
+/// Length of an ORE epoch, used to estimate how many wallets can be mined.
+const EPOCH_DURATION: Duration = Duration::from_secs(60);
+
+#[derive(Parser, Debug, Clone)]
+pub struct BenchmarkHashrateArgs {
+    #[arg(
+        long,
+        default_value = "25",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        help = "Number of synthetic preimages mined by each run"
+    )]
+    pub preimages: usize,
+
+    #[arg(long, default_value = "10", help = "Maximum number of seconds each run may take")]
+    pub duration: u64,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "CPU thread counts to benchmark, e.g. 1,4,8. Defaults to 1, half and all of the CPUs"
+    )]
+    pub threads: Vec<usize>,
+}
+
+impl Miner {
+    pub async fn benchmark_hashrate(&self, args: &BenchmarkHashrateArgs) {
+        let duration = Duration::from_secs(args.duration);
+        let hash_and_pubkey = (0..args.preimages)
+            .map(|_| (Hash::new_from_array(rand::random()), Pubkey::new_unique()))
+            .collect_vec();
+
+        let threads = match args.threads.as_slice() {
+            [] => {
+                let all = utils::available_threads();
+                vec![1, all / 2, all]
+            }
+            threads => threads.to_vec(),
+        };
+
+        let mut workers = threads
+            .into_iter()
+            .filter(|threads| *threads > 0)
+            .unique()
+            .map(|threads| (format!("cpu x{threads}"), WorkerSpec::cpu(), threads))
+            .collect_vec();
+
+        if utils::get_gpu_nonce_worker_path().exists() {
+            workers.push(("gpu".to_string(), WorkerSpec::gpu(None, true), 0));
+        } else {
+            info!("gpu nonce worker not found, skipping");
+        }
+
+        let mut rows = vec![];
+
+        for (name, worker, threads) in workers {
+            for (level, difficulty) in [("easy", EASY_DIFFICULTY), ("hard", HARD_DIFFICULTY)] {
+                let difficulty = Hash::new_from_array(difficulty);
+
+                info!(worker = %name, difficulty = level, "benchmarking");
+
+                let (elapsed, results) = match self
+                    .try_mine_hashes(worker.clone(), threads, duration, &difficulty, &hash_and_pubkey, None)
+                    .await
+                {
+                    Ok(value) => value,
+                    Err(err) => {
+                        error!(worker = %name, difficulty = level, "benchmark failed: {err:#}");
+                        continue;
+                    }
+                };
+
+                let solved = results.iter().filter(|result| result.is_some()).count();
+                let hash_rate = solved as f64 * expected_hashes(&difficulty) / elapsed.as_secs_f64();
+
+                info!(
+                    worker = %name,
+                    difficulty = level,
+                    solved,
+                    elapsed = format_duration!(elapsed),
+                    "benchmark done"
+                );
+
+                rows.push((name.clone(), level, solved, elapsed, hash_rate));
+            }
+        }
+
+        println!(
+            "{:<12} {:<10} {:>8} {:>10} {:>12} {:>16}",
+            "worker", "difficulty", "solved", "elapsed", "MH/s", "wallets/epoch"
+        );
+
+        for (name, level, solved, elapsed, hash_rate) in rows {
+            let wallets =
+                hash_rate * EPOCH_DURATION.as_secs_f64() / expected_hashes(&Hash::new_from_array(HARD_DIFFICULTY));
+
+            println!(
+                "{:<12} {:<10} {:>8} {:>10} {:>12.2} {:>16.0}",
+                name,
+                level,
+                format!("{solved}/{}", args.preimages),
+                format_duration!(elapsed).to_string(),
+                hash_rate / 1e6,
+                wallets
+            );
+        }
+    }
+}
+
+/// Expected number of hashes to find a nonce, the inverse of the probability
+/// that a hash is less than or equal to the difficulty.
+fn expected_hashes(difficulty: &Hash) -> f64 {
+    let probability = difficulty
+        .to_bytes()
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| *byte as f64 / 256f64.powi(i as i32 + 1))
+        .sum::<f64>();
+
+    1.0 / probability.max(f64::MIN_POSITIVE)
+}
@@ -0,0 +1,219 @@
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use futures_util::{future::join_all, StreamExt};
+use serde_json::json;
+use solana_sdk::{pubkey::Pubkey, system_instruction, transaction::Transaction};
+
+use crate::{benchmark_rpc::EndpointSamples, constant, jito, Miner};
+
+#[derive(Parser, Debug, Clone)]
+pub struct BenchmarkJitoArgs {
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "https://amsterdam.mainnet.block-engine.jito.wtf,https://frankfurt.mainnet.block-engine.jito.wtf,https://ny.mainnet.block-engine.jito.wtf,https://tokyo.mainnet.block-engine.jito.wtf,https://slc.mainnet.block-engine.jito.wtf",
+        help = "Block engines to benchmark, the value of --jito-url"
+    )]
+    pub endpoints: Vec<String>,
+
+    #[arg(long, default_value = jito::TIP_STREAM_URL, help = "Tip websocket timed until its first message")]
+    pub tip_stream: String,
+
+    #[arg(long, default_value = "2000")]
+    pub timeout_ms: u64,
+
+    #[arg(long, default_value = "10", help = "Probes sent to each endpoint")]
+    pub samples: usize,
+
+    #[arg(long, default_value = "1000", help = "Milliseconds between two samples")]
+    pub interval_ms: u64,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Also time the rejection of a bundle with an unsigned transaction, nothing is spent"
+    )]
+    pub send_bundle: bool,
+}
+
+impl Miner {
+    /// Probes every block engine at once `--samples` times and prints the
+    /// latency percentiles of each request, the block engines sorted as
+    /// benchmark-rpc sorts the RPCs.
+    pub async fn benchmark_jito(&self, args: &BenchmarkJitoArgs) {
+        let client = match reqwest::Client::builder()
+            .timeout(Duration::from_millis(args.timeout_ms))
+            .build()
+        {
+            Ok(client) => client,
+            Err(err) => {
+                tracing::error!("fail to build http client: {err:#}");
+                return;
+            }
+        };
+
+        // Rejected for its missing signature before anything is simulated.
+        let payer = Pubkey::new_unique();
+        let bundle = jito::encode_bundle(&[Transaction::new_with_payer(
+            &[system_instruction::transfer(&payer, constant::pick_jito_recipient(), 0)],
+            Some(&payer),
+        )]);
+
+        let mut tip_accounts = args
+            .endpoints
+            .iter()
+            .map(|_| EndpointSamples::default())
+            .collect::<Vec<_>>();
+        let mut rejections = args
+            .endpoints
+            .iter()
+            .map(|_| EndpointSamples::default())
+            .collect::<Vec<_>>();
+        let mut tip_stream = EndpointSamples::default();
+
+        for sample in 0..args.samples.max(1) {
+            if sample > 0 {
+                tokio::time::sleep(Duration::from_millis(args.interval_ms)).await;
+            }
+
+            let results = join_all(
+                args.endpoints
+                    .iter()
+                    .map(|endpoint| Self::time_jito_request(&client, endpoint, "getTipAccounts", json!([]), true)),
+            )
+            .await;
+
+            for (samples, result) in tip_accounts.iter_mut().zip(results) {
+                match result {
+                    Some(latency) => samples.latencies.push(latency),
+                    None => samples.errors += 1,
+                }
+            }
+
+            if args.send_bundle {
+                let results =
+                    join_all(args.endpoints.iter().map(|endpoint| {
+                        Self::time_jito_request(&client, endpoint, "sendBundle", json!([bundle]), false)
+                    }))
+                    .await;
+
+                for (samples, result) in rejections.iter_mut().zip(results) {
+                    match result {
+                        Some(latency) => samples.latencies.push(latency),
+                        None => samples.errors += 1,
+                    }
+                }
+            }
+
+            match Self::time_tip_stream(&args.tip_stream, Duration::from_millis(args.timeout_ms)).await {
+                Some(latency) => tip_stream.latencies.push(latency),
+                None => tip_stream.errors += 1,
+            }
+
+            tracing::debug!(sample, "jito benchmark sample");
+        }
+
+        for samples in tip_accounts.iter_mut().chain(&mut rejections) {
+            samples.latencies.sort();
+        }
+
+        tip_stream.latencies.sort();
+
+        let mut result = args
+            .endpoints
+            .iter()
+            .zip(tip_accounts)
+            .zip(rejections)
+            .map(|((endpoint, tip_accounts), rejections)| (endpoint, tip_accounts, rejections))
+            .collect::<Vec<_>>();
+
+        result.sort_by(|(_, a, _), (_, b, _)| a.score().total_cmp(&b.score()));
+
+        println!(
+            "{:<52} {:<14} {:>9} {:>9} {:>9} {:>9} {:>7}",
+            "endpoint", "request", "min", "p50", "p95", "max", "errors"
+        );
+
+        for (endpoint, tip_accounts, rejections) in &result {
+            print_row(endpoint, "getTipAccounts", tip_accounts);
+
+            if args.send_bundle {
+                print_row(endpoint, "sendBundle", rejections);
+            }
+        }
+
+        print_row(&args.tip_stream, "first tip", &tip_stream);
+    }
+
+    /// Round trip of a JSON-RPC request to the bundles endpoint of
+    /// `block_engine`. An error status only fails the sample when
+    /// `must_succeed`, a rejection still measures the round trip.
+    async fn time_jito_request(
+        client: &reqwest::Client,
+        block_engine: &str,
+        method: &'static str,
+        params: serde_json::Value,
+        must_succeed: bool,
+    ) -> Option<Duration> {
+        let start = Instant::now();
+
+        let response = client
+            .post(jito::bundles_url(block_engine))
+            .json(&json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params}))
+            .send()
+            .await;
+
+        let response = match response {
+            Ok(response) => response,
+            Err(err) => {
+                tracing::debug!(block_engine, method, "request failed: {err:#}");
+                return None;
+            }
+        };
+
+        let status = response.status();
+        let text = response.text().await.ok()?;
+        let latency = start.elapsed();
+
+        if must_succeed && !status.is_success() {
+            tracing::debug!(block_engine, method, %status, "request failed: {text}");
+            return None;
+        }
+
+        Some(latency)
+    }
+
+    /// Time from connecting to the tip websocket to its first message.
+    async fn time_tip_stream(url: &str, timeout: Duration) -> Option<Duration> {
+        let start = Instant::now();
+
+        let first_message = async {
+            let (mut stream, _) = tokio_tungstenite::connect_async(url).await.ok()?;
+            stream.next().await?.ok()
+        };
+
+        match tokio::time::timeout(timeout, first_message).await {
+            Ok(Some(_)) => Some(start.elapsed()),
+            _ => {
+                tracing::debug!(url, "no tip received");
+                None
+            }
+        }
+    }
+}
+
+fn print_row(endpoint: &str, request: &str, samples: &EndpointSamples) {
+    let ms = |latency: Duration| format!("{:.1}ms", latency.as_secs_f64() * 1000.0);
+
+    println!(
+        "{:<52} {:<14} {:>9} {:>9} {:>9} {:>9} {:>6.1}%",
+        endpoint,
+        request,
+        ms(samples.latencies.first().copied().unwrap_or_default()),
+        ms(samples.percentile(50)),
+        ms(samples.percentile(95)),
+        ms(samples.latencies.last().copied().unwrap_or_default()),
+        samples.error_rate() * 100.0
+    );
+}
@@ -1,9 +1,27 @@
-use std::time::{Duration, Instant};
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use clap::Parser;
-use solana_client::nonblocking::rpc_client::RpcClient;
+use clap::{Parser, ValueEnum};
+use futures_util::future::join_all;
+use serde::Serialize;
+use solana_client::{
+    client_error::ClientError,
+    nonblocking::rpc_client::RpcClient,
+    rpc_config::RpcSimulateTransactionConfig,
+};
+use solana_sdk::{
+    hash::Hash,
+    signature::{Keypair, Signature},
+    signer::Signer,
+    system_instruction,
+    transaction::Transaction,
+};
 
-use crate::Miner;
+use crate::{cluster, constant, Miner};
 
 #[derive(Parser, Debug, Clone)]
 pub struct BenchmarkRpcArgs {
@@ -12,10 +30,208 @@ pub struct BenchmarkRpcArgs {
 
     #[arg(long, value_delimiter = ',')]
     pub endpoints: Vec<String>,
+
+    #[arg(
+        long,
+        default_value = "1",
+        help = "Probes sent to each endpoint, more than one reports latency percentiles and slot lag"
+    )]
+    pub samples: usize,
+
+    #[arg(long, default_value = "1000", help = "Milliseconds between two samples")]
+    pub interval_ms: u64,
+
+    #[arg(long, value_enum, default_value = "slot", help = "Requests sent to each endpoint")]
+    pub workload: Workload,
+
+    #[arg(long, help = "Print the results as JSON")]
+    pub json: bool,
+
+    #[arg(long, help = "Write the JSON results to this file instead of stdout")]
+    pub out: Option<String>,
+
+    #[arg(
+        long,
+        value_parser = parse_seconds,
+        help = "Benchmark again every this many seconds until stopped"
+    )]
+    pub watch: Option<Duration>,
+
+    #[arg(
+        long,
+        default_value = "50",
+        help = "With --watch, warn when a p95 latency is this many percent above its average"
+    )]
+    pub regression_pct: f64,
+}
+
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    value
+        .parse::<u64>()
+        .map(Duration::from_secs)
+        .map_err(|_| format!("invalid interval {value}, expected seconds"))
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Workload {
+    /// getSlot only.
+    Slot,
+    /// The requests the miner sends every round.
+    Mining,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum MiningRequest {
+    LatestBlockhash,
+    SystemAccounts,
+    SignatureStatuses,
+    SimulateTransaction,
+}
+
+impl MiningRequest {
+    const ALL: [Self; 4] = [
+        Self::LatestBlockhash,
+        Self::SystemAccounts,
+        Self::SignatureStatuses,
+        Self::SimulateTransaction,
+    ];
+
+    fn method(self) -> &'static str {
+        match self {
+            Self::LatestBlockhash => "getLatestBlockhash",
+            Self::SystemAccounts => "getMultipleAccounts",
+            Self::SignatureStatuses => "getSignatureStatuses",
+            Self::SimulateTransaction => "simulateTransaction",
+        }
+    }
+}
+
+/// Roughly the time a slot takes, a slot of lag weighs as much in the score.
+const SLOT_LAG_PENALTY_MS: f64 = 400.0;
+
+#[derive(Debug, Default)]
+pub(crate) struct EndpointSamples {
+    pub latencies: Vec<Duration>,
+    pub errors: usize,
+    /// Errors telling the rate limit of the endpoint was hit.
+    pub throttled: usize,
+    /// Errors telling the endpoint doesn't serve the method.
+    pub unsupported: usize,
+    /// Most slots behind the endpoint with the highest slot of a sample.
+    pub max_slot_lag: u64,
+}
+
+impl EndpointSamples {
+    pub fn percentile(&self, percentile: usize) -> Duration {
+        match self.latencies.len() {
+            0 => Duration::ZERO,
+            len => self.latencies[(len - 1) * percentile / 100],
+        }
+    }
+
+    pub fn error_rate(&self) -> f64 {
+        self.errors as f64 / (self.errors + self.latencies.len()).max(1) as f64
+    }
+
+    /// p95 latency plus the slot lag, scaled up by the error rate. Lower is
+    /// better, an endpoint that never answered scores infinity.
+    pub fn score(&self) -> f64 {
+        if self.latencies.is_empty() {
+            return f64::INFINITY;
+        }
+
+        let p95 = self.percentile(95).as_secs_f64() * 1000.0;
+
+        (p95 + self.max_slot_lag as f64 * SLOT_LAG_PENALTY_MS) / (1.0 - self.error_rate())
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct MethodReport {
+    method: &'static str,
+    samples: usize,
+    min_ms: f64,
+    p50_ms: f64,
+    p95_ms: f64,
+    max_ms: f64,
+    errors: usize,
+    throttled: usize,
+    unsupported: usize,
+    status: &'static str,
+}
+
+impl MethodReport {
+    fn new(method: &'static str, samples: &EndpointSamples) -> Self {
+        let ms = |latency: Duration| latency.as_secs_f64() * 1000.0;
+
+        let status = match samples {
+            EndpointSamples { unsupported, .. } if *unsupported > 0 => "unsupported",
+            EndpointSamples { throttled, .. } if *throttled > 0 => "throttled",
+            EndpointSamples { errors, .. } if *errors > 0 => "failing",
+            _ => "ok",
+        };
+
+        Self {
+            method,
+            samples: samples.latencies.len() + samples.errors,
+            min_ms: ms(samples.latencies.first().copied().unwrap_or_default()),
+            p50_ms: ms(samples.percentile(50)),
+            p95_ms: ms(samples.percentile(95)),
+            max_ms: ms(samples.latencies.last().copied().unwrap_or_default()),
+            errors: samples.errors,
+            throttled: samples.throttled,
+            unsupported: samples.unsupported,
+            status,
+        }
+    }
+
+    fn error_rate(&self) -> f64 {
+        self.errors as f64 / self.samples.max(1) as f64
+    }
+}
+
+/// The JSON schema of `--json`, one object per endpoint and round.
+#[derive(Debug, Serialize)]
+struct EndpointReport {
+    endpoint: String,
+    /// Unix seconds at the start of the round.
+    timestamp: u64,
+    methods: Vec<MethodReport>,
+    /// Only measured by the slot workload.
+    slot_lag: Option<u64>,
+    /// Only computed by the slot workload, `null` when it never answered.
+    score: Option<f64>,
 }
 
 impl Miner {
     pub async fn benchmark_rpc(&self, args: &BenchmarkRpcArgs) {
+        if let Some(interval) = args.watch {
+            return Self::watch_rpc(args, interval).await;
+        }
+
+        let json = args.json || args.out.is_some();
+
+        if json || args.workload == Workload::Mining || args.samples > 1 {
+            let reports = Self::run_benchmark(args).await;
+
+            if !json {
+                return Self::print_reports(args.workload, &reports);
+            }
+
+            let content = serde_json::to_string_pretty(&reports).unwrap();
+
+            match &args.out {
+                Some(path) => {
+                    if let Err(err) = fs::write(path, content) {
+                        tracing::error!("fail to write benchmark to {path}: {err}");
+                    }
+                }
+                None => println!("{content}"),
+            }
+
+            return;
+        }
+
         let mut tasks = vec![];
         let timeout = Duration::from_millis(args.timeout_ms);
 
@@ -71,6 +287,327 @@ impl Miner {
         }
     }
 
+    /// Probes every endpoint at once `--samples` times, so that the slot lag
+    /// is measured against the same moment.
+    async fn measure_slot(args: &BenchmarkRpcArgs) -> Vec<EndpointSamples> {
+        let timeout = Duration::from_millis(args.timeout_ms);
+        let clients = args
+            .endpoints
+            .iter()
+            .map(|rpc| RpcClient::new_with_timeout(rpc.clone(), timeout))
+            .collect::<Vec<_>>();
+
+        let mut samples = args
+            .endpoints
+            .iter()
+            .map(|_| EndpointSamples::default())
+            .collect::<Vec<_>>();
+
+        for sample in 0..args.samples.max(1) {
+            if sample > 0 {
+                tokio::time::sleep(Duration::from_millis(args.interval_ms)).await;
+            }
+
+            let results = join_all(clients.iter().map(|client| async move {
+                let start = Instant::now();
+                client.get_slot().await.ok().map(|slot| (slot, start.elapsed()))
+            }))
+            .await;
+
+            let best_slot = results.iter().flatten().map(|(slot, _)| *slot).max();
+
+            for (endpoint, result) in samples.iter_mut().zip(results) {
+                match (result, best_slot) {
+                    (Some((slot, latency)), Some(best_slot)) => {
+                        endpoint.latencies.push(latency);
+                        endpoint.max_slot_lag = endpoint.max_slot_lag.max(best_slot - slot);
+                    }
+                    _ => endpoint.errors += 1,
+                }
+            }
+
+            tracing::debug!(sample, best_slot, "rpc benchmark sample");
+        }
+
+        for endpoint in &mut samples {
+            endpoint.latencies.sort();
+        }
+
+        samples
+    }
+
+    /// Measures each request of a mining round `--samples` times per
+    /// endpoint, the endpoints are benchmarked one after the other so that
+    /// they don't compete for the bandwidth.
+    async fn measure_mining(args: &BenchmarkRpcArgs) -> Vec<Vec<(MiningRequest, EndpointSamples)>> {
+        let timeout = Duration::from_millis(args.timeout_ms);
+
+        // Simulated with a recent blockhash and without checking the
+        // signature, the fee payer doesn't need to exist.
+        let payer = Keypair::new();
+        let tx = Transaction::new_signed_with_payer(
+            &[system_instruction::transfer(&payer.pubkey(), &payer.pubkey(), 0)],
+            Some(&payer.pubkey()),
+            &[&payer],
+            Hash::default(),
+        );
+        let signatures = (0..constant::SIGNATURE_STATUS_LIMIT)
+            .map(|_| Signature::new_unique())
+            .collect::<Vec<_>>();
+
+        let mut result = Vec::with_capacity(args.endpoints.len());
+
+        for rpc in &args.endpoints {
+            let client = RpcClient::new_with_timeout(rpc.clone(), timeout);
+            let mut methods = Vec::with_capacity(MiningRequest::ALL.len());
+
+            for request in MiningRequest::ALL {
+                let mut samples = EndpointSamples::default();
+
+                for sample in 0..args.samples.max(1) {
+                    if sample > 0 {
+                        tokio::time::sleep(Duration::from_millis(args.interval_ms)).await;
+                    }
+
+                    let start = Instant::now();
+
+                    match Self::send_mining_request(&client, request, &tx, &signatures).await {
+                        Ok(()) => samples.latencies.push(start.elapsed()),
+                        Err(err) => {
+                            let err = err.to_string();
+                            tracing::debug!(rpc, method = request.method(), "request failed: {err}");
+
+                            samples.errors += 1;
+
+                            if err.contains("429") || err.to_lowercase().contains("too many requests") {
+                                samples.throttled += 1;
+                            } else if err.contains("-32601") || err.to_lowercase().contains("method not found") {
+                                samples.unsupported += 1;
+                            }
+                        }
+                    }
+                }
+
+                samples.latencies.sort();
+                methods.push((request, samples));
+            }
+
+            result.push(methods);
+        }
+
+        result
+    }
+
+    /// One report per endpoint, sorted by score for the slot workload and in
+    /// the order of `--endpoints` for the mining one.
+    async fn run_benchmark(args: &BenchmarkRpcArgs) -> Vec<EndpointReport> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        match args.workload {
+            Workload::Slot => {
+                let mut reports = args
+                    .endpoints
+                    .iter()
+                    .zip(Self::measure_slot(args).await)
+                    .map(|(rpc, samples)| EndpointReport {
+                        endpoint: rpc.clone(),
+                        timestamp,
+                        slot_lag: Some(samples.max_slot_lag),
+                        score: Some(samples.score()).filter(|score| score.is_finite()),
+                        methods: vec![MethodReport::new("getSlot", &samples)],
+                    })
+                    .collect::<Vec<_>>();
+
+                reports.sort_by(|a, b| {
+                    let score = |report: &EndpointReport| report.score.unwrap_or(f64::INFINITY);
+                    score(a).total_cmp(&score(b))
+                });
+
+                reports
+            }
+            Workload::Mining => args
+                .endpoints
+                .iter()
+                .zip(Self::measure_mining(args).await)
+                .map(|(rpc, methods)| EndpointReport {
+                    endpoint: rpc.clone(),
+                    timestamp,
+                    slot_lag: None,
+                    score: None,
+                    methods: methods
+                        .iter()
+                        .map(|(request, samples)| MethodReport::new(request.method(), samples))
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+
+    fn print_reports(workload: Workload, reports: &[EndpointReport]) {
+        let ms = |latency: f64| format!("{latency:.1}ms");
+
+        match workload {
+            Workload::Slot => {
+                println!(
+                    "{:<48} {:>9} {:>9} {:>9} {:>9} {:>7} {:>7} {:>9}",
+                    "rpc", "min", "p50", "p95", "max", "errors", "lag", "score"
+                );
+
+                for report in reports {
+                    let method = &report.methods[0];
+
+                    println!(
+                        "{:<48} {:>9} {:>9} {:>9} {:>9} {:>6.1}% {:>7} {:>9.1}",
+                        report.endpoint,
+                        ms(method.min_ms),
+                        ms(method.p50_ms),
+                        ms(method.p95_ms),
+                        ms(method.max_ms),
+                        method.error_rate() * 100.0,
+                        report.slot_lag.unwrap_or_default(),
+                        report.score.unwrap_or(f64::INFINITY)
+                    );
+                }
+            }
+            Workload::Mining => {
+                println!(
+                    "{:<48} {:<21} {:>9} {:>9} {:>9} {:>9} {:>7} status",
+                    "rpc", "method", "min", "p50", "p95", "max", "errors"
+                );
+
+                for report in reports {
+                    for method in &report.methods {
+                        println!(
+                            "{:<48} {:<21} {:>9} {:>9} {:>9} {:>9} {:>6.1}% {}",
+                            report.endpoint,
+                            method.method,
+                            ms(method.min_ms),
+                            ms(method.p50_ms),
+                            ms(method.p95_ms),
+                            ms(method.max_ms),
+                            method.error_rate() * 100.0,
+                            method.status
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Benchmarks every `interval` until the process is stopped, logging a
+    /// line per endpoint and warning when a method gets slower than its
+    /// average of the previous rounds by more than `--regression-pct`, or
+    /// starts failing.
+    async fn watch_rpc(args: &BenchmarkRpcArgs, interval: Duration) {
+        // (endpoint, method) -> (mean p95 of the rounds, rounds)
+        let mut baseline = HashMap::<(String, &'static str), (f64, u32)>::new();
+        let threshold = 1.0 + args.regression_pct / 100.0;
+
+        loop {
+            let reports = Self::run_benchmark(args).await;
+
+            for report in &reports {
+                let summary = report
+                    .methods
+                    .iter()
+                    .map(|method| {
+                        format!(
+                            "{} p50={:.1}ms p95={:.1}ms err={}/{}",
+                            method.method, method.p50_ms, method.p95_ms, method.errors, method.samples
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                tracing::info!(rpc = %report.endpoint, lag = report.slot_lag, "{summary}");
+
+                for method in &report.methods {
+                    let key = (report.endpoint.clone(), method.method);
+
+                    if method.errors > 0 {
+                        tracing::warn!(
+                            rpc = %report.endpoint,
+                            method = method.method,
+                            errors = method.errors,
+                            status = method.status,
+                            "rpc regression: requests failed"
+                        );
+                    }
+
+                    if method.errors == method.samples {
+                        continue;
+                    }
+
+                    let (mean, rounds) = baseline.entry(key).or_insert((method.p95_ms, 0));
+
+                    if *rounds > 0 && method.p95_ms > *mean * threshold {
+                        tracing::warn!(
+                            rpc = %report.endpoint,
+                            method = method.method,
+                            p95_ms = method.p95_ms,
+                            mean_p95_ms = *mean,
+                            "rpc regression: p95 latency above the average"
+                        );
+                    }
+
+                    *mean = (*mean * *rounds as f64 + method.p95_ms) / (*rounds + 1) as f64;
+                    *rounds += 1;
+                }
+            }
+
+            if args.json || args.out.is_some() {
+                // One JSON line per round, so that the file can be tailed.
+                let line = serde_json::to_string(&reports).unwrap();
+
+                match &args.out {
+                    Some(path) => {
+                        if let Err(err) = OpenOptions::new()
+                            .create(true)
+                            .append(true)
+                            .open(path)
+                            .and_then(|mut file| writeln!(file, "{line}"))
+                        {
+                            tracing::error!("fail to write benchmark to {path}: {err}");
+                        }
+                    }
+                    None => println!("{line}"),
+                }
+            }
+
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    async fn send_mining_request(
+        client: &RpcClient,
+        request: MiningRequest,
+        tx: &Transaction,
+        signatures: &[Signature],
+    ) -> Result<(), ClientError> {
+        match request {
+            MiningRequest::LatestBlockhash => client.get_latest_blockhash().await.map(|_| ()),
+            MiningRequest::SystemAccounts => client
+                .get_multiple_accounts(cluster::system_accounts())
+                .await
+                .map(|_| ()),
+            MiningRequest::SignatureStatuses => client.get_signature_statuses(signatures).await.map(|_| ()),
+            MiningRequest::SimulateTransaction => client
+                .simulate_transaction_with_config(
+                    tx,
+                    RpcSimulateTransactionConfig {
+                        sig_verify: false,
+                        replace_recent_blockhash: true,
+                        ..RpcSimulateTransactionConfig::default()
+                    },
+                )
+                .await
+                .map(|_| ()),
+        }
+    }
+
     pub async fn test_cluster(client: RpcClient) -> Option<(u64, Duration)> {
         let start = Instant::now();
         let slot = client.get_slot().await.ok()?;
@@ -0,0 +1,148 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures_util::StreamExt;
+use once_cell::sync::OnceCell;
+use solana_client::nonblocking::{pubsub_client::PubsubClient, rpc_client::RpcClient};
+use solana_sdk::{clock::Slot, hash::Hash};
+use tokio::sync::RwLock;
+use tracing::{debug, error, info};
+
+use crate::{utils, Miner};
+
+/// Slots between two blockhash refreshes while the slot subscription is up.
+const REFRESH_SLOTS: u64 = 4;
+
+/// Oldest blockhash most callers accept before fetching one over HTTP
+/// themselves. Covers a dropped or lagging refresher.
+pub const MAX_AGE: Duration = Duration::from_secs(5);
+
+/// Oldest blockhash a mining bundle is built with, the slot it was fetched at
+/// tells when the bundle expires.
+pub const BUNDLE_MAX_AGE: Duration = Duration::from_secs(2);
+
+/// Set once from `--blockhash-poll-ms`, the slots are followed over the
+/// websocket without it.
+static POLL_INTERVAL: OnceCell<Duration> = OnceCell::new();
+
+pub fn set_poll_interval(interval: Duration) {
+    let _ = POLL_INTERVAL.set(interval);
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Latest {
+    slot: Slot,
+    blockhash: Hash,
+    fetched_at: Instant,
+}
+
+/// Latest blockhash shared by every task of a command, refreshed in the
+/// background every few slots of a websocket slot subscription or polled at
+/// `--blockhash-poll-ms`.
+#[derive(Clone)]
+pub struct BlockhashCache {
+    client: Arc<RpcClient>,
+    latest: Arc<RwLock<Option<Latest>>>,
+}
+
+impl BlockhashCache {
+    pub fn spawn(client: Arc<RpcClient>, rpc: &str) -> Self {
+        let cache = Self {
+            client,
+            latest: Arc::new(RwLock::new(None)),
+        };
+
+        match POLL_INTERVAL.get() {
+            Some(interval) => tokio::spawn(cache.clone().poll(*interval)),
+            None => tokio::spawn(cache.clone().subscribe(utils::ws_url(rpc))),
+        };
+
+        cache
+    }
+
+    /// Same as `Miner::get_latest_blockhash_and_slot`, from the cache unless
+    /// it is older than `max_age`.
+    pub async fn get(&self, max_age: Duration) -> eyre::Result<(Slot, Hash)> {
+        if let Some(latest) = *self.latest.read().await {
+            let age = latest.fetched_at.elapsed();
+
+            if age < max_age {
+                debug!(slot = latest.slot, age_ms = age.as_millis() as u64, "cached blockhash");
+                return Ok((latest.slot, latest.blockhash));
+            }
+
+            debug!(age_ms = age.as_millis() as u64, "cached blockhash too old, fetching");
+        }
+
+        self.refresh().await
+    }
+
+    async fn refresh(&self) -> eyre::Result<(Slot, Hash)> {
+        let (slot, blockhash) = Miner::get_latest_blockhash_and_slot(&self.client).await?;
+
+        *self.latest.write().await = Some(Latest {
+            slot,
+            blockhash,
+            fetched_at: Instant::now(),
+        });
+
+        Ok((slot, blockhash))
+    }
+
+    async fn poll(self, interval: Duration) {
+        info!(interval_ms = interval.as_millis() as u64, "polling blockhash");
+
+        loop {
+            if let Err(err) = self.refresh().await {
+                error!("fail to refresh blockhash: {err:#}");
+            }
+
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    async fn subscribe(self, url: String) {
+        loop {
+            let pubsub = match PubsubClient::new(&url).await {
+                Ok(pubsub) => pubsub,
+                Err(err) => {
+                    error!(url, "fail to connect to slot subscription: {err:#}");
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    continue;
+                }
+            };
+
+            let (mut slots, _unsubscribe) = match pubsub.slot_subscribe().await {
+                Ok(value) => value,
+                Err(err) => {
+                    error!(url, "fail to subscribe to slots: {err:#}");
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    continue;
+                }
+            };
+
+            info!(url, "subscribed to slots");
+
+            let mut refreshed_at = 0;
+
+            while let Some(info) = slots.next().await {
+                if info.slot < refreshed_at + REFRESH_SLOTS {
+                    continue;
+                }
+
+                refreshed_at = info.slot;
+
+                if let Err(err) = self.refresh().await {
+                    error!("fail to refresh blockhash: {err:#}");
+                }
+            }
+
+            drop(slots);
+
+            info!("slot subscription disconnected, retries in 5 seconds");
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    }
+}
@@ -1,5 +1,9 @@
 use std::{
-    sync::{atomic::AtomicU64, Arc},
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 
@@ -10,14 +14,27 @@ use tokio::sync::{RwLock, Semaphore};
 use tracing::{debug, error, info, warn};
 
 use crate::{
+    blockhash::{self, BlockhashCache},
+    cluster,
+    compute_budget,
     constant,
     constant::FEE_PER_SIGNER,
     format_duration,
     format_reward,
+    history,
     jito,
     jito::{subscribe_jito_tips, JitoTips},
+    metrics,
+    nonce_cache::NonceCache,
+    notify,
+    profit::ProfitCheck,
+    program_error,
+    stats::{self, MiningStats, RunLimit},
+    system_accounts::SystemAccountsCache,
     utils,
+    utils::{BackoffPolicy, BusTx, TxOutcome},
     wait_continue,
+    AccountError,
     Miner,
 };
 #[derive(Debug, Clone, Parser)]
@@ -25,7 +42,11 @@ pub struct BundleMineArgs {
     #[arg(long, help = "The folder that contains all the keys used to claim $ORE")]
     pub key_folder: String,
 
-    #[arg(long, default_value = "4", help = "Number of threads to use for nonce calculation")]
+    #[arg(
+        long,
+        default_value_t = utils::available_threads(),
+        help = "Number of threads to use for nonce calculation, defaults to the number of CPUs"
+    )]
     pub threads: usize,
 
     #[arg(
@@ -42,121 +63,355 @@ pub struct BundleMineArgs {
     )]
     pub max_adaptive_tip: u64,
 
+    #[arg(
+        long,
+        default_value_t = jito::DEFAULT_MIN_TIP,
+        help = "The minimum adaptive tip in lamports, the tip never goes below it unless --max-adaptive-tip is lower"
+    )]
+    pub min_tip: u64,
+
     #[arg(long, default_value = "2", help = "The maximum number of buses to use for mining")]
     pub max_buses: usize,
+
+    #[arg(long, help = "Stop after this many mining rounds, counted across all miner workers")]
+    pub max_rounds: Option<u64>,
+
+    #[arg(
+        long,
+        value_parser = utils::parse_duration,
+        help = "Stop after running for this long, e.g. 90s, 30m or 1h"
+    )]
+    pub run_for: Option<Duration>,
+
+    #[arg(
+        long,
+        help = "Value of 1 ORE in lamports, the rounds expected to earn less than --min-profit-lamports are skipped \
+                with it"
+    )]
+    pub lamports_per_ore: Option<u64>,
+
+    #[arg(
+        long,
+        default_value = "0",
+        allow_hyphen_values = true,
+        help = "Least expected profit of a round in lamports, the rewards valued at --lamports-per-ore minus the fees \
+                and tips"
+    )]
+    pub min_profit_lamports: i64,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Mine every round, even the ones expected to be unprofitable"
+    )]
+    pub force: bool,
+}
+
+impl BundleMineArgs {
+    pub fn profit_check(&self) -> ProfitCheck {
+        ProfitCheck {
+            lamports_per_ore: self.lamports_per_ore,
+            min_profit: self.min_profit_lamports,
+            force: self.force,
+        }
+    }
 }
 
 impl Miner {
     pub async fn bundle_mine(&self, args: &BundleMineArgs) {
-        let signer = Self::read_keys(&args.key_folder);
+        if args.threads == 0 {
+            panic!("threads must be greater than 0");
+        }
+
+        let signer = match Self::read_keys(&args.key_folder, self.recursive) {
+            Ok(accounts) => accounts,
+            Err(err) => {
+                error!("{err:#}");
+                return;
+            }
+        };
         let semaphore = Arc::new(Semaphore::new(args.concurrency));
         let reward_counter = Arc::new(AtomicU64::new(0));
         let tips = Arc::new(RwLock::new(JitoTips::default()));
+        let stats = Arc::new(MiningStats::default());
+        let limit = RunLimit::new(args.max_rounds, args.run_for);
+        let blockhash_cache = BlockhashCache::spawn(Miner::get_client_confirmed(&self.rpc), &self.rpc);
+        let system_accounts = SystemAccountsCache::new(Miner::get_client_confirmed(&self.rpc));
 
         subscribe_jito_tips(tips.clone()).await;
 
+        if let Some(first) = signer.first() {
+            self.measure_mine_units(
+                &Miner::get_client_confirmed(&self.rpc),
+                &system_accounts,
+                first.pubkey(),
+                args.threads,
+            )
+            .await;
+        }
+
+        let mut workers = vec![];
+
         for (i, keys) in signer.chunks(25).enumerate() {
             let miner = self.clone();
             let args = args.clone();
             let semaphore = semaphore.clone();
             let reward_counter = reward_counter.clone();
             let tips = tips.clone();
+            let stats = stats.clone();
+            let limit = limit.clone();
+            let blockhash_cache = blockhash_cache.clone();
+            let system_accounts = system_accounts.clone();
             let signers = keys
                 .iter()
                 .map(|key| Arc::new(key.insecure_clone()))
                 .collect::<Vec<_>>();
 
-            tokio::spawn(async move {
+            workers.push(tokio::spawn(async move {
                 miner
-                    .bundle_mine_worker(i, args, signers, semaphore, reward_counter, tips)
+                    .bundle_mine_worker(
+                        i,
+                        args,
+                        signers,
+                        semaphore,
+                        reward_counter,
+                        tips,
+                        stats,
+                        limit,
+                        blockhash_cache,
+                        system_accounts,
+                    )
                     .await;
-            });
+            }));
         }
 
+        // Workers watch their bundles inline, they return once the last one is
+        // confirmed or dropped.
+        let mut workers = std::pin::pin!(futures_util::future::join_all(workers));
+        let mut reported_at = Instant::now();
+
         loop {
-            tokio::time::sleep(Duration::from_secs(10 * 60)).await;
+            tokio::select! {
+                _ = &mut workers => break,
+                _ = tokio::time::sleep(Duration::from_secs(1)) => {}
+            }
+
+            if stats::stop_requested() {
+                info!("stopping, waiting for sent bundles to confirm");
 
-            let rewards = reward_counter.swap(0, std::sync::atomic::Ordering::Relaxed);
+                if tokio::time::timeout(constant::WATCH_DRAIN_TIMEOUT, &mut workers)
+                    .await
+                    .is_err()
+                {
+                    warn!("some bundles are still unconfirmed, exiting anyway");
+                }
+
+                break;
+            }
+
+            if reported_at.elapsed() < Duration::from_secs(10 * 60) {
+                continue;
+            }
+
+            reported_at = Instant::now();
+
+            let rewards = reward_counter.swap(0, Ordering::Relaxed);
             if rewards > 0 {
                 info!(rewards = format_reward!(rewards), "reward mined");
             }
         }
+
+        stats.log_summary();
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn bundle_mine_worker(
         self,
         miner: usize,
         args: BundleMineArgs,
-        signers: Vec<Arc<Keypair>>,
+        mut signers: Vec<Arc<Keypair>>,
         semaphore: Arc<Semaphore>,
         reward_counter: Arc<AtomicU64>,
         tips: Arc<RwLock<JitoTips>>,
+        stats: Arc<MiningStats>,
+        limit: RunLimit,
+        blockhash_cache: BlockhashCache,
+        system_accounts: SystemAccountsCache,
     ) {
         info!(miner, accounts = signers.len(), "miner started");
 
         let client = Miner::get_client_confirmed(&self.rpc);
-        let mut tip = self.priority_fee.expect("jito tip should set");
+        let nonce_cache = NonceCache::default();
+        let mut tip = self.current_priority_fee().expect("jito tip should set");
+        let mut send_failures = 0;
 
-        let proof_pda = signers
+        let mut proof_pda = signers
             .iter()
             .map(|k| utils::get_proof_pda_no_cache(k.pubkey()))
             .collect_vec();
 
         loop {
-            let signers_balances =
-                match Self::get_balances(&client, &signers.iter().map(|k| k.pubkey()).collect::<Vec<_>>()).await {
-                    Ok(b) => b,
-                    Err(err) => {
-                        error!(miner, "fail to get signers balances: {err:#}");
-                        continue;
-                    }
-                };
-
-            let now = Instant::now();
-            let _permit = semaphore.clone().acquire_owned().await;
-            let mining_queue_duration = now.elapsed();
+            if limit.reached(stats.rounds.load(Ordering::Relaxed)) {
+                info!(miner, "stopping");
+                return;
+            }
 
-            let (treasury, clock, buses) = match Self::get_system_accounts(&client).await {
-                Ok(accounts) => accounts,
+            let signers_pubkey = signers.iter().map(|k| k.pubkey()).collect::<Vec<_>>();
+            let signers_balances = match utils::retry_with_backoff("get signers balances", BackoffPolicy::RPC, || {
+                Self::get_balances(&client, &signers_pubkey)
+            })
+            .await
+            {
+                Ok(b) => b,
                 Err(err) => {
-                    error!(miner, "fail to fetch system accounts: {err:#}");
+                    error!(miner, "{err:#}");
                     wait_continue!(500);
                 }
             };
 
-            let proofs = match Self::get_proof_accounts(&client, &proof_pda).await {
+            metrics::set_balances(&signers_balances);
+            notify::check_balances(&signers_pubkey, &signers_balances);
+
+            let now = Instant::now();
+            let _permit = semaphore.clone().acquire_owned().await;
+            let mining_queue_duration = now.elapsed();
+
+            let (treasury, clock, buses) =
+                match utils::retry_with_backoff("fetch system accounts", BackoffPolicy::RPC, || system_accounts.get())
+                    .await
+                {
+                    Ok(accounts) => accounts,
+                    Err(err) => {
+                        error!(miner, "{err:#}");
+                        wait_continue!(500);
+                    }
+                };
+
+            let proofs = match utils::retry_with_backoff("fetch proof accounts", BackoffPolicy::RPC, || {
+                Self::get_proof_accounts(&client, &proof_pda)
+            })
+            .await
+            {
                 Ok(proofs) => proofs,
-                Err(err) => {
-                    error!(miner, "fail to fetch proof accounts: {err:#}");
-                    wait_continue!(500);
-                }
+                Err(err) => match AccountError::unusable_account(&err) {
+                    Some(pubkey) => {
+                        // Fetching it again won't help, the wallet is left out.
+                        let i = proof_pda.iter().position(|pda| *pda == pubkey).unwrap();
+                        error!(miner, signer = %signers[i].pubkey(), "{err:#}, excluding the wallet");
+                        notify::notify(
+                            &format!("proof_missing/{pubkey}"),
+                            format!(
+                                "proof of wallet {} is unusable, excluded from mining: {err:#}",
+                                signers[i].pubkey()
+                            ),
+                        );
+
+                        signers.remove(i);
+                        proof_pda.remove(i);
+
+                        if signers.is_empty() {
+                            error!(miner, "no wallet left to mine with, stopping");
+                            return;
+                        }
+
+                        continue;
+                    }
+                    None => {
+                        error!(miner, "{err:#}");
+                        wait_continue!(500);
+                    }
+                },
             };
 
+            for (signer, proof) in signers.iter().zip(&proofs) {
+                metrics::set_claimable_rewards(&signer.pubkey(), proof.claimable_rewards);
+            }
+
             let reset_threshold = treasury.last_reset_at.saturating_add(ore::EPOCH_DURATION);
             let time_to_next_epoch = Self::get_time_to_next_epoch(&treasury, &clock, reset_threshold);
 
-            let (mining_duration, mining_results) = self
-                .mine_hashes_cpu(
-                    args.threads,
-                    &treasury.difficulty.into(),
-                    &signers
-                        .iter()
-                        .zip(proofs.iter())
-                        .map(|(signer, proof)| (proof.hash.into(), signer.pubkey()))
-                        .collect::<Vec<_>>(),
-                )
-                .await;
+            // The bundles of the other buses fail once one lands, a single tip
+            // is paid per round.
+            let projected_tip = match args.max_adaptive_tip > 0 {
+                true => tips
+                    .read()
+                    .await
+                    .adaptive_tip(args.min_tip, args.max_adaptive_tip)
+                    .unwrap_or(tip),
+                false => tip,
+            };
+
+            if !args
+                .profit_check()
+                .worth_mining(treasury.reward_rate, signers.len(), 1, projected_tip)
+            {
+                // The other workers mine while this one waits for the epoch.
+                drop(_permit);
+                wait_continue!(time_to_next_epoch.as_millis() as u64);
+            }
+
+            let difficulty: solana_sdk::keccak::Hash = treasury.difficulty.into();
+            let hash_and_pubkey = signers
+                .iter()
+                .zip(proofs.iter())
+                .map(|(signer, proof)| (proof.hash.into(), signer.pubkey()))
+                .collect::<Vec<(solana_sdk::keccak::Hash, _)>>();
+
+            // Nonces of a dropped bundle are still valid, only the others are
+            // mined.
+            let cached = hash_and_pubkey
+                .iter()
+                .map(|(hash, pubkey)| nonce_cache.get(pubkey, hash, &difficulty))
+                .collect::<Vec<_>>();
+
+            let to_mine = hash_and_pubkey
+                .iter()
+                .zip(&cached)
+                .filter(|(_, cached)| cached.is_none())
+                .map(|(hash_and_pubkey, _)| *hash_and_pubkey)
+                .collect::<Vec<_>>();
+
+            if stats::stop_requested() {
+                info!(miner, "stopping");
+                return;
+            }
+
+            let (mining_duration, mined) = if to_mine.is_empty() {
+                (Duration::ZERO, vec![])
+            } else {
+                match self.mine_hashes_cpu(args.threads, &difficulty, &to_mine).await {
+                    Ok(value) => value,
+                    Err(err) => {
+                        error!(miner, "fail to mine: {err:#}");
+                        wait_continue!(500);
+                    }
+                }
+            };
+
+            let mut mined = mined.into_iter();
+            let mining_results = cached
+                .iter()
+                .map(|cached| cached.or_else(|| mined.next()).expect("every signer should be mined"))
+                .collect::<Vec<_>>();
+
+            for ((hash, pubkey), result) in hash_and_pubkey.iter().zip(&mining_results) {
+                nonce_cache.insert(*pubkey, *hash, difficulty, *result);
+            }
+
+            drop(_permit);
 
             if mining_duration > time_to_next_epoch {
                 warn!("mining took too long, waiting for next epoch");
                 wait_continue!(time_to_next_epoch.as_millis() as u64);
             }
-            drop(_permit);
+
+            stats.record_round(mining_duration);
 
             debug!(
                 miner,
                 mining = format_duration!(mining_duration),
                 queue = format_duration!(mining_queue_duration),
+                reused = signers.len() - to_mine.len(),
                 "mining done"
             );
 
@@ -168,84 +423,177 @@ impl Miner {
                 wait_continue!(time_to_next_epoch.as_millis() as u64);
             }
 
-            let rewards = treasury.reward_rate.saturating_mul(signers.len() as u64);
-
             if args.max_adaptive_tip > 0 {
-                let tips = *tips.read().await;
-
-                if tips.p50() > 0 {
-                    tip = args.max_adaptive_tip.min(30000.max(tips.p50() + 1));
+                if let Some(adaptive_tip) = tips.read().await.adaptive_tip(args.min_tip, args.max_adaptive_tip) {
+                    tip = adaptive_tip;
                 }
             }
 
-            let signer_and_mining_results = signers.iter().zip(mining_results.into_iter()).collect::<Vec<_>>();
+            metrics::ADAPTIVE_TIP.set(tip as i64);
 
-            let (send_at_slot, blockhash) = match Self::get_latest_blockhash_and_slot(&client).await {
-                Ok(value) => value,
-                Err(err) => {
-                    error!(miner, "fail to get latest blockhash: {err:#}");
-                    continue;
-                }
-            };
+            let signer_and_mining_results = signers.iter().zip(mining_results).collect::<Vec<_>>();
+
+            let (send_at_slot, blockhash) =
+                match utils::retry_with_backoff("get latest blockhash", BackoffPolicy::RPC, || {
+                    blockhash_cache.get(blockhash::BUNDLE_MAX_AGE)
+                })
+                .await
+                {
+                    Ok(value) => value,
+                    Err(err) => {
+                        error!(miner, "{err:#}");
+                        continue;
+                    }
+                };
+
+            // Fee payers are checked before sending, a transaction whose payer
+            // can't cover the fees gets the whole bundle rejected.
+            let bundle_tipper =
+                match utils::pick_richest_account(&signers_balances, &signers.iter().map(|s| s.pubkey()).collect_vec())
+                {
+                    Some(pubkey) => pubkey,
+                    None => {
+                        warn!(miner, "no signer holds any SOL, waiting for next epoch");
+                        wait_continue!(time_to_next_epoch.as_millis() as u64);
+                    }
+                };
+            let tipper_balance = signers_balances.get(&bundle_tipper).copied().unwrap_or_default();
+
+            if tipper_balance < tip + FEE_PER_SIGNER * 5 {
+                warn!(miner, %bundle_tipper, balance = tipper_balance, tip, "no signer can pay the tip");
+                wait_continue!(time_to_next_epoch.as_millis() as u64);
+            }
+
+            let funded_batches = signer_and_mining_results
+                .chunks(5)
+                .filter_map(|batch| {
+                    let fee_payer = match utils::pick_richest_account(
+                        &signers_balances,
+                        &batch.iter().map(|s| s.0.pubkey()).collect_vec(),
+                    ) {
+                        Some(pubkey) => pubkey,
+                        None => {
+                            warn!(
+                                miner,
+                                signers = batch.len(),
+                                "no signer of the transaction holds any SOL, dropping it"
+                            );
+                            return None;
+                        }
+                    };
+
+                    let balance = signers_balances.get(&fee_payer).copied().unwrap_or_default();
+                    let mut cost = FEE_PER_SIGNER * batch.len() as u64 + compute_budget::priority_fee(batch.len());
+
+                    if fee_payer == bundle_tipper {
+                        cost += tip;
+                    }
+
+                    if balance < cost {
+                        for (signer, _) in batch {
+                            let pubkey = signer.pubkey();
+                            let balance = signers_balances.get(&pubkey).copied().unwrap_or_default();
+
+                            warn!(miner, %pubkey, balance, cost, "insufficient balance for fee, dropping transaction");
+                        }
+
+                        return None;
+                    }
+
+                    Some((fee_payer, batch))
+                })
+                .collect_vec();
+
+            if funded_batches.is_empty() {
+                warn!(miner, "no fee payer can cover the fees, waiting for next epoch");
+                wait_continue!(time_to_next_epoch.as_millis() as u64);
+            }
+
+            // Mining may outlast the Ctrl-C, nothing is sent after it.
+            if stats::stop_requested() {
+                info!(miner, "stopping");
+                return;
+            }
+
+            // Measured before the first bundle so that its limit is the simulated one.
+            if let (Some(bus), Some((fee_payer, batch))) = (available_bus.first(), funded_batches.first()) {
+                let mine_ixs = batch
+                    .iter()
+                    .map(|(signer, (hash, nonce))| {
+                        cluster::instruction(ore::instruction::mine(
+                            signer.pubkey(),
+                            cluster::bus_address(bus.id),
+                            (*hash).into(),
+                            *nonce,
+                        ))
+                    })
+                    .collect_vec();
+
+                compute_budget::measure(&client, *fee_payer, &mine_ixs, batch.len()).await;
+            }
+
+            let rewards = treasury
+                .reward_rate
+                .saturating_mul(funded_batches.iter().map(|(_, batch)| batch.len() as u64).sum());
 
             let confirm_start = Instant::now();
 
+            // The transactions of the bundles, the same in every bus.
+            let mut bus_txs = funded_batches
+                .iter()
+                .map(|(_, batch)| {
+                    let carries_tip =
+                        jito::enabled() && batch.iter().any(|(signer, _)| signer.pubkey() == bundle_tipper);
+
+                    BusTx {
+                        signatures: vec![],
+                        signers: batch.iter().map(|(signer, _)| signer.pubkey()).collect(),
+                        fees: FEE_PER_SIGNER * batch.len() as u64 + compute_budget::priority_fee(batch.len()),
+                        tip: if carries_tip { tip } else { 0 },
+                    }
+                })
+                .collect_vec();
+
             // Bundle limit
             let tasks = available_bus
                 .into_iter()
                 .take(args.max_buses)
                 .map(|bus| {
                     let mut bundle = Vec::with_capacity(5);
-                    let mut fee_payer_and_cost = vec![];
-
-                    let bundle_tipper = utils::pick_richest_account(
-                        &signers_balances,
-                        &signers.iter().map(|s| s.pubkey()).collect_vec(),
-                    );
-
-                    for batch in signer_and_mining_results.chunks(5) {
-                        let fee_payer_this_batch = utils::pick_richest_account(
-                            &signers_balances,
-                            &batch.iter().map(|s| s.0.pubkey()).collect_vec(),
-                        );
 
+                    for (fee_payer_this_batch, batch) in &funded_batches {
                         let mut tx_signers = Vec::with_capacity(batch.len());
-                        let mut ixs = Vec::with_capacity(batch.len());
+                        let mut ixs = compute_budget::bundle_instructions(batch.len());
 
-                        for (signer, (hash, nonce)) in batch {
-                            ixs.push(ore::instruction::mine(
+                        for (signer, (hash, nonce)) in *batch {
+                            ixs.push(cluster::instruction(ore::instruction::mine(
                                 signer.pubkey(),
-                                ore::BUS_ADDRESSES[bus.id as usize],
+                                cluster::bus_address(bus.id),
                                 (*hash).into(),
                                 *nonce,
-                            ));
+                            )));
 
                             tx_signers.push(*signer);
 
                             if bundle_tipper == signer.pubkey() {
-                                ixs.push(jito::build_bribe_ix(&bundle_tipper, tip));
+                                ixs.extend(jito::build_bribe_ix(&bundle_tipper, tip));
                             }
                         }
 
-                        let mut tx = Transaction::new_with_payer(&ixs, Some(&fee_payer_this_batch));
+                        let mut tx = Transaction::new_with_payer(&ixs, Some(fee_payer_this_batch));
                         tx.sign(&tx_signers, blockhash);
 
                         bundle.push(tx);
-
-                        let cost = FEE_PER_SIGNER * tx_signers.len() as u64 + tip;
-                        fee_payer_and_cost.push((fee_payer_this_batch, cost));
                     }
 
-                    (
-                        tokio::spawn(async move { jito::send_bundle(bundle).await }),
-                        fee_payer_and_cost,
-                    )
+                    let signatures = bundle.iter().map(|tx| tx.signatures[0]).collect_vec();
+                    (signatures, tokio::spawn(async move { jito::send_bundle(bundle).await }))
                 })
                 .collect::<Vec<_>>();
 
-            let mut signatures = vec![];
+            let mut sent = 0;
 
-            for (task, fee_payer_and_cost) in tasks {
+            for (bundle_signatures, task) in tasks {
                 let (signature, bundle_id) = match task.await.unwrap() {
                     Ok(r) => r,
                     Err(err) => {
@@ -254,30 +602,28 @@ impl Miner {
                     }
                 };
 
-                for (fee_payer, cost) in fee_payer_and_cost {
-                    let balance = match client.get_balance(&fee_payer).await {
-                        Ok(b) => b,
-                        Err(err) => {
-                            error!(miner, %fee_payer, "fail to get balance: {err:#}");
-                            continue;
-                        }
-                    };
+                stats.record_sent();
+                debug!(miner, ?bundle_id, ?signature, "bundle sent");
+                sent += 1;
 
-                    if balance < cost {
-                        error!(miner, %fee_payer, balance, cost, "insufficient balance for fee");
-                        continue;
-                    }
+                for (tx, signature) in bus_txs.iter_mut().zip(bundle_signatures) {
+                    tx.signatures.push(signature);
                 }
-
-                debug!(miner, ?bundle_id, ?signature, "bundle sent");
-                signatures.push(signature);
             }
 
-            if signatures.is_empty() {
-                warn!(miner, "no bundle sent");
+            if sent == 0 {
+                // The nonces are cached, the next round resends right away
+                // without mining in between.
+                let delay = BackoffPolicy::RPC.delay(send_failures);
+                send_failures += 1;
+
+                warn!(miner, retry = send_failures, delay = ?delay, "no bundle sent");
+                tokio::time::sleep(delay).await;
                 continue;
             }
 
+            send_failures = 0;
+
             let tips = *tips.read().await;
             info!(
                 miner,
@@ -290,10 +636,17 @@ impl Miner {
                 "bundles sent"
             );
 
+            let signatures = bus_txs
+                .iter()
+                .flat_map(|tx| tx.signatures.iter().copied())
+                .collect_vec();
             let mut latest_slot = send_at_slot;
-            let mut landed_tx = vec![];
+            let mut outcomes = utils::bus_tx_outcomes(&bus_txs, &[]);
+            let mut invalidated = false;
 
-            while landed_tx.is_empty() && latest_slot < send_at_slot + constant::SLOT_EXPIRATION {
+            while !outcomes.iter().all(|(_, outcome)| outcome.is_confirmed()) &&
+                latest_slot < send_at_slot + constant::SLOT_EXPIRATION
+            {
                 tokio::time::sleep(Duration::from_secs(2)).await;
                 debug!(miner, latest_slot, send_at_slot, "checking bundle status");
 
@@ -307,21 +660,130 @@ impl Miner {
                 };
 
                 latest_slot = slot;
-                landed_tx = utils::find_landed_txs(&signatures, statuses);
+                outcomes = utils::bus_tx_outcomes(&bus_txs, &utils::find_tx_outcomes(&signatures, statuses));
+
+                if outcomes.iter().any(|(_, outcome)| outcome.is_confirmed()) {
+                    continue;
+                }
+
+                // A bundle landing after the epoch reset earns nothing, no
+                // point in waiting for it to expire.
+                match system_accounts.get().await {
+                    Ok((latest, _, _)) if latest.last_reset_at > treasury.last_reset_at => {
+                        invalidated = true;
+                        break;
+                    }
+                    Ok(_) => {}
+                    Err(err) => debug!(miner, "fail to check the epoch reset: {err:#}"),
+                }
+            }
+
+            if invalidated {
+                stats.record_invalidated();
+
+                warn!(
+                    miner,
+                    confirm = format_duration!(confirm_start.elapsed()),
+                    tip,
+                    "bundle invalidated by epoch reset"
+                );
+
+                continue;
+            }
+
+            let mut landed_tx = vec![];
+            let mut landed_signers = vec![];
+            let mut failed_txs = 0;
+            let mut tip_paid = 0;
+
+            for ((sig, outcome), tx) in outcomes.iter().zip(&bus_txs) {
+                match outcome {
+                    TxOutcome::Landed { .. } => {
+                        landed_tx.push(*sig);
+                        landed_signers.extend(tx.signers.iter().copied());
+                        tip_paid += tx.tip;
+                    }
+                    TxOutcome::Failed { slot, err } => {
+                        failed_txs += 1;
+                        warn!(
+                            miner,
+                            tx = %sig,
+                            slot,
+                            "transaction landed but failed: {}",
+                            program_error::describe(err, None)
+                        );
+                    }
+                    TxOutcome::Pending => {}
+                }
+            }
+
+            if failed_txs > 0 {
+                stats.record_failed(failed_txs);
             }
 
             if !landed_tx.is_empty() {
+                let confirm_duration = confirm_start.elapsed();
+                let total_rewards_before = proofs.iter().map(|proof| proof.total_rewards).collect::<Vec<_>>();
+
+                let actual_rewards = match Self::get_rewards_delta(&client, &proof_pda, &total_rewards_before).await {
+                    Ok(deltas) => {
+                        for (signer, delta) in signers.iter().zip(&deltas) {
+                            debug!(miner, signer = %signer.pubkey(), rewards = format_reward!(*delta), "signer rewards");
+                        }
+
+                        let deltas_by_signer = signers
+                            .iter()
+                            .map(|signer| signer.pubkey())
+                            .zip(deltas.iter().copied())
+                            .collect::<HashMap<_, _>>();
+
+                        history::mined(
+                            utils::landed_slot(&outcomes),
+                            tip_paid,
+                            landed_signers
+                                .iter()
+                                .map(|pubkey| (*pubkey, deltas_by_signer.get(pubkey).copied().unwrap_or_default())),
+                        );
+
+                        let actual_rewards = deltas.iter().sum::<u64>();
+
+                        if actual_rewards == 0 {
+                            warn!(
+                                miner,
+                                "bundle landed but earned no rewards, epoch reset before landing?"
+                            );
+                        }
+
+                        actual_rewards
+                    }
+                    Err(err) => {
+                        warn!(miner, "fail to fetch rewards, using estimate: {err:#}");
+                        rewards
+                    }
+                };
+
                 info!(
                     miner,
                     mining = format_duration!(mining_duration),
                     queue = format_duration!(mining_queue_duration),
-                    confirm = format_duration!(confirm_start.elapsed()),
-                    rewards = format_reward!(rewards),
+                    confirm = format_duration!(confirm_duration),
+                    rewards = format_reward!(actual_rewards),
+                    rewards.estimated = format_reward!(rewards),
                     first_tx = ?landed_tx.first().unwrap(),
                     "bundle mined",
                 );
-                reward_counter.fetch_add(rewards, std::sync::atomic::Ordering::Relaxed);
+                reward_counter.fetch_add(actual_rewards, Ordering::Relaxed);
+                stats.record_landed(tip_paid, actual_rewards, confirm_duration);
+            } else if failed_txs > 0 {
+                warn!(
+                    miner,
+                    failed = failed_txs,
+                    rewards.estimated = format_reward!(rewards),
+                    "bundle landed but every transaction failed, nothing earned"
+                );
             } else {
+                stats.record_dropped();
+
                 warn!(
                     miner,
                     mining = format_duration!(mining_duration),
@@ -1,35 +1,50 @@
 use std::{
-    collections::HashMap,
-    sync::{atomic::AtomicUsize, Arc},
+    collections::{HashMap, HashSet, VecDeque},
+    fs,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+        Mutex,
+    },
     time::{Duration, Instant},
 };
 
 use clap::Parser;
+use eyre::Context;
 use itertools::Itertools;
 use ore::state::Bus;
+use serde::Serialize;
 use solana_client::nonblocking::rpc_client::RpcClient;
-use solana_sdk::{
-    clock::Slot,
-    hash::Hash,
-    pubkey::Pubkey,
-    signature::{Keypair, Signature},
-    signer::Signer,
-    transaction::Transaction,
-};
+use solana_sdk::{clock::Slot, pubkey::Pubkey, signature::Keypair, signer::Signer, transaction::Transaction};
 use tokio::sync::{
-    mpsc::{channel, Sender},
+    mpsc::{channel, error::TrySendError, unbounded_channel, Sender, UnboundedReceiver},
     RwLock,
 };
 use tracing::{debug, error, info, warn};
 
 use crate::{
+    blockhash::{self, BlockhashCache},
+    cluster,
+    compute_budget,
     constant,
+    dashboard,
     format_duration,
     format_reward,
+    history,
     jito,
     jito::{subscribe_jito_tips, JitoTips},
+    metrics,
+    nonce_cache::NonceCache,
+    notify,
+    profit::ProfitCheck,
+    program_error,
+    stats,
+    stats::{BatchOutcome, BatchState, InFlight, MiningStats, RunLimit},
+    system_accounts::SystemAccountsCache,
     utils,
+    utils::{BackoffPolicy, BusTx, TxOutcome},
     wait_return,
+    AccountError,
     Miner,
 };
 
@@ -45,8 +60,174 @@ pub struct BundleMineGpuArgs {
     )]
     pub max_adaptive_tip: u64,
 
+    #[arg(
+        long,
+        default_value_t = jito::DEFAULT_MIN_TIP,
+        help = "The minimum adaptive tip in lamports, the tip never goes below it unless --max-adaptive-tip is lower"
+    )]
+    pub min_tip: u64,
+
     #[arg(long, default_value = "2", help = "The maximum number of buses to use for mining")]
     pub max_buses: usize,
+
+    #[arg(
+        long,
+        default_value = "4",
+        help = "The maximum number of 25 signers batches mined together. Raise it to keep a big GPU busy"
+    )]
+    pub max_concurrent_batches: usize,
+
+    #[arg(
+        long,
+        default_value = "4",
+        help = "Number of CPU threads to use when the GPU worker is unavailable"
+    )]
+    pub fallback_threads: usize,
+
+    #[arg(
+        long,
+        help = "Stop mining after this many seconds and send bundles for the accounts solved so far. Defaults to \
+                the time left in the current epoch"
+    )]
+    pub mining_timeout: Option<u64>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Read all GPU worker results at once instead of streaming them, for GPU workers that can't stream"
+    )]
+    pub no_gpu_stream: bool,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "GPU devices to mine on, e.g. 0,1,2,3. Each batch is split across one worker per device"
+    )]
+    pub gpu_devices: Vec<usize>,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Lamports a signer must hold on top of the transaction fee to be mined with. Signers below it are \
+                skipped until topped up"
+    )]
+    pub fee_reserve: u64,
+
+    #[arg(
+        long,
+        help = "Keypair used to top up signers whose balance falls below --min-balance"
+    )]
+    pub funder: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "0.01",
+        help = "SOL balance below which a signer is topped up by the funder"
+    )]
+    pub min_balance: f64,
+
+    #[arg(long, default_value = "0.05", help = "SOL balance a signer is topped up to")]
+    pub top_up_to: f64,
+
+    #[arg(
+        long,
+        default_value = "600",
+        help = "Minimum number of seconds between two top-ups of a signer"
+    )]
+    pub top_up_cooldown: u64,
+
+    #[arg(
+        long,
+        default_value = "2",
+        help = "Hold the bundles until the epoch resets when fewer seconds than this are left in the epoch after \
+                mining, instead of paying tips for bundles that land too late"
+    )]
+    pub reset_buffer_secs: u64,
+
+    #[arg(long, help = "Stop after this many mining rounds")]
+    pub max_rounds: Option<u64>,
+
+    #[arg(
+        long,
+        value_parser = utils::parse_duration,
+        help = "Stop after running for this long, e.g. 90s, 30m or 1h"
+    )]
+    pub run_for: Option<Duration>,
+
+    #[arg(long, default_value = "10", help = "Minutes between two aggregate statistics logs")]
+    pub stats_interval: u64,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Show a terminal dashboard of the accounts batches instead of the logs"
+    )]
+    pub tui: bool,
+
+    #[arg(
+        long,
+        help = "JSON file the pubkeys of every accounts batch are written to at startup"
+    )]
+    pub batch_map: Option<String>,
+
+    #[arg(
+        long,
+        help = "Send the same bundles again every this many milliseconds until they land or their blockhash expires"
+    )]
+    pub resend_interval_ms: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Wallets mining at a time, a multiple of 25. The others wait on standby and take the place of the \
+                batches whose bundle landed"
+    )]
+    pub active_wallets: Option<usize>,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Epochs a batch rests after its bundle landed before it can be promoted again, with --active-wallets"
+    )]
+    pub cooldown_epochs: u64,
+
+    #[arg(
+        long,
+        help = "Value of 1 ORE in lamports, the rounds expected to earn less than --min-profit-lamports are skipped \
+                with it"
+    )]
+    pub lamports_per_ore: Option<u64>,
+
+    #[arg(
+        long,
+        default_value = "0",
+        allow_hyphen_values = true,
+        help = "Least expected profit of a round in lamports, the rewards valued at --lamports-per-ore minus the fees \
+                and tips"
+    )]
+    pub min_profit_lamports: i64,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Mine every round, even the ones expected to be unprofitable"
+    )]
+    pub force: bool,
+}
+
+impl BundleMineGpuArgs {
+    pub fn profit_check(&self) -> ProfitCheck {
+        ProfitCheck {
+            lamports_per_ore: self.lamports_per_ore,
+            min_profit: self.min_profit_lamports,
+            force: self.force,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct BatchMapEntry {
+    id: usize,
+    pubkeys: Vec<String>,
 }
 
 impl Miner {
@@ -55,23 +236,73 @@ impl Miner {
             panic!("max buses must be greater than 0");
         }
 
+        if args.fallback_threads == 0 {
+            panic!("fallback threads must be greater than 0");
+        }
+
+        if args.max_concurrent_batches == 0 {
+            panic!("max concurrent batches must be greater than 0");
+        }
+
         let client = Miner::get_client_confirmed(&self.rpc);
+        let blockhash_cache = BlockhashCache::spawn(client.clone(), &self.rpc);
+        let system_accounts = SystemAccountsCache::new(client.clone());
+        let funder = Funder::new(args);
+        let stats = Arc::new(MiningStats::default());
+        let nonce_cache = NonceCache::default();
+        let limit = RunLimit::new(args.max_rounds, args.run_for);
+
+        if args.stats_interval > 0 {
+            stats.spawn_reporter(Duration::from_secs(args.stats_interval * 60));
+        }
 
-        let all_signers = Self::read_keys(&args.key_folder)
-            .into_iter()
-            .map(Box::new)
-            .collect::<Vec<_>>();
+        let mut all_signers = match Self::read_keys(&args.key_folder, self.recursive) {
+            Ok(accounts) => accounts.into_iter().map(Box::new).collect::<Vec<_>>(),
+            Err(err) => {
+                error!("{err:#}");
+                return;
+            }
+        };
+
+        // Sorted so that a batch id names the same signers on every machine
+        // and run, whatever order the key files are read in.
+        all_signers.sort_by_key(|signer| signer.pubkey());
 
         if all_signers.len() % Accounts::size() != 0 {
             panic!("number of keys must be a multiple of {}", Accounts::size());
         }
 
+        if let Some(active_wallets) = args.active_wallets {
+            if active_wallets == 0 || active_wallets % Accounts::size() != 0 {
+                panic!("active wallets must be a positive multiple of {}", Accounts::size());
+            }
+        }
+
         info!("{} keys loaded", all_signers.len());
 
+        if let Some(first) = all_signers.first() {
+            self.measure_mine_units(&client, &system_accounts, first.pubkey(), args.fallback_threads)
+                .await;
+        }
+
+        let total_batches = all_signers.len() / Accounts::size();
+        let mut rotation = Rotation::new(args, total_batches);
+        let concurrent_batches = args.max_concurrent_batches.min(rotation.active_target);
+
+        info!(
+            batches = total_batches,
+            batches.concurrent = concurrent_batches,
+            preimages = concurrent_batches * Accounts::size(),
+            gpu_devices = args.gpu_devices.len().max(1),
+            "mining parallelism"
+        );
+
         let idle_accounts_counter = Arc::new(AtomicUsize::new(all_signers.len()));
 
-        // Setup channels
-        let (ch_accounts, mut ch_accounts_receiver) = channel::<Accounts>(all_signers.len() / Accounts::size());
+        // Setup channels, every batch is queued at once and released back into
+        // the channel after mining.
+        let (ch_accounts, mut ch_accounts_receiver) =
+            channel::<Accounts>(total_batches.max(args.max_concurrent_batches));
 
         let batches = all_signers
             .into_iter()
@@ -89,12 +320,29 @@ impl Miner {
                         .map(|k| utils::get_proof_pda_no_cache(k.pubkey()))
                         .collect(),
                     signers,
+                    landed: false,
                     release_stuff: (ch_accounts.clone(), idle_accounts_counter.clone()),
                 }
             })
             .collect::<Vec<_>>();
 
+        for accounts in &batches {
+            info!(
+                acc.id = accounts.id,
+                first = %accounts.pubkey.first().unwrap(),
+                last = %accounts.pubkey.last().unwrap(),
+                "accounts batch"
+            );
+        }
+
+        if let Some(path) = &args.batch_map {
+            if let Err(err) = write_batch_map(path, &batches) {
+                error!("{err:#}");
+            }
+        }
+
         for signers in batches {
+            stats.set_batch_state(signers.id, BatchState::Idle);
             ch_accounts.send(signers).await.unwrap();
         }
 
@@ -105,51 +353,97 @@ impl Miner {
         subscribe_jito_tips(tips.clone()).await;
         info!("subscribed to jito tip stream");
 
-        loop {
-            let mut batch = Vec::new();
+        let dashboard = match args.tui {
+            true => match dashboard::start(stats.clone(), tips.clone()) {
+                Ok(dashboard) => Some(dashboard),
+                Err(err) => {
+                    error!("fail to start the dashboard, logging instead: {err:#}");
+                    None
+                }
+            },
+            false => None,
+        };
 
+        while !limit.reached(stats.rounds.load(Ordering::Relaxed)) {
             while let Ok(accounts) = ch_accounts_receiver.try_recv() {
-                batch.push(accounts);
-                if batch.len() >= 4 {
-                    break;
-                }
+                rotation.push(accounts);
             }
 
+            let mut batch = rotation.take(args.max_concurrent_batches);
+
             if batch.is_empty() {
                 debug!("no more batches, waiting for more signers");
                 tokio::time::sleep(Duration::from_millis(500)).await;
                 continue;
             }
 
+            let taken = batch.len() * Accounts::size();
             let idle_accounts = idle_accounts_counter
-                .fetch_sub(batch.len() * Accounts::size(), std::sync::atomic::Ordering::Relaxed) -
-                batch.len() * Accounts::size();
+                .fetch_sub(taken, Ordering::Relaxed)
+                .saturating_sub(taken);
 
             loop {
                 let result = self
-                    .mine_with_accounts(args, client.clone(), tips.clone(), batch, idle_accounts)
+                    .mine_with_accounts(
+                        args,
+                        client.clone(),
+                        &blockhash_cache,
+                        &system_accounts,
+                        tips.clone(),
+                        funder.as_ref(),
+                        &stats,
+                        &nonce_cache,
+                        batch,
+                        idle_accounts,
+                    )
                     .await;
 
                 batch = match result {
+                    Some(batch_to_retry) if stats::stop_requested() => {
+                        release_all(batch_to_retry).await;
+                        break;
+                    }
                     Some(batch_to_retry) => batch_to_retry,
                     None => break,
                 }
             }
         }
+
+        info!("stopping, waiting for sent bundles to confirm");
+
+        if !stats.wait_in_flight(constant::WATCH_DRAIN_TIMEOUT).await {
+            warn!("some bundles are still unconfirmed, exiting anyway");
+        }
+
+        drop(dashboard);
+
+        stats.log_summary();
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn mine_with_accounts(
         &self,
         args: &BundleMineGpuArgs,
         client: Arc<RpcClient>,
+        blockhash_cache: &BlockhashCache,
+        system_accounts: &SystemAccountsCache,
         tips: Arc<RwLock<JitoTips>>,
+        funder: Option<&Funder>,
+        stats: &Arc<MiningStats>,
+        nonce_cache: &NonceCache,
         batch: Vec<Accounts>,
         idle_accounts: usize,
     ) -> Option<Vec<Accounts>> {
-        let (treasury, clock, buses) = match Self::get_system_accounts(&client).await {
+        let (treasury, clock, buses) = match utils::retry_with_backoff(
+            "fetch system accounts",
+            BackoffPolicy::RPC,
+            || system_accounts.get(),
+        )
+        .await
+        {
             Ok(accounts) => accounts,
             Err(err) => {
-                error!("fail to fetch system accounts: {err:#}");
+                error!("{err:#}");
                 wait_return!(500, Some(batch));
             }
         };
@@ -164,46 +458,138 @@ impl Miner {
             .flat_map(|accounts| accounts.proof_pda.clone())
             .collect::<Vec<_>>();
 
-        let signer_balances = match Self::get_balances(&client, &all_pubkey).await {
+        let mut signer_balances = match utils::retry_with_backoff("get signers balances", BackoffPolicy::RPC, || {
+            Self::get_balances(&client, &all_pubkey)
+        })
+        .await
+        {
             Ok(b) => b,
             Err(err) => {
-                error!("fail to get signers balances: {err:#}");
+                error!("{err:#}");
                 wait_return!(500, Some(batch));
             }
         };
 
-        let proofs = match Self::get_proof_accounts(&client, &proof_pda).await {
-            Ok(proofs) => proofs,
-            Err(err) => {
-                error!("fail to fetch proof accounts: {err:#}");
-                wait_return!(500, Some(batch));
+        if let Some(funder) = funder {
+            if funder
+                .top_up(&client, blockhash_cache, &all_pubkey, &signer_balances)
+                .await
+            {
+                signer_balances = match utils::retry_with_backoff("get signers balances", BackoffPolicy::RPC, || {
+                    Self::get_balances(&client, &all_pubkey)
+                })
+                .await
+                {
+                    Ok(b) => b,
+                    Err(err) => {
+                        error!("{err:#}");
+                        wait_return!(500, Some(batch));
+                    }
+                };
             }
+        }
+
+        let proofs = match utils::retry_with_backoff("fetch proof accounts", BackoffPolicy::RPC, || {
+            Self::get_proof_accounts(&client, &proof_pda)
+        })
+        .await
+        {
+            Ok(proofs) => proofs,
+            Err(err) => match AccountError::unusable_account(&err) {
+                Some(pubkey) => {
+                    // Fetching it again won't help, the accounts holding the
+                    // wallet are not released and leave the rotation.
+                    let (excluded, batch): (Vec<_>, Vec<_>) = batch
+                        .into_iter()
+                        .partition(|accounts| accounts.proof_pda.contains(&pubkey));
+
+                    for accounts in excluded {
+                        error!(acc.id = accounts.id, "{err:#}, excluding the accounts");
+                        accounts.discard();
+                    }
+
+                    notify::notify(
+                        &format!("proof_missing/{pubkey}"),
+                        format!("proof {pubkey} is unusable, its accounts batch is excluded from mining: {err:#}"),
+                    );
+
+                    return (!batch.is_empty()).then_some(batch);
+                }
+                None => {
+                    error!("{err:#}");
+                    wait_return!(500, Some(batch));
+                }
+            },
         };
 
+        metrics::set_balances(&signer_balances);
+        notify::check_balances(&all_pubkey, &signer_balances);
+
+        for (pubkey, proof) in all_pubkey.iter().zip(&proofs) {
+            metrics::set_claimable_rewards(pubkey, proof.claimable_rewards);
+        }
+
         let reset_threshold = treasury.last_reset_at.saturating_add(ore::EPOCH_DURATION);
         let time_to_next_epoch = Self::get_time_to_next_epoch(&treasury, &clock, reset_threshold);
 
-        let hash_and_pubkey = all_pubkey
+        stats.record_round_info(treasury.reward_rate, treasury.difficulty.into(), time_to_next_epoch);
+
+        // A signer that can't pay the fee fails the whole bundle, so only the
+        // others are mined. Balances are fetched every round, a skipped signer
+        // rejoins once topped up.
+        let min_balance = constant::FEE_PER_SIGNER.saturating_add(args.fee_reserve);
+        let mining_positions = all_pubkey
             .iter()
-            .zip(proofs.iter())
-            .map(|(signer, proof)| (solana_sdk::keccak::Hash::new_from_array(proof.hash.0), *signer))
-            .collect::<Vec<_>>();
-        let (mining_duration, mining_results) = self
-            .mine_hashes_gpu(&treasury.difficulty.into(), &hash_and_pubkey)
-            .await;
+            .enumerate()
+            .filter(|(_, pubkey)| {
+                let balance = signer_balances.get(pubkey).copied().unwrap_or_default();
+
+                if balance < min_balance {
+                    warn!(%pubkey, balance, required = min_balance, "insufficient balance, skipping signer");
+                }
+
+                balance >= min_balance
+            })
+            .map(|(i, _)| i)
+            .collect_vec();
 
-        if mining_duration > time_to_next_epoch {
-            warn!("mining took too long, waiting for next epoch");
+        if mining_positions.is_empty() {
+            warn!("no signer has enough balance, waiting for next epoch");
             wait_return!(time_to_next_epoch.as_millis() as u64, Some(batch));
-        } else {
-            info!(
-                accounts = Accounts::size() * batch.len(),
-                accounts.idle = idle_accounts,
-                mining = format_duration!(mining_duration),
-                "mining done"
-            );
         }
 
+        // Nonces of a dropped bundle are still valid, those signers go straight
+        // to the bundles. They are put after the mined ones so that the worker
+        // results keep indexing the first positions.
+        let difficulty: solana_sdk::keccak::Hash = treasury.difficulty.into();
+        let mut cached = vec![];
+        let mut to_mine = vec![];
+
+        for i in mining_positions {
+            let proof_hash = solana_sdk::keccak::Hash::new_from_array(proofs[i].hash.0);
+
+            match nonce_cache.get(&all_pubkey[i], &proof_hash, &difficulty) {
+                Some(result) => cached.push((i, result)),
+                None => to_mine.push(i),
+            }
+        }
+
+        let hash_and_pubkey = to_mine
+            .iter()
+            .map(|i| {
+                (
+                    solana_sdk::keccak::Hash::new_from_array(proofs[*i].hash.0),
+                    all_pubkey[*i],
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mining_positions = to_mine
+            .iter()
+            .copied()
+            .chain(cached.iter().map(|(i, _)| *i))
+            .collect_vec();
+
         let available_bus = Self::find_buses(buses, treasury.reward_rate.saturating_mul(all_pubkey.len() as u64 + 20))
             .into_iter()
             .take(args.max_buses)
@@ -213,52 +599,158 @@ impl Miner {
             wait_return!(time_to_next_epoch.as_millis() as u64, Some(batch));
         }
 
-        let rewards = treasury.reward_rate.saturating_mul(25);
-        let tip = self.priority_fee.expect("priority fee should be set");
-
-        let (send_at_slot, blockhash) = match Self::get_latest_blockhash_and_slot(&client).await {
-            Ok(value) => value,
-            Err(err) => {
-                error!("fail to get latest blockhash: {err:#}");
-                wait_return!(time_to_next_epoch.as_millis() as u64, Some(batch));
-            }
+        let reward_rate = treasury.reward_rate;
+        let tip = self.current_priority_fee().expect("priority fee should be set");
+        let projected_tip = match args.max_adaptive_tip > 0 {
+            true => tips
+                .read()
+                .await
+                .adaptive_tip(args.min_tip, args.max_adaptive_tip)
+                .unwrap_or(tip),
+            false => tip,
         };
 
+        if !args
+            .profit_check()
+            .worth_mining(reward_rate, mining_positions.len(), batch.len(), projected_tip)
+        {
+            wait_return!(time_to_next_epoch.as_millis() as u64, Some(batch));
+        }
+
+        let mining_timeout = args
+            .mining_timeout
+            .map(Duration::from_secs)
+            .unwrap_or(time_to_next_epoch);
+
+        let batch_ids = batch.iter().map(|accounts| accounts.id).collect_vec();
+        let batch_size = batch.len();
+        let (results_sender, results) = unbounded_channel();
+
+        // Bundles are built as soon as the results of an accounts batch are all
+        // available, the task releases the batches left unsolved if mining fails.
         let task = SendBundleTask {
             client,
+            blockhash_cache: blockhash_cache.clone(),
+            system_accounts: system_accounts.clone(),
             tips,
+            stats: stats.clone(),
+            _in_flight: stats.track(),
             batch,
             available_bus,
             signer_balances,
-            mining_duration,
-            mining_results,
-            rewards,
+            mining_positions,
+            proof_hashes: proofs
+                .iter()
+                .map(|proof| solana_sdk::keccak::Hash::new_from_array(proof.hash.0))
+                .collect(),
+            total_rewards: proofs.iter().map(|proof| proof.total_rewards).collect(),
+            mining_start: Instant::now(),
+            time_to_next_epoch,
+            reset_buffer: Duration::from_secs(args.reset_buffer_secs),
+            resend_interval: args.resend_interval_ms.map(|ms| Duration::from_millis(ms.max(1))),
+            last_reset_at: treasury.last_reset_at,
+            results,
+            reward_rate,
             tip,
+            min_tip: args.min_tip,
             max_tip: args.max_adaptive_tip,
-            slot: send_at_slot,
-            blockhash,
         };
 
         tokio::spawn(task.work());
 
+        for id in &batch_ids {
+            stats.set_batch_state(*id, BatchState::Mining);
+        }
+
+        for (k, (_, (hash, nonce))) in cached.iter().enumerate() {
+            let _ = results_sender.send((hash_and_pubkey.len() + k, *hash, *nonce));
+        }
+
+        let (mining_duration, solved) = if hash_and_pubkey.is_empty() {
+            (Duration::ZERO, 0)
+        } else {
+            match self
+                .mine_hashes_gpu(
+                    &args.gpu_devices,
+                    args.fallback_threads,
+                    !args.no_gpu_stream,
+                    mining_timeout,
+                    &difficulty,
+                    &hash_and_pubkey,
+                    Some(&results_sender),
+                )
+                .await
+            {
+                Ok((mining_duration, results)) => {
+                    for ((hash, pubkey), result) in hash_and_pubkey.iter().zip(&results) {
+                        if let Some(result) = result {
+                            nonce_cache.insert(*pubkey, *hash, difficulty, *result);
+                        }
+                    }
+
+                    (mining_duration, results.iter().filter(|r| r.is_some()).count())
+                }
+                Err(err) => {
+                    error!(acc.ids = ?batch_ids, "fail to mine: {err:#}");
+                    wait_return!(500, None);
+                }
+            }
+        };
+
+        stats.record_round(mining_duration);
+
+        info!(
+            accounts = Accounts::size() * batch_size,
+            accounts.solved = solved,
+            accounts.reused = cached.len(),
+            accounts.idle = idle_accounts,
+            mining = format_duration!(mining_duration),
+            "mining done"
+        );
+
         None
     }
 }
 
+/// Hands the batches back to the rotation, a batch dropped instead is taken
+/// for lost and requeued by `Accounts::drop`.
+async fn release_all(batch: Vec<Accounts>) {
+    for accounts in batch {
+        accounts.release().await;
+    }
+}
+
+fn write_batch_map(path: &str, batches: &[Accounts]) -> eyre::Result<()> {
+    let entries = batches
+        .iter()
+        .map(|accounts| BatchMapEntry {
+            id: accounts.id,
+            pubkeys: accounts.pubkey.iter().map(|pubkey| pubkey.to_string()).collect(),
+        })
+        .collect_vec();
+
+    fs::write(path, serde_json::to_string_pretty(&entries)?)
+        .with_context(|| format!("fail to write batch map {path}"))?;
+
+    info!(path, batches = entries.len(), "batch map written");
+
+    Ok(())
+}
+
 struct Accounts {
     pub id: usize,
     #[allow(clippy::vec_box)]
     pub signers: Vec<Box<Keypair>>,
     pub pubkey: Vec<Pubkey>,
     pub proof_pda: Vec<Pubkey>,
+    /// Whether the last bundle of the batch landed, read by `Rotation`.
+    landed: bool,
     release_stuff: (Sender<Accounts>, Arc<AtomicUsize>),
 }
 
 impl Accounts {
     pub async fn release(self) {
-        self.release_stuff
-            .1
-            .fetch_add(Self::size(), std::sync::atomic::Ordering::Relaxed);
+        self.release_stuff.1.fetch_add(Self::size(), Ordering::Relaxed);
 
         self.release_stuff
             .0
@@ -268,25 +760,39 @@ impl Accounts {
             .expect("failed to release accounts");
     }
 
+    /// Drops the accounts for good, without requeueing them.
+    pub fn discard(mut self) {
+        self.signers.clear();
+    }
+
     pub const fn size() -> usize {
         25
     }
 
     #[allow(clippy::too_many_arguments)]
     pub async fn watch_signatures(
-        self,
+        mut self,
         client: Arc<RpcClient>,
-        signatures: Vec<Signature>,
+        system_accounts: &SystemAccountsCache,
+        last_reset_at: i64,
+        txs: Vec<BusTx>,
+        resender: Option<jito::BundleResender>,
         tip: u64,
         tips: Arc<RwLock<JitoTips>>,
+        stats: &MiningStats,
         send_at_slot: Slot,
         sent_at_time: Instant,
         rewards: u64,
+        total_rewards_before: Vec<u64>,
     ) {
+        let signatures = txs.iter().flat_map(|tx| tx.signatures.iter().copied()).collect_vec();
         let mut latest_slot = send_at_slot;
-        let mut landed_tx = vec![];
+        let mut outcomes = utils::bus_tx_outcomes(&txs, &[]);
+        let mut invalidated = false;
 
-        while landed_tx.is_empty() && latest_slot < send_at_slot + constant::SLOT_EXPIRATION {
+        while !outcomes.iter().all(|(_, outcome)| outcome.is_confirmed()) &&
+            latest_slot < send_at_slot + constant::SLOT_EXPIRATION
+        {
             tokio::time::sleep(Duration::from_secs(2)).await;
             debug!(
                 acc.id = self.id,
@@ -312,30 +818,159 @@ impl Accounts {
             };
 
             latest_slot = slot;
-            landed_tx = utils::find_landed_txs(&signatures, statuses);
+            outcomes = utils::bus_tx_outcomes(&txs, &utils::find_tx_outcomes(&signatures, statuses));
+
+            if outcomes.iter().any(|(_, outcome)| outcome.is_confirmed()) {
+                continue;
+            }
+
+            // A bundle landing after the epoch reset earns nothing, the
+            // accounts go back to mining instead of waiting for it to expire.
+            match system_accounts.get().await {
+                Ok((treasury, _, _)) if treasury.last_reset_at > last_reset_at => {
+                    invalidated = true;
+                    break;
+                }
+                Ok(_) => {}
+                Err(err) => debug!(acc.id = self.id, "fail to check the epoch reset: {err:#}"),
+            }
+        }
+
+        let resends = resender.map_or(0, jito::BundleResender::stop);
+
+        if invalidated {
+            stats.record_invalidated();
+            stats.record_batch_outcome(self.id, BatchOutcome::Invalidated);
+
+            warn!(
+                acc.id = self.id,
+                confirm = format_duration!(sent_at_time.elapsed()),
+                tip,
+                resends,
+                "bundle invalidated by epoch reset"
+            );
+
+            self.release().await;
+            return;
+        }
+
+        let mut landed_tx = vec![];
+        let mut landed_signers = vec![];
+        let mut failed_txs = 0;
+        let mut cost = 0;
+        let mut tip_paid = 0;
+
+        for ((sig, outcome), tx) in outcomes.iter().zip(&txs) {
+            match outcome {
+                TxOutcome::Landed { .. } => {
+                    landed_tx.push(*sig);
+                    landed_signers.extend(tx.signers.iter().copied());
+                    cost += tx.fees + tx.tip;
+                    tip_paid += tx.tip;
+                }
+                TxOutcome::Failed { slot, err } => {
+                    failed_txs += 1;
+                    warn!(
+                        acc.id = self.id,
+                        tx = %sig,
+                        slot,
+                        "transaction landed but failed: {}",
+                        program_error::describe(err, None)
+                    );
+                }
+                TxOutcome::Pending => {}
+            }
+        }
+
+        if failed_txs > 0 {
+            stats.record_failed(failed_txs);
         }
 
         if !landed_tx.is_empty() {
-            let cost = 25 * constant::FEE_PER_SIGNER + tip;
+            let confirm_duration = sent_at_time.elapsed();
+
+            // The estimate is off whenever the epoch reset before landing, the
+            // proofs tell what was actually earned.
+            let actual_rewards = match Miner::get_rewards_delta(&client, &self.proof_pda, &total_rewards_before).await {
+                Ok(deltas) => {
+                    for (pubkey, delta) in self.pubkey.iter().zip(&deltas) {
+                        debug!(acc.id = self.id, %pubkey, rewards = format_reward!(*delta), "signer rewards");
+                    }
+
+                    let deltas_by_signer = self
+                        .pubkey
+                        .iter()
+                        .copied()
+                        .zip(deltas.iter().copied())
+                        .collect::<HashMap<_, _>>();
+
+                    history::mined(
+                        utils::landed_slot(&outcomes),
+                        tip_paid,
+                        landed_signers
+                            .iter()
+                            .map(|pubkey| (*pubkey, deltas_by_signer.get(pubkey).copied().unwrap_or_default())),
+                    );
+
+                    let actual_rewards = deltas.iter().sum::<u64>();
+
+                    if actual_rewards == 0 {
+                        warn!(
+                            acc.id = self.id,
+                            "bundle landed but earned no rewards, epoch reset before landing?"
+                        );
+                    }
+
+                    actual_rewards
+                }
+                Err(err) => {
+                    warn!(acc.id = self.id, "fail to fetch rewards, using estimate: {err:#}");
+                    rewards
+                }
+            };
+
+            stats.record_landed(tip_paid, actual_rewards, confirm_duration);
+            stats.record_batch_outcome(
+                self.id,
+                BatchOutcome::Landed {
+                    rewards: actual_rewards,
+                },
+            );
+            self.landed = true;
 
             info!(
                 acc.id = self.id,
-                confirm = format_duration!(sent_at_time.elapsed()),
-                rewards = format_reward!(rewards),
+                confirm = format_duration!(confirm_duration),
+                rewards = format_reward!(actual_rewards),
+                rewards.estimated = format_reward!(rewards),
                 cost = format_reward!(cost),
-                tip,
+                tip = tip_paid,
+                resends,
                 tx.first = ?landed_tx.first().unwrap(),
                 "bundle mined",
             );
+        } else if failed_txs > 0 {
+            stats.record_batch_outcome(self.id, BatchOutcome::Failed);
+
+            warn!(
+                acc.id = self.id,
+                failed = failed_txs,
+                rewards.estimated = format_reward!(rewards),
+                "bundle landed but every transaction failed, nothing earned"
+            );
         } else {
             let tips = *tips.read().await;
 
+            stats.record_dropped();
+            stats.record_batch_outcome(self.id, BatchOutcome::Dropped);
+
             warn!(
                 acc.id = self.id,
                 confirm = format_duration!(sent_at_time.elapsed()),
                 tip,
                 tips.p25 = tips.p25(),
                 tips.p50 = tips.p50(),
+                resends,
                 "bundle dropped"
             );
         }
@@ -344,129 +979,697 @@ impl Accounts {
     }
 }
 
+impl Drop for Accounts {
+    /// Accounts are only dropped without `release` when a bundle task or
+    /// watcher panics, they would leave the rotation until restart. They are
+    /// requeued instead.
+    fn drop(&mut self) {
+        let (sender, idle_accounts) = &self.release_stuff;
+
+        // Already moved out by a previous drop, or shutting down.
+        if self.signers.is_empty() || sender.is_closed() {
+            return;
+        }
+
+        let accounts = Accounts {
+            id: self.id,
+            signers: std::mem::take(&mut self.signers),
+            pubkey: std::mem::take(&mut self.pubkey),
+            proof_pda: std::mem::take(&mut self.proof_pda),
+            landed: false,
+            release_stuff: self.release_stuff.clone(),
+        };
+
+        idle_accounts.fetch_add(Self::size(), Ordering::Relaxed);
+
+        match sender.try_send(accounts) {
+            Ok(()) => {
+                metrics::LOST_BATCHES_RECOVERED.inc();
+                error!(acc.id = self.id, "accounts dropped without release, requeued");
+            }
+            Err(err) => {
+                idle_accounts.fetch_sub(Self::size(), Ordering::Relaxed);
+                error!(
+                    acc.id = self.id,
+                    "accounts dropped without release, fail to requeue: {err}"
+                );
+
+                let (TrySendError::Full(mut accounts) | TrySendError::Closed(mut accounts)) = err;
+                accounts.signers.clear();
+            }
+        }
+    }
+}
+
+/// Picks the batches of each round. Every released batch is mined again in
+/// turn, unless `--active-wallets` or `--cooldown-epochs` are given: only the
+/// batches of the active set are mined then, and a batch whose bundle landed
+/// leaves it for the cooldown while the longest waiting one takes its place.
+struct Rotation {
+    enabled: bool,
+    active_target: usize,
+    cooldown: Duration,
+    active: HashSet<usize>,
+    /// Released batches of the active set, in the order they are mined again.
+    ready: VecDeque<Accounts>,
+    /// Batches out of the active set, promoted first come first served.
+    standby: VecDeque<Accounts>,
+    cooling: Vec<(Instant, Accounts)>,
+}
+
+impl Rotation {
+    fn new(args: &BundleMineGpuArgs, total_batches: usize) -> Self {
+        let active_target = args
+            .active_wallets
+            .map_or(total_batches, |wallets| (wallets / Accounts::size()).min(total_batches));
+        let cooldown = Duration::from_secs(args.cooldown_epochs * ore::EPOCH_DURATION as u64);
+        let enabled = active_target < total_batches || !cooldown.is_zero();
+
+        if enabled {
+            info!(
+                batches.active = active_target,
+                batches.standby = total_batches - active_target,
+                cooldown_epochs = args.cooldown_epochs,
+                "rotating the accounts batches"
+            );
+        }
+
+        Self {
+            enabled,
+            active_target,
+            cooldown,
+            active: HashSet::new(),
+            ready: VecDeque::new(),
+            standby: VecDeque::new(),
+            cooling: vec![],
+        }
+    }
+
+    /// Files a batch queued at startup or released after mining.
+    fn push(&mut self, accounts: Accounts) {
+        if !self.enabled {
+            self.ready.push_back(accounts);
+            return;
+        }
+
+        if accounts.landed && self.active.remove(&accounts.id) {
+            info!(
+                acc.id = accounts.id,
+                cooldown = format_duration!(self.cooldown),
+                "bundle landed, batch rotated out"
+            );
+
+            match self.cooldown.is_zero() {
+                true => self.standby.push_back(accounts),
+                false => self.cooling.push((Instant::now() + self.cooldown, accounts)),
+            }
+        } else if self.active.contains(&accounts.id) {
+            self.ready.push_back(accounts);
+        } else if self.active.len() < self.active_target {
+            self.active.insert(accounts.id);
+            self.ready.push_back(accounts);
+        } else {
+            self.standby.push_back(accounts);
+        }
+    }
+
+    /// Up to `max` batches to mine. The batches done cooling down return to
+    /// the standby first, then the active set is filled up from it.
+    fn take(&mut self, max: usize) -> Vec<Accounts> {
+        let now = Instant::now();
+        let (cooled, cooling): (Vec<_>, Vec<_>) = std::mem::take(&mut self.cooling)
+            .into_iter()
+            .partition(|(until, _)| *until <= now);
+
+        self.cooling = cooling;
+
+        for (_, accounts) in cooled {
+            debug!(acc.id = accounts.id, "batch cooled down");
+            self.standby.push_back(accounts);
+        }
+
+        while self.active.len() < self.active_target {
+            let accounts = match self.standby.pop_front() {
+                Some(accounts) => accounts,
+                None => break,
+            };
+
+            info!(
+                acc.id = accounts.id,
+                active = self.active.len() + 1,
+                standby = self.standby.len(),
+                cooling = self.cooling.len(),
+                "batch promoted to the active set"
+            );
+
+            self.active.insert(accounts.id);
+            self.ready.push_back(accounts);
+        }
+
+        let taken = max.min(self.ready.len());
+
+        self.ready
+            .drain(..taken)
+            .map(|mut accounts| {
+                accounts.landed = false;
+                accounts
+            })
+            .collect()
+    }
+}
+
+/// Tops up signers from a funder keypair, at most once per cooldown for each
+/// signer.
+struct Funder {
+    keypair: Keypair,
+    min_balance: u64,
+    top_up_to: u64,
+    cooldown: Duration,
+    last_top_up: Mutex<HashMap<Pubkey, Instant>>,
+    spent: AtomicU64,
+}
+
+impl Funder {
+    fn new(args: &BundleMineGpuArgs) -> Option<Self> {
+        let keypair = args.funder.as_ref()?;
+        let keypair = utils::read_keypair(keypair).expect("fail to read funder keypair");
+
+        let min_balance = spl_token::ui_amount_to_amount(args.min_balance, 9);
+        let top_up_to = spl_token::ui_amount_to_amount(args.top_up_to, 9);
+
+        if top_up_to <= min_balance {
+            panic!("top up amount must be greater than the minimum balance");
+        }
+
+        info!(funder = %keypair.pubkey(), min_balance, top_up_to, "auto top-up enabled");
+
+        Some(Self {
+            keypair,
+            min_balance,
+            top_up_to,
+            cooldown: Duration::from_secs(args.top_up_cooldown),
+            last_top_up: Default::default(),
+            spent: AtomicU64::new(0),
+        })
+    }
+
+    /// Tops up the `signers` below the minimum balance and waits for the
+    /// transfers to confirm. Returns whether any transfer confirmed. Wallets
+    /// without lamports are missing from `balances`, they count as 0. Only the
+    /// confirmed top-ups start the cooldown and count as spent.
+    async fn top_up(
+        &self,
+        client: &RpcClient,
+        blockhash_cache: &BlockhashCache,
+        signers: &[Pubkey],
+        balances: &HashMap<Pubkey, u64>,
+    ) -> bool {
+        let amounts = {
+            let last_top_up = self.last_top_up.lock().unwrap();
+
+            signers
+                .iter()
+                .map(|pubkey| (*pubkey, balances.get(pubkey).copied().unwrap_or(0)))
+                .filter(|(pubkey, balance)| {
+                    *balance < self.min_balance &&
+                        last_top_up.get(pubkey).map_or(true, |at| at.elapsed() >= self.cooldown)
+                })
+                .map(|(pubkey, balance)| (pubkey, self.top_up_to - balance))
+                .collect_vec()
+        };
+
+        if amounts.is_empty() {
+            return false;
+        }
+
+        let funder = self.keypair.pubkey();
+        let total = amounts.iter().map(|(_, amount)| amount).sum::<u64>();
+
+        match client.get_balance(&funder).await {
+            Ok(balance) if balance < total => {
+                warn!(%funder, balance, required = total, "funder balance too low, skipping top-up");
+                return false;
+            }
+            Ok(_) => {}
+            Err(err) => {
+                error!(%funder, "fail to get funder balance: {err:#}");
+                return false;
+            }
+        }
+
+        info!(%funder, signers = amounts.len(), lamports = total, "topping up signers");
+
+        let outcomes = Miner::transfer_and_confirm(client, blockhash_cache, &self.keypair, &amounts, None).await;
+
+        for outcome in outcomes.iter().filter(|outcome| !outcome.confirmed()) {
+            warn!(
+                %funder,
+                tx = %outcome.signature,
+                signers = ?outcome.transfers.iter().map(|(pubkey, _)| pubkey).collect_vec(),
+                "fail to top up signers: {}",
+                outcome.err.as_deref().unwrap_or_default()
+            );
+        }
+
+        let topped_up = outcomes
+            .iter()
+            .filter(|outcome| outcome.confirmed())
+            .flat_map(|outcome| outcome.transfers.iter().copied())
+            .collect_vec();
+
+        if topped_up.is_empty() {
+            return false;
+        }
+
+        {
+            let mut last_top_up = self.last_top_up.lock().unwrap();
+            let now = Instant::now();
+
+            for (pubkey, _) in &topped_up {
+                last_top_up.insert(*pubkey, now);
+            }
+        }
+
+        let confirmed = topped_up.iter().map(|(_, amount)| amount).sum::<u64>();
+        let spent = self.spent.fetch_add(confirmed, Ordering::Relaxed) + confirmed;
+
+        info!(
+            %funder,
+            signers = ?topped_up.iter().map(|(pubkey, _)| pubkey).collect_vec(),
+            lamports = confirmed,
+            lamports.total = spent,
+            "signers topped up"
+        );
+
+        true
+    }
+}
+
+/// Position in the accounts, mining result and signer.
+type SolvedSigner<'a> = (usize, (solana_sdk::keccak::Hash, u64), &'a Box<Keypair>);
+
 struct SendBundleTask {
     client: Arc<RpcClient>,
+    blockhash_cache: BlockhashCache,
+    system_accounts: SystemAccountsCache,
     tips: Arc<RwLock<JitoTips>>,
+    stats: Arc<MiningStats>,
+    _in_flight: InFlight,
     batch: Vec<Accounts>,
     available_bus: Vec<Bus>,
     signer_balances: HashMap<Pubkey, u64>,
-    mining_duration: Duration,
-    mining_results: Vec<(solana_sdk::keccak::Hash, u64)>,
-    rewards: u64,
+    /// Position in the batch of each mined signer, the others are skipped.
+    mining_positions: Vec<usize>,
+    /// Proof hash each signer was mined against, by position in the batch.
+    proof_hashes: Vec<solana_sdk::keccak::Hash>,
+    /// `total_rewards` of each proof before mining, by position in the batch.
+    total_rewards: Vec<u64>,
+    mining_start: Instant,
+    time_to_next_epoch: Duration,
+    reset_buffer: Duration,
+    resend_interval: Option<Duration>,
+    /// `treasury.last_reset_at` when the batch was fetched, a reset is observed
+    /// once it advances.
+    last_reset_at: i64,
+    results: UnboundedReceiver<(usize, solana_sdk::keccak::Hash, u64)>,
+    reward_rate: u64,
     tip: u64,
+    min_tip: u64,
     max_tip: u64,
-
-    slot: Slot,
-    blockhash: Hash,
 }
 
 impl SendBundleTask {
-    async fn work(self) {
+    /// Re-fetches the proofs of the solved signers and drops the ones whose
+    /// hash moved since it was mined, e.g. because a previous bundle landed in
+    /// the meantime. Their mine instruction would fail and take the whole
+    /// bundle down, they are mined again once the accounts is released.
+    async fn drop_stale_proofs<'a>(
+        &self,
+        accounts: &Accounts,
+        mined_hashes: &[solana_sdk::keccak::Hash],
+        solved: Vec<SolvedSigner<'a>>,
+    ) -> Vec<SolvedSigner<'a>> {
+        let proof_pda = solved.iter().map(|(j, _, _)| accounts.proof_pda[*j]).collect_vec();
+
+        let proofs = match Miner::get_proof_accounts(&self.client, &proof_pda).await {
+            Ok(proofs) => proofs,
+            Err(err) => {
+                warn!(
+                    acc.id = accounts.id,
+                    "fail to refresh proof accounts, sending as mined: {err:#}"
+                );
+                return solved;
+            }
+        };
+
+        solved
+            .into_iter()
+            .zip(proofs)
+            .filter(|((j, _, signer), proof)| {
+                let unchanged = proof.hash.0 == mined_hashes[*j].to_bytes();
+
+                if !unchanged {
+                    warn!(
+                        acc.id = accounts.id,
+                        signer = %signer.pubkey(),
+                        "proof hash changed since mining, dropping signer"
+                    );
+                }
+
+                unchanged
+            })
+            .map(|(solved, _)| solved)
+            .collect()
+    }
+
+    /// Polls the treasury until `last_reset_at` advances, then refreshes the
+    /// reward rate. Returns false if no reset was observed within `timeout`.
+    async fn wait_for_reset(&mut self, timeout: Duration) -> bool {
+        let start = Instant::now();
+
+        loop {
+            if start.elapsed() >= timeout {
+                return false;
+            }
+
+            tokio::time::sleep(Duration::from_millis(500)).await;
+
+            let treasury = match Miner::get_system_accounts(&self.client).await {
+                Ok((treasury, _, _)) => treasury,
+                Err(err) => {
+                    error!("fail to fetch system accounts: {err:#}");
+                    continue;
+                }
+            };
+
+            if treasury.last_reset_at <= self.last_reset_at {
+                continue;
+            }
+
+            self.last_reset_at = treasury.last_reset_at;
+            self.reward_rate = treasury.reward_rate;
+
+            return true;
+        }
+    }
+
+    async fn release(&self, accounts: Accounts) {
+        self.stats.record_batch_outcome(accounts.id, BatchOutcome::Released);
+        accounts.release().await;
+    }
+
+    async fn work(mut self) {
         let tips_now = *self.tips.read().await;
 
         let tip = if self.max_tip > 0 {
-            let p50 = tips_now.p50();
-            if p50 == 0 {
-                self.tip
-            } else {
-                let tip = p50 + 1;
-                tip.max(50000).min(self.max_tip)
-            }
+            tips_now.adaptive_tip(self.min_tip, self.max_tip).unwrap_or(self.tip)
         } else {
             self.tip
         };
 
-        let signer_and_mining_results = self
-            .mining_results
-            .into_iter()
-            .chunks(Accounts::size())
-            .into_iter()
-            .map(|c| c.collect_vec())
-            .collect_vec()
-            .into_iter()
-            .zip(self.batch.into_iter())
-            .collect::<Vec<_>>();
+        metrics::ADAPTIVE_TIP.set(tip as i64);
+
+        let mut mining_results = vec![None; self.batch.len() * Accounts::size()];
+        let mut solved_at = vec![Duration::ZERO; self.batch.len() * Accounts::size()];
+        let mut mined = vec![false; self.batch.len() * Accounts::size()];
+        let mut mining_done = false;
+        let mut reset_observed = false;
+
+        for position in &self.mining_positions {
+            mined[*position] = true;
+        }
 
         // Bundle limit
-        for (mining_results, accounts) in signer_and_mining_results {
-            let mut signatures = vec![];
+        for (i, accounts) in std::mem::take(&mut self.batch).into_iter().enumerate() {
+            let range = i * Accounts::size()..(i + 1) * Accounts::size();
+
+            while !mining_done && range.clone().any(|j| mined[j] && mining_results[j].is_none()) {
+                match self.results.recv().await {
+                    Some((index, hash, nonce)) => {
+                        let position = self.mining_positions[index];
+                        mining_results[position] = Some((hash, nonce));
+                        solved_at[position] = self.mining_start.elapsed();
+                    }
+                    None => mining_done = true,
+                }
+            }
+
+            // Mining stopped at the deadline or signers were skipped, bundles are
+            // sent for the solved signers and the whole accounts is mined again
+            // once released.
+            let solved = mining_results[range.clone()]
+                .iter()
+                .zip(&accounts.signers)
+                .enumerate()
+                .filter_map(|(j, (result, signer))| result.map(|result| (j, result, signer)))
+                .collect_vec();
+
+            if solved.is_empty() {
+                warn!(
+                    acc.id = accounts.id,
+                    "mining didn't complete, releasing unsolved accounts"
+                );
+                self.release(accounts).await;
+                continue;
+            }
 
-            let tipper = utils::pick_richest_account(&self.signer_balances, &accounts.pubkey);
-            let material_to_build_bundle = mining_results.chunks(5).zip(accounts.signers.chunks(5));
+            self.stats.set_batch_state(accounts.id, BatchState::Sending);
+
+            let skipped = range.clone().filter(|j| !mined[*j]).count();
+
+            if solved.len() + skipped < Accounts::size() {
+                warn!(
+                    acc.id = accounts.id,
+                    solved = solved.len(),
+                    skipped,
+                    unsolved = Accounts::size() - solved.len() - skipped,
+                    "mining didn't complete, sending bundles for solved accounts"
+                );
+            }
+
+            let mining_duration = solved_at[range.clone()].iter().copied().max().unwrap_or_default();
+
+            // Bundles sent right before the reset tend to land after it, earning
+            // nothing but still paying the tip. They are held and sent once the
+            // reset is observed, the stale proofs check below still applies.
+            let time_left = self.time_to_next_epoch.saturating_sub(self.mining_start.elapsed());
+
+            if !reset_observed && time_left <= self.reset_buffer {
+                info!(
+                    acc.id = accounts.id,
+                    mining = format_duration!(mining_duration),
+                    time_left = format_duration!(time_left),
+                    "epoch about to reset, holding bundles"
+                );
+
+                reset_observed = self.wait_for_reset(time_left + constant::RESET_WAIT_GRACE).await;
+
+                if !reset_observed {
+                    warn!(acc.id = accounts.id, "epoch reset not observed, releasing accounts");
+                    self.release(accounts).await;
+                    continue;
+                }
+
+                info!(acc.id = accounts.id, "epoch reset, sending held bundles");
+            }
+
+            let solved = self
+                .drop_stale_proofs(&accounts, &self.proof_hashes[range.clone()], solved)
+                .await;
+
+            if solved.is_empty() {
+                warn!(acc.id = accounts.id, "all proof hashes changed, releasing accounts");
+                self.release(accounts).await;
+                continue;
+            }
+
+            let (slot, blockhash) = match utils::retry_with_backoff("get latest blockhash", BackoffPolicy::RPC, || {
+                self.blockhash_cache.get(blockhash::BUNDLE_MAX_AGE)
+            })
+            .await
+            {
+                Ok(value) => value,
+                Err(err) => {
+                    error!(acc.id = accounts.id, "{err:#}");
+                    self.release(accounts).await;
+                    continue;
+                }
+            };
+
+            // The transactions of the bundles, the same in every bus.
+            let mut bus_txs: Vec<BusTx> = vec![];
+
+            let solved_pubkey = solved.iter().map(|(_, _, signer)| signer.pubkey()).collect_vec();
+            let tipper = match utils::pick_richest_account(&self.signer_balances, &solved_pubkey) {
+                Some(pubkey) => pubkey,
+                None => {
+                    error!(
+                        acc.id = accounts.id,
+                        "no signer holds any SOL to pay the tip, skipping the accounts"
+                    );
+                    self.release(accounts).await;
+                    continue;
+                }
+            };
             let send_bundle_time = Instant::now();
 
-            debug!(accounts = ?accounts.pubkey, %tipper, "building bundle");
+            debug!(accounts = ?solved_pubkey, %tipper, "building bundle");
+
+            let mut sent_bundles = vec![];
 
-            for bus in &self.available_bus {
+            for (bus_index, bus) in self.available_bus.iter().enumerate() {
                 let mut bundle = Vec::with_capacity(5);
 
-                for (hash_and_nonce, signers) in material_to_build_bundle.clone() {
-                    let fee_payer_this_batch = signers
-                        .iter()
-                        .map(|s| s.pubkey())
-                        .max_by_key(|pubkey| self.signer_balances.get(pubkey).unwrap())
-                        .expect("signers balances should not be empty");
+                for solved in solved.chunks(5) {
+                    let solved_pubkey = solved.iter().map(|(_, _, s)| s.pubkey()).collect_vec();
+                    let fee_payer_this_batch = match utils::pick_richest_account(&self.signer_balances, &solved_pubkey)
+                    {
+                        Some(pubkey) => pubkey,
+                        None => {
+                            warn!(
+                                acc.id = accounts.id,
+                                "no signer of the transaction holds any SOL, skipping it"
+                            );
+                            continue;
+                        }
+                    };
 
                     let mut tx_signers = Vec::with_capacity(5);
-                    let mut ixs = Vec::with_capacity(6);
+                    let mut mine_ixs = Vec::with_capacity(solved.len() + 1);
 
-                    for ((hash, nonce), signer) in hash_and_nonce.iter().zip(signers.iter()) {
+                    for (_, (hash, nonce), signer) in solved {
                         debug!(%tipper, signer = %signer.pubkey(), "adding mine instruction");
 
-                        ixs.push(ore::instruction::mine(
+                        mine_ixs.push(cluster::instruction(ore::instruction::mine(
                             signer.pubkey(),
-                            ore::BUS_ADDRESSES[bus.id as usize],
+                            cluster::bus_address(bus.id),
                             ore::state::Hash(hash.to_bytes()),
                             *nonce,
-                        ));
+                        )));
 
-                        tx_signers.push(signer);
+                        tx_signers.push(*signer);
 
                         if tipper == signer.pubkey() {
-                            ixs.push(jito::build_bribe_ix(&tipper, tip));
+                            mine_ixs.extend(jito::build_bribe_ix(&tipper, tip));
                         }
                     }
 
-                    let tx = Transaction::new_signed_with_payer(
-                        &ixs,
-                        Some(&fee_payer_this_batch),
-                        &tx_signers,
-                        self.blockhash,
-                    );
+                    // Awaited before the first send so that its limit is the simulated one.
+                    compute_budget::measure(&self.client, fee_payer_this_batch, &mine_ixs, solved.len()).await;
 
+                    let mut ixs = compute_budget::bundle_instructions(solved.len());
+                    ixs.extend(mine_ixs);
+
+                    let tx =
+                        Transaction::new_signed_with_payer(&ixs, Some(&fee_payer_this_batch), &tx_signers, blockhash);
+
+                    if bus_index == 0 {
+                        let carries_tip = jito::enabled() && tx_signers.iter().any(|signer| signer.pubkey() == tipper);
+
+                        bus_txs.push(BusTx {
+                            signatures: vec![],
+                            signers: tx_signers.iter().map(|signer| signer.pubkey()).collect(),
+                            fees: constant::FEE_PER_SIGNER * tx.signatures.len() as u64 +
+                                compute_budget::priority_fee(solved.len()),
+                            tip: if carries_tip { tip } else { 0 },
+                        });
+                    }
+
+                    bus_txs[bundle.len()].signatures.push(tx.signatures[0]);
                     bundle.push(tx);
                 }
 
                 let sig = bundle[0].signatures[0];
 
+                if self.resend_interval.is_some() {
+                    sent_bundles.push(bundle.clone());
+                }
+
                 match jito::send_bundle(bundle).await {
-                    Ok((_, bundle_id)) => debug!(acc.id = accounts.id, %sig, bundle = %bundle_id, "bundle sent"),
+                    Ok((_, bundle_id)) => {
+                        self.stats.record_sent();
+                        debug!(acc.id = accounts.id, %sig, bundle = %bundle_id, "bundle sent")
+                    }
                     Err(err) => error!(acc.id = accounts.id, %sig, "fail to send bundle: {err:#}"),
                 }
-
-                signatures.push(sig);
             }
 
             info!(
                 acc.id = accounts.id,
-                mining = format_duration!(self.mining_duration),
+                mining = format_duration!(mining_duration),
                 tip,
                 tip.p25 = tips_now.p25(),
                 tip.p50 = tips_now.p50(),
-                slot = self.slot,
+                slot,
                 "bundles sent"
             );
 
+            self.stats.record_batch_sent(accounts.id, tip);
+
+            let rewards = self.reward_rate.saturating_mul(solved.len() as u64);
+            let total_rewards_before = self.total_rewards[range].to_vec();
+            let resender = self
+                .resend_interval
+                .map(|interval| jito::BundleResender::spawn(self.client.clone(), sent_bundles, slot, interval));
+
             tokio::spawn({
                 let client = self.client.clone();
+                let system_accounts = self.system_accounts.clone();
+                let last_reset_at = self.last_reset_at;
                 let tips = self.tips.clone();
+                let stats = self.stats.clone();
+                let in_flight = self.stats.track();
 
                 async move {
                     accounts
-                        .watch_signatures(client, signatures, tip, tips, self.slot, send_bundle_time, self.rewards)
+                        .watch_signatures(
+                            client,
+                            &system_accounts,
+                            last_reset_at,
+                            bus_txs,
+                            resender,
+                            tip,
+                            tips,
+                            &stats,
+                            slot,
+                            send_bundle_time,
+                            rewards,
+                            total_rewards_before,
+                        )
                         .await;
+
+                    drop(in_flight);
                 }
             });
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn release_all_does_not_recover_lost_batches() {
+        let (sender, mut receiver) = channel::<Accounts>(1);
+        let idle_accounts = Arc::new(AtomicUsize::new(0));
+        let signer = Box::new(Keypair::new());
+        let accounts = Accounts {
+            id: 0,
+            pubkey: vec![signer.pubkey()],
+            proof_pda: vec![],
+            signers: vec![signer],
+            landed: false,
+            release_stuff: (sender, idle_accounts.clone()),
+        };
+
+        let recovered = metrics::LOST_BATCHES_RECOVERED.get();
+
+        release_all(vec![accounts]).await;
+
+        assert_eq!(metrics::LOST_BATCHES_RECOVERED.get(), recovered);
+        assert_eq!(idle_accounts.load(Ordering::Relaxed), Accounts::size());
+
+        receiver.try_recv().expect("batch should be released").discard();
+    }
+}
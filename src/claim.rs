@@ -1,20 +1,73 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+    time::Duration,
+};
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use eyre::{bail, Context};
+use futures_util::{
+    future::{FutureExt, LocalBoxFuture},
+    stream::{FuturesUnordered, StreamExt},
+};
+use itertools::Itertools;
 use ore::{state::Proof, utils::AccountDeserialize};
 use rand::Rng;
-use solana_client::rpc_config::RpcSimulateTransactionConfig;
-use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signer, transaction::Transaction};
-use tracing::{debug, error, info};
+use solana_client::{nonblocking::rpc_client::RpcClient, rpc_config::RpcSimulateTransactionConfig};
+use solana_sdk::{
+    clock::Slot,
+    commitment_config::CommitmentConfig,
+    hash::Hash,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Signature, Signer},
+    transaction::Transaction,
+};
+use spl_associated_token_account::instruction::create_associated_token_account_idempotent;
+use spl_token::{solana_program::program_pack::Pack, state::Account as TokenAccount};
+use tokio::sync::RwLock;
+use tracing::{debug, error, info, warn};
 
-use crate::{constant, format_reward, jito, utils, Miner};
+use crate::{
+    blockhash::{self, BlockhashCache},
+    claim_report::{ClaimReport, ClaimStatus},
+    cluster,
+    constant,
+    format_reward,
+    history,
+    jito::{self, subscribe_jito_tips, JitoTips},
+    metrics,
+    notify,
+    program_error,
+    stats,
+    utils,
+    utils::TxOutcome,
+    Miner,
+};
 
-const RECHECK_INTERVAL: Duration = Duration::from_secs(60 * 5);
+/// Passes over the batches that failed to simulate or kept being dropped.
+const CLAIM_RETRIES: usize = 2;
+
+/// Drops of the same bundle before its wallets are left for the next pass.
+const MAX_BUNDLE_DROPS: usize = 5;
+
+/// Claims of a transaction that also creates token accounts, the create
+/// instructions and their programs don't fit along 5 claims and the bribe.
+const CLAIMS_CREATING_ATAS: usize = 3;
 
 #[derive(Parser, Debug, Clone)]
 pub struct ClaimArgs {
-    #[arg(long)]
-    pub beneficiary: Pubkey,
+    #[arg(long, required_unless_present = "self_ata")]
+    pub beneficiary: Option<Pubkey>,
+
+    #[arg(
+        long = "self",
+        default_value = "false",
+        conflicts_with = "beneficiary",
+        help = "Claim the rewards of each wallet to its own token account, created along with the claim when missing"
+    )]
+    pub self_ata: bool,
 
     #[arg(long, help = "The folder that contains all the keys used to claim $ORE")]
     pub key_folder: String,
@@ -26,32 +79,359 @@ pub struct ClaimArgs {
     )]
     pub auto: bool,
 
+    #[arg(
+        long,
+        default_value = "300",
+        help = "Seconds to wait before checking the rewards again with --auto"
+    )]
+    pub recheck_interval: u64,
+
+    #[arg(
+        long,
+        conflicts_with = "recheck_interval",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Check the rewards again after this number of epoch resets with --auto instead of a fixed interval"
+    )]
+    pub after_epochs: Option<u64>,
+
     #[arg(
         long = "threshold",
         default_value = "0",
-        help = "Claim rewards when total rewards exceed this threshold"
+        help = "Claim rewards when the rewards of all the wallets together exceed this threshold, or those of each \
+                bundle with --threshold-scope batch"
     )]
     pub threshold_ui_amount: f64,
+
+    #[arg(long, value_enum, default_value = "total", help = "What the threshold applies to")]
+    pub threshold_scope: ThresholdScope,
+
+    #[arg(
+        long = "address",
+        value_delimiter = ',',
+        help = "Only claim from these wallets, their keypairs must be in the key folder"
+    )]
+    pub addresses: Vec<Pubkey>,
+
+    #[arg(
+        long = "min-per-wallet",
+        default_value = "0",
+        help = "Skip the wallets with less claimable rewards than this amount of $ORE"
+    )]
+    pub min_per_wallet_ui_amount: f64,
+
+    #[arg(
+        long = "amount",
+        conflicts_with = "leave_ui_amount",
+        help = "Claim this amount of $ORE from each wallet, the wallets with less are skipped"
+    )]
+    pub amount_ui_amount: Option<f64>,
+
+    #[arg(
+        long = "leave",
+        help = "Leave this amount of $ORE in each proof account, the wallets with less are skipped"
+    )]
+    pub leave_ui_amount: Option<f64>,
+
+    #[arg(
+        long,
+        help = "Write one record per wallet and claim attempt to this file, JSON lines if it ends with .json, CSV \
+                otherwise"
+    )]
+    pub report: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "The maximum tip to pay for jito, a dropped bundle is sent again with a tip following the landed p50 \
+                up to it. Set to 0 to keep the tip fixed"
+    )]
+    pub max_tip: u64,
+
+    #[arg(
+        long,
+        default_value = "1",
+        help = "Number of bundles sent without waiting for the previous ones to land"
+    )]
+    pub max_in_flight: usize,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Create the beneficiary token account with the first claim when it doesn't exist"
+    )]
+    pub create_ata: bool,
+
+    #[arg(
+        long,
+        help = "Keypair paying the beneficiary token account rent with --create-ata, the richest claimer of the first \
+                transaction pays it otherwise"
+    )]
+    pub fee_payer: Option<String>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThresholdScope {
+    /// The rewards of all the wallets, everything is claimed once they exceed
+    /// the threshold
+    Total,
+    /// The rewards of each bundle of up to 25 wallets
+    Batch,
 }
 
 impl ClaimArgs {
     pub fn threshold(&self) -> u64 {
         (self.threshold_ui_amount * (10u64.pow(ore::TOKEN_DECIMALS as u32) as f64)) as u64
     }
+
+    pub fn min_per_wallet(&self) -> u64 {
+        (self.min_per_wallet_ui_amount * (10u64.pow(ore::TOKEN_DECIMALS as u32) as f64)) as u64
+    }
+
+    /// How much to claim out of `claimable_rewards`, none when the wallet is
+    /// skipped.
+    pub fn claim_amount(&self, claimable_rewards: u64) -> Option<u64> {
+        let to_amount = |ui_amount: f64| (ui_amount * (10u64.pow(ore::TOKEN_DECIMALS as u32) as f64)) as u64;
+
+        let amount = match (self.amount_ui_amount, self.leave_ui_amount) {
+            (Some(amount), _) => Some(to_amount(amount)).filter(|amount| claimable_rewards >= *amount),
+            (None, Some(leave)) => claimable_rewards.checked_sub(to_amount(leave)),
+            (None, None) => Some(claimable_rewards),
+        };
+
+        amount.filter(|amount| *amount > 0)
+    }
+}
+
+/// Up to 5 claim transactions sent together, along with what they claim.
+struct ClaimBundle {
+    /// Instructions and fee payer of each transaction, without the bribe.
+    txs: Vec<(Vec<Instruction>, Pubkey)>,
+    signers: Vec<Vec<Arc<dyn Signer>>>,
+    /// `(wallet, claimable, amount)` of each claim.
+    wallets: Vec<(Pubkey, u64, u64)>,
+    /// Claims of each transaction, in the order of `wallets`.
+    claims: Vec<usize>,
+    rewards: u64,
+    drops: usize,
+    tip: u64,
+}
+
+impl ClaimBundle {
+    fn new(tip: u64) -> Self {
+        Self {
+            txs: vec![],
+            signers: vec![],
+            wallets: vec![],
+            claims: vec![],
+            rewards: 0,
+            drops: 0,
+            tip,
+        }
+    }
+
+    /// The bribe goes on the first transaction, rebuilt with the current tip
+    /// every time the bundle is sent.
+    fn sign(&self, blockhash: Hash) -> Vec<Transaction> {
+        self.txs
+            .iter()
+            .zip(self.signers.iter())
+            .enumerate()
+            .map(|(i, ((ixs, fee_payer), signers))| {
+                let mut ixs = ixs.clone();
+
+                if i == 0 {
+                    ixs.extend(jito::build_bribe_ix(fee_payer, self.tip));
+                }
+
+                let mut tx = Transaction::new_with_payer(&ixs, Some(fee_payer));
+                tx.sign(signers.as_slice(), blockhash);
+                tx
+            })
+            .collect()
+    }
+
+    /// Splits off the transactions that landed, `(signature, slot, claims)` of
+    /// each of them, the bundle keeps the others. A jito bundle lands as a
+    /// whole, over RPC its transactions land one by one.
+    fn split_landed(self, outcomes: &[(Signature, TxOutcome)]) -> (Vec<LandedClaims>, Self) {
+        let mut landed = vec![];
+        let mut rest = Self {
+            drops: self.drops,
+            ..Self::new(self.tip)
+        };
+        let mut wallets = self.wallets.into_iter();
+
+        for (((tx, signers), claims), (signature, outcome)) in
+            self.txs.into_iter().zip(self.signers).zip(self.claims).zip(outcomes)
+        {
+            let tx_wallets = wallets.by_ref().take(claims).collect::<Vec<_>>();
+
+            match outcome {
+                TxOutcome::Landed { slot } => landed.push((*signature, *slot, tx_wallets)),
+                _ => {
+                    rest.rewards += tx_wallets.iter().map(|(_, _, amount)| amount).sum::<u64>();
+                    rest.wallets.extend(tx_wallets);
+                    rest.txs.push(tx);
+                    rest.signers.push(signers);
+                    rest.claims.push(claims);
+                }
+            }
+        }
+
+        (landed, rest)
+    }
+}
+
+/// `(signature, slot, (wallet, claimable, amount))` of a landed claim
+/// transaction.
+type LandedClaims = (Signature, Slot, Vec<(Pubkey, u64, u64)>);
+
+/// `(wallet, signer, claimable, amount)` of a wallet to claim from.
+type ClaimableWallet = (Pubkey, Arc<dyn Signer>, u64, u64);
+
+/// What a claim round claimed so far and the wallets its next pass retries.
+struct ClaimTally {
+    total: u64,
+    remaining: u64,
+    claimed: u64,
+    /// Wallets of the batches that failed to simulate or kept being dropped,
+    /// often a transient blockhash or slot issue, and their last error.
+    failed: Vec<ClaimableWallet>,
+    errors: HashMap<Pubkey, String>,
+}
+
+impl ClaimTally {
+    fn new(wallets: &[ClaimableWallet]) -> Self {
+        let total = wallets.iter().map(|(_, _, _, amount)| amount).sum();
+
+        Self {
+            total,
+            remaining: total,
+            claimed: 0,
+            failed: vec![],
+            errors: HashMap::new(),
+        }
+    }
+
+    /// A bundle claiming `rewards` for `wallets` landed.
+    fn landed(&mut self, wallets: &[(Pubkey, u64, u64)], rewards: u64) {
+        self.remaining = self.remaining.saturating_sub(rewards);
+        self.claimed += rewards;
+
+        for (pubkey, _, _) in wallets {
+            self.errors.remove(pubkey);
+        }
+    }
+
+    /// The `wallets` of a bundle that failed to simulate or was given up on
+    /// are still claimable, the next pass claims them again from `to_claim`.
+    fn failed(&mut self, to_claim: &[ClaimableWallet], wallets: &[(Pubkey, u64, u64)], err: &str) {
+        for (pubkey, _, _) in wallets {
+            self.errors.insert(*pubkey, err.to_string());
+        }
+
+        self.failed.extend(
+            to_claim
+                .iter()
+                .filter(|(pubkey, _, _, _)| wallets.iter().any(|(failed, _, _)| failed == pubkey))
+                .cloned(),
+        );
+    }
+
+    /// The wallets the next pass claims, `None` when nothing failed.
+    fn next_pass(&mut self) -> Option<Vec<ClaimableWallet>> {
+        match self.failed.is_empty() {
+            true => None,
+            false => Some(std::mem::take(&mut self.failed)),
+        }
+    }
+
+    fn failed_amount(&self) -> u64 {
+        self.failed.iter().map(|(_, _, _, amount)| amount).sum()
+    }
+
+    /// `(wallet, amount, last error)` of the wallets left unclaimed.
+    fn unclaimed(&self) -> impl Iterator<Item = (Pubkey, u64, &str)> {
+        self.failed.iter().map(|(pubkey, _, _, amount)| {
+            let err = self.errors.get(pubkey).map(String::as_str).unwrap_or("unknown");
+            (*pubkey, *amount, err)
+        })
+    }
 }
 
 impl Miner {
     pub async fn claim(&self, args: &ClaimArgs) {
         let client = Miner::get_client_confirmed(&self.rpc);
-        let accounts = Self::read_keys(&args.key_folder);
-        let jito_tip = self.priority_fee.expect("jito tip is required");
+        let mut accounts = match Self::read_keys(&args.key_folder, self.recursive) {
+            Ok(accounts) => accounts,
+            Err(err) => {
+                error!("{err:#}");
+                return;
+            }
+        };
+        let mut skipped_by_address = 0;
+
+        if !args.addresses.is_empty() {
+            for address in &args.addresses {
+                if !accounts.iter().any(|key| key.pubkey() == *address) {
+                    error!(%address, "no keypair found for the address, skipping");
+                }
+            }
+
+            let total = accounts.len();
+            accounts.retain(|key| args.addresses.contains(&key.pubkey()));
+            skipped_by_address = total - accounts.len();
+        }
 
-        let beneficiary_ata = utils::get_ore_ata(args.beneficiary);
-        info!(ata = %beneficiary_ata, recipient = %args.beneficiary);
+        let jito_tip = self.current_priority_fee().expect("jito tip is required");
+        let blockhash_cache = BlockhashCache::spawn(client.clone(), &self.rpc);
+        let tips = Arc::new(RwLock::new(JitoTips::default()));
+
+        if args.max_tip > 0 {
+            subscribe_jito_tips(tips.clone()).await;
+        }
+
+        let mut beneficiary_ata = None;
+
+        // Created by the first claim transaction of the first bundle that lands.
+        let mut create_ata = false;
+
+        if let Some(beneficiary) = args.beneficiary {
+            let (ata, ata_exists) = match beneficiary_token_account(&client, beneficiary).await {
+                Ok(value) => value,
+                Err(err) => {
+                    error!("{err:#}");
+                    return;
+                }
+            };
+
+            beneficiary_ata = Some(ata);
+            info!(%ata, recipient = %beneficiary);
+
+            if !ata_exists && !args.create_ata {
+                error!(%ata, "beneficiary token account does not exist, run with --create-ata to create it");
+                return;
+            }
+
+            if !ata_exists {
+                info!(%ata, "beneficiary token account does not exist, creating it with the first claim");
+                create_ata = true;
+            }
+        } else {
+            info!("claiming to the token account of each wallet");
+        }
+
+        let ata_payer = match args.fee_payer.as_ref().map(utils::read_keypair).transpose() {
+            Ok(ata_payer) => ata_payer.map(|keypair| Arc::new(keypair) as Arc<dyn Signer>),
+            Err(err) => {
+                error!("fail to read the fee payer keypair: {err:#}");
+                return;
+            }
+        };
 
         let owner_proof_pdas = accounts
             .iter()
-            .map(|key| (utils::get_proof_pda(key.pubkey())))
+            .map(|key| utils::get_proof_pda(key.pubkey()))
             .collect::<Vec<_>>();
 
         if owner_proof_pdas.is_empty() {
@@ -59,8 +439,25 @@ impl Miner {
             return;
         }
 
+        // Created before any claim is sent, an unwritable path stops here.
+        let mut report = match args.report.as_ref().map(|path| (path, ClaimReport::create(path))) {
+            Some((_, Ok(report))) => Some(report),
+            Some((path, Err(err))) => {
+                error!(path, "fail to create claim report: {err:#}");
+                return;
+            }
+            None => None,
+        };
+
+        let mut claimed = 0;
+        let mut claimed_by_wallet = HashMap::<Pubkey, u64>::new();
+
+        // Of the latest round, rewards accumulate so it changes between rounds.
+        let mut skipped_by_min;
+
         loop {
             let mut claimable = Vec::with_capacity(owner_proof_pdas.len());
+            skipped_by_min = 0;
 
             // Fetch claimable amount of each account
             for (batch_pda, batch_account) in owner_proof_pdas
@@ -77,234 +474,832 @@ impl Miner {
                         let account_data = account?.data;
                         let proof = Proof::try_from_bytes(&account_data).ok()?;
 
-                        if proof.claimable_rewards == 0 {
+                        metrics::set_claimable_rewards(&key.pubkey(), proof.claimable_rewards);
+
+                        if proof.claimable_rewards < args.min_per_wallet() {
+                            skipped_by_min += 1;
                             return None;
                         }
 
+                        let amount = args.claim_amount(proof.claimable_rewards)?;
+
                         Some((
                             key.pubkey(),
                             Arc::new(key.insecure_clone()) as Arc<dyn Signer>,
                             proof.claimable_rewards,
+                            amount,
                         ))
                     });
 
                 claimable.extend(batch_accounts);
             }
 
-            claimable.sort_by_key(|(_, _, amount)| *amount);
+            claimable.sort_by_key(|(_, _, _, amount)| *amount);
             claimable.reverse();
 
-            let mut remaining = claimable.iter().map(|(_, _, amount)| amount).sum::<u64>();
-            let mut batch_iter = claimable.chunks(5);
+            // Wallets whose token account is created along with their claim.
+            let mut missing_atas = HashSet::new();
+
+            if args.self_ata {
+                let owners = claimable.iter().map(|(pubkey, _, _, _)| *pubkey).collect::<Vec<_>>();
+
+                for batch in owners.chunks(constant::FETCH_ACCOUNT_LIMIT) {
+                    let atas = batch.iter().map(|owner| utils::get_ore_ata(*owner)).collect::<Vec<_>>();
+
+                    match client.get_multiple_accounts(&atas).await {
+                        Ok(accounts) => missing_atas.extend(
+                            batch
+                                .iter()
+                                .zip(accounts)
+                                .filter(|(_, account)| account.is_none())
+                                .map(|(owner, _)| *owner),
+                        ),
+                        Err(err) => {
+                            // Creating an existing one is a no-op.
+                            error!("fail to get token accounts, creating them anyway: {err:#}");
+                            missing_atas.extend(batch.iter().copied());
+                        }
+                    }
+                }
+
+                if !missing_atas.is_empty() {
+                    info!(
+                        accounts = missing_atas.len(),
+                        "creating the missing token accounts with the claims"
+                    );
+                }
+            }
+
+            let mut tally = ClaimTally::new(&claimable);
 
-            info!("total rewards: {}", utils::ore_ui_amount(remaining));
+            info!("total rewards: {}", utils::ore_ui_amount(tally.total));
             info!("total claimable accounts: {}", claimable.len());
+            info!(
+                skipped.address = skipped_by_address,
+                skipped.min_per_wallet = skipped_by_min,
+                "wallets skipped by the filters"
+            );
+
+            let mut below_threshold = args.threshold_scope == ThresholdScope::Total && tally.total < args.threshold();
+
+            if below_threshold {
+                info!(
+                    total.rewards = format_reward!(tally.total),
+                    threshold = format_reward!(args.threshold()),
+                    "total reward is less than threshold, will not claim"
+                );
 
-            let mut txs = vec![];
-            let mut total_rewards_in_this_batch = 0;
-            let mut signers_for_txs = vec![];
-            let mut accounts_in_this_batch = 0;
+                if let Some(report) = &mut report {
+                    let wallets = claimable
+                        .iter()
+                        .map(|(pubkey, _, claimable_before, amount)| (*pubkey, *claimable_before, *amount))
+                        .collect::<Vec<_>>();
 
-            loop {
-                while txs.len() < 5 {
-                    let batch = match batch_iter.next() {
-                        Some(batch) => batch,
+                    report.record_all(&wallets, None, None, ClaimStatus::BelowThreshold);
+                }
+            }
+
+            let mut to_claim = claimable;
+
+            for retry in 0..=CLAIM_RETRIES {
+                if below_threshold {
+                    break;
+                }
+
+                if retry > 0 {
+                    if stats::stop_requested() {
+                        break;
+                    }
+
+                    to_claim = match tally.next_pass() {
+                        Some(failed) => failed,
                         None => break,
                     };
+                    info!(retry, accounts = to_claim.len(), "retrying failed claims");
+                }
 
-                    let mut ixs = vec![];
-                    let mut signers = vec![];
+                let mut batch_iter = claim_batches(&to_claim, &missing_atas).into_iter();
+                // Dropped bundles, sent again before new ones are built. A wallet
+                // is in a single bundle, either queued here or in flight.
+                let mut resend = VecDeque::new();
+                let mut in_flight =
+                    FuturesUnordered::<LocalBoxFuture<(ClaimBundle, Vec<(Signature, TxOutcome)>)>>::new();
+                let mut stopping = false;
 
-                    for (pubkey, signer, amount) in batch {
-                        ixs.push(ore::instruction::claim(*pubkey, beneficiary_ata, *amount));
-                        signers.push(signer.clone());
-                        total_rewards_in_this_batch += amount;
+                loop {
+                    if !stopping && stats::stop_requested() {
+                        info!(in_flight = in_flight.len(), "stop requested, not sending more claims");
+                        stopping = true;
                     }
 
-                    let mut fee_payer = signers[rand::thread_rng().gen_range(0..signers.len())].pubkey();
+                    let nothing_to_send = stopping || (resend.is_empty() && (below_threshold || batch_iter.len() == 0));
 
-                    match Self::get_balances(
-                        &client,
-                        &signers.iter().map(|signer| signer.pubkey()).collect::<Vec<_>>(),
-                    )
-                    .await
-                    {
-                        Ok(value) => {
-                            // pick richest
-                            fee_payer = value
-                                .iter()
-                                .max_by_key(|(_, balance)| *balance)
-                                .map(|(pubkey, _)| *pubkey)
-                                .expect("no signers found");
+                    if in_flight.len() >= args.max_in_flight.max(1) || (nothing_to_send && !in_flight.is_empty()) {
+                        let (bundle, outcomes) = in_flight.next().await.expect("no bundle in flight");
+                        let first_tx = outcomes[0].0;
+                        let tip_paid = matches!(outcomes[0].1, TxOutcome::Landed { .. });
+                        let (landed, mut bundle) = bundle.split_landed(&outcomes);
+
+                        if tip_paid {
+                            metrics::BUNDLES_LANDED.inc();
+                            metrics::TIPS_PAID.inc_by(bundle.tip);
+
+                            if let (true, Some(ata)) = (create_ata, beneficiary_ata) {
+                                info!(%ata, "beneficiary token account created");
+                                create_ata = false;
+                            }
                         }
-                        Err(err) => {
-                            error!("fail to get balances for signers: {err:#}");
+
+                        for (tx, slot, wallets) in &landed {
+                            let rewards = wallets.iter().map(|(_, _, amount)| amount).sum::<u64>();
+
+                            if let Some(report) = &mut report {
+                                report.record_all(wallets, Some(*tx), Some(*slot), ClaimStatus::Claimed);
+                            }
+
+                            for (pubkey, _, amount) in wallets {
+                                let destination = beneficiary_ata.unwrap_or_else(|| utils::get_ore_ata(*pubkey));
+                                history::claimed(*pubkey, *amount, destination, *tx, Some(*slot));
+
+                                *claimed_by_wallet.entry(*pubkey).or_default() += amount;
+                                missing_atas.remove(pubkey);
+                            }
+
+                            tally.landed(wallets, rewards);
+                            claimed += rewards;
+
+                            info!(
+                                %tx,
+                                total.rewards.remaing = format_reward!(tally.remaining),
+                                this.batch.rewards = format_reward!(rewards),
+                                this.batch.accounts = wallets.len(),
+                                remaining = tally.remaining,
+                                "claim successfully"
+                            );
                         }
-                    };
 
-                    if txs.is_empty() {
-                        ixs.push(jito::build_bribe_ix(&fee_payer, jito_tip));
-                    }
+                        // What didn't land is sent again, the bribe moves to
+                        // the first transaction left.
+                        if !bundle.txs.is_empty() {
+                            metrics::BUNDLES_DROPPED.inc();
+                            bundle.drops += 1;
 
-                    txs.push(Transaction::new_with_payer(&ixs, Some(&fee_payer)));
-                    accounts_in_this_batch += signers.len();
-                    signers_for_txs.push(signers);
-                }
+                            if let Some(report) = &mut report {
+                                report.record_all(&bundle.wallets, Some(first_tx), None, ClaimStatus::Dropped);
+                            }
 
-                if txs.is_empty() {
-                    break;
-                }
+                            error!(
+                                %first_tx,
+                                total.rewards.remaing = format_reward!(tally.remaining),
+                                this.batch.rewards = format_reward!(bundle.rewards),
+                                this.batch.accounts = bundle.wallets.len(),
+                                remaining = tally.remaining,
+                                tip = bundle.tip,
+                                drops = bundle.drops,
+                                "bundle dropped, retrying"
+                            );
 
-                if total_rewards_in_this_batch < args.threshold() {
-                    info!(
-                        total.rewards.remaing = format_reward!(remaining),
-                        this.batch.rewards = format_reward!(total_rewards_in_this_batch),
-                        this.batch.accounts = accounts_in_this_batch,
-                        "batch reward is less than threshold, will not claim"
-                    );
-                    break;
-                }
+                            resend.push_back(bundle);
+                        }
 
-                let (send_at_slot, blockhash) = match Self::get_latest_blockhash_and_slot(&client).await {
-                    Ok(value) => value,
-                    Err(err) => {
-                        error!("fail to get latest blockhash: {err:#}");
                         continue;
                     }
-                };
-
-                let bundle = txs
-                    .iter()
-                    .zip(signers_for_txs.iter())
-                    .map(|(tx, signers)| {
-                        let mut tx = tx.clone();
-                        tx.sign(signers.as_slice(), blockhash);
-                        tx
-                    })
-                    .collect::<Vec<_>>();
-
-                let mut sim_failed = false;
-
-                for tx in &bundle {
-                    let sim_result = client
-                        .simulate_transaction_with_config(
-                            tx,
-                            RpcSimulateTransactionConfig {
-                                sig_verify: false,
-                                commitment: Some(CommitmentConfig::processed()),
-                                encoding: None,
-                                accounts: None,
-                                min_context_slot: None,
-                                replace_recent_blockhash: true,
-                                inner_instructions: false,
-                            },
-                        )
-                        .await;
-
-                    debug!("simulation result: {sim_result:?}");
-                    match sim_result {
-                        Ok(r) => {
-                            if let Some(err) = &r.value.err {
-                                error!("simulation returns error: {err:#}");
-                            } else {
+
+                    if nothing_to_send {
+                        break;
+                    }
+
+                    let mut bundle = match resend.pop_front() {
+                        Some(bundle) => bundle,
+                        None => {
+                            let mut bundle = ClaimBundle::new(jito_tip);
+
+                            for batch in batch_iter.by_ref().take(5) {
+                                let mut ixs = vec![];
+                                let mut signers = vec![];
+
+                                for (pubkey, signer, claimable_before, amount) in batch {
+                                    let ata = beneficiary_ata.unwrap_or_else(|| utils::get_ore_ata(*pubkey));
+
+                                    if missing_atas.contains(pubkey) {
+                                        ixs.push(create_associated_token_account_idempotent(
+                                            pubkey,
+                                            pubkey,
+                                            &cluster::mint(),
+                                            &spl_token::id(),
+                                        ));
+                                    }
+
+                                    ixs.push(cluster::instruction(ore::instruction::claim(*pubkey, ata, *amount)));
+                                    bundle.wallets.push((*pubkey, *claimable_before, *amount));
+                                    signers.push(signer.clone());
+                                    bundle.rewards += amount;
+                                }
+
+                                let mut fee_payer = signers[rand::thread_rng().gen_range(0..signers.len())].pubkey();
+                                let signer_pubkeys = signers.iter().map(|signer| signer.pubkey()).collect::<Vec<_>>();
+
+                                match Self::get_balances(&client, &signer_pubkeys).await {
+                                    // Wallets without lamports are missing from the
+                                    // balances, the simulation fails the chunk if
+                                    // none of them can pay.
+                                    Ok(value) => match utils::pick_richest_account(&value, &signer_pubkeys) {
+                                        Some(richest) => fee_payer = richest,
+                                        None => warn!(
+                                            accounts = signer_pubkeys.len(),
+                                            "no signer holds lamports to pay the claim fee"
+                                        ),
+                                    },
+                                    Err(err) => {
+                                        error!("fail to get balances for signers: {err:#}");
+                                    }
+                                };
+
+                                if bundle.txs.is_empty() {
+                                    // Idempotent, so every bundle built before the
+                                    // first one lands carries it.
+                                    if let (true, Some(beneficiary)) = (create_ata, args.beneficiary) {
+                                        let funder = match &ata_payer {
+                                            Some(ata_payer) => {
+                                                signers.push(ata_payer.clone());
+                                                ata_payer.pubkey()
+                                            }
+                                            None => fee_payer,
+                                        };
+
+                                        ixs.insert(
+                                            0,
+                                            create_associated_token_account_idempotent(
+                                                &funder,
+                                                &beneficiary,
+                                                &cluster::mint(),
+                                                &spl_token::id(),
+                                            ),
+                                        );
+                                    }
+                                }
+
+                                bundle.txs.push((ixs, fee_payer));
+                                bundle.signers.push(signers);
+                                bundle.claims.push(batch.len());
+                            }
+
+                            if args.threshold_scope == ThresholdScope::Batch && bundle.rewards < args.threshold() {
+                                info!(
+                                    total.rewards.remaing = format_reward!(tally.remaining),
+                                    this.batch.rewards = format_reward!(bundle.rewards),
+                                    this.batch.accounts = bundle.wallets.len(),
+                                    "bundle reward is less than threshold, will not claim the remaining bundles"
+                                );
+
+                                below_threshold = true;
+
+                                if let Some(report) = &mut report {
+                                    let not_batched = batch_iter
+                                        .clone()
+                                        .flatten()
+                                        .map(|(pubkey, _, claimable_before, amount)| {
+                                            (*pubkey, *claimable_before, *amount)
+                                        })
+                                        .collect::<Vec<_>>();
+
+                                    report.record_all(&bundle.wallets, None, None, ClaimStatus::BelowThreshold);
+                                    report.record_all(&not_batched, None, None, ClaimStatus::BelowThreshold);
+                                }
+
                                 continue;
                             }
+
+                            bundle
                         }
+                    };
+
+                    let (send_at_slot, blockhash) = match blockhash_cache.get(blockhash::MAX_AGE).await {
+                        Ok(value) => value,
                         Err(err) => {
-                            error!("fail to simulate transaction: {err:#}");
+                            error!("fail to get latest blockhash: {err:#}");
+                            resend.push_front(bundle);
+                            continue;
+                        }
+                    };
+
+                    if bundle.drops > 0 && args.max_tip > 0 {
+                        if let Some(adaptive_tip) = tips.read().await.adaptive_tip(jito_tip, args.max_tip) {
+                            bundle.tip = bundle.tip.max(adaptive_tip);
                         }
                     }
 
-                    sim_failed = true;
-                    break;
-                }
+                    let txs = bundle.sign(blockhash);
+                    let signatures = txs.iter().map(|tx| tx.signatures[0]).collect::<Vec<_>>();
 
-                if sim_failed {
-                    txs.clear();
-                    remaining -= total_rewards_in_this_batch;
-                    accounts_in_this_batch = 0;
-                    total_rewards_in_this_batch = 0;
-                    signers_for_txs.clear();
-                    continue;
-                }
+                    // A stubborn bundle doesn't block the run, its wallets are
+                    // retried after the pass.
+                    let gave_up = bundle.drops >= MAX_BUNDLE_DROPS;
+                    let mut batch_error = gave_up.then(|| format!("bundle dropped {} times", bundle.drops));
 
-                let (tx, bundle_id) = match jito::send_bundle(bundle).await {
-                    Ok(value) => value,
-                    Err(err) => {
-                        error!("fail to send bundle: {err:#}");
-                        tokio::time::sleep(Duration::from_secs(2)).await;
-                        continue;
+                    for tx in txs.iter().filter(|_| !gave_up) {
+                        let sim_result = client
+                            .simulate_transaction_with_config(
+                                tx,
+                                RpcSimulateTransactionConfig {
+                                    sig_verify: false,
+                                    commitment: Some(utils::commitment_or(CommitmentConfig::processed())),
+                                    encoding: None,
+                                    accounts: None,
+                                    min_context_slot: None,
+                                    replace_recent_blockhash: true,
+                                    inner_instructions: false,
+                                },
+                            )
+                            .await;
+
+                        debug!("simulation result: {sim_result:?}");
+                        let err = match sim_result {
+                            Ok(r) => match r.value.err {
+                                Some(err) => {
+                                    format!("simulation returns error: {}", program_error::describe(&err, Some(tx)))
+                                }
+                                None => continue,
+                            },
+                            Err(err) => format!("fail to simulate transaction: {err:#}"),
+                        };
+
+                        error!("{err}");
+                        batch_error = Some(err);
+                        break;
                     }
-                };
 
-                info!(
-                    first_tx = %tx,
-                    %bundle_id,
-                    total.rewards.remaing = format_reward!(remaining),
-                    this.batch.rewards = format_reward!(total_rewards_in_this_batch),
-                    this.batch.accounts = accounts_in_this_batch,
-                    slot = send_at_slot,
-                    "bundle sent");
-
-                let mut latest_slot = send_at_slot;
-                let mut mined = false;
-
-                while !mined && latest_slot < send_at_slot + constant::SLOT_EXPIRATION {
-                    tokio::time::sleep(Duration::from_secs(2)).await;
-                    debug!(
-                        ?tx,
-                        total.rewards.remaing = format_reward!(remaining),
-                        this.batch.rewards = format_reward!(total_rewards_in_this_batch),
-                        this.batch.accounts = accounts_in_this_batch,
-                        slot = send_at_slot,
-                        "checking bundle status"
-                    );
+                    // Not claimed but still claimable, retried after the pass.
+                    if let Some(err) = batch_error {
+                        if gave_up {
+                            error!(accounts = bundle.wallets.len(), "{err}, moving on to the next batch");
+                        } else if let Some(report) = &mut report {
+                            report.record_all(&bundle.wallets, None, None, ClaimStatus::SimFailed);
+                        }
 
-                    let (statuses, slot) = match Self::get_signature_statuses(&client, &[tx]).await {
+                        tally.failed(&to_claim, &bundle.wallets, &err);
+
+                        continue;
+                    }
+
+                    let (tx, bundle_id) = match jito::send_bundle(txs).await {
                         Ok(value) => value,
                         Err(err) => {
-                            error!(send_at_slot, "fail to get bundle status: {err:#}");
+                            error!("fail to send bundle: {err:#}");
                             tokio::time::sleep(Duration::from_secs(2)).await;
+                            resend.push_front(bundle);
                             continue;
                         }
                     };
 
-                    mined = !utils::find_landed_txs(&[tx], statuses).is_empty();
-                    latest_slot = slot;
-                }
+                    metrics::BUNDLES_SENT.inc();
 
-                if mined {
                     info!(
-                        total.rewards.remaing = format_reward!(remaining),
-                        this.batch.rewards = format_reward!(total_rewards_in_this_batch),
-                        this.batch.accounts = accounts_in_this_batch,
-                        remaining,
-                        "claim successfully"
-                    );
-
-                    txs.clear();
-                    remaining -= total_rewards_in_this_batch;
-                    accounts_in_this_batch = 0;
-                    signers_for_txs.clear();
-                    total_rewards_in_this_batch = 0;
-                } else {
-                    error!(
-                        total.rewards.remaing = format_reward!(remaining),
-                        this.batch.rewards = format_reward!(total_rewards_in_this_batch),
-                        this.batch.accounts = accounts_in_this_batch,
-                        remaining,
+                        first_tx = %tx,
+                        %bundle_id,
+                        total.rewards.remaing = format_reward!(tally.remaining),
+                        this.batch.rewards = format_reward!(bundle.rewards),
+                        this.batch.accounts = bundle.wallets.len(),
+                        tip = bundle.tip,
+                        in_flight = in_flight.len() + 1,
                         slot = send_at_slot,
-                        "bundle dropped, retrying"
-                    );
+                        "bundle sent");
+
+                    in_flight
+                        .push(Self::watch_claim_bundle(client.clone(), bundle, signatures, send_at_slot).boxed_local());
                 }
             }
 
-            if !args.auto {
+            info!(
+                claimable = format_reward!(tally.total),
+                claimed = format_reward!(tally.claimed),
+                failed = format_reward!(tally.failed_amount()),
+                remaining = format_reward!(tally.remaining),
+                "claim reconciliation"
+            );
+
+            for (pubkey, amount, err) in tally.unclaimed() {
+                error!(%pubkey, amount = format_reward!(amount), "not claimed: {err}");
+            }
+
+            if !args.auto || stats::stop_requested() {
                 break;
             }
 
-            info!("will check reward again in {RECHECK_INTERVAL:?}");
-            tokio::time::sleep(RECHECK_INTERVAL).await
+            match args.after_epochs {
+                Some(epochs) => {
+                    info!("will check reward again after {epochs} epochs");
+                    Self::wait_for_epochs(&client, epochs).await;
+                }
+                None => {
+                    let interval = Duration::from_secs(args.recheck_interval);
+                    info!("will check reward again in {interval:?}");
+                    sleep_unless_stopped(interval).await;
+                }
+            }
+        }
+
+        if !claimed_by_wallet.is_empty() {
+            println!("{:<44} {:<44} {:>14}", "wallet", "token account", "claimed");
+
+            for (pubkey, amount) in claimed_by_wallet.iter().sorted_by_key(|(_, amount)| Reverse(**amount)) {
+                let ata = beneficiary_ata.unwrap_or_else(|| utils::get_ore_ata(*pubkey));
+                println!(
+                    "{:<44} {:<44} {:>14}",
+                    pubkey.to_string(),
+                    ata.to_string(),
+                    utils::ore_ui_amount(*amount)
+                );
+            }
+        }
+
+        if let Some(report) = &mut report {
+            report.finish();
+        }
+
+        info!(
+            claimed = format_reward!(claimed),
+            skipped.address = skipped_by_address,
+            skipped.min_per_wallet = skipped_by_min,
+            "claim summary"
+        );
+
+        notify::notify(
+            "claim_completed",
+            format!(
+                "claim completed: {} ORE claimed, {} wallets skipped by address, {} below the minimum",
+                utils::ore_ui_amount(claimed),
+                skipped_by_address,
+                skipped_by_min
+            ),
+        );
+    }
+
+    /// Waits until the treasury was reset `epochs` times, sleeping until each
+    /// expected reset instead of polling.
+    async fn wait_for_epochs(client: &RpcClient, epochs: u64) {
+        let mut last_reset_at = None;
+        let mut resets = 0;
+
+        while !stats::stop_requested() {
+            let (treasury, clock, _) = match Self::get_system_accounts(client).await {
+                Ok(value) => value,
+                Err(err) => {
+                    error!("fail to get treasury: {err:#}");
+                    sleep_unless_stopped(Duration::from_secs(5)).await;
+                    continue;
+                }
+            };
+
+            if last_reset_at.is_some_and(|last_reset_at| last_reset_at != treasury.last_reset_at) {
+                resets += 1;
+            }
+
+            last_reset_at = Some(treasury.last_reset_at);
+
+            if resets >= epochs {
+                break;
+            }
+
+            // The reset lands with the first transaction past the threshold,
+            // which may take a moment.
+            let reset_threshold = treasury.last_reset_at.saturating_add(ore::EPOCH_DURATION);
+            let time_to_next_epoch = match clock.unix_timestamp < reset_threshold {
+                true => Self::get_time_to_next_epoch(&treasury, &clock, reset_threshold),
+                false => Duration::ZERO,
+            };
+
+            debug!(resets, epochs, ?time_to_next_epoch, "waiting for the next epoch");
+            sleep_unless_stopped(time_to_next_epoch + Duration::from_secs(2)).await;
+        }
+    }
+
+    /// Watches the transactions of a bundle until they are all confirmed or
+    /// their blockhash expires, the ones still pending were dropped. A failed
+    /// claim is retried as a dropped one, the same instructions with a fresh
+    /// blockhash.
+    async fn watch_claim_bundle(
+        client: Arc<RpcClient>,
+        bundle: ClaimBundle,
+        signatures: Vec<Signature>,
+        send_at_slot: Slot,
+    ) -> (ClaimBundle, Vec<(Signature, TxOutcome)>) {
+        let mut outcomes = signatures
+            .iter()
+            .map(|signature| (*signature, TxOutcome::Pending))
+            .collect::<Vec<_>>();
+        let mut latest_slot = send_at_slot;
+
+        while !outcomes.iter().all(|(_, outcome)| outcome.is_confirmed()) &&
+            latest_slot < send_at_slot + constant::SLOT_EXPIRATION
+        {
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            debug!(
+                first_tx = ?signatures[0],
+                this.batch.rewards = format_reward!(bundle.rewards),
+                this.batch.accounts = bundle.wallets.len(),
+                slot = send_at_slot,
+                "checking bundle status"
+            );
+
+            let (statuses, slot) = match Self::get_signature_statuses(&client, &signatures).await {
+                Ok(value) => value,
+                Err(err) => {
+                    error!(send_at_slot, "fail to get bundle status: {err:#}");
+                    tokio::time::sleep(Duration::from_secs(2)).await;
+                    continue;
+                }
+            };
+
+            for ((tx, outcome), (_, found)) in outcomes.iter_mut().zip(utils::find_tx_outcomes(&signatures, statuses)) {
+                if outcome.is_confirmed() {
+                    continue;
+                }
+
+                if let TxOutcome::Failed { slot, err } = &found {
+                    metrics::TXS_FAILED.inc();
+                    error!(
+                        %tx,
+                        slot,
+                        "claim transaction landed but failed: {}",
+                        program_error::describe(err, None)
+                    );
+                }
+
+                *outcome = found;
+            }
+
+            latest_slot = slot;
+        }
+
+        (bundle, outcomes)
+    }
+}
+
+/// Sleeps for `duration`, returns early when a stop is requested.
+async fn sleep_unless_stopped(duration: Duration) {
+    let start = std::time::Instant::now();
+
+    while start.elapsed() < duration && !stats::stop_requested() {
+        tokio::time::sleep(Duration::from_secs(1).min(duration.saturating_sub(start.elapsed()))).await;
+    }
+}
+
+/// The token account the rewards go to with `--beneficiary`, and whether it
+/// exists. A claim to an account of another mint, of another owner or frozen
+/// fails on-chain with an opaque error, an existing one is checked first.
+/// `beneficiary` may also be the token account itself instead of its owner.
+async fn beneficiary_token_account(client: &RpcClient, beneficiary: Pubkey) -> eyre::Result<(Pubkey, bool)> {
+    let ata = utils::get_ore_ata(beneficiary);
+    let accounts = client
+        .get_multiple_accounts_with_commitment(&[beneficiary, ata], utils::commitment_or(CommitmentConfig::confirmed()))
+        .await
+        .context("fail to get beneficiary token account")?
+        .value;
+
+    if let Some(account) = accounts[0].as_ref().filter(|account| account.owner == spl_token::id()) {
+        let token_account = TokenAccount::unpack(&account.data).with_context(|| {
+            format!("--beneficiary {beneficiary} is owned by the token program but isn't a token account")
+        })?;
+
+        check_token_account(beneficiary, &token_account)?;
+
+        warn!(
+            ata = %beneficiary,
+            owner = %token_account.owner,
+            "--beneficiary is a token account instead of a wallet, claiming to it directly"
+        );
+
+        return Ok((beneficiary, true));
+    }
+
+    let account = match &accounts[1] {
+        Some(account) => account,
+        None => return Ok((ata, false)),
+    };
+
+    if account.owner != spl_token::id() {
+        bail!(
+            "beneficiary token account {ata} is owned by {} instead of the token program",
+            account.owner
+        );
+    }
+
+    let token_account = TokenAccount::unpack(&account.data)
+        .with_context(|| format!("beneficiary token account {ata} isn't a token account"))?;
+
+    if token_account.owner != beneficiary {
+        bail!(
+            "beneficiary token account {ata} belongs to {} instead of {beneficiary}",
+            token_account.owner
+        );
+    }
+
+    check_token_account(ata, &token_account)?;
+
+    Ok((ata, true))
+}
+
+/// Splits `wallets` in transactions of up to 5 claims, or
+/// `CLAIMS_CREATING_ATAS` once one of them creates its token account.
+fn claim_batches<'a>(wallets: &'a [ClaimableWallet], missing_atas: &HashSet<Pubkey>) -> Vec<&'a [ClaimableWallet]> {
+    let mut batches = vec![];
+    let mut start = 0;
+    let mut creates_ata = false;
+
+    for (i, (pubkey, _, _, _)) in wallets.iter().enumerate() {
+        let creates = missing_atas.contains(pubkey);
+        let limit = match creates_ata || creates {
+            true => CLAIMS_CREATING_ATAS,
+            false => 5,
+        };
+
+        if i - start >= limit {
+            batches.push(&wallets[start..i]);
+            start = i;
+            creates_ata = false;
+        }
+
+        creates_ata |= creates;
+    }
+
+    if start < wallets.len() {
+        batches.push(&wallets[start..]);
+    }
+
+    batches
+}
+
+fn check_token_account(address: Pubkey, token_account: &TokenAccount) -> eyre::Result<()> {
+    if token_account.mint != cluster::mint() {
+        bail!(
+            "beneficiary token account {address} holds mint {} instead of ORE {}",
+            token_account.mint,
+            cluster::mint()
+        );
+    }
+
+    if token_account.is_frozen() {
+        bail!("beneficiary token account {address} is frozen, claims to it would fail");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::signature::Keypair;
+
+    use super::*;
+
+    fn wallets(amounts: &[u64]) -> Vec<ClaimableWallet> {
+        amounts
+            .iter()
+            .map(|amount| {
+                let keypair = Keypair::new();
+                (keypair.pubkey(), Arc::new(keypair) as Arc<dyn Signer>, *amount, *amount)
+            })
+            .collect()
+    }
+
+    /// The `(wallet, claimable, amount)` a bundle of `wallets` claims.
+    fn bundle(wallets: &[ClaimableWallet]) -> (Vec<(Pubkey, u64, u64)>, u64) {
+        let claims = wallets
+            .iter()
+            .map(|(pubkey, _, claimable, amount)| (*pubkey, *claimable, *amount))
+            .collect::<Vec<_>>();
+        let rewards = claims.iter().map(|(_, _, amount)| amount).sum();
+
+        (claims, rewards)
+    }
+
+    #[test]
+    fn claim_batches_hold_5_claims_without_missing_token_accounts() {
+        let to_claim = wallets(&[1; 12]);
+        let sizes = claim_batches(&to_claim, &HashSet::new())
+            .iter()
+            .map(|batch| batch.len())
+            .collect::<Vec<_>>();
+
+        assert_eq!(sizes, vec![5, 5, 2]);
+    }
+
+    #[test]
+    fn claim_batches_creating_token_accounts_hold_fewer_claims() {
+        let to_claim = wallets(&[1; 12]);
+        let missing_atas = [to_claim[4].0, to_claim[5].0, to_claim[6].0, to_claim[7].0]
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let sizes = claim_batches(&to_claim, &missing_atas)
+            .iter()
+            .map(|batch| batch.len())
+            .collect::<Vec<_>>();
+
+        assert_eq!(sizes, vec![4, 3, 3, 2]);
+    }
+
+    #[test]
+    fn tally_follows_landed_dropped_and_failed_bundles() {
+        let to_claim = wallets(&[100, 50, 30, 20]);
+        let mut tally = ClaimTally::new(&to_claim);
+
+        assert_eq!((tally.total, tally.remaining, tally.claimed), (200, 200, 0));
+
+        let (first, first_rewards) = bundle(&to_claim[..2]);
+        let (second, second_rewards) = bundle(&to_claim[2..3]);
+        let (third, _) = bundle(&to_claim[3..]);
+
+        // The second bundle is dropped and resent, it only counts once landed.
+        tally.landed(&first, first_rewards);
+        tally.failed(&to_claim, &third, "simulation returns error");
+        tally.landed(&second, second_rewards);
+
+        assert_eq!((tally.remaining, tally.claimed, tally.failed_amount()), (20, 180, 20));
+        assert_eq!(
+            tally.unclaimed().collect::<Vec<_>>(),
+            vec![(to_claim[3].0, 20, "simulation returns error")]
+        );
+
+        let retry = tally.next_pass().unwrap();
+        assert_eq!(
+            retry.iter().map(|(pubkey, ..)| *pubkey).collect::<Vec<_>>(),
+            vec![to_claim[3].0]
+        );
+
+        let (retried, retried_rewards) = bundle(&retry);
+        tally.landed(&retried, retried_rewards);
+
+        assert_eq!((tally.remaining, tally.claimed, tally.failed_amount()), (0, 200, 0));
+        assert_eq!(tally.unclaimed().count(), 0);
+        assert!(tally.next_pass().is_none());
+    }
+
+    #[test]
+    fn tally_keeps_the_last_error_of_wallets_failing_every_pass() {
+        let to_claim = wallets(&[40, 10]);
+        let mut tally = ClaimTally::new(&to_claim);
+
+        let (claims, _) = bundle(&to_claim);
+        tally.failed(&to_claim, &claims, "bundle dropped 5 times");
+
+        let retry = tally.next_pass().unwrap();
+        assert_eq!(retry.len(), 2);
+        assert_eq!(tally.failed_amount(), 0);
+
+        tally.failed(&retry, &claims[1..], "simulation returns error");
+
+        let (landed, landed_rewards) = bundle(&retry[..1]);
+        tally.landed(&landed, landed_rewards);
+
+        assert_eq!((tally.remaining, tally.claimed, tally.failed_amount()), (10, 40, 10));
+        assert_eq!(
+            tally.unclaimed().collect::<Vec<_>>(),
+            vec![(to_claim[1].0, 10, "simulation returns error")]
+        );
+    }
+
+    #[test]
+    fn split_landed_keeps_the_transactions_that_did_not_land() {
+        let to_claim = wallets(&[100, 50, 30, 20, 10]);
+        let mut claim_bundle = ClaimBundle::new(1000);
+        claim_bundle.drops = 1;
+
+        for batch in [&to_claim[..2], &to_claim[2..3], &to_claim[3..]] {
+            let (claims, rewards) = bundle(batch);
+
+            claim_bundle.txs.push((vec![], batch[0].0));
+            claim_bundle
+                .signers
+                .push(batch.iter().map(|(_, signer, ..)| signer.clone()).collect());
+            claim_bundle.claims.push(claims.len());
+            claim_bundle.wallets.extend(claims);
+            claim_bundle.rewards += rewards;
         }
+
+        let signatures = (0..3).map(|_| Signature::new_unique()).collect::<Vec<_>>();
+        let outcomes = vec![
+            (signatures[0], TxOutcome::Pending),
+            (signatures[1], TxOutcome::Landed { slot: 7 }),
+            (
+                signatures[2],
+                TxOutcome::Failed {
+                    slot: 7,
+                    err: solana_sdk::transaction::TransactionError::AccountNotFound,
+                },
+            ),
+        ];
+
+        let (landed, rest) = claim_bundle.split_landed(&outcomes);
+
+        assert_eq!(landed, vec![(signatures[1], 7, bundle(&to_claim[2..3]).0)]);
+
+        let (first, first_rewards) = bundle(&to_claim[..2]);
+        let (third, third_rewards) = bundle(&to_claim[3..]);
+        assert_eq!(rest.wallets, [first, third].concat());
+        assert_eq!(rest.claims, vec![2, 2]);
+        assert_eq!(rest.txs.len(), 2);
+        assert_eq!(rest.signers.len(), 2);
+        assert_eq!(rest.rewards, first_rewards + third_rewards);
+        assert_eq!((rest.tip, rest.drops), (1000, 1));
     }
 }
@@ -0,0 +1,137 @@
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+};
+
+use serde::Serialize;
+use solana_sdk::{clock::Slot, pubkey::Pubkey, signature::Signature};
+use tracing::error;
+
+use crate::utils;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClaimStatus {
+    Claimed,
+    BelowThreshold,
+    SimFailed,
+    Dropped,
+}
+
+impl ClaimStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            ClaimStatus::Claimed => "claimed",
+            ClaimStatus::BelowThreshold => "below-threshold",
+            ClaimStatus::SimFailed => "sim-failed",
+            ClaimStatus::Dropped => "dropped",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ClaimRecord {
+    wallet: String,
+    proof: String,
+    claimable: u64,
+    amount: u64,
+    signature: Option<String>,
+    slot: Option<Slot>,
+    status: &'static str,
+}
+
+/// One record per wallet and claim attempt, written as JSON lines when the
+/// path ends with `.json` and as CSV otherwise. Every record is written to
+/// the file right away, an interrupted run still leaves a usable report.
+pub struct ClaimReport {
+    file: File,
+    json: bool,
+    claimable: u64,
+    claimed: u64,
+    wallets: usize,
+}
+
+impl ClaimReport {
+    pub fn create(path: &str) -> io::Result<Self> {
+        let json = Path::new(path).extension().is_some_and(|ext| ext == "json");
+        let mut file = File::create(path)?;
+
+        if !json {
+            writeln!(file, "wallet,proof,claimable,amount,signature,slot,status")?;
+        }
+
+        Ok(Self {
+            file,
+            json,
+            claimable: 0,
+            claimed: 0,
+            wallets: 0,
+        })
+    }
+
+    /// Records the `(wallet, claimable, amount)` of a batch with the same
+    /// outcome.
+    pub fn record_all(
+        &mut self,
+        wallets: &[(Pubkey, u64, u64)],
+        signature: Option<Signature>,
+        slot: Option<Slot>,
+        status: ClaimStatus,
+    ) {
+        for (wallet, claimable, amount) in wallets {
+            if status == ClaimStatus::Claimed {
+                self.claimable += claimable;
+                self.claimed += amount;
+                self.wallets += 1;
+            }
+
+            let record = ClaimRecord {
+                wallet: wallet.to_string(),
+                proof: utils::get_proof_pda(*wallet).to_string(),
+                claimable: *claimable,
+                amount: *amount,
+                signature: signature.map(|signature| signature.to_string()),
+                slot,
+                status: status.as_str(),
+            };
+
+            self.write(&record);
+        }
+    }
+
+    /// Appends the totals of the claimed records, the wallet column holds
+    /// their count.
+    pub fn finish(&mut self) {
+        let record = ClaimRecord {
+            wallet: self.wallets.to_string(),
+            proof: String::new(),
+            claimable: self.claimable,
+            amount: self.claimed,
+            signature: None,
+            slot: None,
+            status: "total",
+        };
+
+        self.write(&record);
+    }
+
+    fn write(&mut self, record: &ClaimRecord) {
+        let line = match self.json {
+            true => serde_json::to_string(record).unwrap(),
+            false => format!(
+                "{},{},{},{},{},{},{}",
+                record.wallet,
+                record.proof,
+                record.claimable,
+                record.amount,
+                record.signature.as_deref().unwrap_or_default(),
+                record.slot.map(|slot| slot.to_string()).unwrap_or_default(),
+                record.status
+            ),
+        };
+
+        if let Err(err) = writeln!(self.file, "{line}") {
+            error!("fail to write claim report: {err:#}");
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use clap::ValueEnum;
+use eyre::bail;
+use once_cell::sync::OnceCell;
+use solana_sdk::{instruction::Instruction, pubkey::Pubkey, sysvar};
+
+use crate::utils;
+
+/// Used as `--rpc` with `--cluster devnet` when it isn't given.
+pub const DEVNET_RPC: &str = "https://api.devnet.solana.com";
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cluster {
+    Mainnet,
+    /// The ore program deployed on devnet, without jito.
+    Devnet,
+    /// A program given by `--program-id` and `--mint`, without jito.
+    Custom,
+}
+
+/// The accounts of the ore program the miner works with.
+#[derive(Debug)]
+pub struct ProgramAddresses {
+    pub program_id: Pubkey,
+    pub mint: Pubkey,
+    pub treasury: Pubkey,
+    pub buses: [Pubkey; ore::BUS_COUNT],
+    /// The treasury, the clock and the buses, fetched together every round.
+    pub system_accounts: Vec<Pubkey>,
+}
+
+/// Set once from `--cluster`, mainnet when not set.
+static ADDRESSES: OnceCell<ProgramAddresses> = OnceCell::new();
+
+impl ProgramAddresses {
+    fn new(program_id: Pubkey, mint: Pubkey) -> Self {
+        let (treasury, buses) = match program_id == ore::ID {
+            true => (ore::TREASURY_ADDRESS, ore::BUS_ADDRESSES),
+            false => (
+                Pubkey::find_program_address(&[ore::TREASURY], &program_id).0,
+                std::array::from_fn(|id| Pubkey::find_program_address(&[ore::BUS, &[id as u8]], &program_id).0),
+            ),
+        };
+
+        let mut system_accounts = vec![treasury, sysvar::clock::ID];
+        system_accounts.extend(buses);
+
+        Self {
+            program_id,
+            mint,
+            treasury,
+            buses,
+            system_accounts,
+        }
+    }
+}
+
+pub fn init(cluster: Cluster, program_id: Option<Pubkey>, mint: Option<Pubkey>) -> eyre::Result<()> {
+    let (program_id, mint) = match (cluster, program_id, mint) {
+        (Cluster::Custom, Some(program_id), Some(mint)) => (program_id, mint),
+        (Cluster::Custom, _, _) => bail!("--cluster custom requires --program-id and --mint"),
+        (_, program_id, mint) => (program_id.unwrap_or(ore::ID), mint.unwrap_or(ore::MINT_ADDRESS)),
+    };
+
+    let _ = ADDRESSES.set(ProgramAddresses::new(program_id, mint));
+
+    Ok(())
+}
+
+pub fn addresses() -> &'static ProgramAddresses {
+    ADDRESSES.get_or_init(|| ProgramAddresses::new(ore::ID, ore::MINT_ADDRESS))
+}
+
+pub fn program_id() -> Pubkey {
+    addresses().program_id
+}
+
+pub fn mint() -> Pubkey {
+    addresses().mint
+}
+
+pub fn bus_address(id: u64) -> Pubkey {
+    addresses().buses[id as usize]
+}
+
+pub fn system_accounts() -> &'static [Pubkey] {
+    &addresses().system_accounts
+}
+
+/// `ix` as built by the ore crate, which only knows the mainnet program, with
+/// the accounts of the selected program instead.
+pub fn instruction(mut ix: Instruction) -> Instruction {
+    let addresses = addresses();
+
+    if addresses.program_id == ore::ID && addresses.mint == ore::MINT_ADDRESS {
+        return ix;
+    }
+
+    // The proof is derived from the signer, the first account of every ore
+    // instruction.
+    let proof = ix.accounts.iter().find(|account| account.is_signer).map(|signer| {
+        (
+            Pubkey::find_program_address(&[ore::PROOF, signer.pubkey.as_ref()], &ore::ID).0,
+            utils::get_proof_pda(signer.pubkey),
+        )
+    });
+
+    let treasury_ata =
+        spl_associated_token_account::get_associated_token_address(&ore::TREASURY_ADDRESS, &ore::MINT_ADDRESS);
+
+    ix.program_id = addresses.program_id;
+
+    for account in &mut ix.accounts {
+        account.pubkey = match account.pubkey {
+            pubkey if pubkey == ore::ID => addresses.program_id,
+            pubkey if pubkey == ore::MINT_ADDRESS => addresses.mint,
+            pubkey if pubkey == ore::TREASURY_ADDRESS => addresses.treasury,
+            pubkey if pubkey == treasury_ata => utils::get_treasury_ata(),
+            pubkey if proof.is_some_and(|(mainnet, _)| pubkey == mainnet) => proof.unwrap().1,
+            pubkey => match ore::BUS_ADDRESSES.iter().position(|bus| *bus == pubkey) {
+                Some(id) => addresses.buses[id],
+                None => pubkey,
+            },
+        };
+    }
+
+    ix
+}
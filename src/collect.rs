@@ -1,41 +1,122 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
 use clap::Parser;
+use ore::{state::Proof, utils::AccountDeserialize};
+use solana_client::nonblocking::rpc_client::RpcClient;
 use solana_sdk::{
-    pubkey::Pubkey, signature::{Keypair, Signer},
-    signer::EncodableKey, system_instruction, transaction::Transaction,
-    message::Message
+    instruction::Instruction,
+    message::Message,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction,
+    transaction::Transaction,
+};
+use spl_token::{solana_program::program_pack::Pack, state::Account as TokenAccount};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    blockhash::{self, BlockhashCache},
+    cluster,
+    constant,
+    jito,
+    metrics,
+    program_error,
+    utils,
+    utils::TxOutcome,
+    Miner,
 };
-use tracing::{error, info};
-use crate::Miner;
+
+/// Sends of the same bundle before its wallets are given up on.
+const COLLECT_BUNDLE_RETRIES: usize = 5;
+
+/// Passes over the wallets that were not swept, with fresh balances.
+const COLLECT_RETRIES: usize = 2;
 
 #[derive(Parser, Debug, Clone)]
 pub struct CollectArgs {
-    #[arg(long, help = "The folder that contains all the keys used to collect the remaining balance.")]
+    #[arg(
+        long,
+        help = "The folder that contains all the keys used to collect the remaining balance."
+    )]
     pub key_folder: String,
 
     #[arg(long, help = "The beneficiary account that will receive the remaining balance.")]
     pub beneficiary: Pubkey,
 
-    #[arg(long, default_value = "", help = "The keypair file to use as fee payer. If not provided, the first key in the key_folder will be used.")]
+    #[arg(
+        long,
+        default_value = "",
+        help = "The keypair file to use as fee payer. If not provided, the first key in the key_folder will be used."
+    )]
     pub fee_payer: String,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Send plain transactions instead of jito bundles tipped with --priority-fee."
+    )]
+    pub no_jito: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Also sweep the $ORE of each wallet to the beneficiary token account and close the emptied token accounts."
+    )]
+    pub include_ore: bool,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "SOL to leave in each wallet on top of the rent exemption, e.g. for mining fees."
+    )]
+    pub reserve: f64,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Print what each wallet would transfer and the total without sending anything."
+    )]
+    pub dry_run: bool,
+}
+
+/// What a wallet sends to the beneficiary, signed by it.
+struct CollectTransfer<'a> {
+    keypair: &'a Keypair,
+    instructions: Vec<Instruction>,
+    balance: u64,
+    lamports: u64,
+    ore: u64,
+}
+
+enum CollectOutcome {
+    Swept { lamports: u64, ore: u64 },
+    BelowReserve { balance: u64 },
+    FeePayer { balance: u64 },
+    Failed(String),
 }
 
 impl Miner {
     pub async fn collect(&self, args: &CollectArgs) {
         let client = Miner::get_client_confirmed(&self.rpc);
-        let accounts = Self::read_keys(&args.key_folder);
-       
-        let fee_payer_account: Keypair = if (&args.fee_payer).is_empty() {
+        let accounts = match Self::read_keys(&args.key_folder, self.recursive) {
+            Ok(accounts) => accounts,
+            Err(err) => {
+                error!("{err:#}");
+                return;
+            }
+        };
+
+        let fee_payer_account: Keypair = if args.fee_payer.is_empty() {
             accounts[0].insecure_clone() // sorry for this
         } else {
-            Keypair::read_from_file(&args.fee_payer).unwrap()
+            utils::read_keypair(&args.fee_payer).unwrap()
         };
 
         info!("use account {} as fee payer", fee_payer_account.pubkey());
-        
-        let mut instructions = Vec::new();
-        let mut signers = Vec::new();
-       
+
         let balance_fee_payer = client
             .get_balance(&fee_payer_account.pubkey())
             .await
@@ -43,68 +124,809 @@ impl Miner {
 
         info!("Fee payer balance: {}", balance_fee_payer);
 
-        for keypair in accounts.iter() {
-            let pubkey = keypair.pubkey();
-            let balance = client
-                .get_balance(&pubkey)
-                .await
-                .expect("Failed to get balance");
-            let rent_exemption = client
-                .get_minimum_balance_for_rent_exemption(0)
-                .await
-                .expect("Failed to get minimum balance for rent exemption");
+        let rent_exemption = client
+            .get_minimum_balance_for_rent_exemption(0)
+            .await
+            .expect("Failed to get minimum balance for rent exemption");
+
+        // Keeps the rent, the share of the fee of its own signature and what
+        // was asked for.
+        let reserve = rent_exemption + constant::FEE_PER_SIGNER + spl_token::ui_amount_to_amount(args.reserve, 9);
+
+        let beneficiary_ata = utils::get_ore_ata(args.beneficiary);
+
+        if args.include_ore {
+            match client.get_account(&beneficiary_ata).await {
+                Ok(_) => info!("sweeping $ORE to {}", beneficiary_ata),
+                Err(err) => {
+                    error!(ata = %beneficiary_ata, "fail to get beneficiary token account: {err:#}");
+                    return;
+                }
+            }
+        }
+
+        // The token transfers take room, fewer wallets fit in a transaction.
+        let wallets_per_tx = if args.include_ore { 4 } else { 8 };
+
+        let mut outcomes = HashMap::new();
+        let mut unclaimed = HashSet::new();
+        let mut pending = accounts.iter().collect::<Vec<_>>();
+        let blockhash_cache = BlockhashCache::spawn(client.clone(), &self.rpc);
+
+        for retry in 0..=COLLECT_RETRIES {
+            if pending.is_empty() {
+                break;
+            }
 
-            if balance - rent_exemption > 0 {
-                let instruction = system_instruction::transfer(
-                    &pubkey,
-                    &args.beneficiary,
-                    balance - rent_exemption,
+            if retry > 0 {
+                info!(
+                    retry,
+                    wallets = pending.len(),
+                    "retrying the wallets not swept with fresh balances"
                 );
-                instructions.push(instruction);
-                signers.push(keypair);
-                info!("Bundling transfer of {} from {} to {}", balance, pubkey, args.beneficiary)
             }
 
-            if instructions.len() >= 8 {
-                signers.push(&fee_payer_account);
-                
-                let recent_blockhash = client
-                .get_latest_blockhash()
+            let transfers = Self::scan_collect_wallets(
+                &client,
+                args,
+                fee_payer_account.pubkey(),
+                reserve,
+                &pending,
+                &mut outcomes,
+                &mut unclaimed,
+            )
+            .await;
+
+            if args.dry_run {
+                Self::print_collect_dry_run(&accounts, &transfers, &outcomes);
+                return;
+            }
+
+            let results = Self::send_collect_transfers(
+                &client,
+                &blockhash_cache,
+                &fee_payer_account,
+                &transfers,
+                wallets_per_tx,
+                self.current_priority_fee().filter(|_| !args.no_jito),
+                balance_fee_payer,
+            )
+            .await;
+
+            pending = record_collect_results(results, &mut outcomes);
+        }
+
+        Self::print_collect_report(&accounts, &outcomes);
+
+        let mut wallets_swept = 0;
+        let mut wallets_failed = 0;
+        let mut lamports_collected = 0;
+        let mut ore_collected = 0;
+
+        for outcome in outcomes.values() {
+            match outcome {
+                CollectOutcome::Swept { lamports, ore } => {
+                    wallets_swept += 1;
+                    lamports_collected += lamports;
+                    ore_collected += ore;
+                }
+                CollectOutcome::Failed(_) => wallets_failed += 1,
+                CollectOutcome::BelowReserve { .. } | CollectOutcome::FeePayer { .. } => {}
+            }
+        }
+
+        info!(
+            wallets.scanned = accounts.len(),
+            wallets.swept = wallets_swept,
+            wallets.failed = wallets_failed,
+            lamports.collected = lamports_collected,
+            ore.collected = utils::ore_ui_amount(ore_collected),
+            wallets.unclaimed = unclaimed.len(),
+            "collect summary"
+        );
+
+        // Only the wallets above the reserve are failed ones.
+        if wallets_failed > 0 {
+            std::process::exit(1);
+        }
+    }
+
+    /// Builds the transfers of the wallets above the reserve from fresh
+    /// balances, recording the wallets left alone.
+    async fn scan_collect_wallets<'a>(
+        client: &RpcClient,
+        args: &CollectArgs,
+        fee_payer: Pubkey,
+        reserve: u64,
+        wallets: &[&'a Keypair],
+        outcomes: &mut HashMap<Pubkey, CollectOutcome>,
+        unclaimed: &mut HashSet<Pubkey>,
+    ) -> Vec<CollectTransfer<'a>> {
+        let beneficiary_ata = utils::get_ore_ata(args.beneficiary);
+        let mut transfers = Vec::new();
+
+        for batch in wallets.chunks(constant::FETCH_ACCOUNT_LIMIT) {
+            let pubkeys = batch.iter().map(|keypair| keypair.pubkey()).collect::<Vec<_>>();
+            let balances = Self::get_balances(client, &pubkeys)
                 .await
-                .expect("Failed to get recent blockhash");
+                .expect("Failed to get balances");
 
-                let transaction = Transaction::new_signed_with_payer(
-                    &instructions,
-                    Some(&fee_payer_account.pubkey()),
-                    &signers,
-                    recent_blockhash,
-                );
-                
-                let message = Message::new(&instructions, Some(&fee_payer_account.pubkey()));
-                let estimate_transfer_fee = client.get_fee_for_message(&message).await.expect("Failed to get fee for message");
+            let (ore_accounts, proof_accounts) = if args.include_ore {
+                let atas = pubkeys
+                    .iter()
+                    .map(|pubkey| utils::get_ore_ata(*pubkey))
+                    .collect::<Vec<_>>();
+                let proofs = pubkeys
+                    .iter()
+                    .map(|pubkey| utils::get_proof_pda(*pubkey))
+                    .collect::<Vec<_>>();
 
-                if estimate_transfer_fee > balance_fee_payer {
-                    error!("Insufficient funds to pay for transaction fee");
-                    return;
+                (
+                    client
+                        .get_multiple_accounts(&atas)
+                        .await
+                        .expect("Failed to get token accounts"),
+                    client
+                        .get_multiple_accounts(&proofs)
+                        .await
+                        .expect("Failed to get proof accounts"),
+                )
+            } else {
+                (vec![None; batch.len()], vec![None; batch.len()])
+            };
+
+            for ((keypair, ore_account), proof_account) in batch.iter().zip(ore_accounts).zip(proof_accounts) {
+                let pubkey = keypair.pubkey();
+
+                // Draining the fee payer would fail the batches it pays for.
+                if pubkey == fee_payer {
+                    debug!(%pubkey, "skipping the fee payer");
+                    let balance = balances.get(&pubkey).copied().unwrap_or_default();
+                    outcomes.insert(pubkey, CollectOutcome::FeePayer { balance });
+                    continue;
+                }
+
+                if let Some(proof) = proof_account
+                    .as_ref()
+                    .and_then(|account| Proof::try_from_bytes(&account.data).ok())
+                {
+                    if proof.claimable_rewards > 0 && unclaimed.insert(pubkey) {
+                        warn!(%pubkey, rewards = utils::ore_ui_amount(proof.claimable_rewards), "wallet has unclaimed rewards, run claim first");
+                    }
+                }
+
+                let mut instructions = vec![];
+                let mut freed_rent = 0;
+                let mut ore_amount = 0;
+                let ata = utils::get_ore_ata(pubkey);
+
+                if let Some(ore_account) = ore_account.filter(|_| ata != beneficiary_ata) {
+                    match TokenAccount::unpack(&ore_account.data) {
+                        Ok(token_account) => {
+                            if token_account.amount > 0 {
+                                instructions.push(
+                                    spl_token::instruction::transfer_checked(
+                                        &spl_token::id(),
+                                        &ata,
+                                        &cluster::mint(),
+                                        &beneficiary_ata,
+                                        &pubkey,
+                                        &[],
+                                        token_account.amount,
+                                        ore::TOKEN_DECIMALS,
+                                    )
+                                    .expect("Failed to build token transfer"),
+                                );
+                            }
+
+                            // The rent goes back to the wallet and is swept right after.
+                            instructions.push(
+                                spl_token::instruction::close_account(&spl_token::id(), &ata, &pubkey, &pubkey, &[])
+                                    .expect("Failed to build close account"),
+                            );
+
+                            freed_rent = ore_account.lamports;
+                            ore_amount = token_account.amount;
+                            info!(
+                                "Bundling transfer of {} $ORE from {} to {}",
+                                utils::ore_ui_amount(token_account.amount),
+                                ata,
+                                beneficiary_ata
+                            )
+                        }
+                        Err(err) => error!(%pubkey, %ata, "fail to parse token account: {err:#}"),
+                    }
+                }
+
+                let balance = balances.get(&pubkey).copied().unwrap_or_default() + freed_rent;
+                let amount = balance.saturating_sub(reserve);
+
+                if amount > 0 {
+                    let instruction = system_instruction::transfer(&pubkey, &args.beneficiary, amount);
+                    instructions.push(instruction);
+                    info!(
+                        "Bundling transfer of {} from {} to {}",
+                        amount, pubkey, args.beneficiary
+                    )
+                } else {
+                    debug!(%pubkey, balance, reserve, "balance at or below the rent exemption, skipping");
+                }
+
+                if instructions.is_empty() {
+                    outcomes.insert(pubkey, CollectOutcome::BelowReserve { balance });
+                    continue;
+                }
+
+                transfers.push(CollectTransfer {
+                    keypair,
+                    instructions,
+                    balance,
+                    lamports: amount,
+                    ore: ore_amount,
+                });
+            }
+        }
+
+        transfers
+    }
+
+    fn print_collect_dry_run(
+        accounts: &[Keypair],
+        transfers: &[CollectTransfer],
+        outcomes: &HashMap<Pubkey, CollectOutcome>,
+    ) {
+        let transfers = transfers
+            .iter()
+            .map(|transfer| (transfer.keypair.pubkey(), transfer))
+            .collect::<HashMap<_, _>>();
+
+        println!(
+            "{:<44} {:>14} {:>14} {:>14} note",
+            "wallet", "balance", "transfer", "$ORE"
+        );
+
+        for pubkey in accounts.iter().map(|keypair| keypair.pubkey()) {
+            let (balance, lamports, ore, note) = match (transfers.get(&pubkey), outcomes.get(&pubkey)) {
+                (Some(transfer), _) => (transfer.balance, transfer.lamports, transfer.ore, ""),
+                (None, Some(CollectOutcome::FeePayer { balance })) => (*balance, 0, 0, "fee payer, skipped"),
+                (None, Some(CollectOutcome::BelowReserve { balance })) => (*balance, 0, 0, "below the reserve"),
+                _ => continue,
+            };
+
+            println!(
+                "{:<44} {:>14} {:>14} {:>14} {}",
+                pubkey.to_string(),
+                spl_token::amount_to_ui_amount(balance, 9),
+                spl_token::amount_to_ui_amount(lamports, 9),
+                utils::ore_ui_amount(ore),
+                note
+            );
+        }
+
+        println!(
+            "{:<44} {:>14} {:>14} {:>14}",
+            "total",
+            "",
+            spl_token::amount_to_ui_amount(transfers.values().map(|transfer| transfer.lamports).sum(), 9),
+            utils::ore_ui_amount(transfers.values().map(|transfer| transfer.ore).sum())
+        );
+    }
+
+    fn print_collect_report(accounts: &[Keypair], outcomes: &HashMap<Pubkey, CollectOutcome>) {
+        println!(
+            "{:<44} {:<14} {:>14} {:>14} error",
+            "wallet", "outcome", "transfer", "$ORE"
+        );
+
+        for pubkey in accounts.iter().map(|keypair| keypair.pubkey()) {
+            let (outcome, lamports, ore, err) = match outcomes.get(&pubkey) {
+                Some(CollectOutcome::Swept { lamports, ore }) => ("swept", *lamports, *ore, ""),
+                Some(CollectOutcome::BelowReserve { .. }) => ("skipped", 0, 0, ""),
+                Some(CollectOutcome::FeePayer { .. }) => ("fee payer", 0, 0, ""),
+                Some(CollectOutcome::Failed(err)) => ("failed", 0, 0, err.as_str()),
+                None => continue,
+            };
+
+            println!(
+                "{:<44} {:<14} {:>14} {:>14} {}",
+                pubkey.to_string(),
+                outcome,
+                spl_token::amount_to_ui_amount(lamports, 9),
+                utils::ore_ui_amount(ore),
+                err
+            );
+        }
+    }
+
+    /// Sends the transfers in bundles tipping `jito_tip`, or in plain
+    /// transactions without it. Returns the result of each transfer.
+    async fn send_collect_transfers<'t, 'a>(
+        client: &RpcClient,
+        blockhash_cache: &BlockhashCache,
+        fee_payer_account: &Keypair,
+        transfers: &'t [CollectTransfer<'a>],
+        wallets_per_tx: usize,
+        jito_tip: Option<u64>,
+        balance_fee_payer: u64,
+    ) -> Vec<(&'t CollectTransfer<'a>, Result<(), String>)> {
+        let mut results = vec![];
+
+        match jito_tip {
+            Some(jito_tip) => {
+                for bundle in collect_chunks(transfers, wallets_per_tx, true) {
+                    let result = Self::send_collect_bundle(
+                        client,
+                        blockhash_cache,
+                        fee_payer_account,
+                        bundle,
+                        wallets_per_tx,
+                        jito_tip,
+                    )
+                    .await;
+                    results.extend(bundle.iter().zip(result));
+                }
+            }
+            None => {
+                for batch in collect_chunks(transfers, wallets_per_tx, false) {
+                    let instructions = batch
+                        .iter()
+                        .flat_map(|transfer| transfer.instructions.clone())
+                        .collect::<Vec<_>>();
+                    let mut signers = batch.iter().map(|transfer| transfer.keypair).collect::<Vec<_>>();
+
+                    let result = Self::send_collect_batch(
+                        client,
+                        blockhash_cache,
+                        fee_payer_account,
+                        &instructions,
+                        &mut signers,
+                        balance_fee_payer,
+                    )
+                    .await;
+                    results.extend(batch.iter().map(|transfer| (transfer, result.clone())));
+                }
+            }
+        }
+
+        results
+    }
+
+    /// Sends the transfers of a batch along with the fee payer signature.
+    async fn send_collect_batch<'a>(
+        client: &RpcClient,
+        blockhash_cache: &BlockhashCache,
+        fee_payer_account: &'a Keypair,
+        instructions: &[Instruction],
+        signers: &mut Vec<&'a Keypair>,
+        balance_fee_payer: u64,
+    ) -> Result<(), String> {
+        signers.push(fee_payer_account);
+
+        let (_, recent_blockhash) = blockhash_cache
+            .get(blockhash::MAX_AGE)
+            .await
+            .map_err(|err| format!("Failed to get recent blockhash: {err:#}"))?;
+
+        let transaction = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&fee_payer_account.pubkey()),
+            signers.as_slice(),
+            recent_blockhash,
+        );
+
+        let message = Message::new(instructions, Some(&fee_payer_account.pubkey()));
+        let estimate_transfer_fee = client
+            .get_fee_for_message(&message)
+            .await
+            .expect("Failed to get fee for message");
+
+        if estimate_transfer_fee > balance_fee_payer {
+            error!("Insufficient funds to pay for transaction fee");
+            return Err("Insufficient funds to pay for transaction fee".to_string());
+        }
+
+        info!("Estimate transfer fee: {}", estimate_transfer_fee);
+
+        match client.send_and_confirm_transaction(&transaction).await {
+            Ok(signature) => {
+                info!("Bundled transfer succeeded. Signature: {}", signature);
+                Ok(())
+            }
+            Err(err) => {
+                error!("Bundled transfer failed: err {}", err);
+                Err(err.to_string())
+            }
+        }
+    }
+
+    /// Sends up to 5 transactions of `wallets_per_tx` wallets as a bundle, the
+    /// bribe paid by the fee payer, and sends what didn't land again with a
+    /// fresh blockhash until `COLLECT_BUNDLE_RETRIES` is reached. Over RPC the
+    /// transactions land one by one, each is settled on its own. Returns the
+    /// result of each transfer.
+    async fn send_collect_bundle(
+        client: &RpcClient,
+        blockhash_cache: &BlockhashCache,
+        fee_payer_account: &Keypair,
+        transfers: &[CollectTransfer<'_>],
+        wallets_per_tx: usize,
+        jito_tip: u64,
+    ) -> Vec<Result<(), String>> {
+        let mut results = vec![None; transfers.len()];
+        let mut last_error = String::new();
+
+        for attempt in 1..=COLLECT_BUNDLE_RETRIES {
+            let pending = (0..transfers.len())
+                .filter(|i| results[*i].is_none())
+                .collect::<Vec<_>>();
+
+            if pending.is_empty() {
+                break;
+            }
+
+            let (send_at_slot, blockhash) = match blockhash_cache.get(blockhash::MAX_AGE).await {
+                Ok(value) => value,
+                Err(err) => {
+                    error!("fail to get latest blockhash: {err:#}");
+                    last_error = format!("fail to get latest blockhash: {err:#}");
+                    tokio::time::sleep(Duration::from_secs(2)).await;
+                    continue;
                 }
+            };
 
-                info!("Estimate transfer fee: {}", estimate_transfer_fee);
+            let batches = collect_chunks(&pending, wallets_per_tx, false).collect::<Vec<_>>();
+            let bundle = batches
+                .iter()
+                .enumerate()
+                .map(|(i, batch)| {
+                    let mut instructions = batch
+                        .iter()
+                        .flat_map(|transfer| transfers[*transfer].instructions.clone())
+                        .collect::<Vec<_>>();
+                    let mut signers = batch
+                        .iter()
+                        .map(|transfer| transfers[*transfer].keypair)
+                        .collect::<Vec<_>>();
+                    signers.push(fee_payer_account);
 
-                match client.send_and_confirm_transaction(&transaction).await {
-                    Ok(signature) => {
-                        info!("Bundled transfer succeeded. Signature: {}", signature);
+                    if i == 0 {
+                        instructions.extend(jito::build_bribe_ix(&fee_payer_account.pubkey(), jito_tip));
                     }
+
+                    Transaction::new_signed_with_payer(
+                        &instructions,
+                        Some(&fee_payer_account.pubkey()),
+                        signers.as_slice(),
+                        blockhash,
+                    )
+                })
+                .collect::<Vec<_>>();
+            let signatures = bundle.iter().map(|tx| tx.signatures[0]).collect::<Vec<_>>();
+
+            let (tx, bundle_id) = match jito::send_bundle(bundle).await {
+                Ok(value) => value,
+                Err(err) => {
+                    error!(attempt, "fail to send bundle: {err:#}");
+                    last_error = format!("fail to send bundle: {err:#}");
+                    tokio::time::sleep(Duration::from_secs(2)).await;
+                    continue;
+                }
+            };
+
+            info!(first_tx = %tx, %bundle_id, wallets = pending.len(), attempt, slot = send_at_slot, "bundle sent");
+
+            let mut outcomes = signatures
+                .iter()
+                .map(|signature| (*signature, TxOutcome::Pending))
+                .collect::<Vec<_>>();
+            let mut latest_slot = send_at_slot;
+
+            while !outcomes.iter().all(|(_, outcome)| outcome.is_confirmed()) &&
+                latest_slot < send_at_slot + constant::SLOT_EXPIRATION
+            {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+
+                let (statuses, slot) = match Self::get_signature_statuses(client, &signatures).await {
+                    Ok(value) => value,
                     Err(err) => {
-                        error!("Bundled transfer failed: err {}", err);
+                        error!(send_at_slot, "fail to get bundle status: {err:#}");
+                        continue;
                     }
+                };
+
+                for ((_, outcome), (_, found)) in
+                    outcomes.iter_mut().zip(utils::find_tx_outcomes(&signatures, statuses))
+                {
+                    if !outcome.is_confirmed() {
+                        *outcome = found;
+                    }
+                }
+
+                latest_slot = slot;
+            }
+
+            let mut dropped = 0;
+
+            for (batch, (signature, outcome)) in batches.iter().zip(outcomes) {
+                let result = match outcome {
+                    TxOutcome::Landed { .. } => {
+                        info!(tx = %signature, wallets = batch.len(), "bundled transfers landed");
+                        Ok(())
+                    }
+                    // The balances moved under the transfers, sending them again
+                    // won't help.
+                    TxOutcome::Failed { err, .. } => {
+                        let err = program_error::describe(&err, None);
+
+                        metrics::TXS_FAILED.inc();
+                        error!(tx = %signature, wallets = batch.len(), "bundled transfers landed but failed: {err}");
+                        Err(format!("transaction failed: {err}"))
+                    }
+                    TxOutcome::Pending => {
+                        dropped += batch.len();
+                        continue;
+                    }
+                };
+
+                for transfer in batch.iter() {
+                    results[*transfer] = Some(result.clone());
                 }
+            }
+
+            if dropped > 0 {
+                error!(first_tx = %tx, wallets = dropped, attempt, "bundle dropped, retrying");
+                last_error = format!("bundle dropped {attempt} times");
+            }
+        }
+
+        results
+            .into_iter()
+            .map(|result| result.unwrap_or_else(|| Err(last_error.clone())))
+            .collect()
+    }
+}
+
+/// Records the outcome of each transfer and returns the wallets not swept,
+/// tried again with fresh balances.
+fn record_collect_results<'a>(
+    results: Vec<(&CollectTransfer<'a>, Result<(), String>)>,
+    outcomes: &mut HashMap<Pubkey, CollectOutcome>,
+) -> Vec<&'a Keypair> {
+    let mut pending = vec![];
 
-                instructions.clear();
-                signers.clear();
+    for (transfer, result) in results {
+        let outcome = match result {
+            Ok(()) => CollectOutcome::Swept {
+                lamports: transfer.lamports,
+                ore: transfer.ore,
+            },
+            Err(err) => {
+                pending.push(transfer.keypair);
+                CollectOutcome::Failed(err)
             }
+        };
+
+        outcomes.insert(transfer.keypair.pubkey(), outcome);
+    }
+
+    pending
+}
+
+/// The transfers of each transaction, or of each bundle of 5 transactions when
+/// `bundled`. The last one holds the remaining transfers, however few.
+fn collect_chunks<T>(transfers: &[T], wallets_per_tx: usize, bundled: bool) -> std::slice::Chunks<'_, T> {
+    match bundled {
+        true => transfers.chunks(wallets_per_tx * 5),
+        false => transfers.chunks(wallets_per_tx),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use serde_json::{json, Value};
+    use solana_client::{
+        client_error::Result as ClientResult,
+        rpc_client::RpcClientConfig,
+        rpc_request::RpcRequest,
+        rpc_sender::{RpcSender, RpcTransportStats},
+    };
+    use solana_sdk::{commitment_config::CommitmentConfig, hash::Hash, signature::Signature};
+    use solana_transaction_status::{EncodedTransaction, TransactionBinaryEncoding};
+
+    use super::*;
+
+    /// Answers the requests of a collect pass from fixed balances, every sent
+    /// transaction is confirmed right away.
+    struct MockCluster {
+        balances: HashMap<Pubkey, u64>,
+        sent: Arc<Mutex<Vec<Signature>>>,
+    }
+
+    #[async_trait]
+    impl RpcSender for MockCluster {
+        async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+            let context = json!({ "slot": 1 });
+
+            let result = match request {
+                RpcRequest::GetMultipleAccounts => {
+                    let accounts = params[0]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|pubkey| {
+                            let pubkey = pubkey.as_str().unwrap().parse::<Pubkey>().unwrap();
+
+                            self.balances.get(&pubkey).map(|lamports| {
+                                json!({
+                                    "lamports": lamports,
+                                    "data": ["", "base64"],
+                                    "owner": solana_sdk::system_program::ID.to_string(),
+                                    "executable": false,
+                                    "rentEpoch": 0,
+                                    "space": 0,
+                                })
+                            })
+                        })
+                        .collect::<Vec<_>>();
+
+                    json!({ "context": context, "value": accounts })
+                }
+                RpcRequest::GetLatestBlockhash => json!({
+                    "context": context,
+                    "value": { "blockhash": Hash::default().to_string(), "lastValidBlockHeight": 100 },
+                }),
+                RpcRequest::GetFeeForMessage => json!({ "context": context, "value": 5000 }),
+                RpcRequest::GetVersion => json!({ "solana-core": "1.18.9", "feature-set": 0 }),
+                RpcRequest::SendTransaction => {
+                    let tx = EncodedTransaction::Binary(
+                        params[0].as_str().unwrap().to_string(),
+                        TransactionBinaryEncoding::Base64,
+                    )
+                    .decode()
+                    .unwrap();
+
+                    self.sent.lock().unwrap().push(tx.signatures[0]);
+                    json!(tx.signatures[0].to_string())
+                }
+                RpcRequest::GetSignatureStatuses => {
+                    let statuses = params[0]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|_| {
+                            json!({
+                                "slot": 1,
+                                "confirmations": null,
+                                "err": null,
+                                "status": { "Ok": null },
+                                "confirmationStatus": "finalized",
+                            })
+                        })
+                        .collect::<Vec<_>>();
+
+                    json!({ "context": context, "value": statuses })
+                }
+                request => panic!("unexpected request {request}"),
+            };
+
+            Ok(result)
+        }
+
+        fn get_transport_stats(&self) -> RpcTransportStats {
+            RpcTransportStats::default()
+        }
+
+        fn url(&self) -> String {
+            "http://mock-cluster".to_string()
+        }
+    }
+
+    #[tokio::test]
+    async fn every_wallet_above_the_reserve_is_swept() {
+        let fee_payer = Keypair::new();
+        let beneficiary = Pubkey::new_unique();
+        let wallets = (0..11).map(|_| Keypair::new()).collect::<Vec<_>>();
+        let reserve = 1_000;
+
+        let mut balances = wallets
+            .iter()
+            .enumerate()
+            .map(|(i, wallet)| (wallet.pubkey(), reserve + 100 * (i as u64 + 1)))
+            .collect::<HashMap<_, _>>();
+        balances.insert(fee_payer.pubkey(), 1_000_000);
+
+        let sent = Arc::new(Mutex::new(vec![]));
+        let cluster = MockCluster {
+            balances: balances.clone(),
+            sent: sent.clone(),
+        };
+        let url = cluster.url();
+        let client = Arc::new(RpcClient::new_sender(
+            cluster,
+            RpcClientConfig::with_commitment(CommitmentConfig::confirmed()),
+        ));
+        let blockhash_cache = BlockhashCache::spawn(client.clone(), &url);
+
+        let beneficiary_arg = beneficiary.to_string();
+        let args = CollectArgs::parse_from([
+            "collect",
+            "--key-folder",
+            "keys",
+            "--beneficiary",
+            beneficiary_arg.as_str(),
+            "--no-jito",
+        ]);
+
+        let pending = wallets.iter().collect::<Vec<_>>();
+        let mut outcomes = HashMap::new();
+        let mut unclaimed = HashSet::new();
+
+        let transfers = Miner::scan_collect_wallets(
+            &client,
+            &args,
+            fee_payer.pubkey(),
+            reserve,
+            &pending,
+            &mut outcomes,
+            &mut unclaimed,
+        )
+        .await;
+
+        let results =
+            Miner::send_collect_transfers(&client, &blockhash_cache, &fee_payer, &transfers, 8, None, 1_000_000).await;
+        let pending = record_collect_results(results, &mut outcomes);
+
+        assert!(pending.is_empty());
+        assert_eq!(sent.lock().unwrap().len(), 2);
+
+        for wallet in &wallets {
+            let expected = balances[&wallet.pubkey()] - reserve;
+
+            assert!(
+                matches!(outcomes.get(&wallet.pubkey()), Some(CollectOutcome::Swept { lamports, ore: 0 }) if *lamports == expected),
+                "{} not swept",
+                wallet.pubkey()
+            );
         }
-        
-     
     }
 
-}
\ No newline at end of file
+    fn chunk_sizes(wallets: usize, wallets_per_tx: usize, bundled: bool) -> Vec<usize> {
+        let transfers = (0..wallets).collect::<Vec<_>>();
+
+        collect_chunks(&transfers, wallets_per_tx, bundled)
+            .map(<[usize]>::len)
+            .collect()
+    }
+
+    #[test]
+    fn transactions_send_the_last_partial_batch() {
+        assert_eq!(chunk_sizes(7, 4, false), vec![4, 3]);
+        assert_eq!(chunk_sizes(9, 4, false), vec![4, 4, 1]);
+        assert_eq!(chunk_sizes(9, 8, false), vec![8, 1]);
+    }
+
+    #[test]
+    fn bundles_send_the_last_partial_batch() {
+        assert_eq!(chunk_sizes(7, 4, true), vec![7]);
+        assert_eq!(chunk_sizes(9, 8, true), vec![9]);
+        assert_eq!(chunk_sizes(45, 4, true), vec![20, 20, 5]);
+    }
+
+    #[test]
+    fn every_wallet_is_sent_once_in_order() {
+        let transfers = (0..9).collect::<Vec<_>>();
+
+        for bundled in [false, true] {
+            let sent = collect_chunks(&transfers, 4, bundled)
+                .flat_map(|chunk| collect_chunks(chunk, 4, false))
+                .flatten()
+                .copied()
+                .collect::<Vec<_>>();
+
+            assert_eq!(sent, transfers);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::OnceCell;
+use solana_client::{nonblocking::rpc_client::RpcClient, rpc_config::RpcSimulateTransactionConfig};
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    compute_budget::ComputeBudgetInstruction,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    transaction::Transaction,
+};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::{cluster, program_error, system_accounts::SystemAccountsCache, utils, worker::WorkerSpec, Miner};
+
+/// Compute units the runtime gives an instruction when the transaction
+/// requests no limit.
+const DEFAULT_INSTRUCTION_UNITS: u32 = 200_000;
+
+/// Most compute units a transaction may request, the simulations run with it
+/// so that they never fail for the budget.
+const MAX_UNITS: u32 = 1_400_000;
+
+/// How long after a failed simulation the next send goes without one.
+const MEASURE_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long the startup measurement mines its nonce before leaving the
+/// measurement to the first send.
+const MEASURE_MINING_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Set once from `--compute-unit-margin` and `--compute-unit-price`.
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+/// Compute units of a mine instruction as simulated, 0 until measured.
+static MEASURED: AtomicU32 = AtomicU32::new(0);
+
+/// When the last simulation failed, also serializes the simulations.
+static LAST_FAILURE: Mutex<Option<Instant>> = Mutex::const_new(None);
+
+struct Config {
+    margin_percent: u32,
+    price: Option<u64>,
+}
+
+pub fn init(margin_percent: u32, price: Option<u64>) {
+    let _ = CONFIG.set(Config { margin_percent, price });
+}
+
+/// Compute unit limit of a transaction of `mine_ixs` mine instructions, the
+/// simulated units plus `--compute-unit-margin`. None until measured, the
+/// transactions then request no limit rather than a guessed one.
+pub fn mine_limit(mine_ixs: usize) -> Option<u32> {
+    let measured = MEASURED.load(Ordering::Relaxed);
+
+    if measured == 0 {
+        return None;
+    }
+
+    let margin = CONFIG.get().map_or(0, |config| config.margin_percent);
+    let units = measured as u64 * mine_ixs as u64 * (100 + margin as u64) / 100;
+
+    Some(units.min(MAX_UNITS as u64) as u32)
+}
+
+/// Units a transaction of `mine_ixs` mine instructions is charged the price
+/// of, the runtime default without a measured limit.
+pub fn charged_units(mine_ixs: usize) -> u32 {
+    mine_limit(mine_ixs).unwrap_or_else(|| DEFAULT_INSTRUCTION_UNITS.saturating_mul(mine_ixs as u32).min(MAX_UNITS))
+}
+
+/// Put in front of the mine instructions of a bundle transaction, the limit
+/// once measured and the price only with `--compute-unit-price` since the
+/// jito tip pays for the landing.
+pub fn bundle_instructions(mine_ixs: usize) -> Vec<Instruction> {
+    let mut ixs = vec![];
+
+    if let Some(limit) = mine_limit(mine_ixs) {
+        ixs.push(ComputeBudgetInstruction::set_compute_unit_limit(limit));
+    }
+
+    if let Some(price) = CONFIG.get().and_then(|config| config.price) {
+        ixs.push(ComputeBudgetInstruction::set_compute_unit_price(price));
+    }
+
+    ixs
+}
+
+/// Lamports `--compute-unit-price` adds to the fee of a bundle transaction of
+/// `mine_ixs` mine instructions.
+pub fn priority_fee(mine_ixs: usize) -> u64 {
+    let price = CONFIG.get().and_then(|config| config.price).unwrap_or(0);
+
+    (price * charged_units(mine_ixs) as u64).div_ceil(1_000_000)
+}
+
+/// Simulates `ixs`, holding `mine_ixs` mine instructions, to measure the
+/// units of a mine instruction, awaited before a send so that the limit is
+/// measured from the first transaction on. Does nothing once measured, a
+/// failed simulation is tried again after `MEASURE_RETRY_INTERVAL`.
+pub async fn measure(client: &RpcClient, payer: Pubkey, ixs: &[Instruction], mine_ixs: usize) {
+    if mine_ixs == 0 || MEASURED.load(Ordering::Relaxed) != 0 {
+        return;
+    }
+
+    // Concurrent senders wait for the one simulation instead of each running one.
+    let mut last_failure = LAST_FAILURE.lock().await;
+
+    if MEASURED.load(Ordering::Relaxed) != 0 ||
+        last_failure.is_some_and(|failed_at| failed_at.elapsed() < MEASURE_RETRY_INTERVAL)
+    {
+        return;
+    }
+
+    let mut all_ixs = vec![ComputeBudgetInstruction::set_compute_unit_limit(MAX_UNITS)];
+    all_ixs.extend(
+        ixs.iter()
+            .filter(|ix| ix.program_id != solana_sdk::compute_budget::ID)
+            .cloned(),
+    );
+
+    // Unsigned, the simulation doesn't verify the signatures.
+    let tx = Transaction::new_with_payer(&all_ixs, Some(&payer));
+
+    match simulate_units(client, &tx).await {
+        Ok(units) => {
+            let per_ix = units.div_ceil(mine_ixs as u64) as u32;
+            MEASURED.store(per_ix, Ordering::Relaxed);
+
+            info!(
+                units.total = units,
+                units.per_ix = per_ix,
+                mine_ixs,
+                limit.one = charged_units(1),
+                limit.five = charged_units(5),
+                "mine compute units simulated"
+            );
+        }
+        Err(err) => {
+            *last_failure = Some(Instant::now());
+            warn!("fail to simulate the mine compute units, requesting no limit: {err:#}");
+        }
+    }
+}
+
+impl Miner {
+    /// Mines a nonce for `signer` on the CPU and simulates its mine
+    /// instruction, so that the compute units are measured and logged at
+    /// startup. Gives up after `MEASURE_MINING_TIMEOUT`, the rounds measure
+    /// them before sending if it fails.
+    pub async fn measure_mine_units(
+        &self,
+        client: &RpcClient,
+        system_accounts: &SystemAccountsCache,
+        signer: Pubkey,
+        threads: usize,
+    ) {
+        let mined = async {
+            let (treasury, _, _) = system_accounts.get().await?;
+            let proof = Self::get_proof_accounts(client, &[utils::get_proof_pda_no_cache(signer)]).await?[0];
+            let difficulty: solana_sdk::keccak::Hash = treasury.difficulty.into();
+            let (_, mined) = self
+                .try_mine_hashes(
+                    WorkerSpec::cpu(),
+                    threads,
+                    MEASURE_MINING_TIMEOUT,
+                    &difficulty,
+                    &[(proof.hash.into(), signer)],
+                    None,
+                )
+                .await?;
+
+            mined[0].ok_or_else(|| eyre::eyre!("no nonce found in {}s", MEASURE_MINING_TIMEOUT.as_secs()))
+        }
+        .await;
+
+        match mined {
+            Ok((hash, nonce)) => {
+                let ix = cluster::instruction(ore::instruction::mine(
+                    signer,
+                    cluster::bus_address(0),
+                    hash.into(),
+                    nonce,
+                ));
+
+                measure(client, signer, &[ix], 1).await;
+            }
+            Err(err) => warn!("fail to mine a nonce to measure the mine compute units: {err:#}"),
+        }
+    }
+}
+
+async fn simulate_units(client: &RpcClient, tx: &Transaction) -> eyre::Result<u64> {
+    let result = client
+        .simulate_transaction_with_config(
+            tx,
+            RpcSimulateTransactionConfig {
+                sig_verify: false,
+                replace_recent_blockhash: true,
+                commitment: Some(utils::commitment_or(CommitmentConfig::processed())),
+                ..RpcSimulateTransactionConfig::default()
+            },
+        )
+        .await?
+        .value;
+
+    if let Some(err) = result.err {
+        eyre::bail!("simulation failed: {}", program_error::describe(&err, Some(tx)));
+    }
+
+    result
+        .units_consumed
+        .ok_or_else(|| eyre::eyre!("the RPC didn't return the consumed units"))
+}
@@ -0,0 +1,357 @@
+use std::{ffi::OsString, fs, path::Path};
+
+use clap::{ArgMatches, Command, CommandFactory, Parser, Subcommand};
+use eyre::{bail, Context};
+use toml::{Table, Value};
+use tracing::error;
+
+use crate::Miner;
+
+/// Read from the working directory when `--config` isn't given.
+pub const DEFAULT_CONFIG: &str = "ore-miner.toml";
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ConfigCommand {
+    /// Print every global option in effect and the options of the subcommands
+    /// with a table in the config file
+    Show,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    pub command: ConfigCommand,
+}
+
+/// The command line arguments with the values of the config file added in
+/// front of the ones they default. Global options are keys of the top level,
+/// the defaults of a subcommand are keys of its table, e.g. `[claim]`. The
+/// command line wins over the file.
+pub fn args_with_config() -> eyre::Result<Vec<OsString>> {
+    merge_config(std::env::args_os().collect())
+}
+
+/// `args` with the values of the config file added, see `args_with_config`.
+fn merge_config(mut args: Vec<OsString>) -> eyre::Result<Vec<OsString>> {
+    let path = match config_path(&args) {
+        Some(path) => path,
+        None => return Ok(args),
+    };
+
+    let content = fs::read_to_string(&path).with_context(|| format!("fail to read config file {path}"))?;
+    let table = content
+        .parse::<Table>()
+        .map_err(|err| eyre::eyre!("invalid config file {path}: {err}"))?;
+
+    let command = Miner::command();
+    let subcommand = args
+        .iter()
+        .enumerate()
+        .skip(1)
+        .find(|(_, arg)| {
+            command
+                .get_subcommands()
+                .any(|sub| arg.to_str() == Some(sub.get_name()))
+        })
+        .map(|(i, arg)| (i, arg.to_string_lossy().to_string()));
+
+    let subcommand_end = match &subcommand {
+        Some((i, _)) => *i,
+        None => args.len(),
+    };
+
+    let mut global = vec![];
+    let mut local = vec![];
+
+    for (key, value) in &table {
+        match value {
+            Value::Table(section) => {
+                let sub = command
+                    .get_subcommands()
+                    .find(|sub| sub.get_name() == key.replace('_', "-"))
+                    .ok_or_else(|| unknown_key(&content, &path, None, key))?;
+
+                let applies = subcommand.as_ref().is_some_and(|(_, name)| name == sub.get_name());
+
+                for (key, value) in section {
+                    let present = applies && is_present(&args[subcommand_end..], key);
+                    let values = option_args(sub, &content, &path, Some(sub.get_name()), key, value)?;
+
+                    if applies && !present {
+                        local.extend(values);
+                    }
+                }
+            }
+            value => {
+                let values = option_args(&command, &content, &path, None, key, value)?;
+
+                if !is_present(&args[..subcommand_end], key) {
+                    global.extend(values);
+                }
+            }
+        }
+    }
+
+    if let Some((i, _)) = subcommand {
+        args.splice(i + 1..i + 1, local);
+    }
+
+    args.splice(1..1, global);
+
+    Ok(args)
+}
+
+/// `--config` of the command line, the default file when it exists.
+fn config_path(args: &[OsString]) -> Option<String> {
+    for (i, arg) in args.iter().enumerate() {
+        let arg = arg.to_string_lossy();
+
+        if arg == "--config" {
+            return args.get(i + 1).map(|path| path.to_string_lossy().to_string());
+        }
+
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(path.to_string());
+        }
+    }
+
+    Path::new(DEFAULT_CONFIG).exists().then(|| DEFAULT_CONFIG.to_string())
+}
+
+fn is_present(args: &[OsString], key: &str) -> bool {
+    let flag = format!("--{}", key.replace('_', "-"));
+
+    args.iter().any(|arg| {
+        let arg = arg.to_string_lossy();
+        arg == flag || arg.starts_with(&format!("{flag}="))
+    })
+}
+
+/// The command line form of `key = value`, a boolean is a flag given only
+/// when true and an array a comma-separated list.
+fn option_args(
+    command: &Command,
+    content: &str,
+    path: &str,
+    section: Option<&str>,
+    key: &str,
+    value: &Value,
+) -> eyre::Result<Vec<OsString>> {
+    let long = key.replace('_', "-");
+
+    let arg = command
+        .get_arguments()
+        .find(|arg| arg.get_long() == Some(long.as_str()) && long != "config")
+        .ok_or_else(|| unknown_key(content, path, section, key))?;
+
+    let flag = OsString::from(format!("--{long}"));
+
+    if !arg.get_action().takes_values() {
+        return match value {
+            Value::Boolean(true) => Ok(vec![flag]),
+            Value::Boolean(false) => Ok(vec![]),
+            _ => bail!(
+                "`{key}` must be true or false at line {}",
+                key_line(content, section, key)
+            ),
+        };
+    }
+
+    let value = match value {
+        Value::String(value) => value.clone(),
+        Value::Integer(value) => value.to_string(),
+        Value::Float(value) => value.to_string(),
+        Value::Boolean(value) => value.to_string(),
+        Value::Array(values) => values
+            .iter()
+            .map(|value| match value {
+                Value::String(value) => Ok(value.clone()),
+                Value::Integer(value) => Ok(value.to_string()),
+                Value::Float(value) => Ok(value.to_string()),
+                _ => bail!(
+                    "`{key}` has an unsupported value at line {}",
+                    key_line(content, section, key)
+                ),
+            })
+            .collect::<eyre::Result<Vec<_>>>()?
+            .join(","),
+        _ => bail!(
+            "`{key}` has an unsupported value at line {}",
+            key_line(content, section, key)
+        ),
+    };
+
+    Ok(vec![flag, OsString::from(value)])
+}
+
+fn unknown_key(content: &str, path: &str, section: Option<&str>, key: &str) -> eyre::Report {
+    let line = key_line(content, section, key);
+
+    match section {
+        Some(section) => eyre::eyre!("unknown key `{key}` in [{section}] at line {line} of {path}"),
+        None => eyre::eyre!("unknown key `{key}` at line {line} of {path}"),
+    }
+}
+
+/// 1-based line of `key` in `section`, or of the `[key]` table header.
+fn key_line(content: &str, section: Option<&str>, key: &str) -> usize {
+    let mut current = None;
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+
+        if let Some(header) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            let header = header.trim().to_string();
+
+            if section.is_none() && header == key {
+                return i + 1;
+            }
+
+            current = Some(header);
+            continue;
+        }
+
+        let in_section = match section {
+            Some(section) => current.as_deref().map(|current| current.replace('_', "-")) == Some(section.to_string()),
+            None => current.is_none(),
+        };
+
+        let name = line.split('=').next().unwrap_or_default().trim().trim_matches('"');
+
+        if in_section && name == key {
+            return i + 1;
+        }
+    }
+
+    0
+}
+
+impl Miner {
+    pub fn config(&self, args: &ConfigArgs) {
+        match args.command {
+            ConfigCommand::Show => {
+                if let Err(err) = self.show_config() {
+                    error!("{err:#}");
+                }
+            }
+        }
+    }
+
+    /// Every global option as parsed, then every subcommand with a table in
+    /// the config file as it would run, the file merged with its defaults.
+    fn show_config(&self) -> eyre::Result<()> {
+        let args = std::env::args_os().collect::<Vec<_>>();
+        let command = Miner::command();
+
+        // The global options of the command line, up to `config`.
+        let config_at = args
+            .iter()
+            .skip(1)
+            .position(|arg| arg.to_str() == Some("config"))
+            .map_or(args.len(), |i| i + 1);
+        let global_args = &args[..config_at];
+
+        println!("config = {:?}", self.config.as_deref().unwrap_or(DEFAULT_CONFIG));
+
+        let matches = command
+            .clone()
+            .ignore_errors(true)
+            .get_matches_from(merge_config(args.clone())?);
+
+        for (key, value) in option_values(&command, &matches) {
+            match (key.as_str(), value) {
+                // Replaced by the devnet one with `--cluster devnet`.
+                ("rpc", _) => println!("rpc = {:?}", self.rpc),
+                (key, Some(value)) => println!("{key} = {value}"),
+                (key, None) => println!("# {key} ="),
+            }
+        }
+
+        let path = self.config.clone().unwrap_or_else(|| DEFAULT_CONFIG.to_string());
+        let table = match fs::read_to_string(&path) {
+            Ok(content) => content
+                .parse::<Table>()
+                .map_err(|err| eyre::eyre!("invalid config file {path}: {err}"))?,
+            Err(_) => return Ok(()),
+        };
+
+        for (key, value) in &table {
+            if !value.is_table() {
+                continue;
+            }
+
+            let name = key.replace('_', "-");
+            let subcommand = command
+                .find_subcommand(&name)
+                .ok_or_else(|| eyre::eyre!("unknown subcommand table [{key}] in {path}"))?;
+
+            let mut sub_args = global_args.to_vec();
+            sub_args.push(OsString::from(&name));
+
+            let matches = command
+                .clone()
+                .ignore_errors(true)
+                .get_matches_from(merge_config(sub_args)?);
+            let sub_matches = matches
+                .subcommand_matches(&name)
+                .ok_or_else(|| eyre::eyre!("fail to parse the [{key}] table of {path}"))?;
+
+            println!("\n[{name}]");
+
+            for (key, value) in option_values(subcommand, sub_matches) {
+                match value {
+                    Some(value) => println!("{key} = {value}"),
+                    None => println!("# {key} ="),
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The config file key and the TOML value of every option of `command`,
+/// `None` for the ones neither given nor defaulted.
+fn option_values(command: &Command, matches: &ArgMatches) -> Vec<(String, Option<Value>)> {
+    command
+        .get_arguments()
+        .filter_map(|arg| {
+            let long = arg.get_long()?;
+
+            if matches!(long, "config" | "help" | "version") {
+                return None;
+            }
+
+            let values = matches.get_raw(arg.get_id().as_str()).map(|values| {
+                values
+                    .map(|value| toml_value(&value.to_string_lossy()))
+                    .collect::<Vec<_>>()
+            });
+
+            let value = values.map(|mut values| match values.len() {
+                1 => values.remove(0),
+                _ => Value::Array(values),
+            });
+
+            Some((long.replace('-', "_"), value))
+        })
+        .collect()
+}
+
+/// The value as the config file would have it, a string unless it parses as
+/// an integer, a boolean or a float with a decimal point.
+fn toml_value(value: &str) -> Value {
+    if let Ok(value) = value.parse::<i64>() {
+        return Value::Integer(value);
+    }
+
+    if let Ok(value) = value.parse::<bool>() {
+        return Value::Boolean(value);
+    }
+
+    // Digits only may be too large for an integer, e.g. the system program.
+    match value.contains('.').then(|| value.parse::<f64>()) {
+        Some(Ok(float)) if float.is_finite() => Value::Float(float),
+        _ => Value::String(value.to_string()),
+    }
+}
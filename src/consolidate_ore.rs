@@ -0,0 +1,284 @@
+use std::time::Duration;
+
+use clap::Parser;
+use solana_client::{nonblocking::rpc_client::RpcClient, rpc_config::RpcSendTransactionConfig};
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    signer::Signer,
+    transaction::Transaction,
+};
+use solana_transaction_status::UiTransactionEncoding;
+use spl_token::{solana_program::program_pack::Pack, state::Account as TokenAccount};
+use tracing::{error, info};
+
+use crate::{
+    blockhash::{self, BlockhashCache},
+    cluster,
+    constant,
+    format_reward,
+    utils,
+    Miner,
+};
+
+/// Wallets swept by a single transaction, each of them signs it.
+const CONSOLIDATE_BATCH_SIZE: usize = 5;
+
+#[derive(Parser, Debug, Clone)]
+pub struct ConsolidateOreArgs {
+    #[arg(
+        long,
+        help = "The folder that contains the keys of the wallets to sweep the $ORE from"
+    )]
+    pub key_folder: String,
+
+    #[arg(long, help = "The wallet receiving the $ORE in its token account")]
+    pub beneficiary: Pubkey,
+
+    #[arg(
+        long,
+        help = "Keypair paying the transaction fees, the first key of the key folder pays them otherwise"
+    )]
+    pub fee_payer: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Close the emptied token accounts, their rent goes back to each wallet"
+    )]
+    pub close: bool,
+}
+
+impl Miner {
+    pub async fn consolidate_ore(&self, args: &ConsolidateOreArgs) {
+        let client = Self::get_client_confirmed(&self.rpc);
+        let accounts = match Self::read_keys(&args.key_folder, self.recursive) {
+            Ok(accounts) => accounts,
+            Err(err) => {
+                error!("{err:#}");
+                return;
+            }
+        };
+
+        let fee_payer = match &args.fee_payer {
+            Some(path) => match utils::read_keypair(path) {
+                Ok(fee_payer) => fee_payer,
+                Err(err) => {
+                    error!("fail to read the fee payer keypair: {err:#}");
+                    return;
+                }
+            },
+            None => match accounts.first() {
+                Some(key) => key.insecure_clone(),
+                None => {
+                    info!("no wallets found");
+                    return;
+                }
+            },
+        };
+
+        let beneficiary_ata = utils::get_ore_ata(args.beneficiary);
+        info!(ata = %beneficiary_ata, recipient = %args.beneficiary, fee_payer = %fee_payer.pubkey());
+
+        match client
+            .get_account_with_commitment(&beneficiary_ata, utils::commitment_or(CommitmentConfig::confirmed()))
+            .await
+        {
+            Ok(response) if response.value.is_some() => {}
+            Ok(_) => {
+                error!(ata = %beneficiary_ata, "beneficiary token account does not exist");
+                return;
+            }
+            Err(err) => {
+                error!("fail to get beneficiary token account: {err:#}");
+                return;
+            }
+        }
+
+        // (wallet, token account, amount) of each token account to sweep.
+        let mut to_sweep = vec![];
+
+        for batch in accounts.chunks(constant::FETCH_ACCOUNT_LIMIT) {
+            let atas = batch
+                .iter()
+                .map(|key| utils::get_ore_ata(key.pubkey()))
+                .collect::<Vec<_>>();
+            let token_accounts = client
+                .get_multiple_accounts(&atas)
+                .await
+                .expect("Failed to get token accounts");
+
+            for ((key, ata), account) in batch.iter().zip(atas).zip(token_accounts) {
+                if ata == beneficiary_ata {
+                    continue;
+                }
+
+                let amount = match account.map(|account| TokenAccount::unpack(&account.data)) {
+                    None => continue,
+                    Some(Ok(token_account)) => token_account.amount,
+                    Some(Err(err)) => {
+                        error!(wallet = %key.pubkey(), %ata, "fail to parse token account: {err:#}");
+                        continue;
+                    }
+                };
+
+                if amount > 0 || args.close {
+                    to_sweep.push((key, ata, amount));
+                }
+            }
+        }
+
+        if to_sweep.is_empty() {
+            info!("no token account to sweep");
+            return;
+        }
+
+        let total_amount = to_sweep.iter().map(|(_, _, amount)| amount).sum::<u64>();
+        info!(
+            accounts = to_sweep.len(),
+            amount = format_reward!(total_amount),
+            "sweeping token accounts"
+        );
+
+        let batches = to_sweep
+            .chunks(CONSOLIDATE_BATCH_SIZE)
+            .map(|batch| {
+                let mut ixs = vec![];
+                let mut signers = vec![&fee_payer];
+
+                for (key, ata, amount) in batch {
+                    let owner = key.pubkey();
+
+                    if *amount > 0 {
+                        ixs.push(
+                            spl_token::instruction::transfer_checked(
+                                &spl_token::id(),
+                                ata,
+                                &cluster::mint(),
+                                &beneficiary_ata,
+                                &owner,
+                                &[],
+                                *amount,
+                                ore::TOKEN_DECIMALS,
+                            )
+                            .expect("fail to build transfer instruction"),
+                        );
+                    }
+
+                    if args.close {
+                        ixs.push(
+                            spl_token::instruction::close_account(&spl_token::id(), ata, &owner, &owner, &[])
+                                .expect("fail to build close instruction"),
+                        );
+                    }
+
+                    if owner != fee_payer.pubkey() {
+                        signers.push(*key);
+                    }
+                }
+
+                let amount = batch.iter().map(|(_, _, amount)| amount).sum::<u64>();
+                (ixs, signers, amount)
+            })
+            .collect::<Vec<_>>();
+
+        let blockhash_cache = BlockhashCache::spawn(client.clone(), &self.rpc);
+
+        Self::sweep_and_confirm(&client, &blockhash_cache, &fee_payer, batches).await;
+    }
+
+    /// Sends the sweep transactions and waits until all of them are confirmed,
+    /// resending the expired ones. A transaction failing on chain is not sent
+    /// again, the balances changed under it.
+    async fn sweep_and_confirm(
+        client: &RpcClient,
+        blockhash_cache: &BlockhashCache,
+        fee_payer: &Keypair,
+        batches: Vec<(Vec<Instruction>, Vec<&Keypair>, u64)>,
+    ) {
+        let mut batch_and_txs = batches
+            .into_iter()
+            .map(|batch| (batch, Signature::default()))
+            .collect::<Vec<_>>();
+
+        while !batch_and_txs.is_empty() {
+            let (slot, blockhash) = match blockhash_cache.get(blockhash::MAX_AGE).await {
+                Ok(r) => r,
+                Err(err) => {
+                    error!("failed to get latest blockhash: {:#}", err);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    continue;
+                }
+            };
+
+            for ((ixs, signers, amount), sig) in batch_and_txs.iter_mut() {
+                let tx =
+                    Transaction::new_signed_with_payer(ixs, Some(&fee_payer.pubkey()), signers.as_slice(), blockhash);
+
+                let calculated_sig = tx.signatures.first().unwrap();
+                *sig = *calculated_sig;
+
+                let send_cfg = RpcSendTransactionConfig {
+                    skip_preflight: false,
+                    preflight_commitment: Some(utils::commitment_or(CommitmentConfig::confirmed()).commitment),
+                    encoding: Some(UiTransactionEncoding::Base58),
+                    max_retries: Some(5),
+                    min_context_slot: Some(slot),
+                };
+
+                match client.send_transaction_with_config(&tx, send_cfg).await {
+                    Ok(sig) => info!("transaction sent: {sig}, amount: {}", utils::ore_ui_amount(*amount)),
+                    Err(err) => error!(tx = %calculated_sig, "failed to send tx: {err:#}"),
+                }
+            }
+
+            let mut latest_slot = slot;
+            let mut signatures = batch_and_txs.iter().map(|(_, sig)| *sig).collect::<Vec<_>>();
+
+            while !signatures.is_empty() && latest_slot <= slot + constant::SLOT_EXPIRATION {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                info!(
+                    remaining_tx = signatures.len(),
+                    "waiting for all transactions to be confirmed"
+                );
+
+                let response = match client.get_signature_statuses(&signatures).await {
+                    Ok(r) => r,
+                    Err(err) => {
+                        error!("failed to get signature statuses: {:#}", err);
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                        continue;
+                    }
+                };
+
+                latest_slot = response.context.slot;
+                let statuses = response.value;
+
+                let mut sig_to_purge_in_query = vec![];
+
+                for (status, sig) in statuses.iter().zip(signatures.iter()) {
+                    let status = match status {
+                        None => continue,
+                        Some(s) => s,
+                    };
+
+                    if !status.satisfies_commitment(utils::commitment_or(CommitmentConfig::confirmed())) {
+                        continue;
+                    }
+
+                    sig_to_purge_in_query.push(*sig);
+                    batch_and_txs.retain(|(_, s)| !s.eq(sig));
+
+                    match &status.err {
+                        None => info!(tx = %sig, "transaction confirmed: {sig}"),
+                        Some(err) => error!(tx = %sig, "transaction failed: {err:#}"),
+                    }
+                }
+
+                signatures.retain(|s| !sig_to_purge_in_query.contains(s));
+            }
+        }
+    }
+}
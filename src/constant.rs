@@ -1,13 +1,26 @@
+use std::time::Duration;
+
 use rand::Rng;
 use solana_sdk::{pubkey, pubkey::Pubkey};
 
 pub const FEE_PER_SIGNER: u64 = 5000;
 
 pub const SLOT_EXPIRATION: u64 = 151 + 5;
+/// Roughly the time `SLOT_EXPIRATION` slots take, after which every bundle
+/// watcher has given up.
+pub const WATCH_DRAIN_TIMEOUT: Duration = Duration::from_secs(75);
 
 pub const FETCH_ACCOUNT_LIMIT: usize = 100;
+/// Most signatures a single getSignatureStatuses request accepts.
+pub const SIGNATURE_STATUS_LIMIT: usize = 256;
 pub const TRANSFER_BATCH_SIZE: usize = 21;
 
+pub const GPU_MAX_FAILURES: usize = 3;
+pub const WORKER_DEADLINE_GRACE: Duration = Duration::from_secs(2);
+/// How long past the expected end of the epoch held bundles wait for the reset
+/// before being given up.
+pub const RESET_WAIT_GRACE: Duration = Duration::from_secs(30);
+
 pub const JITO_RECIPIENTS: [Pubkey; 8] = [
     pubkey!("96gYZGLnJYVFmbjzopPSU6QiEV5fGqZNyN9nmNhvrZU5"),
     pubkey!("HFqU5x63VTqvQss8hp11i4wVV8bD44PvwucfZ2bU7gRe"),
@@ -0,0 +1,204 @@
+use std::{
+    io::{self, Stdout},
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crossterm::{
+    cursor,
+    execute,
+    terminal::{EnterAlternateScreen, LeaveAlternateScreen},
+};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Layout},
+    style::{Modifier, Style},
+    widgets::{Block, Borders, Paragraph, Row, Table},
+    Frame,
+    Terminal,
+};
+use tokio::{sync::RwLock, task::JoinHandle};
+
+use crate::{
+    format_duration,
+    jito::JitoTips,
+    stats::{self, BatchOutcome, BatchState, BatchStats, MiningStats, RoundInfo},
+    utils,
+};
+
+const REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+
+type DashboardTerminal = Terminal<CrosstermBackend<Stdout>>;
+
+/// Redraws the mining stats until dropped. The logs are muted meanwhile, they
+/// would scroll the dashboard away.
+pub struct Dashboard {
+    terminal: Arc<Mutex<Option<DashboardTerminal>>>,
+    task: JoinHandle<()>,
+    log_level: log::LevelFilter,
+}
+
+pub fn start(stats: Arc<MiningStats>, tips: Arc<RwLock<JitoTips>>) -> eyre::Result<Dashboard> {
+    execute!(io::stdout(), EnterAlternateScreen, cursor::Hide)?;
+
+    let terminal = Arc::new(Mutex::new(Some(Terminal::new(CrosstermBackend::new(io::stdout()))?)));
+    let log_level = log::max_level();
+    let started_at = Instant::now();
+
+    log::set_max_level(log::LevelFilter::Off);
+
+    let task = tokio::spawn({
+        let terminal = terminal.clone();
+
+        async move {
+            loop {
+                let tips = *tips.read().await;
+
+                if let Some(terminal) = terminal.lock().unwrap().as_mut() {
+                    let _ = terminal.draw(|frame| draw(frame, &stats, &tips, started_at));
+                }
+
+                tokio::time::sleep(REFRESH_INTERVAL).await;
+            }
+        }
+    });
+
+    Ok(Dashboard {
+        terminal,
+        task,
+        log_level,
+    })
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        self.task.abort();
+
+        // Taken under the lock, a draw in progress completes first.
+        drop(self.terminal.lock().unwrap().take());
+
+        let _ = execute!(io::stdout(), LeaveAlternateScreen, cursor::Show);
+        log::set_max_level(self.log_level);
+    }
+}
+
+fn draw(frame: &mut Frame, stats: &MiningStats, tips: &JitoTips, started_at: Instant) {
+    let [header, batches, footer] =
+        Layout::vertical([Constraint::Length(3), Constraint::Min(0), Constraint::Length(3)]).areas(frame.size());
+
+    let title = match stats::stop_requested() {
+        true => " ore-miner - stopping, waiting for sent bundles ",
+        false => " ore-miner ",
+    };
+
+    frame.render_widget(
+        Paragraph::new(header_line(stats.round_info(), tips))
+            .block(Block::default().borders(Borders::ALL).title(title)),
+        header,
+    );
+
+    let rows = stats.batches().into_iter().map(|(id, batch)| batch_row(id, &batch));
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(6),
+            Constraint::Length(10),
+            Constraint::Length(12),
+            Constraint::Length(24),
+            Constraint::Length(8),
+            Constraint::Length(8),
+        ],
+    )
+    .header(
+        Row::new(["batch", "state", "last tip", "last outcome", "landed", "dropped"])
+            .style(Style::default().add_modifier(Modifier::BOLD)),
+    )
+    .block(Block::default().borders(Borders::ALL).title(" batches "));
+
+    frame.render_widget(table, batches);
+
+    frame.render_widget(
+        Paragraph::new(footer_line(stats, started_at)).block(Block::default().borders(Borders::ALL)),
+        footer,
+    );
+}
+
+fn header_line(round: Option<RoundInfo>, tips: &JitoTips) -> String {
+    let tips = format!("jito tips p25 {}  p50 {}", tips.p25(), tips.p50());
+
+    let round = match round {
+        Some(round) => round,
+        None => return format!("waiting for the first round  |  {tips}"),
+    };
+
+    let reset_in = round.epoch_reset_at.saturating_duration_since(Instant::now());
+
+    format!(
+        "reward rate {} ORE  |  difficulty {} zero bits  |  epoch reset in {}  |  {tips}",
+        utils::ore_ui_amount(round.reward_rate),
+        leading_zero_bits(&round.difficulty.to_bytes()),
+        format_duration!(reset_in),
+    )
+}
+
+fn footer_line(stats: &MiningStats, started_at: Instant) -> String {
+    let rewards = stats.rewards.load(Ordering::Relaxed);
+    let hours = started_at.elapsed().as_secs_f64() / 3600.0;
+
+    let per_hour = match hours > 0.0 {
+        true => utils::ore_ui_amount((rewards as f64 / hours) as u64),
+        false => 0.0,
+    };
+
+    format!(
+        "tips spent {} SOL  |  rewards {} ORE ({per_hour:.4} ORE/h)  |  bundles sent {}  landed {}  dropped {}  \
+         invalidated {}",
+        spl_token::amount_to_ui_amount(stats.tips_paid.load(Ordering::Relaxed), 9),
+        utils::ore_ui_amount(rewards),
+        stats.bundles_sent.load(Ordering::Relaxed),
+        stats.bundles_landed.load(Ordering::Relaxed),
+        stats.bundles_dropped.load(Ordering::Relaxed),
+        stats.bundles_invalidated.load(Ordering::Relaxed),
+    )
+}
+
+fn batch_row(id: usize, batch: &BatchStats) -> Row<'static> {
+    let state = match batch.state {
+        BatchState::Idle => "idle",
+        BatchState::Mining => "mining",
+        BatchState::Sending => "sending",
+        BatchState::Watching => "watching",
+    };
+
+    let last_outcome = match batch.last_outcome {
+        Some(BatchOutcome::Landed { rewards }) => format!("landed {} ORE", utils::ore_ui_amount(rewards)),
+        Some(BatchOutcome::Failed) => "failed".to_string(),
+        Some(BatchOutcome::Dropped) => "dropped".to_string(),
+        Some(BatchOutcome::Invalidated) => "invalidated by reset".to_string(),
+        Some(BatchOutcome::Released) => "released unsent".to_string(),
+        None => "-".to_string(),
+    };
+
+    Row::new([
+        id.to_string(),
+        state.to_string(),
+        batch.last_tip.map_or("-".to_string(), |tip| tip.to_string()),
+        last_outcome,
+        batch.landed.to_string(),
+        batch.dropped.to_string(),
+    ])
+}
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut bits = 0;
+
+    for byte in bytes {
+        bits += byte.leading_zeros();
+
+        if *byte != 0 {
+            break;
+        }
+    }
+
+    bits
+}
@@ -1,24 +1,180 @@
+use std::{fs, path::Path};
+
 use clap::Parser;
 use serde_json::json;
-use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::{
+    derivation_path::DerivationPath,
+    signature::{Keypair, Signer},
+    signer::{
+        keypair::{generate_seed_from_seed_phrase_and_passphrase, keypair_from_seed_and_derivation_path},
+        EncodableKey,
+    },
+};
+use tracing::{error, info};
 
-use crate::Miner;
+use crate::{utils, Miner};
 
-#[derive(Debug, Parser, Clone, Copy)]
+#[derive(Debug, Parser, Clone)]
 pub struct GenerateWalletArgs {
     #[arg()]
     pub count: usize,
+
+    #[arg(
+        long,
+        help = "Write each keypair to <pubkey>.json in this folder instead of printing it, the folder is created when \
+                missing"
+    )]
+    pub out_dir: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        requires = "out_dir",
+        help = "Overwrite the existing keypair files"
+    )]
+    pub force: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "mnemonic_file",
+        help = "Derive the keypairs from this seed phrase along m/44'/501'/<index>'/0' instead of generating random ones"
+    )]
+    pub mnemonic: Option<String>,
+
+    #[arg(long, help = "Read the seed phrase from this file")]
+    pub mnemonic_file: Option<String>,
+
+    #[arg(long, default_value = "0", help = "Index of the first derived keypair")]
+    pub start_index: u32,
+
+    #[arg(
+        long,
+        default_value = "false",
+        requires = "out_dir",
+        help = "Check the keypair files of --out-dir against the derived keypairs instead of writing them"
+    )]
+    pub verify: bool,
 }
 
 impl Miner {
     pub fn generate_wallet(&self, args: &GenerateWalletArgs) {
-        for _ in 0..args.count {
-            let keypair = Keypair::new();
-            let valued = keypair.to_bytes().iter().map(|b| json!(*b)).collect::<Vec<_>>();
+        let mnemonic = match (&args.mnemonic, &args.mnemonic_file) {
+            (Some(mnemonic), _) => Some(mnemonic.clone()),
+            (None, Some(path)) => match fs::read_to_string(path) {
+                Ok(mnemonic) => Some(mnemonic),
+                Err(err) => {
+                    error!(path, "fail to read the seed phrase: {err:#}");
+                    return;
+                }
+            },
+            (None, None) => None,
+        };
+
+        let keypairs = match &mnemonic {
+            Some(mnemonic) => {
+                // Extra spaces and line breaks, e.g. of a file, don't change
+                // the keys.
+                let mnemonic = match utils::seed_phrase(mnemonic) {
+                    Ok(mnemonic) => mnemonic,
+                    Err(err) => {
+                        error!("invalid seed phrase: {err:#}");
+                        return;
+                    }
+                };
+                let seed = generate_seed_from_seed_phrase_and_passphrase(&mnemonic, "");
+
+                (args.start_index..)
+                    .take(args.count)
+                    .map(|index| {
+                        let path = DerivationPath::new_bip44(Some(index), Some(0));
+                        keypair_from_seed_and_derivation_path(&seed, Some(path)).expect("fail to derive keypair")
+                    })
+                    .collect::<Vec<_>>()
+            }
+            None if args.verify => {
+                error!("--verify needs --mnemonic or --mnemonic-file");
+                return;
+            }
+            None => (0..args.count).map(|_| Keypair::new()).collect::<Vec<_>>(),
+        };
+
+        match &args.out_dir {
+            Some(out_dir) if args.verify => Self::verify_keypairs(out_dir, &keypairs, args.start_index),
+            Some(out_dir) => Self::write_keypairs(out_dir, &keypairs, args.force),
+            None => {
+                for keypair in keypairs {
+                    let valued = keypair.to_bytes().iter().map(|b| json!(*b)).collect::<Vec<_>>();
+
+                    let key_array = serde_json::to_string(&json!(valued)).unwrap();
+
+                    println!("{key_array} | {}", keypair.pubkey());
+                }
+            }
+        }
+    }
+
+    /// Writes each keypair to `<pubkey>.json` in `out_dir`, nothing is written
+    /// when a file exists and `force` is not set.
+    fn write_keypairs(out_dir: &str, keypairs: &[Keypair], force: bool) {
+        if let Err(err) = fs::create_dir_all(out_dir) {
+            error!(out_dir, "fail to create the folder: {err:#}");
+            return;
+        }
+
+        let paths = keypairs
+            .iter()
+            .map(|keypair| Path::new(out_dir).join(format!("{}.json", keypair.pubkey())))
+            .collect::<Vec<_>>();
+
+        if !force {
+            if let Some(path) = paths.iter().find(|path| path.exists()) {
+                error!(path = %path.display(), "keypair file exists, run with --force to overwrite it");
+                return;
+            }
+        }
+
+        println!("{:<44} file", "pubkey");
+
+        for (keypair, path) in keypairs.iter().zip(&paths) {
+            if let Err(err) = keypair.write_to_file(path) {
+                error!(path = %path.display(), "fail to write keypair: {err:#}");
+                return;
+            }
+
+            println!("{:<44} {}", keypair.pubkey().to_string(), path.display());
+        }
+
+        info!(out_dir, count = keypairs.len(), "keypairs written");
+    }
+
+    /// Compares the keypair files of `out_dir` with the derived keypairs,
+    /// exits with an error when one is missing or differs.
+    fn verify_keypairs(out_dir: &str, keypairs: &[Keypair], start_index: u32) {
+        let mut failures = 0;
+
+        println!("{:<6} {:<44} status", "index", "pubkey");
+
+        for (index, keypair) in (start_index..).zip(keypairs) {
+            let path = Path::new(out_dir).join(format!("{}.json", keypair.pubkey()));
+
+            let status = match Keypair::read_from_file(&path) {
+                Ok(file) if file.to_bytes() == keypair.to_bytes() => "ok",
+                Ok(_) => "differs",
+                Err(_) if !path.exists() => "missing",
+                Err(_) => "unreadable",
+            };
+
+            if status != "ok" {
+                failures += 1;
+            }
+
+            println!("{:<6} {:<44} {}", index, keypair.pubkey().to_string(), status);
+        }
 
-            let key_array = serde_json::to_string(&json!(valued)).unwrap();
+        info!(out_dir, checked = keypairs.len(), failures, "keypairs verified");
 
-            println!("{key_array} | {}", keypair.pubkey());
+        if failures > 0 {
+            std::process::exit(1);
         }
     }
 }
@@ -0,0 +1,295 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    sync::Mutex,
+    thread::JoinHandle,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::Parser;
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{clock::Slot, pubkey::Pubkey, signature::Signature};
+use tokio::sync::mpsc;
+use tracing::{error, warn};
+
+use crate::{constant, utils, Miner};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Set from `--state-db` until `shutdown`, nothing is recorded without it.
+static WRITER: Mutex<Option<Writer>> = Mutex::new(None);
+
+struct Writer {
+    sender: mpsc::UnboundedSender<Record>,
+    thread: JoinHandle<()>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event {
+    /// The mine transaction of the wallet landed, the bundle tip is shared by
+    /// the wallets that earned rewards with it.
+    Mined {
+        wallet: String,
+        slot: Slot,
+        rewards: u64,
+        /// Lamports.
+        tip: u64,
+        /// Lamports.
+        fee: u64,
+    },
+    Claimed {
+        wallet: String,
+        amount: u64,
+        destination: String,
+        signature: String,
+        slot: Option<Slot>,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    /// Unix seconds.
+    timestamp: u64,
+    #[serde(flatten)]
+    event: Event,
+}
+
+/// Appends the recorded events to `path` as JSON lines. The file is written
+/// by a thread of its own, recording an event never waits for the disk.
+pub fn init(path: &str) -> eyre::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("fail to open state db {path}"))?;
+
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Record>();
+    let path = path.to_string();
+
+    let thread = std::thread::spawn(move || {
+        while let Some(record) = receiver.blocking_recv() {
+            let line = serde_json::to_string(&record).unwrap();
+
+            if let Err(err) = writeln!(file, "{line}") {
+                error!(path, "fail to write to state db: {err}");
+            }
+        }
+
+        if let Err(err) = file.flush() {
+            error!(path, "fail to flush state db: {err}");
+        }
+    });
+
+    *WRITER.lock().unwrap() = Some(Writer { sender, thread });
+
+    Ok(())
+}
+
+/// Stops recording and waits until the events recorded so far are written,
+/// called before exiting so that the last ones aren't lost.
+pub fn shutdown() {
+    let writer = WRITER.lock().unwrap().take();
+
+    if let Some(Writer { sender, thread }) = writer {
+        // The thread drains the channel and returns once the sender is gone.
+        drop(sender);
+
+        if thread.join().is_err() {
+            error!("state db writer panicked");
+        }
+    }
+}
+
+fn record(event: Event) {
+    if let Some(writer) = WRITER.lock().unwrap().as_ref() {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let _ = writer.sender.send(Record { timestamp, event });
+    }
+}
+
+/// Records the `(wallet, reward delta)` of every signer of a landed bundle,
+/// each paying its signature and an equal share of the tip, whether it
+/// earned something or not.
+pub fn mined(slot: Slot, tip: u64, wallets: impl IntoIterator<Item = (Pubkey, u64)>) {
+    let wallets = wallets.into_iter().collect::<Vec<_>>();
+
+    for (wallet, rewards) in &wallets {
+        record(Event::Mined {
+            wallet: wallet.to_string(),
+            slot,
+            rewards: *rewards,
+            tip: tip / wallets.len() as u64,
+            fee: constant::FEE_PER_SIGNER,
+        });
+    }
+}
+
+pub fn claimed(wallet: Pubkey, amount: u64, destination: Pubkey, signature: Signature, slot: Option<Slot>) {
+    record(Event::Claimed {
+        wallet: wallet.to_string(),
+        amount,
+        destination: destination.to_string(),
+        signature: signature.to_string(),
+        slot,
+    });
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct ReportArgs {
+    #[arg(long, value_parser = parse_date, help = "First day of the report, YYYY-MM-DD in UTC")]
+    pub since: Option<u64>,
+
+    #[arg(long, value_parser = parse_date, help = "Last day of the report, YYYY-MM-DD in UTC")]
+    pub until: Option<u64>,
+
+    #[arg(long, value_delimiter = ',', help = "Only report these wallets")]
+    pub wallets: Vec<Pubkey>,
+}
+
+/// Unix seconds at the start of the day.
+fn parse_date(value: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid date {value}, expected YYYY-MM-DD");
+
+    let mut parts = value
+        .splitn(3, '-')
+        .map(|part| part.parse::<i64>().map_err(|_| invalid()));
+    let (year, month, day) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(year), Some(month), Some(day)) => (year?, month?, day?),
+        _ => return Err(invalid()),
+    };
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || year < 1970 {
+        return Err(invalid());
+    }
+
+    // Days since the epoch of the proleptic Gregorian calendar, as in
+    // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    Ok(days as u64 * SECONDS_PER_DAY)
+}
+
+#[derive(Debug, Default)]
+struct WalletReport {
+    landed: u64,
+    rewards: u64,
+    tips: u64,
+    fees: u64,
+    claimed: u64,
+}
+
+impl Miner {
+    pub fn report(&self, args: &ReportArgs) {
+        let path = match &self.state_db {
+            Some(path) => path,
+            None => {
+                error!("--state-db is required to report");
+                return;
+            }
+        };
+
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) => {
+                error!(path, "fail to read state db: {err}");
+                return;
+            }
+        };
+
+        let since = args.since.unwrap_or(0);
+        let until = args.until.map_or(u64::MAX, |until| until + SECONDS_PER_DAY);
+        let mut wallets = BTreeMap::<String, WalletReport>::new();
+        let mut invalid = 0;
+
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let record = match serde_json::from_str::<Record>(line) {
+                Ok(record) => record,
+                Err(_) => {
+                    invalid += 1;
+                    continue;
+                }
+            };
+
+            if record.timestamp < since || record.timestamp >= until {
+                continue;
+            }
+
+            let wallet = match &record.event {
+                Event::Mined { wallet, .. } | Event::Claimed { wallet, .. } => wallet,
+            };
+
+            if !args.wallets.is_empty() && !args.wallets.iter().any(|pubkey| pubkey.to_string() == *wallet) {
+                continue;
+            }
+
+            let report = wallets.entry(wallet.clone()).or_default();
+
+            match record.event {
+                Event::Mined { rewards, tip, fee, .. } => {
+                    report.landed += 1;
+                    report.rewards += rewards;
+                    report.tips += tip;
+                    report.fees += fee;
+                }
+                Event::Claimed { amount, .. } => report.claimed += amount,
+            }
+        }
+
+        if invalid > 0 {
+            warn!(path, lines = invalid, "skipped unreadable lines of the state db");
+        }
+
+        println!(
+            "{:<44} {:>8} {:>14} {:>12} {:>12} {:>14} {:>14}",
+            "wallet", "landed", "rewards", "tips", "fees", "ore/sol", "claimed"
+        );
+
+        let mut total = WalletReport::default();
+
+        for (wallet, report) in &wallets {
+            print_report_row(wallet, report);
+
+            total.landed += report.landed;
+            total.rewards += report.rewards;
+            total.tips += report.tips;
+            total.fees += report.fees;
+            total.claimed += report.claimed;
+        }
+
+        print_report_row("total", &total);
+    }
+}
+
+fn print_report_row(wallet: &str, report: &WalletReport) {
+    let sol = |lamports: u64| spl_token::amount_to_ui_amount(lamports, 9);
+
+    // ORE earned per SOL spent on tips and fees.
+    let cost = sol(report.tips + report.fees);
+    let ore_per_sol = match cost > 0.0 {
+        true => format!("{:.4}", utils::ore_ui_amount(report.rewards) / cost),
+        false => "-".to_string(),
+    };
+
+    println!(
+        "{:<44} {:>8} {:>14} {:>12} {:>12} {:>14} {:>14}",
+        wallet,
+        report.landed,
+        utils::ore_ui_amount(report.rewards),
+        sol(report.tips),
+        sol(report.fees),
+        ore_per_sol,
+        utils::ore_ui_amount(report.claimed)
+    );
+}
@@ -1,14 +1,57 @@
-use std::{fmt::Formatter, sync::Arc};
+use std::{
+    fmt::Formatter,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use futures_util::stream::StreamExt;
+use once_cell::sync::OnceCell;
 use serde::{de, Deserialize};
 use serde_json::{json, Value};
-use solana_sdk::{pubkey::Pubkey, signature::Signature, transaction::Transaction};
+use solana_client::{nonblocking::rpc_client::RpcClient, rpc_config::RpcSendTransactionConfig};
+use solana_sdk::{clock::Slot, pubkey::Pubkey, signature::Signature, transaction::Transaction};
 use solana_transaction_status::{Encodable, EncodedTransaction, UiTransactionEncoding};
 use tokio::{sync::RwLock, task::JoinHandle};
-use tracing::info;
+use tracing::{debug, info, warn};
 
-use crate::{constant, Miner};
+use crate::{constant, metrics, Miner};
+
+/// Used when `--jito-url` isn't given.
+pub const DEFAULT_BLOCK_ENGINE: &str = "https://ny.mainnet.block-engine.jito.wtf";
+
+/// Default `--min-tip` of `bundle-mine` and `bundle-mine-gpu`, the floor of
+/// the adaptive tip in lamports.
+pub const DEFAULT_MIN_TIP: u64 = 30_000;
+
+pub const TIP_STREAM_URL: &str = "ws://bundles-api-rest.jito.wtf/api/v1/bundles/tip_stream";
+
+/// Set once from `--jito-url`.
+static BLOCK_ENGINE: OnceCell<String> = OnceCell::new();
+
+/// RPC the bundles are sent to as plain transactions when jito is disabled.
+static RPC_FALLBACK: OnceCell<String> = OnceCell::new();
+
+pub fn set_block_engine(url: String) {
+    let _ = BLOCK_ENGINE.set(url);
+}
+
+/// Sends the transactions of every bundle to `rpc` one by one, without tip,
+/// for clusters without a block engine.
+pub fn disable(rpc: String) {
+    let _ = RPC_FALLBACK.set(rpc);
+}
+
+pub fn enabled() -> bool {
+    RPC_FALLBACK.get().is_none()
+}
+
+/// The bundles endpoint of the block engine at `base`.
+pub fn bundles_url(base: &str) -> String {
+    format!("{}/api/v1/bundles", base.trim_end_matches('/'))
+}
 
 #[derive(Debug, Deserialize)]
 pub struct JitoResponse<T> {
@@ -19,8 +62,10 @@ async fn make_jito_request<T>(method: &'static str, params: Value) -> eyre::Resu
 where
     T: de::DeserializeOwned,
 {
+    let block_engine = BLOCK_ENGINE.get().map_or(DEFAULT_BLOCK_ENGINE, |url| url.as_str());
+
     let response = reqwest::Client::new()
-        .post("https://ny.mainnet.block-engine.jito.wtf/api/v1/bundles")
+        .post(bundles_url(block_engine))
         .header("Content-Type", "application/json")
         .json(&json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params}))
         .send()
@@ -57,21 +102,112 @@ pub async fn send_bundle(bundle: Vec<Transaction>) -> eyre::Result<(Signature, S
         .first()
         .expect("empty transaction");
 
-    let bundle = bundle
-        .into_iter()
+    if let Some(rpc) = RPC_FALLBACK.get() {
+        return send_over_rpc(rpc, &bundle)
+            .await
+            .map(|()| (signature, "rpc".to_string()));
+    }
+
+    let response: JitoResponse<String> = make_jito_request("sendBundle", json!([encode_bundle(&bundle)])).await?;
+
+    Ok((signature, response.result))
+}
+
+/// Sends the same signed bundles again every interval, jito only considers a
+/// bundle for the auctions shortly after it arrives. Runs until stopped, or
+/// until the blockhash fetched at `sent_slot` has expired.
+pub struct BundleResender {
+    task: JoinHandle<()>,
+    resends: Arc<AtomicU64>,
+}
+
+impl BundleResender {
+    pub fn spawn(client: Arc<RpcClient>, bundles: Vec<Vec<Transaction>>, sent_slot: Slot, interval: Duration) -> Self {
+        let resends = Arc::new(AtomicU64::new(0));
+
+        let task = tokio::spawn({
+            let resends = resends.clone();
+
+            async move {
+                loop {
+                    tokio::time::sleep(interval).await;
+
+                    match client.get_slot().await {
+                        Ok(slot) if slot > sent_slot + constant::SLOT_EXPIRATION => break,
+                        Ok(_) => {}
+                        Err(err) => debug!("fail to get slot, sending the bundles again anyway: {err:#}"),
+                    }
+
+                    for bundle in &bundles {
+                        let signature = bundle[0].signatures[0];
+
+                        match send_bundle(bundle.clone()).await {
+                            Ok((_, bundle_id)) => {
+                                let resends = resends.fetch_add(1, Ordering::Relaxed) + 1;
+                                debug!(%signature, bundle = %bundle_id, resends, "bundle sent again");
+                            }
+                            Err(err) => debug!(%signature, "fail to send bundle again: {err:#}"),
+                        }
+                    }
+                }
+            }
+        });
+
+        Self { task, resends }
+    }
+
+    /// Stops resending, returns how many times the bundles were sent again.
+    pub fn stop(self) -> u64 {
+        self.task.abort();
+        self.resends.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for BundleResender {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// The transactions as `sendBundle` takes them, base58 of the binary form.
+pub fn encode_bundle(bundle: &[Transaction]) -> Vec<String> {
+    bundle
+        .iter()
         .map(|tx| match tx.encode(UiTransactionEncoding::Binary) {
             EncodedTransaction::LegacyBinary(b) => b,
             _ => panic!("impossible"),
         })
-        .collect::<Vec<_>>();
+        .collect()
+}
 
-    let response: JitoResponse<String> = make_jito_request("sendBundle", json!([bundle])).await?;
+/// Not atomic like a bundle, a transaction may land without the others. The
+/// ones that fail to send are left to expire like dropped ones, the callers
+/// settle every transaction on its own. Fails when none was sent.
+async fn send_over_rpc(rpc: &str, bundle: &[Transaction]) -> eyre::Result<()> {
+    let client = Miner::get_client_confirmed(rpc);
+    let send_cfg = RpcSendTransactionConfig {
+        skip_preflight: true,
+        ..RpcSendTransactionConfig::default()
+    };
+    let mut failed = 0;
 
-    Ok((signature, response.result))
+    for tx in bundle {
+        if let Err(err) = client.send_transaction_with_config(tx, send_cfg).await {
+            warn!(tx = %tx.signatures[0], "fail to send transaction: {err:#}");
+            failed += 1;
+        }
+    }
+
+    if failed == bundle.len() {
+        eyre::bail!("fail to send the {failed} transactions");
+    }
+
+    Ok(())
 }
 
-pub fn build_bribe_ix(pubkey: &Pubkey, value: u64) -> solana_sdk::instruction::Instruction {
-    solana_sdk::system_instruction::transfer(pubkey, constant::pick_jito_recipient(), value)
+/// None when jito is disabled, there is nobody to tip.
+pub fn build_bribe_ix(pubkey: &Pubkey, value: u64) -> Option<solana_sdk::instruction::Instruction> {
+    enabled().then(|| solana_sdk::system_instruction::transfer(pubkey, constant::pick_jito_recipient(), value))
 }
 
 #[derive(Debug, Clone, Copy, Default, Deserialize)]
@@ -100,6 +236,16 @@ impl JitoTips {
     pub fn p25(&self) -> u64 {
         (self.p25_landed * 1e9f64) as u64
     }
+
+    /// One lamport above the landed p50, raised to `min_tip` then capped at
+    /// `max_tip`, so `max_tip` wins when it is below `min_tip`. `None` until
+    /// the tip stream delivered the first tips.
+    pub fn adaptive_tip(&self, min_tip: u64, max_tip: u64) -> Option<u64> {
+        match self.p50() {
+            0 => None,
+            p50 => Some((p50 + 1).max(min_tip).min(max_tip)),
+        }
+    }
 }
 
 impl std::fmt::Display for JitoTips {
@@ -120,10 +266,8 @@ pub async fn subscribe_jito_tips(tips: Arc<RwLock<JitoTips>>) -> JoinHandle<()>
     tokio::spawn({
         let tips = tips.clone();
         async move {
-            let url = "ws://bundles-api-rest.jito.wtf/api/v1/bundles/tip_stream";
-
             loop {
-                let stream = match tokio_tungstenite::connect_async(url).await {
+                let stream = match tokio_tungstenite::connect_async(TIP_STREAM_URL).await {
                     Ok((ws_stream, _)) => ws_stream,
                     Err(err) => {
                         tracing::error!("fail to connect to jito tip stream: {err:#}");
@@ -155,7 +299,9 @@ pub async fn subscribe_jito_tips(tips: Arc<RwLock<JitoTips>>) -> JoinHandle<()>
                         return;
                     }
 
-                    *tips.write().await = *data.first().unwrap();
+                    let latest = *data.first().unwrap();
+                    metrics::JITO_TIP_P50.set(latest.p50() as i64);
+                    *tips.write().await = latest;
                 })
                 .await;
 
@@ -181,3 +327,40 @@ impl Miner {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tips_with_p50(lamports: u64) -> JitoTips {
+        JitoTips {
+            p50_landed: lamports as f64 / 1e9,
+            ..JitoTips::default()
+        }
+    }
+
+    #[test]
+    fn adaptive_tip_is_none_without_tips() {
+        assert_eq!(JitoTips::default().adaptive_tip(30_000, 400_000), None);
+    }
+
+    #[test]
+    fn adaptive_tip_is_raised_to_min_tip() {
+        assert_eq!(tips_with_p50(10_000).adaptive_tip(30_000, 400_000), Some(30_000));
+    }
+
+    #[test]
+    fn adaptive_tip_is_one_above_p50_between_the_bounds() {
+        assert_eq!(tips_with_p50(100_000).adaptive_tip(30_000, 400_000), Some(100_001));
+    }
+
+    #[test]
+    fn adaptive_tip_is_capped_at_max_tip() {
+        assert_eq!(tips_with_p50(1_000_000).adaptive_tip(30_000, 400_000), Some(400_000));
+    }
+
+    #[test]
+    fn max_tip_wins_over_min_tip() {
+        assert_eq!(tips_with_p50(10_000).adaptive_tip(50_000, 40_000), Some(40_000));
+    }
+}
@@ -1,13 +1,17 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs,
-    path::PathBuf,
+    net::SocketAddr,
+    path::{Path, PathBuf},
     str::FromStr,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use eyre::{bail, ContextCompat};
 use ore::{
     state::{Bus, Proof, Treasury},
@@ -16,6 +20,7 @@ use ore::{
 use serde_json::json;
 use solana_client::{
     nonblocking::rpc_client::RpcClient,
+    rpc_client::RpcClientConfig,
     rpc_request::RpcRequest,
     rpc_response::{Response, RpcBlockhash},
 };
@@ -26,66 +31,408 @@ use solana_sdk::{
     keccak::Hash,
     pubkey::Pubkey,
     signature::{Keypair, Signature},
-    signer::EncodableKey,
-    sysvar,
+    signer::Signer,
 };
 use solana_transaction_status::TransactionStatus;
-use tokio::io::AsyncWriteExt;
-use tracing::{error, log};
+use tokio::sync::mpsc;
+use tracing::{debug, error, log, warn};
+
+use crate::{cluster::Cluster, priority_fee::PriorityFee, worker::WorkerSpec};
 
 mod batch_transfer;
+mod benchmark_hashrate;
+mod benchmark_jito;
 mod benchmark_rpc;
+mod blockhash;
 mod bundle_mine;
 mod bundle_mine_gpu;
-mod collect;
 mod claim;
+mod claim_report;
+mod cluster;
+mod collect;
+mod compute_budget;
+mod config;
+mod consolidate_ore;
 mod constant;
+mod dashboard;
 mod generate_wallet;
+mod history;
 mod jito;
+mod metrics;
+mod mine;
+mod nonce_cache;
+mod notify;
+mod pda_cache;
+mod priority_fee;
+mod profit;
+mod program_error;
+mod rate_limit;
 mod register;
+mod stats;
+mod status;
+mod system_accounts;
 mod utils;
+mod wallets;
+mod watch_proofs;
+mod worker;
+
+/// Number of consecutive GPU worker failures, shared by all batches.
+static GPU_FAILURES: AtomicUsize = AtomicUsize::new(0);
+
+/// Receives `(input index, hash, nonce)` as soon as a result is verified.
+pub type MiningResultSender = mpsc::UnboundedSender<(usize, Hash, u64)>;
+
+/// `--rpc` when it isn't given.
+const DEFAULT_RPC: &str = "https://api.mainnet-beta.solana.com";
 
 #[tokio::main(flavor = "multi_thread")]
 async fn main() {
     Miner::init_pretty_env_logger();
-    let miner = Miner::parse();
-
-    match &miner.command {
-        Command::Claim(args) => miner.claim(args).await,
-        Command::BundleMine(args) => miner.bundle_mine(args).await,
-        Command::BundleMineGpu(args) => miner.bundle_mine_gpu(args).await,
-        Command::Register(args) => miner.register(args).await,
-        Command::BenchmarkRpc(args) => miner.benchmark_rpc(args).await,
-        Command::BatchTransfer(args) => miner.batch_transfer(args).await,
-        Command::JitoTipStream => miner.jito_tip_stream().await,
-        Command::GenerateWallet(args) => miner.generate_wallet(args),
-        Command::Collect(args) => miner.collect(args).await,
+    let args = match config::args_with_config() {
+        Ok(args) => args,
+        Err(err) => {
+            error!("{err:#}");
+            std::process::exit(1);
+        }
+    };
+
+    let mut miner = Miner::parse_from(args);
+
+    if let Err(err) = cluster::init(miner.cluster, miner.program_id, miner.mint) {
+        error!("{err:#}");
+        std::process::exit(1);
+    }
+
+    if miner.cluster != Cluster::Mainnet {
+        if miner.cluster == Cluster::Devnet && miner.rpc == DEFAULT_RPC {
+            miner.rpc = cluster::DEVNET_RPC.to_string();
+        }
+
+        // There is no block engine outside of mainnet.
+        jito::disable(miner.rpc.clone());
+    }
+
+    if let Some(rps) = miner.rpc_rps {
+        if !(rps.is_finite() && rps > 0.0) {
+            error!(rps, "--rpc-rps must be a positive number");
+            std::process::exit(1);
+        }
+
+        rate_limit::init(rps, miner.rpc_burst);
+    }
+
+    if let Some(commitment) = miner.commitment {
+        utils::set_commitment(commitment.into());
+    }
+
+    if let Some(poll_ms) = miner.blockhash_poll_ms {
+        blockhash::set_poll_interval(Duration::from_millis(poll_ms.max(1)));
+    }
+
+    if let Some(url) = &miner.jito_url {
+        jito::set_block_engine(url.clone());
+    }
+
+    compute_budget::init(miner.compute_unit_margin, miner.compute_unit_price);
+
+    // The estimate is a compute unit price, it says nothing of the tip a
+    // bundle needs to land.
+    if let (Some(PriorityFee::Auto), true) = (miner.priority_fee, jito::enabled() && miner.command.pays_jito_tip()) {
+        error!("--priority-fee auto only estimates a compute unit price, give the jito tip in lamports instead");
+        std::process::exit(1);
+    }
+
+    if let Some(PriorityFee::Auto) = miner.priority_fee {
+        let client = Miner::get_client_confirmed(&miner.rpc);
+
+        if let Err(err) = priority_fee::refresh(&client, miner.priority_fee_percentile).await {
+            error!("fail to estimate the priority fee: {err:#}");
+            std::process::exit(1);
+        }
+
+        priority_fee::spawn_refresher(
+            client,
+            miner.priority_fee_percentile,
+            Duration::from_secs(miner.priority_fee_refresh),
+        );
+    }
+
+    if let Some(url) = &miner.webhook_url {
+        notify::init(
+            url.clone(),
+            miner.notify_drops,
+            spl_token::ui_amount_to_amount(miner.notify_min_balance, 9),
+        );
+    }
+
+    if let Some(path) = &miner.pda_cache {
+        if let Err(err) = pda_cache::init(path) {
+            error!("{err:#}");
+            std::process::exit(1);
+        }
+    }
+
+    if let (Some(path), false) = (&miner.state_db, matches!(miner.command, Command::Report(_))) {
+        if let Err(err) = history::init(path) {
+            error!("{err:#}");
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(addr) = miner.metrics_listen {
+        if let Err(err) = metrics::serve(addr).await {
+            error!(%addr, "fail to serve metrics: {err:#}");
+            std::process::exit(1);
+        }
+    }
+
+    let command = async {
+        match &miner.command {
+            Command::Claim(args) => miner.claim(args).await,
+            Command::BundleMine(args) => miner.bundle_mine(args).await,
+            Command::Mine(args) => miner.mine(args).await,
+            Command::BundleMineGpu(args) => miner.bundle_mine_gpu(args).await,
+            Command::Register(args) => miner.register(args).await,
+            Command::BenchmarkRpc(args) => miner.benchmark_rpc(args).await,
+            Command::BenchmarkHashrate(args) => miner.benchmark_hashrate(args).await,
+            Command::BenchmarkJito(args) => miner.benchmark_jito(args).await,
+            Command::BatchTransfer(args) => miner.batch_transfer(args).await,
+            Command::JitoTipStream => miner.jito_tip_stream().await,
+            Command::GenerateWallet(args) => miner.generate_wallet(args),
+            Command::Collect(args) => miner.collect(args).await,
+            Command::ConsolidateOre(args) => miner.consolidate_ore(args).await,
+            Command::Wallets(args) => miner.wallets(args).await,
+            Command::Status(args) => miner.status(args).await,
+            Command::Config(args) => miner.config(args),
+            Command::Report(args) => miner.report(args),
+            Command::WatchProofs(args) => miner.watch_proofs(args).await,
+        }
+    };
+
+    // Mining and claiming stop gracefully on the first Ctrl-C, the other
+    // commands and a second Ctrl-C exit right away.
+    let graceful = matches!(
+        miner.command,
+        Command::Claim(_) | Command::BundleMine(_) | Command::BundleMineGpu(_) | Command::Mine(_)
+    );
+
+    let interrupted = async {
+        let _ = tokio::signal::ctrl_c().await;
+
+        if graceful {
+            warn!("interrupted, waiting for sent bundles, press Ctrl-C again to exit now");
+            stats::request_stop();
+
+            let _ = tokio::signal::ctrl_c().await;
+        }
+
+        warn!("interrupted, stopping nonce workers");
+    };
+
+    // Dropping the command cancels the running mining tasks, which terminates
+    // their nonce workers.
+    tokio::select! {
+        _ = command => {}
+        _ = interrupted => {}
     }
+
+    worker::shutdown().await;
+    history::shutdown();
 }
 
 #[derive(Parser, Debug, Clone)]
 pub struct Miner {
-    #[arg(long, default_value = "https://api.mainnet-beta.solana.com")]
+    #[arg(long, default_value = DEFAULT_RPC, help = "RPC URL, the public devnet one by default with --cluster devnet")]
     pub rpc: String,
 
-    #[arg(long)]
-    pub priority_fee: Option<u64>,
+    #[arg(
+        long,
+        value_enum,
+        default_value = "mainnet",
+        help = "Cluster of the ore program. Outside of mainnet, bundles are sent as plain transactions without tip"
+    )]
+    pub cluster: Cluster,
+
+    #[arg(long, help = "Address of the ore program, required with --cluster custom")]
+    pub program_id: Option<Pubkey>,
+
+    #[arg(long, help = "Address of the ORE mint, required with --cluster custom")]
+    pub mint: Option<Pubkey>,
+
+    #[arg(
+        long,
+        help = "Jito tip in lamports, or compute unit price in micro-lamports without jito. auto estimates the \
+                compute unit price from the recent fees of the ore accounts, it can't be used as a jito tip"
+    )]
+    pub priority_fee: Option<PriorityFee>,
+
+    #[arg(
+        long,
+        default_value = "50",
+        value_parser = clap::value_parser!(u8).range(0..=100),
+        help = "Percentile of the recent fees used by --priority-fee auto"
+    )]
+    pub priority_fee_percentile: u8,
+
+    #[arg(
+        long,
+        default_value = "60",
+        help = "Seconds between two estimations of --priority-fee auto"
+    )]
+    pub priority_fee_refresh: u64,
+
+    #[arg(
+        long,
+        default_value = "20",
+        help = "Percent added to the simulated compute units of the mine transactions for their compute unit limit"
+    )]
+    pub compute_unit_margin: u32,
+
+    #[arg(
+        long,
+        help = "Compute unit price in micro-lamports of the bundled mine transactions, on top of the jito tip. mine \
+                uses --priority-fee"
+    )]
+    pub compute_unit_price: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Requests per second sent to the RPC by all tasks together, not limited without it"
+    )]
+    pub rpc_rps: Option<f64>,
+
+    #[arg(
+        long,
+        default_value = "10",
+        help = "Requests let through at once by --rpc-rps after an idle period"
+    )]
+    pub rpc_burst: u32,
+
+    #[arg(
+        long,
+        help = "Fetch the blockhash shared by the tasks over HTTP every this many milliseconds, instead of every few \
+                slots of a websocket slot subscription"
+    )]
+    pub blockhash_poll_ms: Option<u64>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Commitment of every request and landing check, confirmed for the client and the landing checks and \
+                processed for the account fetches and the simulations without it"
+    )]
+    pub commitment: Option<Commitment>,
+
+    #[arg(
+        long,
+        help = "Block engine the bundles are sent to, e.g. https://amsterdam.mainnet.block-engine.jito.wtf. The New York \
+                one without it"
+    )]
+    pub jito_url: Option<String>,
+
+    #[arg(long, help = "Address to serve Prometheus metrics on, e.g. 0.0.0.0:9090")]
+    pub metrics_listen: Option<SocketAddr>,
+
+    #[arg(
+        long,
+        help = "Webhook receiving a JSON POST on important events, e.g. of Discord or Slack"
+    )]
+    pub webhook_url: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "10",
+        help = "Bundles dropped in a row before notifying the webhook, 0 to never notify"
+    )]
+    pub notify_drops: u64,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Notify the webhook when a mining wallet holds less SOL than this, 0 to never notify"
+    )]
+    pub notify_min_balance: f64,
+
+    #[arg(
+        long,
+        help = "File the landed mining transactions and the claims of each wallet are appended to, read by report"
+    )]
+    pub state_db: Option<String>,
+
+    #[arg(
+        long,
+        help = "File the derived proof addresses are kept in, so that large key folders load faster"
+    )]
+    pub pda_cache: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Also read the keys of the subfolders of --key-folder"
+    )]
+    pub recursive: bool,
+
+    #[arg(
+        long,
+        help = "TOML file with the defaults of the options, ore-miner.toml of the working directory is read otherwise"
+    )]
+    pub config: Option<String>,
 
     #[command(subcommand)]
     pub command: Command,
 }
 
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum Commitment {
+    Processed,
+    Confirmed,
+    Finalized,
+}
+
+impl From<Commitment> for CommitmentConfig {
+    fn from(commitment: Commitment) -> Self {
+        match commitment {
+            Commitment::Processed => CommitmentConfig::processed(),
+            Commitment::Confirmed => CommitmentConfig::confirmed(),
+            Commitment::Finalized => CommitmentConfig::finalized(),
+        }
+    }
+}
+
 #[derive(Subcommand, Debug, Clone)]
 pub enum Command {
     Claim(crate::claim::ClaimArgs),
     BundleMine(crate::bundle_mine::BundleMineArgs),
+    Mine(crate::mine::MineArgs),
     BundleMineGpu(crate::bundle_mine_gpu::BundleMineGpuArgs),
     Register(crate::register::RegisterArgs),
     BenchmarkRpc(crate::benchmark_rpc::BenchmarkRpcArgs),
+    BenchmarkHashrate(crate::benchmark_hashrate::BenchmarkHashrateArgs),
+    BenchmarkJito(crate::benchmark_jito::BenchmarkJitoArgs),
     JitoTipStream,
     GenerateWallet(crate::generate_wallet::GenerateWalletArgs),
     BatchTransfer(crate::batch_transfer::BatchTransferArgs),
     Collect(crate::collect::CollectArgs),
+    ConsolidateOre(crate::consolidate_ore::ConsolidateOreArgs),
+    Wallets(crate::wallets::WalletsArgs),
+    Status(crate::status::StatusArgs),
+    Config(crate::config::ConfigArgs),
+    Report(crate::history::ReportArgs),
+    WatchProofs(crate::watch_proofs::WatchProofsArgs),
+}
+
+impl Command {
+    /// Whether `--priority-fee` is sent as a jito tip rather than a compute
+    /// unit price.
+    pub fn pays_jito_tip(&self) -> bool {
+        match self {
+            Command::Claim(_) | Command::BundleMine(_) | Command::BundleMineGpu(_) => true,
+            Command::Register(args) => !args.no_jito,
+            Command::Collect(args) => !args.no_jito,
+            Command::BatchTransfer(args) => args.jito,
+            _ => false,
+        }
+    }
 }
 
 impl Miner {
@@ -96,27 +443,72 @@ impl Miner {
             .init();
     }
 
+    /// `--priority-fee`, the latest estimate with `--priority-fee auto`.
+    pub fn current_priority_fee(&self) -> Option<u64> {
+        self.priority_fee.map(|fee| fee.value())
+    }
+
     pub fn get_client_confirmed(rpc: &str) -> Arc<RpcClient> {
-        Arc::new(RpcClient::new_with_commitment(
-            rpc.to_string(),
-            CommitmentConfig::confirmed(),
-        ))
+        match rate_limit::limiter() {
+            Some(limiter) => Arc::new(RpcClient::new_sender(
+                rate_limit::RateLimitedSender::new(rpc.to_string(), limiter),
+                RpcClientConfig::with_commitment(utils::commitment_or(CommitmentConfig::confirmed())),
+            )),
+            None => Arc::new(RpcClient::new_with_commitment(
+                rpc.to_string(),
+                utils::commitment_or(CommitmentConfig::confirmed()),
+            )),
+        }
     }
 
-    pub fn read_keys(key_folder: &str) -> Vec<Keypair> {
-        fs::read_dir(key_folder)
-            .expect("Failed to read key folder")
-            .map(|entry| {
-                let path = entry.expect("Failed to read entry").path();
+    /// Keypairs of the key folder sorted by path, so the wallets keep their
+    /// batch across runs. Files that aren't keypairs are skipped with a
+    /// warning and a key found in several files is kept once.
+    pub fn read_keys(key_folder: &str, recursive: bool) -> eyre::Result<Vec<Keypair>> {
+        let mut paths = vec![];
+        Self::find_key_files(Path::new(key_folder), recursive, &mut paths)?;
+        paths.sort();
+
+        let mut pubkeys = HashSet::new();
+        let mut keys = vec![];
+
+        for path in paths {
+            match utils::read_keypair(&path) {
+                Ok(key) if pubkeys.insert(key.pubkey()) => keys.push(key),
+                Ok(key) => warn!(path = %path.display(), pubkey = %key.pubkey(), "duplicate keypair, skipping"),
+                Err(err) => warn!(path = %path.display(), "not a keypair file, skipping: {err}"),
+            }
+        }
 
-                Keypair::read_from_file(&path).unwrap_or_else(|_| panic!("Failed to read keypair from {:?}", path))
-            })
-            .collect::<Vec<_>>()
+        if keys.is_empty() {
+            bail!("no keypair found in {key_folder}");
+        }
+
+        Ok(keys)
+    }
+
+    fn find_key_files(folder: &Path, recursive: bool, paths: &mut Vec<PathBuf>) -> eyre::Result<()> {
+        let entries =
+            fs::read_dir(folder).map_err(|err| eyre::eyre!("fail to read key folder {}: {err}", folder.display()))?;
+
+        for entry in entries {
+            let path = entry?.path();
+
+            if !path.is_dir() {
+                paths.push(path);
+            } else if recursive {
+                Self::find_key_files(&path, recursive, paths)?;
+            }
+        }
+
+        Ok(())
     }
 
     pub async fn get_latest_blockhash_and_slot(client: &RpcClient) -> eyre::Result<(Slot, solana_sdk::hash::Hash)> {
+        let commitment = utils::commitment_or(CommitmentConfig::confirmed()).commitment;
+
         let (blockhash, send_at_slot) = match client
-            .send::<Response<RpcBlockhash>>(RpcRequest::GetLatestBlockhash, json!([{"commitment": "confirmed"}]))
+            .send::<Response<RpcBlockhash>>(RpcRequest::GetLatestBlockhash, json!([{ "commitment": commitment }]))
             .await
         {
             Ok(r) => (r.value.blockhash, r.context.slot),
@@ -136,118 +528,324 @@ impl Miner {
         threads: usize,
         difficulty: &Hash,
         hash_and_pubkey: &[(Hash, Pubkey)],
-    ) -> (Duration, Vec<(Hash, u64)>) {
-        self.mine_hashes(utils::get_nonce_worker_path(), threads, difficulty, hash_and_pubkey)
+    ) -> eyre::Result<(Duration, Vec<(Hash, u64)>)> {
+        self.mine_hashes(WorkerSpec::cpu(), threads, difficulty, hash_and_pubkey)
             .await
     }
 
+    /// Mines with the GPU workers, falling back to the CPU worker if they
+    /// fail. The timeout covers both attempts, a GPU worker that times out
+    /// leaves no time for the fallback.
+    ///
+    /// Inputs are split across one worker per device in `devices`, or a single
+    /// worker seeing every device if it's empty.
+    ///
+    /// Verified results are also sent to `on_result` as soon as they are
+    /// available, results of a failed GPU attempt may be sent again by the
+    /// fallback. Inputs not solved when the timeout elapses are `None`.
+    #[allow(clippy::too_many_arguments)]
     pub async fn mine_hashes_gpu(
         &self,
+        devices: &[usize],
+        fallback_threads: usize,
+        stream: bool,
+        timeout: Duration,
         difficulty: &Hash,
         hash_and_pubkey: &[(Hash, Pubkey)],
-    ) -> (Duration, Vec<(Hash, u64)>) {
-        self.mine_hashes(utils::get_gpu_nonce_worker_path(), 0, difficulty, hash_and_pubkey)
-            .await
+        on_result: Option<&MiningResultSender>,
+    ) -> eyre::Result<(Duration, Vec<Option<(Hash, u64)>>)> {
+        let mining_start = Instant::now();
+        let failures = GPU_FAILURES.load(Ordering::Relaxed);
+
+        if failures < constant::GPU_MAX_FAILURES {
+            let workers = match devices {
+                [] => vec![WorkerSpec::gpu(None, stream)],
+                devices => devices
+                    .iter()
+                    .map(|device| WorkerSpec::gpu(Some(*device), stream))
+                    .collect(),
+            };
+
+            match self
+                .try_mine_hashes_split(workers, timeout, difficulty, hash_and_pubkey, on_result)
+                .await
+            {
+                Ok(result) => {
+                    GPU_FAILURES.store(0, Ordering::Relaxed);
+                    return Ok(result);
+                }
+                Err(err) => {
+                    let failures = GPU_FAILURES.fetch_add(1, Ordering::Relaxed) + 1;
+
+                    if failures >= constant::GPU_MAX_FAILURES {
+                        warn!(failures, "gpu worker keeps failing, staying in cpu mode");
+                    }
+
+                    if mining_start.elapsed() >= timeout {
+                        return Err(err);
+                    }
+
+                    warn!(
+                        failures,
+                        fallback_threads, "gpu worker failed, falling back to cpu: {err:#}"
+                    );
+                }
+            }
+        } else {
+            warn!(failures, fallback_threads, "gpu worker disabled, mining with cpu");
+        }
+
+        let (_, results) = self
+            .try_mine_hashes(
+                WorkerSpec::cpu(),
+                fallback_threads,
+                timeout.saturating_sub(mining_start.elapsed()),
+                difficulty,
+                hash_and_pubkey,
+                on_result,
+            )
+            .await?;
+
+        Ok((mining_start.elapsed(), results))
     }
 
     pub async fn mine_hashes(
         &self,
-        worker: PathBuf,
+        worker: WorkerSpec,
+        threads: usize,
+        difficulty: &Hash,
+        hash_and_pubkey: &[(Hash, Pubkey)],
+    ) -> eyre::Result<(Duration, Vec<(Hash, u64)>)> {
+        let (mining_duration, results) = self
+            .try_mine_hashes(worker, threads, Duration::MAX, difficulty, hash_and_pubkey, None)
+            .await?;
+
+        let results = Self::collect_results(results, hash_and_pubkey)?;
+
+        Ok((mining_duration, results))
+    }
+
+    /// Runs the worker until `timeout` elapses. The worker stops at the
+    /// deadline and returns what it has solved, the unsolved inputs are `None`.
+    /// A worker that doesn't return shortly after is killed. Inputs the worker
+    /// fails to solve are mined once more if there is time left.
+    pub async fn try_mine_hashes(
+        &self,
+        worker: WorkerSpec,
         threads: usize,
+        timeout: Duration,
         difficulty: &Hash,
         hash_and_pubkey: &[(Hash, Pubkey)],
-    ) -> (Duration, Vec<(Hash, u64)>) {
+        on_result: Option<&MiningResultSender>,
+    ) -> eyre::Result<(Duration, Vec<Option<(Hash, u64)>>)> {
         let mining_start = Instant::now();
+        let deadline = mining_start.checked_add(timeout);
+        let mut results = vec![None; hash_and_pubkey.len()];
+        let inputs = hash_and_pubkey.iter().copied().enumerate().collect::<Vec<_>>();
 
-        let mut child = tokio::process::Command::new(worker)
-            .stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            .spawn()
-            .expect("nonce_worker failed to spawn");
+        let mining = Self::mine_indexed(&worker, threads, deadline, difficulty, &inputs, &mut results, on_result);
+        let worker_timeout = timeout.saturating_add(constant::WORKER_DEADLINE_GRACE);
 
-        {
-            let stdin = child.stdin.as_mut().unwrap();
+        match tokio::time::timeout(worker_timeout, mining).await {
+            Ok(result) => result?,
+            Err(_) => bail!("{worker} timed out after {}", format_duration!(worker_timeout)),
+        };
 
-            stdin.write_u8(threads as u8).await.unwrap();
-            stdin.write_all(difficulty.as_ref()).await.unwrap();
+        let mining_duration = mining_start.elapsed();
+        Self::log_mining_round(worker.kind(), difficulty, &results, mining_duration);
 
-            for (hash, pubkey) in hash_and_pubkey {
-                stdin.write_all(hash.as_ref()).await.unwrap();
-                stdin.write_all(pubkey.as_ref()).await.unwrap();
-            }
-        }
+        Ok((mining_duration, results))
+    }
 
-        let output = child.wait_with_output().await.unwrap().stdout;
-        let mut results = vec![];
+    /// Splits the inputs across `workers`, keeping the results in input order.
+    /// The inputs of a failed worker are mined again by the remaining ones
+    /// until the deadline, the unsolved inputs are `None`.
+    async fn try_mine_hashes_split(
+        &self,
+        mut workers: Vec<WorkerSpec>,
+        timeout: Duration,
+        difficulty: &Hash,
+        hash_and_pubkey: &[(Hash, Pubkey)],
+        on_result: Option<&MiningResultSender>,
+    ) -> eyre::Result<(Duration, Vec<Option<(Hash, u64)>>)> {
+        let mining_start = Instant::now();
+        let deadline = mining_start.checked_add(timeout);
+        let mut results = vec![None; hash_and_pubkey.len()];
 
-        for item in output.chunks(40) {
-            let hash = Hash(item[..32].try_into().unwrap());
-            let nonce = u64::from_le_bytes(item[32..40].try_into().unwrap());
+        let mining = async {
+            loop {
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    return Ok(());
+                }
 
-            results.push((hash, nonce));
-        }
+                let pending = hash_and_pubkey
+                    .iter()
+                    .copied()
+                    .enumerate()
+                    .filter(|(i, _)| results[*i].is_none())
+                    .collect::<Vec<_>>();
+
+                if pending.is_empty() {
+                    return Ok(());
+                }
+
+                if workers.is_empty() {
+                    bail!("all workers failed");
+                }
+
+                let chunk_size = pending.len().div_ceil(workers.len());
+                let attempts = workers
+                    .iter()
+                    .zip(pending.chunks(chunk_size))
+                    .map(|(worker, inputs)| async move {
+                        let mut worker_results = vec![None; hash_and_pubkey.len()];
+                        let result =
+                            Self::mine_indexed(worker, 0, deadline, difficulty, inputs, &mut worker_results, on_result)
+                                .await;
+
+                        (worker.clone(), result, worker_results)
+                    });
+
+                let mut failed = vec![];
+
+                for (worker, result, worker_results) in futures_util::future::join_all(attempts).await {
+                    for (i, result) in worker_results.into_iter().enumerate() {
+                        if result.is_some() {
+                            results[i] = result;
+                        }
+                    }
+
+                    if let Err(err) = result {
+                        warn!(%worker, "worker failed, its inputs will be mined by the others: {err:#}");
+                        failed.push(worker);
+                    }
+                }
+
+                workers.retain(|worker| !failed.contains(worker));
+            }
+        };
+
+        let worker_timeout = timeout.saturating_add(constant::WORKER_DEADLINE_GRACE);
+
+        match tokio::time::timeout(worker_timeout, mining).await {
+            Ok(result) => result?,
+            Err(_) => bail!("gpu workers timed out after {}", format_duration!(worker_timeout)),
+        };
 
         let mining_duration = mining_start.elapsed();
-        (mining_duration, results)
+        Self::log_mining_round("gpu", difficulty, &results, mining_duration);
+
+        Ok((mining_duration, results))
     }
 
-    pub fn find_buses(buses: [Bus; ore::BUS_COUNT], required_reward: u64) -> Vec<Bus> {
-        let mut available_bus = buses
+    /// Emits one structured event per mining round, with the same fields for
+    /// every worker type.
+    fn log_mining_round(worker: &str, difficulty: &Hash, results: &[Option<(Hash, u64)>], duration: Duration) {
+        debug!(
+            worker,
+            %difficulty,
+            inputs = results.len(),
+            solved = results.iter().filter(|result| result.is_some()).count(),
+            duration_ms = duration.as_millis() as u64,
+            "mining round"
+        );
+    }
+
+    fn collect_results(
+        results: Vec<Option<(Hash, u64)>>,
+        hash_and_pubkey: &[(Hash, Pubkey)],
+    ) -> eyre::Result<Vec<(Hash, u64)>> {
+        results
             .into_iter()
-            .filter(|bus| bus.rewards >= required_reward)
+            .zip(hash_and_pubkey)
+            .map(|(result, (_, pubkey))| result.with_context(|| format!("worker returned invalid nonce for {pubkey}")))
+            .collect()
+    }
+
+    /// Mines `inputs`, tagged with their index in `results`, re-mining once the
+    /// ones the worker fails to solve before the deadline.
+    async fn mine_indexed(
+        worker: &WorkerSpec,
+        threads: usize,
+        deadline: Option<Instant>,
+        difficulty: &Hash,
+        inputs: &[(usize, (Hash, Pubkey))],
+        results: &mut [Option<(Hash, u64)>],
+        on_result: Option<&MiningResultSender>,
+    ) -> eyre::Result<()> {
+        Self::mine_verified(worker, threads, deadline, difficulty, inputs, results, on_result).await?;
+
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Ok(());
+        }
+
+        let missing = inputs
+            .iter()
+            .filter(|(i, _)| results[*i].is_none())
+            .copied()
             .collect::<Vec<_>>();
 
-        available_bus.sort_by(|a, b| b.rewards.cmp(&a.rewards));
+        if missing.is_empty() {
+            return Ok(());
+        }
 
-        available_bus
+        for (_, (_, pubkey)) in &missing {
+            warn!(%pubkey, "worker returned no valid nonce, re-mining");
+        }
+
+        Self::mine_verified(worker, threads, deadline, difficulty, &missing, results, on_result).await
     }
 
-    pub async fn get_accounts(
-        id: usize,
-        client: &RpcClient,
-        accounts: &[Pubkey],
-    ) -> Option<(Treasury, Clock, [Bus; ore::BUS_COUNT], Vec<Proof>)> {
-        let proof_count = accounts.len() - (2 + ore::BUS_COUNT);
+    /// Mines `inputs`, tagged with their index in `results`, and stores every
+    /// result that passes verification.
+    async fn mine_verified(
+        worker: &WorkerSpec,
+        threads: usize,
+        deadline: Option<Instant>,
+        difficulty: &Hash,
+        inputs: &[(usize, (Hash, Pubkey))],
+        results: &mut [Option<(Hash, u64)>],
+        on_result: Option<&MiningResultSender>,
+    ) -> eyre::Result<()> {
+        let hash_and_pubkey = inputs.iter().map(|(_, input)| *input).collect::<Vec<_>>();
+        let (sender, mut receiver) = mpsc::unbounded_channel();
 
-        let accounts = match client
-            .get_multiple_accounts_with_commitment(accounts, CommitmentConfig::processed())
-            .await
-        {
-            Ok(accounts) => accounts.value,
-            Err(err) => {
-                error!(miner = id, "failed to get proof and treasury accounts: {err}",);
-                return None;
-            }
-        };
+        let mining =
+            async move { worker::mine(worker, threads, deadline, difficulty, &hash_and_pubkey, &sender).await };
 
-        let mut accounts = accounts.into_iter();
-        let treasury: Treasury = parse_account("treasury", accounts.next())?;
-        let clock: Clock = match accounts.next() {
-            Some(Some(account)) => match bincode::deserialize::<Clock>(account.data()) {
-                Ok(account) => account,
-                Err(err) => {
-                    error!(miner = id, "failed to deserialize clock account: {err:#}",);
-                    return None;
+        let verifying = async {
+            while let Some((i, (result, nonce))) = receiver.recv().await {
+                let Some((index, (hash, pubkey))) = inputs.get(i) else {
+                    warn!(index = i, "worker returned result for unknown input");
+                    continue;
+                };
+
+                if !utils::verify_nonce(difficulty, hash, pubkey, &result, nonce) {
+                    warn!(%pubkey, "worker returned invalid nonce");
+                    continue;
+                }
+
+                results[*index] = Some((result, nonce));
+
+                if let Some(on_result) = on_result {
+                    let _ = on_result.send((*index, result, nonce));
                 }
-            },
-            _ => {
-                error!(miner = id, "clock account doesn't exist");
-                return None;
             }
         };
 
-        let mut buses = [Bus { id: 0, rewards: 0 }; ore::BUS_COUNT];
-        let mut proofs = Vec::with_capacity(proof_count);
+        let (mined, ()) = tokio::join!(mining, verifying);
+        mined
+    }
 
-        for bus in buses.iter_mut() {
-            *bus = parse_account("bus", accounts.next())?;
-        }
+    pub fn find_buses(buses: [Bus; ore::BUS_COUNT], required_reward: u64) -> Vec<Bus> {
+        let mut available_bus = buses
+            .into_iter()
+            .filter(|bus| bus.rewards >= required_reward)
+            .collect::<Vec<_>>();
 
-        for _ in 0..proof_count {
-            proofs.push(parse_account("proof", accounts.next())?);
-        }
+        available_bus.sort_by_key(|bus| std::cmp::Reverse(bus.rewards));
 
-        Some((treasury, clock, buses, proofs))
+        available_bus
     }
 
     pub fn get_time_to_next_epoch(treasury: &Treasury, clock: &Clock, reset_threshold: i64) -> Duration {
@@ -259,80 +857,83 @@ impl Miner {
     }
 
     async fn get_system_accounts(client: &RpcClient) -> eyre::Result<(Treasury, Clock, [Bus; ore::BUS_COUNT])> {
-        pub const SYSTEM_ACCOUNTS: &[Pubkey] = &[
-            ore::TREASURY_ADDRESS,
-            sysvar::clock::ID,
-            ore::BUS_ADDRESSES[0],
-            ore::BUS_ADDRESSES[1],
-            ore::BUS_ADDRESSES[2],
-            ore::BUS_ADDRESSES[3],
-            ore::BUS_ADDRESSES[4],
-            ore::BUS_ADDRESSES[5],
-            ore::BUS_ADDRESSES[6],
-            ore::BUS_ADDRESSES[7],
-        ];
-
-        let accounts = match client
-            .get_multiple_accounts_with_commitment(SYSTEM_ACCOUNTS, CommitmentConfig::processed())
+        let mut accounts = client
+            .get_multiple_accounts_with_commitment(
+                cluster::system_accounts(),
+                utils::commitment_or(CommitmentConfig::processed()),
+            )
             .await
-        {
-            Ok(accounts) => accounts.value,
-            Err(err) => bail!("failed to fetch accounts: {err}"),
-        };
+            .map_err(|err| AccountError::RpcError(err.to_string()))?
+            .value;
 
-        let mut accounts = accounts.into_iter();
-        let treasury: Treasury =
-            parse_account("treasury", accounts.next()).context("failed to parse treasury account")?;
+        // The RPC answers an entry per pubkey, a short answer reads as missing
+        // accounts.
+        accounts.resize(cluster::system_accounts().len(), None);
+        let mut accounts = cluster::system_accounts().iter().zip(accounts);
 
-        let clock: Clock = match accounts.next() {
-            Some(Some(account)) => match bincode::deserialize::<Clock>(account.data()) {
-                Ok(account) => account,
-                Err(err) => bail!("failed to deserialize clock account: {err:#}"),
-            },
-            _ => bail!("clock account doesn't exist"),
-        };
+        let (pubkey, account) = accounts.next().unwrap();
+        let treasury: Treasury = parse_account(pubkey, account)?;
+
+        let (pubkey, account) = accounts.next().unwrap();
+        let account = account.ok_or(AccountError::NotFound { pubkey: *pubkey })?;
+        let clock = bincode::deserialize::<Clock>(account.data()).map_err(|err| AccountError::DeserializeFailed {
+            pubkey: *pubkey,
+            source: err,
+        })?;
 
         let mut buses = [Bus { id: 0, rewards: 0 }; ore::BUS_COUNT];
         for bus in buses.iter_mut() {
-            *bus = parse_account("bus", accounts.next()).context("failed to parse bus account")?;
+            let (pubkey, account) = accounts.next().unwrap();
+            *bus = parse_account(pubkey, account)?;
         }
 
         Ok((treasury, clock, buses))
     }
 
     async fn get_proof_accounts(client: &RpcClient, accounts: &[Pubkey]) -> eyre::Result<Vec<Proof>> {
-        let account_data = match client
-            .get_multiple_accounts_with_commitment(accounts, CommitmentConfig::processed())
-            .await
-        {
-            Ok(accounts) => accounts.value,
-            Err(err) => bail!("failed to get proof accounts: {err}"),
-        };
+        let (account_data, _) = utils::fetch_chunked(accounts, constant::FETCH_ACCOUNT_LIMIT, |chunk| async move {
+            client
+                .get_multiple_accounts_with_commitment(chunk, utils::commitment_or(CommitmentConfig::processed()))
+                .await
+                .map(|response| (response.value, response.context.slot))
+                .map_err(|err| AccountError::RpcError(err.to_string()))
+        })
+        .await?;
 
-        let mut proofs = vec![];
-
-        for (i, account) in account_data.into_iter().enumerate() {
-            let account = match account {
-                None => bail!("account {} not registered", accounts[i]),
-                Some(a) => a,
-            };
+        let proofs = accounts
+            .iter()
+            .zip(account_data)
+            .map(|(pubkey, account)| parse_account(pubkey, account))
+            .collect::<Result<Vec<Proof>, _>>()?;
 
-            let proof = match Proof::try_from_bytes(account.data()) {
-                Ok(proof) => proof,
-                Err(err) => bail!("failed to deserialize proof account {}: {err:#}", accounts[i]),
-            };
+        Ok(proofs)
+    }
 
-            proofs.push(*proof);
-        }
+    /// Re-fetches the proofs and returns how much `total_rewards` grew for each
+    /// of them since `total_rewards_before`.
+    pub async fn get_rewards_delta(
+        client: &RpcClient,
+        proof_pda: &[Pubkey],
+        total_rewards_before: &[u64],
+    ) -> eyre::Result<Vec<u64>> {
+        let proofs = Self::get_proof_accounts(client, proof_pda).await?;
 
-        Ok(proofs)
+        Ok(proofs
+            .iter()
+            .zip(total_rewards_before)
+            .map(|(proof, before)| proof.total_rewards.saturating_sub(*before))
+            .collect())
     }
 
     pub async fn get_balances(client: &RpcClient, accounts: &[Pubkey]) -> eyre::Result<HashMap<Pubkey, u64>> {
-        let account_data = match client.get_multiple_accounts(accounts).await {
-            Ok(a) => a,
-            Err(err) => eyre::bail!("fail to get accounts: {err:#}"),
-        };
+        let (account_data, _) = utils::fetch_chunked(accounts, constant::FETCH_ACCOUNT_LIMIT, |chunk| async move {
+            client
+                .get_multiple_accounts_with_commitment(chunk, client.commitment())
+                .await
+                .map(|response| (response.value, response.context.slot))
+        })
+        .await
+        .map_err(|err| eyre::eyre!("fail to get accounts: {err:#}"))?;
 
         let result = account_data
             .into_iter()
@@ -344,39 +945,221 @@ impl Miner {
         Ok(result)
     }
 
+    /// The statuses in the order of `signatures`, with the lowest slot the
+    /// requests were answered at so that nothing is given up too early.
     pub async fn get_signature_statuses(
         client: &RpcClient,
         signatures: &[Signature],
     ) -> eyre::Result<(Vec<Option<TransactionStatus>>, Slot)> {
-        let signatures_params = signatures.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        utils::fetch_chunked(signatures, constant::SIGNATURE_STATUS_LIMIT, |chunk| async move {
+            let signatures_params = chunk.iter().map(|s| s.to_string()).collect::<Vec<_>>();
 
-        let (statuses, slot) = match client
-            .send::<Response<Vec<Option<TransactionStatus>>>>(
-                RpcRequest::GetSignatureStatuses,
-                json!([signatures_params]),
-            )
-            .await
-        {
-            Ok(result) => (result.value, result.context.slot),
-            Err(err) => eyre::bail!("fail to get bundle status: {err}"),
-        };
+            client
+                .send::<Response<Vec<Option<TransactionStatus>>>>(
+                    RpcRequest::GetSignatureStatuses,
+                    json!([signatures_params]),
+                )
+                .await
+                .map(|response| (response.value, response.context.slot))
+        })
+        .await
+        .map_err(|err| eyre::eyre!("fail to get bundle status: {err}"))
+    }
+}
+
+/// Why an account could not be read. A missing or undecodable account stays
+/// so when fetched again, an RPC error may not.
+#[derive(Debug, thiserror::Error)]
+pub enum AccountError {
+    #[error("account {pubkey} doesn't exist")]
+    NotFound { pubkey: Pubkey },
+
+    #[error("failed to deserialize account {pubkey}: {source}")]
+    DeserializeFailed {
+        pubkey: Pubkey,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[error("failed to fetch accounts: {0}")]
+    RpcError(String),
+}
 
-        Ok((statuses, slot))
+impl AccountError {
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::RpcError(_))
+    }
+
+    /// The account that can't be used, when it is the account and not the RPC
+    /// at fault.
+    pub fn unusable_account(err: &eyre::Report) -> Option<Pubkey> {
+        match err.downcast_ref::<AccountError>()? {
+            Self::NotFound { pubkey } | Self::DeserializeFailed { pubkey, .. } => Some(*pubkey),
+            Self::RpcError(_) => None,
+        }
     }
 }
 
-pub fn parse_account<S: AccountDeserialize + Copy>(name: &str, account: Option<Option<Account>>) -> Option<S> {
-    match account {
-        Some(Some(account)) => match S::try_from_bytes(account.data()) {
-            Ok(account) => Some(*account),
-            Err(err) => {
-                error!("failed to deserialize {name} account: {err:#}",);
-                None
-            }
-        },
-        _ => {
-            error!("{name} account doesn't exist");
-            None
+pub fn parse_account<S: AccountDeserialize + Copy>(
+    pubkey: &Pubkey,
+    account: Option<Account>,
+) -> Result<S, AccountError> {
+    let account = account.ok_or(AccountError::NotFound { pubkey: *pubkey })?;
+
+    S::try_from_bytes(account.data())
+        .copied()
+        .map_err(|err| AccountError::DeserializeFailed {
+            pubkey: *pubkey,
+            source: Box::new(err),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty folder of its own under the system temp dir.
+    fn temp_folder(name: &str) -> PathBuf {
+        let folder = std::env::temp_dir().join(format!("ore-miner-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&folder);
+        fs::create_dir_all(&folder).unwrap();
+        folder
+    }
+
+    fn write_keypair(path: PathBuf, keypair: &Keypair) {
+        fs::write(path, serde_json::to_string(&keypair.to_bytes().to_vec()).unwrap()).unwrap();
+    }
+
+    /// Spawning a stub while another test still writes its own fails with
+    /// "text file busy", the worker tests run one at a time.
+    #[cfg(unix)]
+    static WORKER_STUBS: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    /// A CPU worker running `script`, its first argument is `--persistent`
+    /// when spawned as a long-lived worker.
+    #[cfg(unix)]
+    fn stub_worker(name: &str, script: &str) -> WorkerSpec {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = temp_folder(name).join("nonce-worker");
+        fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+
+        WorkerSpec {
+            path,
+            gpu: false,
+            stream: true,
+            device: None,
         }
     }
+
+    #[cfg(unix)]
+    async fn mine_with_stub(
+        worker: WorkerSpec,
+        timeout: Duration,
+    ) -> eyre::Result<(Duration, Vec<Option<(Hash, u64)>>)> {
+        let miner = Miner::parse_from(["ore-miner", "jito-tip-stream"]);
+        let inputs = [(Hash::default(), Pubkey::new_unique())];
+
+        miner
+            .try_mine_hashes(worker, 1, timeout, &Hash::default(), &inputs, None)
+            .await
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn worker_never_writing_output_times_out() {
+        let _lock = WORKER_STUBS.lock().await;
+        let worker = stub_worker("worker-silent", "exec sleep 60");
+        let start = Instant::now();
+
+        let err = mine_with_stub(worker, Duration::from_millis(100)).await.unwrap_err();
+
+        assert!(format!("{err:#}").contains("timed out"), "{err:#}");
+        assert!(start.elapsed() < Duration::from_millis(100) + constant::WORKER_DEADLINE_GRACE * 2);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn worker_exiting_early_fails() {
+        let _lock = WORKER_STUBS.lock().await;
+        let worker = stub_worker("worker-exit", "exit 1");
+
+        assert!(mine_with_stub(worker, Duration::from_secs(10)).await.is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn worker_writing_truncated_records_fails() {
+        let _lock = WORKER_STUBS.lock().await;
+        let worker = stub_worker(
+            "worker-truncated",
+            "[ \"$1\" = --persistent ] && exit 1\ncat > /dev/null\nprintf abc",
+        );
+
+        let err = mine_with_stub(worker, Duration::from_secs(10)).await.unwrap_err();
+
+        assert!(format!("{err:#}").contains("not a multiple of 44"), "{err:#}");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn worker_writing_invalid_nonces_solves_nothing() {
+        let _lock = WORKER_STUBS.lock().await;
+        let worker = stub_worker(
+            "worker-invalid",
+            "[ \"$1\" = --persistent ] && exit 1\ncat > /dev/null\nhead -c 44 /dev/zero",
+        );
+
+        let (_, results) = mine_with_stub(worker, Duration::from_secs(10)).await.unwrap();
+
+        assert_eq!(results, vec![None]);
+    }
+
+    #[test]
+    fn read_keys_skips_invalid_and_duplicate_files() {
+        let folder = temp_folder("read-keys");
+        let (first, second) = (Keypair::new(), Keypair::new());
+
+        write_keypair(folder.join("a.json"), &first);
+        fs::write(folder.join("b.txt"), second.to_base58_string()).unwrap();
+        write_keypair(folder.join("c.json"), &first);
+        fs::write(folder.join("d.json"), "not a keypair").unwrap();
+        fs::create_dir(folder.join("nested")).unwrap();
+        write_keypair(folder.join("nested").join("e.json"), &Keypair::new());
+
+        let keys = Miner::read_keys(folder.to_str().unwrap(), false).unwrap();
+        let pubkeys = keys.iter().map(Keypair::pubkey).collect::<Vec<_>>();
+
+        assert_eq!(pubkeys, vec![first.pubkey(), second.pubkey()]);
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn read_keys_reads_subfolders_with_recursive() {
+        let folder = temp_folder("read-keys-recursive");
+        let (top, nested) = (Keypair::new(), Keypair::new());
+
+        write_keypair(folder.join("a.json"), &top);
+        fs::create_dir(folder.join("nested")).unwrap();
+        write_keypair(folder.join("nested").join("b.json"), &nested);
+        write_keypair(folder.join("nested").join("c.json"), &top);
+
+        let keys = Miner::read_keys(folder.to_str().unwrap(), true).unwrap();
+        let pubkeys = keys.iter().map(Keypair::pubkey).collect::<Vec<_>>();
+
+        assert_eq!(pubkeys, vec![top.pubkey(), nested.pubkey()]);
+
+        fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn read_keys_fails_without_any_keypair() {
+        let folder = temp_folder("read-keys-empty");
+        fs::write(folder.join("a.json"), "not a keypair").unwrap();
+
+        assert!(Miner::read_keys(folder.to_str().unwrap(), true).is_err());
+
+        fs::remove_dir_all(folder).unwrap();
+    }
 }
@@ -0,0 +1,162 @@
+use std::{collections::HashMap, net::SocketAddr};
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram,
+    register_int_counter,
+    register_int_gauge,
+    register_int_gauge_vec,
+    Encoder,
+    Histogram,
+    IntCounter,
+    IntGauge,
+    IntGaugeVec,
+    TextEncoder,
+};
+use solana_sdk::pubkey::Pubkey;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use tracing::{debug, info};
+
+pub static BUNDLES_SENT: Lazy<IntCounter> =
+    Lazy::new(|| register_int_counter!("bundles_sent_total", "Bundles sent to jito").unwrap());
+
+pub static BUNDLES_LANDED: Lazy<IntCounter> =
+    Lazy::new(|| register_int_counter!("bundles_landed_total", "Bundles confirmed on chain").unwrap());
+
+pub static BUNDLES_DROPPED: Lazy<IntCounter> =
+    Lazy::new(|| register_int_counter!("bundles_dropped_total", "Bundles expired without landing").unwrap());
+
+pub static BUNDLES_INVALIDATED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "bundles_invalidated_total",
+        "Bundles given up on because the epoch reset before they landed"
+    )
+    .unwrap()
+});
+
+pub static TIPS_PAID: Lazy<IntCounter> =
+    Lazy::new(|| register_int_counter!("tips_paid_lamports_total", "Jito tips paid by landed bundles").unwrap());
+
+pub static TXS_FAILED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "transactions_failed_total",
+        "Transactions confirmed with an error, they earned or claimed nothing"
+    )
+    .unwrap()
+});
+
+pub static LOST_BATCHES_RECOVERED: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "lost_batches_recovered_total",
+        "Signer batches requeued after being dropped by a failing task"
+    )
+    .unwrap()
+});
+
+pub static MINING_DURATION: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "mining_duration_seconds",
+        "Time spent mining a round",
+        vec![1.0, 2.0, 5.0, 10.0, 15.0, 20.0, 30.0, 45.0, 60.0]
+    )
+    .unwrap()
+});
+
+pub static ADAPTIVE_TIP: Lazy<IntGauge> =
+    Lazy::new(|| register_int_gauge!("adaptive_tip_lamports", "Tip paid by the last bundle").unwrap());
+
+pub static JITO_TIP_P50: Lazy<IntGauge> =
+    Lazy::new(|| register_int_gauge!("jito_tip_p50_lamports", "Median landed tip of the jito tip stream").unwrap());
+
+pub static WALLET_BALANCE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!("wallet_balance_lamports", "SOL balance of the wallets", &["wallet"]).unwrap()
+});
+
+pub static CLAIMABLE_REWARDS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "claimable_rewards",
+        "Claimable $ORE of the wallets, in base units",
+        &["wallet"]
+    )
+    .unwrap()
+});
+
+pub fn set_balances(balances: &HashMap<Pubkey, u64>) {
+    for (pubkey, balance) in balances {
+        WALLET_BALANCE
+            .with_label_values(&[&pubkey.to_string()])
+            .set(*balance as i64);
+    }
+}
+
+pub fn set_claimable_rewards(pubkey: &Pubkey, rewards: u64) {
+    CLAIMABLE_REWARDS
+        .with_label_values(&[&pubkey.to_string()])
+        .set(rewards as i64);
+}
+
+/// Serves the default registry in the text format on every request, whatever
+/// the path.
+pub async fn serve(addr: SocketAddr) -> eyre::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+
+    // Registered on first use otherwise, the metrics are listed from the start.
+    Lazy::force(&BUNDLES_SENT);
+    Lazy::force(&BUNDLES_LANDED);
+    Lazy::force(&BUNDLES_DROPPED);
+    Lazy::force(&BUNDLES_INVALIDATED);
+    Lazy::force(&TIPS_PAID);
+    Lazy::force(&TXS_FAILED);
+    Lazy::force(&LOST_BATCHES_RECOVERED);
+    Lazy::force(&MINING_DURATION);
+    Lazy::force(&ADAPTIVE_TIP);
+    Lazy::force(&JITO_TIP_P50);
+    Lazy::force(&WALLET_BALANCE);
+    Lazy::force(&CLAIMABLE_REWARDS);
+
+    info!(%addr, "serving metrics");
+
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, peer) = match listener.accept().await {
+                Ok(value) => value,
+                Err(err) => {
+                    debug!("fail to accept metrics connection: {err:#}");
+                    continue;
+                }
+            };
+
+            tokio::spawn(async move {
+                // The request itself doesn't matter, it is read so that the
+                // client doesn't see a reset.
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request).await;
+
+                let mut body = vec![];
+                let encoder = TextEncoder::new();
+                encoder.encode(&prometheus::gather(), &mut body).unwrap();
+
+                let header = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    encoder.format_type(),
+                    body.len()
+                );
+
+                if let Err(err) = async {
+                    stream.write_all(header.as_bytes()).await?;
+                    stream.write_all(&body).await?;
+                    stream.shutdown().await
+                }
+                .await
+                {
+                    debug!(%peer, "fail to write metrics: {err:#}");
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
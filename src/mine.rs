@@ -0,0 +1,378 @@
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use rand::Rng;
+use solana_client::{nonblocking::rpc_client::RpcClient, rpc_config::RpcSendTransactionConfig};
+use solana_sdk::{
+    compute_budget::ComputeBudgetInstruction,
+    instruction::Instruction,
+    signature::Keypair,
+    signer::Signer,
+    transaction::Transaction,
+};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    blockhash::{self, BlockhashCache},
+    cluster,
+    compute_budget,
+    constant,
+    format_duration,
+    format_reward,
+    metrics,
+    notify,
+    program_error,
+    stats::{self, MiningStats},
+    utils,
+    utils::{BackoffPolicy, TxOutcome},
+    wait_continue,
+    AccountError,
+    Miner,
+};
+
+/// Compute units of a transaction with a single reset instruction.
+const RESET_COMPUTE_UNITS: u32 = 12_200;
+
+/// Compute units of a transaction with a single register instruction.
+const REGISTER_COMPUTE_UNITS: u32 = 7_660;
+
+/// Only one miner in this many sends the reset of an expired epoch, the
+/// others wait for it.
+const RESET_ODDS: u64 = 20;
+
+#[derive(Debug, Clone, Parser)]
+pub struct MineArgs {
+    #[arg(long, help = "Keypair of the wallet to mine with")]
+    pub keypair: String,
+
+    #[arg(
+        long,
+        default_value_t = utils::available_threads(),
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        help = "Number of threads to use for nonce calculation, defaults to the number of CPUs. With --gpu, the \
+                threads of the CPU fallback"
+    )]
+    pub threads: usize,
+
+    #[arg(long, default_value = "false", help = "Mine with the GPU worker")]
+    pub gpu: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Register the wallet first when it has no proof, it is only reported otherwise"
+    )]
+    pub register: bool,
+}
+
+impl Miner {
+    /// Mines with a single wallet, sending a plain transaction with
+    /// `--priority-fee` as the compute unit price instead of a jito bundle.
+    pub async fn mine(&self, args: &MineArgs) {
+        let signer = match utils::read_keypair(&args.keypair) {
+            Ok(signer) => signer,
+            Err(err) => {
+                error!("{err:#}");
+                return;
+            }
+        };
+
+        let client = Miner::get_client_confirmed(&self.rpc);
+        let blockhash_cache = BlockhashCache::spawn(client.clone(), &self.rpc);
+        let stats = MiningStats::default();
+        let proof_pda = utils::get_proof_pda_no_cache(signer.pubkey());
+        let wallet = signer.pubkey();
+        let wallets = [wallet];
+        let proof_pdas = [proof_pda];
+
+        info!(%wallet, gpu = args.gpu, "mining started");
+
+        while !stats::stop_requested() {
+            let price = self.current_priority_fee().unwrap_or(0);
+
+            let balances = match utils::retry_with_backoff("get wallet balance", BackoffPolicy::RPC, || {
+                Self::get_balances(&client, &wallets)
+            })
+            .await
+            {
+                Ok(balances) => balances,
+                Err(err) => {
+                    error!("{err:#}");
+                    wait_continue!(500);
+                }
+            };
+
+            metrics::set_balances(&balances);
+            notify::check_balances(&wallets, &balances);
+
+            let (treasury, clock, buses) =
+                match utils::retry_with_backoff("fetch system accounts", BackoffPolicy::RPC, || {
+                    Self::get_system_accounts(&client)
+                })
+                .await
+                {
+                    Ok(accounts) => accounts,
+                    Err(err) => {
+                        error!("{err:#}");
+                        wait_continue!(500);
+                    }
+                };
+
+            let proof = match utils::retry_with_backoff("fetch proof account", BackoffPolicy::RPC, || {
+                Self::get_proof_accounts(&client, &proof_pdas)
+            })
+            .await
+            {
+                Ok(proofs) => proofs[0],
+                Err(err) if matches!(err.downcast_ref(), Some(AccountError::NotFound { .. })) => {
+                    if !args.register {
+                        error!(
+                            %wallet,
+                            "{err:#}, the wallet isn't registered. Run again with --register or use the register \
+                             subcommand"
+                        );
+                        return;
+                    }
+
+                    info!(%wallet, "wallet isn't registered, registering");
+
+                    let ix = cluster::instruction(ore::instruction::register(wallet));
+
+                    match Self::send_and_confirm(&client, &blockhash_cache, &signer, price, REGISTER_COMPUTE_UNITS, ix)
+                        .await
+                    {
+                        Ok((_, TxOutcome::Landed { slot })) => info!(%wallet, slot, "wallet registered"),
+                        Ok((tx, TxOutcome::Failed { err, .. })) => {
+                            error!(
+                                "register failed for {wallet}: {}",
+                                program_error::describe(&err, Some(&tx))
+                            );
+                            return;
+                        }
+                        Ok((_, TxOutcome::Pending)) => warn!(%wallet, "register transaction dropped, retrying"),
+                        Err(err) => {
+                            error!(%wallet, "fail to register: {err:#}");
+                            wait_continue!(500);
+                        }
+                    }
+
+                    continue;
+                }
+                Err(err) => {
+                    error!("{err:#}");
+                    wait_continue!(500);
+                }
+            };
+
+            metrics::set_claimable_rewards(&wallet, proof.claimable_rewards);
+
+            let reset_threshold = treasury.last_reset_at.saturating_add(ore::EPOCH_DURATION);
+
+            if clock.unix_timestamp >= reset_threshold {
+                // Mining against an expired epoch fails, somebody has to reset
+                // it first.
+                if rand::thread_rng().gen_range(0..RESET_ODDS) == 0 {
+                    info!("epoch expired, sending reset");
+
+                    let ix = cluster::instruction(ore::instruction::reset(wallet));
+
+                    if let Err(err) =
+                        Self::send_and_confirm(&client, &blockhash_cache, &signer, price, RESET_COMPUTE_UNITS, ix).await
+                    {
+                        warn!("fail to reset the epoch: {err:#}");
+                    }
+                } else {
+                    debug!("epoch expired, waiting for the reset");
+                }
+
+                wait_continue!(1000);
+            }
+
+            let time_to_next_epoch = Self::get_time_to_next_epoch(&treasury, &clock, reset_threshold);
+            let difficulty: solana_sdk::keccak::Hash = treasury.difficulty.into();
+            let hash_and_pubkey = [(proof.hash.into(), wallet)];
+
+            let mined = match args.gpu {
+                true => self
+                    .mine_hashes_gpu(
+                        &[],
+                        args.threads,
+                        true,
+                        time_to_next_epoch,
+                        &difficulty,
+                        &hash_and_pubkey,
+                        None,
+                    )
+                    .await
+                    .map(|(duration, results)| (duration, results[0])),
+                false => self
+                    .mine_hashes_cpu(args.threads, &difficulty, &hash_and_pubkey)
+                    .await
+                    .map(|(duration, results)| (duration, Some(results[0]))),
+            };
+
+            let (mining_duration, (hash, nonce)) = match mined {
+                Ok((duration, Some(result))) => (duration, result),
+                Ok((_, None)) => {
+                    warn!("no nonce found before the end of the epoch");
+                    continue;
+                }
+                Err(err) => {
+                    error!("fail to mine: {err:#}");
+                    wait_continue!(500);
+                }
+            };
+
+            stats.record_round(mining_duration);
+
+            if mining_duration > time_to_next_epoch {
+                warn!("mining took too long, waiting for next epoch");
+                wait_continue!(time_to_next_epoch.as_millis() as u64);
+            }
+
+            let bus = match Self::find_buses(buses, treasury.reward_rate).first() {
+                Some(bus) => bus.id,
+                None => {
+                    warn!("no bus available for mining, waiting for next epoch");
+                    wait_continue!(time_to_next_epoch.as_millis() as u64);
+                }
+            };
+
+            let ix = cluster::instruction(ore::instruction::mine(
+                wallet,
+                cluster::bus_address(bus),
+                hash.into(),
+                nonce,
+            ));
+            compute_budget::measure(&client, wallet, std::slice::from_ref(&ix), 1).await;
+
+            let compute_units = compute_budget::mine_limit(1);
+            let charged_units = compute_budget::charged_units(1);
+            let confirm_start = Instant::now();
+
+            stats.record_sent();
+
+            let (tx, outcome) =
+                match Self::send_and_confirm(&client, &blockhash_cache, &signer, price, compute_units, ix).await {
+                    Ok(value) => value,
+                    Err(err) => {
+                        error!("fail to send transaction: {err:#}");
+                        stats.record_dropped();
+                        wait_continue!(500);
+                    }
+                };
+
+            let confirm_duration = confirm_start.elapsed();
+
+            match outcome {
+                TxOutcome::Landed { slot } => {
+                    let rewards = match Self::get_rewards_delta(&client, &proof_pdas, &[proof.total_rewards]).await {
+                        Ok(deltas) => deltas[0],
+                        Err(err) => {
+                            warn!("fail to fetch rewards, using estimate: {err:#}");
+                            treasury.reward_rate
+                        }
+                    };
+
+                    info!(
+                        mining = format_duration!(mining_duration),
+                        confirm = format_duration!(confirm_duration),
+                        rewards = format_reward!(rewards),
+                        bus,
+                        slot,
+                        "transaction mined"
+                    );
+
+                    let fee = price * charged_units as u64 / 1_000_000;
+                    stats.record_landed(fee, rewards, confirm_duration);
+                }
+                TxOutcome::Failed { slot, err } => {
+                    stats.record_failed(1);
+                    warn!(
+                        slot,
+                        bus,
+                        "transaction landed but failed: {}",
+                        program_error::describe(&err, Some(&tx))
+                    );
+                }
+                TxOutcome::Pending => {
+                    stats.record_dropped();
+                    warn!(
+                        mining = format_duration!(mining_duration),
+                        confirm = format_duration!(confirm_duration),
+                        price,
+                        "transaction dropped"
+                    );
+                }
+            }
+        }
+
+        stats.log_summary();
+    }
+
+    /// Sends `ix` with a compute budget of `compute_units`, the default one
+    /// without, at `price` micro-lamports each and waits until it is confirmed or its blockhash
+    /// expires, `Pending` then meaning dropped. The transaction is returned
+    /// with its outcome to describe its errors.
+    async fn send_and_confirm(
+        client: &RpcClient,
+        blockhash_cache: &BlockhashCache,
+        signer: &Keypair,
+        price: u64,
+        compute_units: Option<u32>,
+        ix: Instruction,
+    ) -> eyre::Result<(Transaction, TxOutcome)> {
+        let mut ixs = vec![];
+
+        if let Some(compute_units) = compute_units {
+            ixs.push(ComputeBudgetInstruction::set_compute_unit_limit(compute_units));
+        }
+
+        ixs.push(ComputeBudgetInstruction::set_compute_unit_price(price));
+        ixs.push(ix);
+
+        let (send_at_slot, blockhash) = blockhash_cache.get(blockhash::MAX_AGE).await?;
+        let tx = Transaction::new_signed_with_payer(&ixs, Some(&signer.pubkey()), &[signer], blockhash);
+
+        // Sent again every status check instead of leaving the retries to
+        // the RPC, which may drop it.
+        let send_cfg = RpcSendTransactionConfig {
+            skip_preflight: true,
+            max_retries: Some(0),
+            min_context_slot: Some(send_at_slot),
+            ..RpcSendTransactionConfig::default()
+        };
+
+        let signature = client.send_transaction_with_config(&tx, send_cfg).await?;
+
+        debug!(%signature, slot = send_at_slot, "transaction sent");
+
+        let mut latest_slot = send_at_slot;
+
+        while latest_slot < send_at_slot + constant::SLOT_EXPIRATION {
+            tokio::time::sleep(Duration::from_secs(2)).await;
+
+            let (statuses, slot) = match Self::get_signature_statuses(client, &[signature]).await {
+                Ok(value) => value,
+                Err(err) => {
+                    warn!(%signature, "fail to get transaction status: {err:#}");
+                    continue;
+                }
+            };
+
+            latest_slot = slot;
+
+            let (_, outcome) = utils::find_tx_outcomes(&[signature], statuses).remove(0);
+
+            if outcome.is_confirmed() {
+                return Ok((tx, outcome));
+            }
+
+            if let Err(err) = client.send_transaction_with_config(&tx, send_cfg).await {
+                debug!(%signature, "fail to resend transaction: {err:#}");
+            }
+        }
+
+        Ok((tx, TxOutcome::Pending))
+    }
+}
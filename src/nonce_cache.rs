@@ -0,0 +1,42 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use solana_sdk::{keccak::Hash, pubkey::Pubkey};
+
+#[derive(Debug, Clone, Copy)]
+struct CachedNonce {
+    proof_hash: Hash,
+    difficulty: Hash,
+    hash: Hash,
+    nonce: u64,
+}
+
+/// Last nonce found for each wallet. A dropped bundle leaves the proof hash
+/// untouched, so the nonce stays valid until a bundle lands or the difficulty
+/// changes and the wallet doesn't need to be mined again.
+#[derive(Debug, Default)]
+pub struct NonceCache(Mutex<HashMap<Pubkey, CachedNonce>>);
+
+impl NonceCache {
+    pub fn get(&self, pubkey: &Pubkey, proof_hash: &Hash, difficulty: &Hash) -> Option<(Hash, u64)> {
+        let cache = self.0.lock().unwrap();
+        let cached = cache.get(pubkey)?;
+
+        if cached.proof_hash != *proof_hash || cached.difficulty != *difficulty {
+            return None;
+        }
+
+        Some((cached.hash, cached.nonce))
+    }
+
+    pub fn insert(&self, pubkey: Pubkey, proof_hash: Hash, difficulty: Hash, (hash, nonce): (Hash, u64)) {
+        self.0.lock().unwrap().insert(
+            pubkey,
+            CachedNonce {
+                proof_hash,
+                difficulty,
+                hash,
+                nonce,
+            },
+        );
+    }
+}
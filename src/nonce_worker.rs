@@ -1,6 +1,10 @@
 use std::{
     io::{Read, Write},
-    sync::{atomic::AtomicBool, Arc},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
 use sha3::{
@@ -8,59 +12,295 @@ use sha3::{
     Keccak256,
 };
 
+/// Little endian u16 thread count, little endian u32 deadline in milliseconds
+/// (0 for none) and the difficulty.
+const HEADER_SIZE: usize = 2 + 4 + 32;
+
+/// Set while a job is being mined.
+static BUSY: AtomicBool = AtomicBool::new(false);
+
+/// Set by SIGINT/SIGTERM during a job, the job ends with the results found so
+/// far and the worker exits.
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
 fn main() {
-    let mut threads_and_diff = [0u8; 33];
+    register_signals();
+
+    if std::env::args().any(|arg| arg == "--persistent") {
+        serve_jobs(std::env::args().any(|arg| arg == "--stream"));
+    } else {
+        mine_once();
+    }
+}
+
+/// An idle worker exits right away on SIGINT/SIGTERM, a busy one stops mining
+/// and leaves it to the job loop to flush the results and exit.
+fn register_signals() {
+    for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+        // SAFETY: the handler only touches atomics and calls the async-signal-
+        // safe exit.
+        unsafe {
+            signal_hook::low_level::register(signal, || {
+                if BUSY.load(Ordering::SeqCst) {
+                    CANCELLED.store(true, Ordering::SeqCst);
+                } else {
+                    signal_hook::low_level::exit(0);
+                }
+            })
+        }
+        .expect("fail to register signal handler");
+    }
+}
+
+fn cancelled() -> bool {
+    CANCELLED.load(Ordering::Relaxed)
+}
+
+/// One-shot mode: read a single job until stdin closes, write a 44 bytes
+/// result record to stdout for every input solved before the deadline.
+fn mine_once() {
+    let mut header = [0u8; HEADER_SIZE];
     let mut preimage = [0u8; 32 + 32];
 
     let mut stdin = std::io::stdin().lock();
-    stdin.read_exact(&mut threads_and_diff).unwrap();
+    stdin.read_exact(&mut header).unwrap();
 
-    let threads = threads_and_diff[0] as usize;
-    let difficulty: [u8; 32] = threads_and_diff[1..].try_into().unwrap();
+    let (threads, deadline, difficulty) = parse_header(&header);
 
-    while stdin.read_exact(&mut preimage[..64]).is_ok() {
-        let found = Arc::new(AtomicBool::new(false));
-        let thread_handles: Vec<_> = (0..threads)
-            .map(|i| {
-                let preimage = preimage;
-                let found = found.clone();
+    BUSY.store(true, Ordering::SeqCst);
 
-                let mut hasher = Keccak256::default();
-                let mut hash_result = Default::default();
+    with_progress(|hashes| {
+        let mut index = 0u32;
 
-                std::thread::spawn(move || {
-                    let mut nonce: u64 = u64::MAX.saturating_div(threads as u64).saturating_mul(i as u64);
+        while !cancelled() && stdin.read_exact(&mut preimage[..64]).is_ok() {
+            if let Some((hash, nonce)) = find_nonce(threads, deadline, difficulty, preimage, hashes.clone()) {
+                let mut stdout = std::io::stdout().lock();
+                stdout.write_all(&encode_result(index, &hash, nonce)).unwrap();
+            }
 
-                    loop {
-                        hasher.update(&preimage);
-                        hasher.update(&nonce.to_le_bytes());
-                        hasher.finalize_into_reset(&mut hash_result);
+            index += 1;
+        }
+    });
 
-                        if nonce % 10000 == 0 && found.load(std::sync::atomic::Ordering::Relaxed) {
-                            return;
-                        }
+    std::io::stdout().flush().unwrap();
+}
+
+/// Persistent mode: every job is a little endian u32 length followed by the
+/// one-shot payload, every response is a little endian u32 length followed by
+/// the result records. Exits when stdin closes.
+///
+/// With `stream`, each record is instead written as soon as it is found and
+/// the job ends with a `u32::MAX` index.
+///
+/// Inputs left unsolved at the deadline have no record.
+fn serve_jobs(stream: bool) {
+    let mut stdin = std::io::stdin().lock();
+    let mut len = [0u8; 4];
+
+    while stdin.read_exact(&mut len).is_ok() {
+        BUSY.store(true, Ordering::SeqCst);
+
+        let mut job = vec![0u8; u32::from_le_bytes(len) as usize];
+        stdin.read_exact(&mut job).unwrap();
+
+        assert!(
+            job.len() >= HEADER_SIZE && (job.len() - HEADER_SIZE) % 64 == 0,
+            "invalid job length {}",
+            job.len()
+        );
+
+        let (threads, deadline, difficulty) = parse_header(&job[..HEADER_SIZE]);
+
+        let output = with_progress(|hashes| {
+            let mut output = Vec::with_capacity((job.len() - HEADER_SIZE) / 64 * 44);
+
+            for (i, preimage) in job[HEADER_SIZE..].chunks(64).enumerate() {
+                if cancelled() {
+                    break;
+                }
+
+                let preimage = preimage.try_into().unwrap();
+
+                let Some((hash, nonce)) = find_nonce(threads, deadline, difficulty, preimage, hashes.clone()) else {
+                    continue;
+                };
+
+                let record = encode_result(i as u32, &hash, nonce);
+
+                if stream {
+                    let mut stdout = std::io::stdout().lock();
+                    stdout.write_all(&record).unwrap();
+                    stdout.flush().unwrap();
+                } else {
+                    output.extend_from_slice(&record);
+                }
+            }
+
+            output
+        });
+
+        let mut stdout = std::io::stdout().lock();
+
+        if stream {
+            stdout.write_all(&u32::MAX.to_le_bytes()).unwrap();
+        } else {
+            stdout.write_all(&(output.len() as u32).to_le_bytes()).unwrap();
+            stdout.write_all(&output).unwrap();
+        }
+
+        stdout.flush().unwrap();
+        drop(stdout);
+
+        if cancelled() {
+            return;
+        }
+
+        BUSY.store(false, Ordering::SeqCst);
 
-                        if hash_result.as_slice().le(&difficulty) {
-                            if found.swap(true, std::sync::atomic::Ordering::Relaxed) {
-                                return;
-                            }
+        // A signal may have been received between the check and clearing the
+        // flag.
+        if cancelled() {
+            return;
+        }
+    }
+}
+
+/// Parses the header that starts every job, exiting if the thread count is 0.
+/// The deadline counts from now.
+fn parse_header(header: &[u8]) -> (usize, Option<Instant>, [u8; 32]) {
+    let threads = u16::from_le_bytes(header[..2].try_into().unwrap()) as usize;
+    let deadline_ms = u32::from_le_bytes(header[2..6].try_into().unwrap());
+    let difficulty = header[6..HEADER_SIZE].try_into().unwrap();
+
+    if threads == 0 {
+        eprintln!("threads must be greater than 0");
+        std::process::exit(1);
+    }
+
+    let deadline = match deadline_ms {
+        0 => None,
+        ms => Some(Instant::now() + Duration::from_millis(ms as u64)),
+    };
+
+    (threads, deadline, difficulty)
+}
+
+/// Little endian u32 input index, hash and little endian nonce.
+fn encode_result(index: u32, hash: &[u8; 32], nonce: u64) -> [u8; 44] {
+    let mut record = [0u8; 44];
+
+    record[..4].copy_from_slice(&index.to_le_bytes());
+    record[4..36].copy_from_slice(hash);
+    record[36..].copy_from_slice(&nonce.to_le_bytes());
+
+    record
+}
+
+/// Runs `job` while writing `hashes=<n> elapsed_ms=<ms>` progress lines to
+/// stderr every second, and a final `done hashes=<n> elapsed_ms=<ms>` line once
+/// it returns. stdout is left to the results.
+fn with_progress<T>(job: impl FnOnce(&Arc<AtomicU64>) -> T) -> T {
+    let hashes = Arc::new(AtomicU64::new(0));
+    let done = Arc::new(AtomicBool::new(false));
+    let start = Instant::now();
+
+    let reporter = std::thread::spawn({
+        let hashes = hashes.clone();
+        let done = done.clone();
+
+        move || loop {
+            std::thread::park_timeout(Duration::from_secs(1));
+
+            if done.load(Ordering::Relaxed) {
+                return;
+            }
+
+            eprintln!(
+                "hashes={} elapsed_ms={}",
+                hashes.load(Ordering::Relaxed),
+                start.elapsed().as_millis()
+            );
+        }
+    });
 
-                            let mut stdout = std::io::stdout().lock();
+    let result = job(&hashes);
 
-                            stdout.write_all(&hash_result).unwrap();
-                            stdout.write_all(&nonce.to_le_bytes()).unwrap();
+    done.store(true, Ordering::Relaxed);
+    reporter.thread().unpark();
+    reporter.join().unwrap();
+
+    eprintln!(
+        "done hashes={} elapsed_ms={}",
+        hashes.load(Ordering::Relaxed),
+        start.elapsed().as_millis()
+    );
+
+    result
+}
+
+/// Searches a nonce satisfying the difficulty, giving up once the deadline
+/// passes.
+fn find_nonce(
+    threads: usize,
+    deadline: Option<Instant>,
+    difficulty: [u8; 32],
+    preimage: [u8; 64],
+    hashes: Arc<AtomicU64>,
+) -> Option<([u8; 32], u64)> {
+    if cancelled() || deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        return None;
+    }
+
+    let found = Arc::new(AtomicBool::new(false));
+    let thread_handles: Vec<_> = (0..threads)
+        .map(|i| {
+            let found = found.clone();
+            let hashes = hashes.clone();
+
+            let mut hasher = Keccak256::default();
+            let mut hash_result = Default::default();
+
+            std::thread::spawn(move || {
+                let mut nonce: u64 = u64::MAX.saturating_div(threads as u64).saturating_mul(i as u64);
+
+                loop {
+                    hasher.update(&preimage);
+                    hasher.update(&nonce.to_le_bytes());
+                    hasher.finalize_into_reset(&mut hash_result);
+
+                    if nonce % 10000 == 0 {
+                        hashes.fetch_add(10000, Ordering::Relaxed);
+
+                        if found.load(Ordering::Relaxed) {
+                            return None;
                         }
 
-                        nonce += 1;
+                        if cancelled() || deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                            return None;
+                        }
                     }
-                })
+
+                    if hash_result.as_slice().le(&difficulty) {
+                        if found.swap(true, Ordering::Relaxed) {
+                            return None;
+                        }
+
+                        return Some((hash_result.as_slice().try_into().unwrap(), nonce));
+                    }
+
+                    nonce += 1;
+                }
             })
-            .collect();
+        })
+        .collect();
 
-        for thread_handle in thread_handles {
-            thread_handle.join().unwrap();
+    let mut result = None;
+
+    for thread_handle in thread_handles {
+        if let Some(found) = thread_handle.join().unwrap() {
+            result = Some(found);
         }
     }
 
-    std::io::stdout().flush().unwrap();
+    result
 }
@@ -0,0 +1,125 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::OnceCell;
+use serde_json::json;
+use solana_sdk::pubkey::Pubkey;
+use tracing::{error, warn};
+
+/// The same event is sent at most once per interval, so a flapping condition
+/// doesn't flood the channel.
+const NOTIFY_COOLDOWN: Duration = Duration::from_secs(30 * 60);
+
+/// Set once from `--webhook-url`, events are only logged without it.
+static NOTIFIER: OnceCell<Notifier> = OnceCell::new();
+
+struct Notifier {
+    client: reqwest::Client,
+    url: String,
+    consecutive_drops: u64,
+    min_balance: u64,
+    sent_at: Mutex<HashMap<String, Instant>>,
+}
+
+pub fn init(url: String, consecutive_drops: u64, min_balance: u64) {
+    let _ = NOTIFIER.set(Notifier {
+        client: reqwest::Client::new(),
+        url,
+        consecutive_drops,
+        min_balance,
+        sent_at: Mutex::new(HashMap::new()),
+    });
+
+    let default_hook = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+
+        // The runtime may be gone, the message is sent from a thread and a
+        // client of its own before the process unwinds.
+        let message = format!("ore-miner panicked: {info}");
+        let _ = std::thread::spawn(move || {
+            if let (Some(notifier), Ok(runtime)) = (
+                NOTIFIER.get(),
+                tokio::runtime::Builder::new_current_thread().enable_all().build(),
+            ) {
+                runtime.block_on(post(&reqwest::Client::new(), &notifier.url, &message));
+            }
+        })
+        .join();
+    }));
+}
+
+/// Sends `message` to the webhook unless `key` was sent within the cooldown.
+pub fn notify(key: &str, message: String) {
+    let notifier = match NOTIFIER.get() {
+        Some(notifier) => notifier,
+        None => return,
+    };
+
+    {
+        let mut sent_at = notifier.sent_at.lock().unwrap();
+        let now = Instant::now();
+
+        if sent_at
+            .get(key)
+            .is_some_and(|at| now.duration_since(*at) < NOTIFY_COOLDOWN)
+        {
+            return;
+        }
+
+        sent_at.insert(key.to_string(), now);
+    }
+
+    tokio::spawn(post(&notifier.client, &notifier.url, message));
+}
+
+/// `content` is read by Discord, `text` by Slack.
+async fn post(client: &reqwest::Client, url: &str, message: impl AsRef<str>) {
+    let message = message.as_ref();
+    let body = json!({ "content": message, "text": message });
+
+    match client.post(url).json(&body).send().await {
+        Ok(response) if response.status().is_success() => {}
+        Ok(response) => warn!(status = %response.status(), "webhook rejected the notification"),
+        Err(err) => error!("fail to send notification: {err:#}"),
+    }
+}
+
+/// Called with the number of bundles dropped in a row.
+pub fn bundles_dropped(consecutive: u64) {
+    if let Some(notifier) = NOTIFIER.get() {
+        if notifier.consecutive_drops > 0 && consecutive >= notifier.consecutive_drops {
+            notify(
+                "bundles_dropped",
+                format!("{consecutive} bundles dropped in a row, none landed since"),
+            );
+        }
+    }
+}
+
+/// Reports the wallets below `--notify-min-balance`, a wallet missing from
+/// `balances` doesn't exist on chain and holds nothing.
+pub fn check_balances(pubkeys: &[Pubkey], balances: &HashMap<Pubkey, u64>) {
+    let min_balance = match NOTIFIER.get() {
+        Some(notifier) if notifier.min_balance > 0 => notifier.min_balance,
+        _ => return,
+    };
+
+    for pubkey in pubkeys {
+        let balance = balances.get(pubkey).copied().unwrap_or_default();
+
+        if balance < min_balance {
+            notify(
+                &format!("low_balance/{pubkey}"),
+                format!(
+                    "wallet {pubkey} is low on SOL: {} SOL",
+                    spl_token::amount_to_ui_amount(balance, 9)
+                ),
+            );
+        }
+    }
+}
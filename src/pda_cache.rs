@@ -0,0 +1,184 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    sync::Mutex,
+};
+
+use eyre::Context;
+use once_cell::sync::OnceCell;
+use rand::seq::IteratorRandom;
+use solana_sdk::{keccak, pubkey::Pubkey};
+use tracing::{error, info, warn};
+
+/// Entries derived again at startup, any mismatch discards the whole file.
+const VERIFY_SAMPLE: usize = 64;
+
+/// Set once from `--pda-cache`, every address is derived without it.
+static CACHE: OnceCell<Mutex<PdaCache>> = OnceCell::new();
+
+struct PdaCache {
+    entries: HashMap<Derivation, Pubkey>,
+    file: File,
+}
+
+/// Only proof addresses are kept, a wrong one fails the program's checks
+/// while a wrong token account would be sent the funds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Derivation {
+    Proof { program_id: Pubkey, authority: Pubkey },
+}
+
+impl Derivation {
+    fn derive(&self) -> Pubkey {
+        match self {
+            Derivation::Proof { program_id, authority } => {
+                Pubkey::find_program_address(&[ore::PROOF, authority.as_ref()], program_id).0
+            }
+        }
+    }
+
+    /// `proof <program id> <authority>`.
+    fn parts(&self) -> (&'static str, &Pubkey, &Pubkey) {
+        match self {
+            Derivation::Proof { program_id, authority } => ("proof", program_id, authority),
+        }
+    }
+}
+
+/// A line is `<kind> <key> <key> <address> <checksum>`, the checksum catches
+/// a damaged line that still parses to valid pubkeys.
+fn format_line(derivation: &Derivation, address: &Pubkey) -> String {
+    let (kind, a, b) = derivation.parts();
+
+    format!("{kind} {a} {b} {address} {}", checksum(kind, a, b, address))
+}
+
+fn parse_line(line: &str) -> Option<(Derivation, Pubkey)> {
+    let mut parts = line.split_whitespace();
+    let (kind, a, b, address, sum) = (
+        parts.next()?,
+        parts.next()?,
+        parts.next()?,
+        parts.next()?,
+        parts.next()?,
+    );
+    let (a, b, address) = (a.parse().ok()?, b.parse().ok()?, address.parse().ok()?);
+
+    if parts.next().is_some() || checksum(kind, &a, &b, &address) != sum {
+        return None;
+    }
+
+    let derivation = match kind {
+        "proof" => Derivation::Proof {
+            program_id: a,
+            authority: b,
+        },
+        _ => return None,
+    };
+
+    Some((derivation, address))
+}
+
+fn checksum(kind: &str, a: &Pubkey, b: &Pubkey, address: &Pubkey) -> String {
+    let hash = keccak::hashv(&[kind.as_bytes(), a.as_ref(), b.as_ref(), address.as_ref()]);
+
+    hash.0[..4].iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Loads the addresses derived by the previous runs from `path` and appends
+/// the ones derived from now on. A random sample is derived again first, the
+/// file is started over if any of them doesn't match since the addresses end
+/// up in signed transactions. So is a file still holding token accounts.
+pub fn init(path: &str) -> eyre::Result<()> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err).with_context(|| format!("fail to read pda cache {path}")),
+    };
+
+    let mut entries = HashMap::new();
+    let mut invalid = 0;
+    let mut token_accounts = 0;
+
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        if line.starts_with("ata ") {
+            token_accounts += 1;
+            continue;
+        }
+
+        match parse_line(line) {
+            Some((derivation, address)) => {
+                entries.insert(derivation, address);
+            }
+            None => invalid += 1,
+        }
+    }
+
+    let mismatched = entries
+        .iter()
+        .choose_multiple(&mut rand::thread_rng(), VERIFY_SAMPLE)
+        .into_iter()
+        .filter(|(derivation, address)| derivation.derive() != **address)
+        .count();
+
+    if invalid > 0 {
+        warn!(path, lines = invalid, "skipped corrupted lines of the pda cache");
+    }
+
+    let mut options = OpenOptions::new();
+
+    if mismatched > 0 {
+        warn!(
+            path,
+            mismatched, "pda cache doesn't match the derivations, starting over"
+        );
+
+        entries.clear();
+        options.write(true).truncate(true);
+    } else if token_accounts > 0 {
+        info!(path, token_accounts, "pda cache no longer keeps token accounts, starting over");
+
+        entries.clear();
+        options.write(true).truncate(true);
+    } else {
+        options.append(true);
+    }
+
+    let file = options
+        .create(true)
+        .open(path)
+        .with_context(|| format!("fail to open pda cache {path}"))?;
+
+    info!(path, addresses = entries.len(), "pda cache loaded");
+
+    let _ = CACHE.set(Mutex::new(PdaCache { entries, file }));
+
+    Ok(())
+}
+
+/// The address of `derivation`, from the cache when it holds it.
+pub fn get(derivation: Derivation) -> Pubkey {
+    let cache = match CACHE.get() {
+        Some(cache) => cache,
+        None => return derivation.derive(),
+    };
+
+    if let Some(address) = cache.lock().unwrap().entries.get(&derivation) {
+        return *address;
+    }
+
+    // Derived outside of the lock, the other lookups don't wait for it.
+    let address = derivation.derive();
+    let mut cache = cache.lock().unwrap();
+
+    if cache.entries.insert(derivation, address).is_some() {
+        return address;
+    }
+
+    if let Err(err) = writeln!(cache.file, "{}", format_line(&derivation, &address)) {
+        error!("fail to write to pda cache: {err}");
+    }
+
+    address
+}
@@ -0,0 +1,105 @@
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use solana_client::nonblocking::rpc_client::RpcClient;
+use tracing::{error, info};
+
+use crate::cluster;
+
+/// Latest estimate of `--priority-fee auto`.
+static ESTIMATE: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy)]
+pub enum PriorityFee {
+    Fixed(u64),
+    /// Estimated from the fees recently paid to write the ore accounts.
+    Auto,
+}
+
+impl FromStr for PriorityFee {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" => Ok(Self::Auto),
+            value => value
+                .parse()
+                .map(Self::Fixed)
+                .map_err(|_| format!("invalid priority fee {value}, expected a number or auto")),
+        }
+    }
+}
+
+impl fmt::Display for PriorityFee {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fixed(fee) => write!(f, "{fee}"),
+            Self::Auto => write!(f, "\"auto\""),
+        }
+    }
+}
+
+impl PriorityFee {
+    pub fn value(&self) -> u64 {
+        match self {
+            Self::Fixed(fee) => *fee,
+            Self::Auto => ESTIMATE.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Queries the fees of the recent slots for the ore program and the buses
+/// and keeps the `percentile` of them, logging the estimate when it changes.
+pub async fn refresh(client: &RpcClient, percentile: u8) -> eyre::Result<u64> {
+    let mut accounts = vec![cluster::program_id()];
+    accounts.extend(cluster::addresses().buses);
+
+    let mut fees = client
+        .get_recent_prioritization_fees(&accounts)
+        .await?
+        .into_iter()
+        .map(|fee| fee.prioritization_fee)
+        .collect::<Vec<_>>();
+
+    fees.sort_unstable();
+
+    let estimate = match fees.len() {
+        0 => 0,
+        len => fees[(len - 1) * percentile as usize / 100],
+    };
+
+    let previous = ESTIMATE.swap(estimate, Ordering::Relaxed);
+
+    if previous != estimate {
+        info!(
+            previous,
+            estimate,
+            percentile,
+            slots = fees.len(),
+            "priority fee estimate changed"
+        );
+    }
+
+    Ok(estimate)
+}
+
+/// Refreshes the estimate every `interval` for the rest of the run, the last
+/// estimate is kept when a refresh fails.
+pub fn spawn_refresher(client: Arc<RpcClient>, percentile: u8, interval: Duration) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+
+            if let Err(err) = refresh(&client, percentile).await {
+                error!("fail to estimate the priority fee: {err:#}");
+            }
+        }
+    });
+}
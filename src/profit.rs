@@ -0,0 +1,61 @@
+use tracing::{debug, warn};
+
+use crate::{compute_budget, constant, format_reward, utils};
+
+/// `--lamports-per-ore`, `--min-profit-lamports` and `--force` of the bundle
+/// mining subcommands.
+#[derive(Debug, Clone, Copy)]
+pub struct ProfitCheck {
+    pub lamports_per_ore: Option<u64>,
+    pub min_profit: i64,
+    pub force: bool,
+}
+
+impl ProfitCheck {
+    /// Whether mining `signers` signers into `bundles` bundles at `tip` is
+    /// expected to earn at least `--min-profit-lamports`, logging the numbers
+    /// when it isn't. Always true without `--lamports-per-ore` or with
+    /// `--force`.
+    pub fn worth_mining(&self, reward_rate: u64, signers: usize, bundles: usize, tip: u64) -> bool {
+        let lamports_per_ore = match (self.force, self.lamports_per_ore) {
+            (false, Some(lamports_per_ore)) => lamports_per_ore,
+            _ => return true,
+        };
+
+        let reward = reward_rate.saturating_mul(signers as u64);
+        let reward_lamports =
+            (reward as u128 * lamports_per_ore as u128 / 10u128.pow(ore::TOKEN_DECIMALS as u32)) as u64;
+
+        // Only one bundle of each batch lands, the others fail with the
+        // proofs it changed and pay nothing.
+        let fees =
+            constant::FEE_PER_SIGNER * signers as u64 + compute_budget::priority_fee(5) * signers.div_ceil(5) as u64;
+        let cost = fees + tip * bundles as u64;
+        let profit = reward_lamports as i64 - cost as i64;
+
+        if profit >= self.min_profit {
+            debug!(
+                reward = format_reward!(reward),
+                reward.lamports = reward_lamports,
+                cost,
+                profit,
+                "round expected to be profitable"
+            );
+
+            return true;
+        }
+
+        warn!(
+            reward = format_reward!(reward),
+            reward.lamports = reward_lamports,
+            cost,
+            cost.fees = fees,
+            cost.tips = tip * bundles as u64,
+            profit,
+            min_profit = self.min_profit,
+            "round not profitable, skipping until next epoch"
+        );
+
+        false
+    }
+}
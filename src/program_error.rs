@@ -0,0 +1,126 @@
+use ore::{instruction::OreInstruction, state::Proof};
+use solana_client::client_error::ClientError;
+use solana_sdk::{
+    instruction::InstructionError,
+    rent::Rent,
+    system_program,
+    transaction::{Transaction, TransactionError},
+};
+
+use crate::cluster;
+
+/// Who raised a custom error code, the programs number their errors from 0
+/// alike.
+enum Source {
+    Ore,
+    /// The register instruction creates the proof through the system
+    /// program, its errors are the system ones.
+    OreRegister,
+    System,
+    Unknown,
+}
+
+/// `err` in human terms, with the raw error kept for the custom codes. `tx`
+/// tells which program raised a custom code, without it the code is only
+/// read as an ore error as a hint.
+pub fn describe(err: &TransactionError, tx: Option<&Transaction>) -> String {
+    describe_from(err, |ix| tx.map_or(Source::Unknown, |tx| source(tx, ix)))
+}
+
+/// `err` of a transaction of register instructions, whose custom codes all
+/// come from the system program.
+pub fn describe_register(err: &TransactionError) -> String {
+    describe_from(err, |_| Source::OreRegister)
+}
+
+fn describe_from(err: &TransactionError, source: impl FnOnce(u8) -> Source) -> String {
+    let (ix, code) = match err {
+        TransactionError::InstructionError(ix, InstructionError::Custom(code)) => (*ix, *code),
+        TransactionError::InstructionError(_, InstructionError::InsufficientFunds) => {
+            return format!("insufficient funds ({err})")
+        }
+        TransactionError::InsufficientFundsForRent { .. } => {
+            return format!("an account would be left with less lamports than its rent ({err})")
+        }
+        TransactionError::InsufficientFundsForFee => return format!("fee payer can't pay the fee ({err})"),
+        _ => return err.to_string(),
+    };
+
+    let source = source(ix);
+
+    let message = match source {
+        Source::Ore | Source::Unknown => ore_error(code),
+        Source::OreRegister => register_error(code),
+        Source::System => system_error(code),
+    };
+
+    match (source, message) {
+        (Source::Unknown, Some(message)) => format!("{err} (ore: {message})"),
+        (_, Some(message)) => format!("{message} ({err})"),
+        (_, None) => err.to_string(),
+    }
+}
+
+/// Describes the transaction error of a failed preflight, the other errors
+/// as is.
+pub fn describe_client_error(err: &ClientError, tx: Option<&Transaction>) -> String {
+    match err.get_transaction_error() {
+        Some(tx_err) => describe(&tx_err, tx),
+        None => format!("{err:#}"),
+    }
+}
+
+fn source(tx: &Transaction, ix: u8) -> Source {
+    let ix = match tx.message.instructions.get(ix as usize) {
+        Some(ix) => ix,
+        None => return Source::Unknown,
+    };
+
+    match tx.message.account_keys.get(ix.program_id_index as usize) {
+        Some(program_id) if *program_id == cluster::program_id() => {
+            match ix.data.first() == Some(&(OreInstruction::Register as u8)) {
+                true => Source::OreRegister,
+                false => Source::Ore,
+            }
+        }
+        Some(program_id) if *program_id == system_program::ID => Source::System,
+        _ => Source::Unknown,
+    }
+}
+
+/// As numbered by the ore program.
+fn ore_error(code: u32) -> Option<String> {
+    let message = match code {
+        0 => "the epoch ended and needs a reset",
+        1 => "hash invalid, the proof changed since it was mined",
+        2 => "hash doesn't satisfy the difficulty",
+        3 => "the bus doesn't hold enough rewards",
+        4 => "claim amount larger than the claimable rewards",
+        _ => return None,
+    };
+
+    Some(message.to_string())
+}
+
+fn register_error(code: u32) -> Option<String> {
+    match code {
+        0 => Some("already registered, the proof account exists".to_string()),
+        1 => Some(format!("insufficient lamports for rent (needs ~{} SOL)", proof_rent())),
+        _ => None,
+    }
+}
+
+fn system_error(code: u32) -> Option<String> {
+    match code {
+        0 => Some("account already in use".to_string()),
+        1 => Some("insufficient lamports".to_string()),
+        _ => None,
+    }
+}
+
+/// SOL a proof account holds to be rent exempt, its discriminator included.
+fn proof_rent() -> f64 {
+    let lamports = Rent::default().minimum_balance(8 + std::mem::size_of::<Proof>());
+
+    (spl_token::amount_to_ui_amount(lamports, 9) * 10_000.0).round() / 10_000.0
+}
@@ -0,0 +1,177 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use once_cell::sync::OnceCell;
+use solana_client::{
+    client_error::Result as ClientResult,
+    rpc_request::RpcRequest,
+    rpc_sender::{RpcSender, RpcTransportStats},
+};
+use solana_rpc_client::http_sender::HttpSender;
+use tracing::warn;
+
+/// Requests waiting longer than this for the limiter are reported.
+const SLOW_REQUEST_DELAY: Duration = Duration::from_millis(500);
+
+/// Delayed requests are reported at most once per interval.
+const SLOW_REQUEST_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Shared by every RPC client of the process, set once from `--rpc-rps`.
+static RPC_LIMITER: OnceCell<TokenBucket> = OnceCell::new();
+
+pub fn init(requests_per_second: f64, burst: u32) {
+    let _ = RPC_LIMITER.set(TokenBucket::new(requests_per_second, burst));
+}
+
+pub fn limiter() -> Option<&'static TokenBucket> {
+    RPC_LIMITER.get()
+}
+
+#[derive(Debug)]
+struct BucketState {
+    /// Goes below zero when requests are queued, each of them then waits for
+    /// the tokens of the ones ahead of it.
+    tokens: f64,
+    updated_at: Instant,
+    delayed: usize,
+    max_delay: Duration,
+    logged_at: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    state: Mutex<BucketState>,
+}
+
+impl TokenBucket {
+    pub fn new(requests_per_second: f64, burst: u32) -> Self {
+        let burst = burst.max(1) as f64;
+
+        Self {
+            rate: requests_per_second,
+            burst,
+            state: Mutex::new(BucketState {
+                tokens: burst,
+                updated_at: Instant::now(),
+                delayed: 0,
+                max_delay: Duration::ZERO,
+                logged_at: None,
+            }),
+        }
+    }
+
+    /// Waits until the request may be sent.
+    pub async fn acquire(&self) {
+        let delay = self.reserve();
+
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// A rate that isn't a positive number lets every request through.
+    fn reserve(&self) -> Duration {
+        if !(self.rate.is_finite() && self.rate > 0.0) {
+            return Duration::ZERO;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        let refill = now.duration_since(state.updated_at).as_secs_f64() * self.rate;
+        state.tokens = (state.tokens + refill).min(self.burst) - 1.0;
+        state.updated_at = now;
+
+        if state.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+
+        let delay = Duration::from_secs_f64(-state.tokens / self.rate);
+
+        if delay > SLOW_REQUEST_DELAY {
+            state.delayed += 1;
+            state.max_delay = state.max_delay.max(delay);
+
+            if state
+                .logged_at
+                .map_or(true, |at| now.duration_since(at) >= SLOW_REQUEST_LOG_INTERVAL)
+            {
+                warn!(
+                    requests = state.delayed,
+                    max_delay_ms = state.max_delay.as_millis() as u64,
+                    rps = self.rate,
+                    "rpc requests delayed by the rate limiter"
+                );
+
+                state.delayed = 0;
+                state.max_delay = Duration::ZERO;
+                state.logged_at = Some(now);
+            }
+        }
+
+        delay
+    }
+}
+
+/// Sends the requests over HTTP once the shared limiter lets them through.
+pub struct RateLimitedSender {
+    inner: HttpSender,
+    limiter: &'static TokenBucket,
+}
+
+impl RateLimitedSender {
+    pub fn new(url: String, limiter: &'static TokenBucket) -> Self {
+        Self {
+            inner: HttpSender::new(url),
+            limiter,
+        }
+    }
+}
+
+#[async_trait]
+impl RpcSender for RateLimitedSender {
+    async fn send(&self, request: RpcRequest, params: serde_json::Value) -> ClientResult<serde_json::Value> {
+        self.limiter.acquire().await;
+        self.inner.send(request, params).await
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.inner.get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.inner.url()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_then_delay() {
+        let bucket = TokenBucket::new(10.0, 2);
+
+        assert_eq!(bucket.reserve(), Duration::ZERO);
+        assert_eq!(bucket.reserve(), Duration::ZERO);
+
+        let delay = bucket.reserve();
+        assert!(delay > Duration::from_millis(90) && delay <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn invalid_rate_does_not_limit() {
+        for rate in [0.0, -1.0, f64::INFINITY, f64::NAN] {
+            let bucket = TokenBucket::new(rate, 1);
+
+            for _ in 0..3 {
+                assert_eq!(bucket.reserve(), Duration::ZERO, "rate {rate}");
+            }
+        }
+    }
+}
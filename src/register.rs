@@ -1,174 +1,1055 @@
-use std::{collections::HashSet, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
 use clap::Parser;
-use rand::Rng;
-use solana_sdk::{signer::Signer, transaction::Transaction};
-use tracing::{error, info};
+use itertools::Itertools;
+use ore::{state::Proof, utils::AccountDeserialize};
+use solana_client::{nonblocking::rpc_client::RpcClient, rpc_config::RpcSendTransactionConfig};
+use solana_sdk::{
+    clock::Slot,
+    commitment_config::CommitmentConfig,
+    compute_budget::ComputeBudgetInstruction,
+    hash::Hash,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    signer::Signer,
+    transaction::{Transaction, TransactionError},
+};
+use solana_transaction_status::UiTransactionEncoding;
+use tracing::{error, info, warn};
 
-use crate::{constant, jito, utils, Miner};
+use crate::{
+    batch_transfer,
+    blockhash::{self, BlockhashCache},
+    cluster,
+    constant,
+    jito,
+    metrics,
+    program_error,
+    utils,
+    utils::TxOutcome,
+    Miner,
+};
+
+/// Passes over the batches that failed to simulate or send, with fresh
+/// balances and blockhash.
+const REGISTER_RETRIES: usize = 3;
 
 #[derive(Parser, Debug, Clone)]
 pub struct RegisterArgs {
-    #[arg(long, help = "The folder that contains all the keys used to claim $ORE")]
-    pub key_folder: String,
+    #[arg(
+        long,
+        required_unless_present = "key_files",
+        help = "The folder that contains all the keys used to claim $ORE"
+    )]
+    pub key_folder: Option<String>,
+
+    #[arg(
+        long = "key-file",
+        value_delimiter = ',',
+        help = "Keypair files to register, alone or on top of the key folder"
+    )]
+    pub key_files: Vec<String>,
+
+    #[arg(
+        long = "address",
+        value_delimiter = ',',
+        help = "Only register these wallets, their keypairs must be in the key folder or key files"
+    )]
+    pub addresses: Vec<Pubkey>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Try to register the wallets that can't afford the proof account rent anyway"
+    )]
+    pub ignore_underfunded: bool,
+
+    #[arg(
+        long,
+        help = "Keypair paying the transaction fees and the tip, the wallets then only need the proof account rent"
+    )]
+    pub fee_payer: Option<String>,
+
+    #[arg(
+        long,
+        help = "Keypair topping the unregistered wallets up to the proof account rent before registering them, it also \
+                pays the fees and the tip unless --fee-payer is set"
+    )]
+    pub fund_from: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Print the registration state, proof hash, claimable rewards and balance of every wallet, then exit"
+    )]
+    pub status: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Print the registration state, then simulate the registration of the unregistered wallets without \
+                sending anything"
+    )]
+    pub dry_run: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Send the transactions over RPC with --priority-fee as the compute unit price instead of jito bundles"
+    )]
+    pub no_jito: bool,
+}
+
+/// What the registration and transfer transactions pay to land.
+#[derive(Debug, Clone, Copy)]
+pub enum LandingFee {
+    /// Lamports of the bribe on the first transaction of each jito bundle.
+    JitoTip(u64),
+    /// Compute unit price in micro-lamports, the transactions are sent over
+    /// RPC.
+    PriorityFee(u64),
+}
+
+/// Accounts of a registration pass, each one is pending until its
+/// transaction lands or fails to simulate or send.
+#[derive(Debug)]
+struct RegisterProgress {
+    pending: usize,
+    registered: usize,
+    failed: usize,
+}
+
+impl RegisterProgress {
+    fn new(accounts: usize) -> Self {
+        Self {
+            pending: accounts,
+            registered: 0,
+            failed: 0,
+        }
+    }
+
+    fn landed(&mut self, accounts: usize) {
+        self.pending -= accounts;
+        self.registered += accounts;
+    }
+
+    fn failed(&mut self, accounts: usize) {
+        self.pending -= accounts;
+        self.failed += accounts;
+    }
+}
+
+impl LandingFee {
+    /// Upper bound of what registering `accounts` wallets costs on top of the
+    /// signature fees. Without a compute unit limit every register
+    /// instruction may use the default 200k units.
+    fn cost(self, accounts: usize) -> u64 {
+        match self {
+            LandingFee::JitoTip(tip) => tip * accounts.div_ceil(25) as u64,
+            LandingFee::PriorityFee(price) => price * 200_000 * accounts as u64 / 1_000_000,
+        }
+    }
 }
 
 impl Miner {
     pub async fn register(&self, args: &RegisterArgs) {
         let client = Miner::get_client_confirmed(&self.rpc);
-        let accounts = Self::read_keys(&args.key_folder);
-        let jito_tip = self.priority_fee.expect("jito tip is required");
-
-        let owner_proof_pdas = accounts
-            .iter()
-            .map(|signer| utils::get_proof_pda(signer.pubkey()))
-            .collect::<Vec<_>>();
+        let accounts = match Self::read_register_keys(args, self.recursive) {
+            Ok(accounts) => accounts,
+            Err(err) => {
+                error!("{err:#}");
+                return;
+            }
+        };
+        let fee = match args.no_jito {
+            true => LandingFee::PriorityFee(self.current_priority_fee().unwrap_or(0)),
+            false => LandingFee::JitoTip(self.current_priority_fee().expect("jito tip is required")),
+        };
+        let fund_from = match args.fund_from.as_ref().map(utils::read_keypair).transpose() {
+            Ok(fund_from) => fund_from,
+            Err(err) => {
+                error!("fail to read the funding keypair: {err:#}");
+                return;
+            }
+        };
+        let fee_payer = match args
+            .fee_payer
+            .as_ref()
+            .or(args.fund_from.as_ref())
+            .map(utils::read_keypair)
+            .transpose()
+        {
+            Ok(fee_payer) => fee_payer,
+            Err(err) => {
+                error!("fail to read the fee payer keypair: {err:#}");
+                return;
+            }
+        };
+        let blockhash_cache = BlockhashCache::spawn(client.clone(), &self.rpc);
 
-        if owner_proof_pdas.is_empty() {
+        if accounts.is_empty() {
             info!("No claimable accounts found");
             return;
         }
 
-        let mut registered = HashSet::new();
+        let pubkeys = accounts.iter().map(|signer| signer.pubkey()).collect_vec();
+        let proofs = Self::fetch_proofs(&client, &pubkeys)
+            .await
+            .expect("Failed to get Proof accounts");
 
-        for batch in owner_proof_pdas.chunks(constant::FETCH_ACCOUNT_LIMIT) {
-            client
-                .get_multiple_accounts(batch)
-                .await
-                .expect("Failed to get Proof accounts")
-                .into_iter()
-                .zip(accounts.iter())
-                .for_each(|(account, signer)| {
-                    if account.is_some() {
-                        registered.insert(signer.pubkey());
-                    }
-                });
+        if args.status || args.dry_run {
+            let balances = match Self::fetch_balances(&client, &pubkeys).await {
+                Ok(balances) => balances,
+                Err(err) => {
+                    error!("fail to get balances: {err:#}");
+                    return;
+                }
+            };
+
+            print_register_status(&pubkeys, &proofs, &balances);
+
+            if args.status {
+                return;
+            }
         }
 
+        let requested = accounts.len();
         let accounts = accounts
             .into_iter()
-            .filter(|signer| !registered.contains(&signer.pubkey()))
+            .filter(|signer| !proofs.contains_key(&signer.pubkey()))
             .collect::<Vec<_>>();
+        let already_registered = requested - accounts.len();
 
-        info!("registering {} accounts", accounts.len());
+        if accounts.is_empty() {
+            info!("all accounts are registered");
+            return;
+        }
 
-        let mut batch_iter = accounts.chunks(5);
-        let mut remaining = accounts.len();
+        if let Some(fund_from) = fund_from.as_ref().filter(|_| !args.dry_run) {
+            if let Err(err) = Self::fund_register_accounts(&client, &blockhash_cache, fund_from, &accounts).await {
+                error!("fail to fund accounts: {err:#}");
+                return;
+            }
+        }
 
-        let mut txs = vec![];
-        let mut accounts_in_this_batch = 0;
-        let mut signers_for_txs = vec![];
+        let (accounts, mut balances) =
+            match Self::check_register_balances(&client, accounts, fee, fee_payer.as_ref(), args.ignore_underfunded)
+                .await
+            {
+                Ok(value) => value,
+                Err(err) => {
+                    error!("fail to check balances: {err:#}");
+                    return;
+                }
+            };
 
-        loop {
-            while txs.len() < 5 {
-                let batch = match batch_iter.next() {
-                    Some(batch) => batch,
-                    None => break,
-                };
+        if args.dry_run {
+            if fund_from.is_some() {
+                warn!("dry run, the wallets are not funded and the simulation assumes they are as they are now");
+            }
 
-                let mut ixs = vec![];
-                let mut signers = vec![];
+            self.simulate_register(&client, &blockhash_cache, &accounts, &balances, fee_payer.as_ref(), fee)
+                .await;
+            return;
+        }
 
-                for signer in batch {
-                    ixs.push(ore::instruction::register(signer.pubkey()));
-                    signers.push(signer);
+        let skipped = requested - already_registered - accounts.len();
+
+        info!("registering {} accounts", accounts.len());
+
+        let mut pending = accounts.iter().collect_vec();
+        let mut last_errors = HashMap::new();
+
+        for retry in 0..=REGISTER_RETRIES {
+            if retry > 0 {
+                info!(retry, accounts = pending.len(), "retrying failed batches");
+
+                let pubkeys = pending.iter().map(|signer| signer.pubkey()).collect_vec();
+                match Self::fetch_balances(&client, &pubkeys).await {
+                    Ok(value) => balances = value,
+                    Err(err) => warn!("fail to refresh balances, using the previous ones: {err:#}"),
                 }
+            }
+
+            let failed = self
+                .register_batches(&client, &blockhash_cache, &pending, &balances, fee_payer.as_ref(), fee)
+                .await;
 
-                let fee_payer = signers[rand::thread_rng().gen_range(0..signers.len())].pubkey();
+            pending.retain(|signer| failed.contains_key(&signer.pubkey()));
+            last_errors.extend(failed);
 
-                if txs.is_empty() {
-                    ixs.push(jito::build_bribe_ix(&fee_payer, jito_tip));
+            // A bundle that failed to confirm in time may still have landed.
+            let pubkeys = pending.iter().map(|signer| signer.pubkey()).collect_vec();
+            match Self::fetch_proofs(&client, &pubkeys).await {
+                Ok(proofs) => pending.retain(|signer| !proofs.contains_key(&signer.pubkey())),
+                Err(err) => warn!("fail to check registered accounts: {err:#}"),
+            }
+
+            if pending.is_empty() {
+                break;
+            }
+        }
+
+        info!(
+            requested,
+            already_registered,
+            registered = accounts.len() - pending.len(),
+            skipped,
+            failed = pending.len(),
+            "register summary"
+        );
+
+        if pending.is_empty() {
+            return;
+        }
+
+        println!("{:<44} last error", "wallet");
+
+        for signer in &pending {
+            let pubkey = signer.pubkey();
+            let err = last_errors.get(&pubkey).map(String::as_str).unwrap_or("unknown");
+            println!("{:<44} {err}", pubkey.to_string());
+        }
+
+        error!(unregistered = pending.len(), "some accounts are not registered");
+        std::process::exit(1);
+    }
+
+    /// Builds the registration transactions the way `register_batches` does
+    /// and prints the simulated outcome of each wallet, nothing is sent.
+    async fn simulate_register(
+        &self,
+        client: &RpcClient,
+        blockhash_cache: &BlockhashCache,
+        accounts: &[Keypair],
+        balances: &HashMap<Pubkey, u64>,
+        fee_payer: Option<&Keypair>,
+        fee: LandingFee,
+    ) {
+        let accounts = accounts.iter().collect_vec();
+        let mut outcomes = vec![];
+
+        for (i, batch) in accounts.chunks(5).enumerate() {
+            let blockhash = match blockhash_cache.get(blockhash::MAX_AGE).await {
+                Ok((_, blockhash)) => blockhash,
+                Err(err) => {
+                    error!("fail to get latest blockhash: {err:#}");
+                    return;
                 }
+            };
+
+            // First transaction of each bundle of 5 carries the bribe.
+            let tx = Self::build_register_tx(batch, balances, fee_payer, fee, i % 5 == 0, blockhash);
 
-                txs.push(Transaction::new_with_payer(&ixs, Some(&fee_payer)));
-                accounts_in_this_batch += signers.len();
-                signers_for_txs.push(signers);
+            match simulate_register_tx(client, &tx).await {
+                Ok(()) => outcomes.extend(batch.iter().map(|signer| (signer.pubkey(), "ok".to_string()))),
+                Err((failed_ix, reason)) => outcomes.extend(register_failures(batch, failed_ix, &reason)),
             }
+        }
+
+        println!("{:<44} outcome", "wallet");
+
+        for (pubkey, outcome) in &outcomes {
+            println!("{:<44} {outcome}", pubkey.to_string());
+        }
 
-            if txs.is_empty() {
+        let failed = outcomes.iter().filter(|(_, outcome)| outcome != "ok").count();
+        info!(accounts = outcomes.len(), failed, "dry run, nothing sent");
+    }
+
+    /// Registers the accounts in bundles of 5 transactions, or 5 transactions
+    /// at a time over RPC, the dropped ones are sent again. Returns the last
+    /// error of the accounts whose batch failed to simulate or send.
+    async fn register_batches(
+        &self,
+        client: &RpcClient,
+        blockhash_cache: &BlockhashCache,
+        accounts: &[&Keypair],
+        balances: &HashMap<Pubkey, u64>,
+        fee_payer: Option<&Keypair>,
+        fee: LandingFee,
+    ) -> HashMap<Pubkey, String> {
+        let mut batch_iter = accounts.chunks(5);
+        let mut progress = RegisterProgress::new(accounts.len());
+        let mut failed = HashMap::new();
+
+        // One transaction per batch of 5 accounts, up to 5 in a bundle.
+        let mut batches: Vec<&[&Keypair]> = vec![];
+
+        loop {
+            while batches.len() < 5 {
+                match batch_iter.next() {
+                    Some(batch) => batches.push(batch),
+                    None => break,
+                }
+            }
+
+            if batches.is_empty() {
                 break;
             }
 
-            let (send_at_slot, blockhash) = match Self::get_latest_blockhash_and_slot(&client).await {
+            let accounts_in_this_batch = batches.iter().map(|batch| batch.len()).sum::<usize>();
+
+            let (send_at_slot, blockhash) = match blockhash_cache.get(blockhash::MAX_AGE).await {
                 Ok(value) => value,
                 Err(err) => {
                     error!("fail to get latest blockhash: {err:#}");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
                     continue;
                 }
             };
 
-            let bundle = txs
+            // The bribe goes on the first transaction, it moves to the next
+            // one when the first is dropped.
+            let bundle = batches
                 .iter()
-                .zip(signers_for_txs.iter())
-                .map(|(tx, signers)| {
-                    let mut tx = tx.clone();
-                    tx.sign(signers.as_slice(), blockhash);
-                    tx
-                })
+                .enumerate()
+                .map(|(i, batch)| Self::build_register_tx(batch, balances, fee_payer, fee, i == 0, blockhash))
                 .collect::<Vec<_>>();
 
-            let mut failed_batch = false;
-
-            for tx in &bundle {
-                let sim_result = match client.simulate_transaction(tx).await {
-                    Ok(r) => r.value,
-                    Err(err) => {
-                        error!("fail to simulate transaction: {err:#}");
-                        failed_batch = true;
-                        break;
-                    }
-                };
+            let mut failing = None;
 
-                if let Some(err) = sim_result.err {
-                    error!("fail to simulate transaction: {err:#}");
-                    failed_batch = true;
+            for (i, tx) in bundle.iter().enumerate() {
+                if let Err(err) = simulate_register_tx(client, tx).await {
+                    failing = Some((i, err));
                     break;
                 }
             }
 
-            if failed_batch {
-                txs.clear();
-                remaining -= accounts_in_this_batch;
-                signers_for_txs.clear();
-                accounts_in_this_batch = 0;
+            // Only the failing transaction is left out, the others are bundled
+            // again with the next batches.
+            if let Some((i, (failed_ix, reason))) = failing {
+                let batch = batches.remove(i);
+                progress.failed(batch.len());
+
+                for (pubkey, err) in register_failures(batch, failed_ix, &reason) {
+                    error!(%pubkey, pending = progress.pending, failed = progress.failed, "{err}, retrying later");
+                    failed.insert(pubkey, err);
+                }
+
                 continue;
             }
 
-            let (tx, bundle_id) = jito::send_bundle(bundle).await.unwrap();
+            // Signature of each batch, none when it failed to send.
+            let sent = match fee {
+                LandingFee::JitoTip(_) => {
+                    let signatures = bundle.iter().map(|tx| Some(tx.signatures[0])).collect_vec();
+
+                    match jito::send_bundle(bundle).await {
+                        Ok((tx, bundle_id)) => {
+                            info!(first_tx = ?tx, %bundle_id, accounts = accounts_in_this_batch, pending = progress.pending, slot = send_at_slot, "bundle sent");
+                            signatures
+                        }
+                        Err(err) => {
+                            let err = format!("fail to send bundle: {err:#}");
+                            progress.failed(accounts_in_this_batch);
+                            error!(
+                                accounts = accounts_in_this_batch,
+                                pending = progress.pending,
+                                failed = progress.failed,
+                                "{err}, retrying later"
+                            );
+
+                            for signer in batches.drain(..).flatten() {
+                                failed.insert(signer.pubkey(), err.clone());
+                            }
+
+                            continue;
+                        }
+                    }
+                }
+                LandingFee::PriorityFee(_) => {
+                    let mut sent = vec![];
+
+                    for (tx, batch) in bundle.iter().zip(batches.iter()) {
+                        match send_register_tx(client, tx, send_at_slot).await {
+                            Ok(signature) => sent.push(Some(signature)),
+                            Err(err) => {
+                                let err = format!("fail to send transaction: {err:#}");
+                                progress.failed(batch.len());
 
-            info!(first_tx = ?tx, %bundle_id, accounts = accounts_in_this_batch, remaining, slot = send_at_slot, "bundle sent");
+                                for (pubkey, err) in register_failures(batch, None, &err) {
+                                    error!(
+                                        %pubkey,
+                                        pending = progress.pending,
+                                        failed = progress.failed,
+                                        "{err}, retrying later"
+                                    );
+                                    failed.insert(pubkey, err);
+                                }
 
+                                sent.push(None);
+                            }
+                        }
+                    }
+
+                    info!(
+                        accounts = accounts_in_this_batch,
+                        pending = progress.pending,
+                        slot = send_at_slot,
+                        "transactions sent"
+                    );
+                    sent
+                }
+            };
+
+            let signatures = sent.iter().flatten().copied().collect_vec();
+            let mut landed = HashSet::new();
+            let mut failed_on_chain = HashMap::new();
             let mut latest_slot = send_at_slot;
-            let mut mined = false;
 
-            while !mined && latest_slot < send_at_slot + constant::SLOT_EXPIRATION {
+            while landed.len() + failed_on_chain.len() < signatures.len() &&
+                latest_slot < send_at_slot + constant::SLOT_EXPIRATION
+            {
                 tokio::time::sleep(Duration::from_secs(2)).await;
 
-                let (statuses, slot) = match Self::get_signature_statuses(&client, &[tx]).await {
+                let (statuses, slot) = match Self::get_signature_statuses(client, &signatures).await {
                     Ok(value) => value,
                     Err(err) => {
-                        error!(send_at_slot, "fail to get bundle status: {err:#}");
+                        error!(send_at_slot, "fail to get transaction statuses: {err:#}");
                         tokio::time::sleep(Duration::from_secs(2)).await;
                         continue;
                     }
                 };
 
-                mined = !utils::find_landed_txs(&[tx], statuses).is_empty();
+                for (sig, outcome) in utils::find_tx_outcomes(&signatures, statuses) {
+                    match outcome {
+                        TxOutcome::Landed { .. } => {
+                            landed.insert(sig);
+                        }
+                        TxOutcome::Failed { err, .. } => {
+                            failed_on_chain.insert(sig, err);
+                        }
+                        TxOutcome::Pending => {}
+                    }
+                }
+
                 latest_slot = slot;
             }
 
-            if mined {
-                txs.clear();
-                remaining -= accounts_in_this_batch;
-                signers_for_txs.clear();
-                accounts_in_this_batch = 0;
-                info!(
-                    accounts = accounts_in_this_batch,
-                    remaining, "bundle sent at slot {send_at_slot}, remaining accounts: {remaining}"
+            batches = settle_batches(
+                batches,
+                sent,
+                &landed,
+                &failed_on_chain,
+                send_at_slot,
+                &mut progress,
+                &mut failed,
+            );
+        }
+
+        info!(
+            registered = progress.registered,
+            failed = progress.failed,
+            "registration pass done"
+        );
+
+        failed
+    }
+
+    fn build_register_tx(
+        batch: &[&Keypair],
+        balances: &HashMap<Pubkey, u64>,
+        fee_payer: Option<&Keypair>,
+        fee: LandingFee,
+        first_in_bundle: bool,
+        blockhash: Hash,
+    ) -> Transaction {
+        let mut ixs = batch
+            .iter()
+            .map(|signer| cluster::instruction(ore::instruction::register(signer.pubkey())))
+            .collect_vec();
+        let mut signers = batch.to_vec();
+
+        let payer = match fee_payer {
+            Some(fee_payer) => {
+                signers.push(fee_payer);
+                fee_payer.pubkey()
+            }
+            // Without a funded wallet the transaction fails to send and the
+            // batch is reported as failed.
+            None => utils::pick_richest_account(balances, &batch.iter().map(|signer| signer.pubkey()).collect_vec())
+                .unwrap_or_else(|| batch[0].pubkey()),
+        };
+
+        // Appended so that the register instructions keep the index of their
+        // wallet in the batch.
+        match fee {
+            LandingFee::JitoTip(tip) if first_in_bundle => ixs.extend(jito::build_bribe_ix(&payer, tip)),
+            LandingFee::JitoTip(_) => {}
+            LandingFee::PriorityFee(price) => ixs.push(ComputeBudgetInstruction::set_compute_unit_price(price)),
+        }
+
+        let mut tx = Transaction::new_with_payer(&ixs, Some(&payer));
+        tx.sign(signers.as_slice(), blockhash);
+        tx
+    }
+
+    /// Proofs of the registered wallets, keyed by the wallet.
+    async fn fetch_proofs(client: &RpcClient, pubkeys: &[Pubkey]) -> eyre::Result<HashMap<Pubkey, Proof>> {
+        let mut proofs = HashMap::new();
+
+        for batch in pubkeys.chunks(constant::FETCH_ACCOUNT_LIMIT) {
+            let proof_pdas = batch.iter().map(|pubkey| utils::get_proof_pda(*pubkey)).collect_vec();
+            let accounts = client.get_multiple_accounts(&proof_pdas).await?;
+
+            for (account, pubkey) in accounts.into_iter().zip(batch) {
+                if let Some(account) = account {
+                    let proof = Proof::try_from_bytes(&account.data)
+                        .map_err(|err| eyre::eyre!("fail to deserialize proof account of {pubkey}: {err:#}"))?;
+                    proofs.insert(*pubkey, *proof);
+                }
+            }
+        }
+
+        Ok(proofs)
+    }
+
+    /// Wallets that were never funded don't exist yet, they are listed with a
+    /// balance of 0.
+    async fn fetch_balances(client: &RpcClient, pubkeys: &[Pubkey]) -> eyre::Result<HashMap<Pubkey, u64>> {
+        let mut balances = HashMap::new();
+
+        for batch in pubkeys.chunks(constant::FETCH_ACCOUNT_LIMIT) {
+            balances.extend(Self::get_balances(client, batch).await?);
+        }
+
+        for pubkey in pubkeys {
+            balances.entry(*pubkey).or_insert(0);
+        }
+
+        Ok(balances)
+    }
+
+    /// Keypairs of the key folder and key files, only the requested addresses
+    /// when there are some. The addresses without a keypair are reported.
+    fn read_register_keys(args: &RegisterArgs, recursive: bool) -> eyre::Result<Vec<Keypair>> {
+        let mut accounts = match &args.key_folder {
+            Some(key_folder) => Self::read_keys(key_folder, recursive)?,
+            None => vec![],
+        };
+
+        for path in &args.key_files {
+            accounts.push(utils::read_keypair(path)?);
+        }
+
+        // A key file may also be in the folder.
+        let mut pubkeys = HashSet::new();
+        accounts.retain(|signer| pubkeys.insert(signer.pubkey()));
+
+        if args.addresses.is_empty() {
+            return Ok(accounts);
+        }
+
+        for address in args.addresses.iter().filter(|address| !pubkeys.contains(address)) {
+            error!(%address, "no keypair found for the address, skipping");
+        }
+
+        Ok(accounts
+            .into_iter()
+            .filter(|signer| args.addresses.contains(&signer.pubkey()))
+            .collect())
+    }
+
+    /// Tops the wallets up to the proof account rent and waits for the
+    /// transfers to confirm. The wallets already holding it are left as is, so
+    /// running it again only funds what is missing.
+    async fn fund_register_accounts(
+        client: &RpcClient,
+        blockhash_cache: &BlockhashCache,
+        funder: &Keypair,
+        accounts: &[Keypair],
+    ) -> eyre::Result<()> {
+        let rent = Self::proof_rent(client).await?;
+        let pubkeys = accounts.iter().map(|signer| signer.pubkey()).collect_vec();
+        let balances = Self::fetch_balances(client, &pubkeys).await?;
+
+        let amounts = pubkeys
+            .iter()
+            .filter(|pubkey| balances[*pubkey] < rent)
+            .map(|pubkey| (*pubkey, rent - balances[pubkey]))
+            .collect_vec();
+
+        if amounts.is_empty() {
+            info!("all accounts are funded");
+            return Ok(());
+        }
+
+        let total = amounts.iter().map(|(_, amount)| amount).sum::<u64>();
+        let fees = constant::FEE_PER_SIGNER * amounts.len().div_ceil(constant::TRANSFER_BATCH_SIZE) as u64;
+        let balance = client.get_balance(&funder.pubkey()).await?;
+
+        if balance < total + fees {
+            eyre::bail!(
+                "funding wallet {} has {balance} lamports, {} are needed to fund {} wallets",
+                funder.pubkey(),
+                total + fees,
+                amounts.len()
+            );
+        }
+
+        info!(
+            accounts = amounts.len(),
+            total = spl_token::amount_to_ui_amount(total, 9),
+            "funding accounts"
+        );
+
+        let outcomes = Self::transfer_and_confirm(client, blockhash_cache, funder, &amounts, None).await;
+        batch_transfer::print_transfer_outcomes(&outcomes);
+
+        Ok(())
+    }
+
+    async fn proof_rent(client: &RpcClient) -> eyre::Result<u64> {
+        Ok(client
+            .get_minimum_balance_for_rent_exemption(8 + std::mem::size_of::<Proof>())
+            .await?)
+    }
+
+    /// Every wallet pays the proof account rent and its signature, the fee
+    /// payer of a transaction also pays the other signatures and the tip.
+    /// Prints the wallets that can't afford their share and leaves them
+    /// out, unless `ignore_underfunded`.
+    ///
+    /// With an external fee payer, the wallets only pay the rent and the fee
+    /// payer must cover every fee and tip.
+    async fn check_register_balances(
+        client: &RpcClient,
+        accounts: Vec<Keypair>,
+        fee: LandingFee,
+        fee_payer: Option<&Keypair>,
+        ignore_underfunded: bool,
+    ) -> eyre::Result<(Vec<Keypair>, HashMap<Pubkey, u64>)> {
+        let rent = Self::proof_rent(client).await?;
+
+        if let Some(fee_payer) = fee_payer {
+            let txs = accounts.len().div_ceil(5) as u64;
+            let required = constant::FEE_PER_SIGNER * (accounts.len() as u64 + txs) + fee.cost(accounts.len());
+            let balance = client.get_balance(&fee_payer.pubkey()).await?;
+
+            if balance < required {
+                eyre::bail!(
+                    "fee payer {} has {balance} lamports, {required} are needed to register {} wallets",
+                    fee_payer.pubkey(),
+                    accounts.len()
                 );
-            } else {
-                error!(accounts = accounts_in_this_batch, remaining, "bundle dropped, retrying");
             }
         }
+
+        let pubkeys = accounts.iter().map(|signer| signer.pubkey()).collect_vec();
+        let balances = Self::fetch_balances(client, &pubkeys).await?;
+
+        let required = match fee_payer {
+            Some(_) => rent,
+            None => rent + constant::FEE_PER_SIGNER,
+        };
+        let underfunded = pubkeys
+            .iter()
+            .filter(|pubkey| balances[*pubkey] < required)
+            .collect_vec();
+
+        if underfunded.is_empty() {
+            return Ok((accounts, balances));
+        }
+
+        println!(
+            "{:<44} {:>14} {:>14} {:>14}",
+            "wallet", "balance", "required", "shortfall"
+        );
+
+        for pubkey in &underfunded {
+            let balance = balances[*pubkey];
+            println!(
+                "{:<44} {:>14} {:>14} {:>14}",
+                pubkey.to_string(),
+                balance,
+                required,
+                required - balance
+            );
+        }
+
+        if fee_payer.is_some() {
+            println!("each wallet needs the proof account rent ({rent} lamports)");
+        } else {
+            let fee = match fee {
+                LandingFee::JitoTip(tip) => format!("the first one the tip ({tip} lamports)"),
+                LandingFee::PriorityFee(price) => format!("the priority fee ({price} micro-lamports per compute unit)"),
+            };
+
+            println!(
+                "each wallet needs the proof account rent ({rent} lamports) and its signature fee, fee payers also \
+                 need the other signature fees and {fee}"
+            );
+        }
+
+        if ignore_underfunded {
+            warn!(
+                underfunded = underfunded.len(),
+                "registering underfunded wallets anyway"
+            );
+            return Ok((accounts, balances));
+        }
+
+        warn!(underfunded = underfunded.len(), "skipping underfunded wallets");
+
+        let accounts = accounts
+            .into_iter()
+            .filter(|signer| balances[&signer.pubkey()] >= required)
+            .collect();
+
+        Ok((accounts, balances))
+    }
+}
+
+/// Program errors are only numeric codes, they are described from the
+/// transaction and the last line logged by the failing program adds the
+/// details, e.g. the lamports missing for the rent.
+fn simulation_error_reason(err: &TransactionError, tx: &Transaction, logs: &[String]) -> String {
+    let logged = logs
+        .iter()
+        .rev()
+        .find_map(|log| match log.strip_prefix("Program log: ") {
+            Some(message) => Some(message),
+            None if !log.starts_with("Program ") => Some(log.as_str()),
+            None => None,
+        });
+
+    let err = program_error::describe(err, Some(tx));
+
+    match logged {
+        Some(message) => format!("{err}: {message}"),
+        None => err,
+    }
+}
+
+async fn send_register_tx(client: &RpcClient, tx: &Transaction, slot: Slot) -> eyre::Result<Signature> {
+    let send_cfg = RpcSendTransactionConfig {
+        skip_preflight: false,
+        preflight_commitment: Some(utils::commitment_or(CommitmentConfig::confirmed()).commitment),
+        encoding: Some(UiTransactionEncoding::Base58),
+        max_retries: Some(5),
+        min_context_slot: Some(slot),
+    };
+
+    // A failed preflight says which program rejected the registration.
+    client
+        .send_transaction_with_config(tx, send_cfg)
+        .await
+        .map_err(|err| eyre::eyre!(program_error::describe_client_error(&err, Some(tx))))
+}
+
+/// Returns the index of the failing instruction, if any, and the reason.
+async fn simulate_register_tx(client: &RpcClient, tx: &Transaction) -> Result<(), (Option<usize>, String)> {
+    let result = match client.simulate_transaction(tx).await {
+        Ok(r) => r.value,
+        Err(err) => return Err((None, format!("fail to simulate transaction: {err:#}"))),
+    };
+
+    match result.err {
+        None => Ok(()),
+        Some(err) => {
+            let failed_ix = match err {
+                TransactionError::InstructionError(ix, _) => Some(ix as usize),
+                _ => None,
+            };
+
+            Err((
+                failed_ix,
+                simulation_error_reason(&err, tx, result.logs.as_deref().unwrap_or_default()),
+            ))
+        }
+    }
+}
+
+/// The register instructions come first, in the order of the batch, the
+/// bribe is last. The wallet whose instruction failed gets the reason, the
+/// others of the transaction are only dropped with it.
+fn register_failures(batch: &[&Keypair], failed_ix: Option<usize>, reason: &str) -> Vec<(Pubkey, String)> {
+    let culprit = failed_ix.and_then(|ix| batch.get(ix)).map(|signer| signer.pubkey());
+
+    batch
+        .iter()
+        .map(|signer| {
+            let pubkey = signer.pubkey();
+            let err = match culprit {
+                Some(culprit) if culprit != pubkey => format!("dropped with the failing transaction of {culprit}"),
+                _ => reason.to_string(),
+            };
+
+            (pubkey, err)
+        })
+        .collect()
+}
+
+fn print_register_status(pubkeys: &[Pubkey], proofs: &HashMap<Pubkey, Proof>, balances: &HashMap<Pubkey, u64>) {
+    println!(
+        "{:<44} {:<10} {:<44} {:>14} {:>14}",
+        "wallet", "registered", "proof hash", "claimable", "balance"
+    );
+
+    for pubkey in pubkeys {
+        let (registered, hash, claimable) = match proofs.get(pubkey) {
+            Some(proof) => (
+                "yes",
+                solana_sdk::keccak::Hash::from(proof.hash).to_string(),
+                utils::ore_ui_amount(proof.claimable_rewards).to_string(),
+            ),
+            None => ("no", "-".to_string(), "-".to_string()),
+        };
+
+        println!(
+            "{:<44} {:<10} {:<44} {:>14} {:>14}",
+            pubkey.to_string(),
+            registered,
+            hash,
+            claimable,
+            spl_token::amount_to_ui_amount(balances[pubkey], 9)
+        );
+    }
+
+    info!(
+        accounts = pubkeys.len(),
+        registered = proofs.len(),
+        "registration status"
+    );
+}
+
+/// Settles the sent batches of `register_batches` on the outcome of their
+/// transaction and returns the dropped ones, to be sent again. A bundle lands
+/// as a whole, RPC transactions one by one. The batches that failed on chain
+/// would fail again, the ones that failed to send were already counted.
+fn settle_batches<'a, 'k>(
+    batches: Vec<&'a [&'k Keypair]>,
+    sent: Vec<Option<Signature>>,
+    landed: &HashSet<Signature>,
+    failed_on_chain: &HashMap<Signature, TransactionError>,
+    send_at_slot: Slot,
+    progress: &mut RegisterProgress,
+    failed: &mut HashMap<Pubkey, String>,
+) -> Vec<&'a [&'k Keypair]> {
+    let mut landed_accounts = 0;
+    let dropped = batches
+        .into_iter()
+        .zip(sent)
+        .filter_map(|(batch, signature)| match signature {
+            Some(signature) if landed.contains(&signature) => {
+                landed_accounts += batch.len();
+                None
+            }
+            Some(signature) if failed_on_chain.contains_key(&signature) => {
+                let err = program_error::describe_register(&failed_on_chain[&signature]);
+                metrics::TXS_FAILED.inc();
+                progress.failed(batch.len());
+
+                for signer in batch.iter() {
+                    error!(pubkey = %signer.pubkey(), tx = %signature, "registration landed but failed: {err}");
+                    failed.insert(signer.pubkey(), format!("transaction failed: {err}"));
+                }
+
+                None
+            }
+            Some(_) => Some(batch),
+            None => None,
+        })
+        .collect_vec();
+
+    if landed_accounts > 0 {
+        progress.landed(landed_accounts);
+        info!(
+            accounts = landed_accounts,
+            registered = progress.registered,
+            pending = progress.pending,
+            "registered at slot {send_at_slot}"
+        );
+    }
+
+    if !dropped.is_empty() {
+        let accounts = dropped.iter().map(|batch| batch.len()).sum::<usize>();
+        error!(accounts, pending = progress.pending, "transactions dropped, retrying");
+    }
+
+    dropped
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::instruction::InstructionError;
+
+    use super::*;
+
+    #[test]
+    fn settle_batches_requeues_only_the_dropped_batches() {
+        let keypairs = (0..12).map(|_| Keypair::new()).collect_vec();
+        let signers = keypairs.iter().collect_vec();
+        let batches = vec![&signers[..5], &signers[5..8], &signers[8..10], &signers[10..]];
+        let signatures = (0..3).map(|_| Signature::new_unique()).collect_vec();
+
+        let landed = HashSet::from([signatures[0]]);
+        let failed_on_chain = HashMap::from([(
+            signatures[1],
+            TransactionError::InstructionError(0, InstructionError::Custom(0)),
+        )]);
+
+        let mut progress = RegisterProgress::new(signers.len());
+        let mut failed = HashMap::new();
+
+        // The last batch failed to send, it was counted then.
+        progress.failed(2);
+
+        let dropped = settle_batches(
+            batches,
+            vec![Some(signatures[0]), Some(signatures[1]), Some(signatures[2]), None],
+            &landed,
+            &failed_on_chain,
+            0,
+            &mut progress,
+            &mut failed,
+        );
+
+        assert_eq!(dropped, vec![&signers[8..10]]);
+        assert_eq!((progress.pending, progress.registered, progress.failed), (2, 5, 5));
+
+        assert_eq!(failed.len(), 3);
+        assert!(signers[5..8]
+            .iter()
+            .all(|signer| failed[&signer.pubkey()].starts_with("transaction failed: ")));
+    }
+
+    #[test]
+    fn settle_batches_sends_every_batch_again_while_none_is_confirmed() {
+        let keypairs = (0..7).map(|_| Keypair::new()).collect_vec();
+        let signers = keypairs.iter().collect_vec();
+        let batches = vec![&signers[..5], &signers[5..]];
+
+        let mut progress = RegisterProgress::new(signers.len());
+        let mut failed = HashMap::new();
+
+        let dropped = settle_batches(
+            batches.clone(),
+            vec![Some(Signature::new_unique()), Some(Signature::new_unique())],
+            &HashSet::new(),
+            &HashMap::new(),
+            0,
+            &mut progress,
+            &mut failed,
+        );
+
+        assert_eq!(dropped, batches);
+        assert_eq!((progress.pending, progress.registered, progress.failed), (7, 0, 0));
+        assert!(failed.is_empty());
+    }
+
+    #[test]
+    fn progress_accounts_for_every_account() {
+        let mut progress = RegisterProgress::new(7);
+
+        progress.failed(2);
+        progress.landed(3);
+
+        assert_eq!(progress.pending + progress.registered + progress.failed, 7);
+        assert_eq!((progress.pending, progress.registered, progress.failed), (2, 3, 2));
     }
 }
@@ -0,0 +1,283 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use solana_sdk::keccak::Hash;
+use tracing::info;
+
+use crate::{format_duration, format_reward, metrics, notify, utils};
+
+/// Set on the first Ctrl-C, mining loops stop starting new rounds and wait for
+/// their sent bundles before exiting.
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+pub fn request_stop() {
+    STOP_REQUESTED.store(true, Ordering::Relaxed);
+}
+
+pub fn stop_requested() -> bool {
+    STOP_REQUESTED.load(Ordering::Relaxed)
+}
+
+/// Counters shared by the mining loop, the bundle tasks and the bundle
+/// watchers.
+#[derive(Debug, Default)]
+pub struct MiningStats {
+    pub rounds: AtomicU64,
+    pub bundles_sent: AtomicU64,
+    pub bundles_landed: AtomicU64,
+    pub bundles_dropped: AtomicU64,
+    /// Bundles given up on once the epoch reset, they can't earn anymore.
+    pub bundles_invalidated: AtomicU64,
+    /// Transactions confirmed with an error.
+    pub txs_failed: AtomicU64,
+    pub tips_paid: AtomicU64,
+    pub rewards: AtomicU64,
+    /// Sum of the confirmation time of the landed bundles.
+    pub confirm_ms: AtomicU64,
+    /// Sum of the mining duration of every round.
+    pub mining_ms: AtomicU64,
+    /// Bundles dropped since the last one landed.
+    consecutive_drops: AtomicU64,
+    in_flight: AtomicUsize,
+    /// By accounts batch id, shown by the `--tui` dashboard.
+    batches: Mutex<BTreeMap<usize, BatchStats>>,
+    round: Mutex<Option<RoundInfo>>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BatchState {
+    #[default]
+    Idle,
+    Mining,
+    Sending,
+    Watching,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchOutcome {
+    Landed {
+        rewards: u64,
+    },
+    /// Landed but every transaction failed.
+    Failed,
+    Dropped,
+    /// The epoch reset before it landed.
+    Invalidated,
+    /// Released without sending, e.g. mining didn't complete.
+    Released,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BatchStats {
+    pub state: BatchState,
+    pub last_tip: Option<u64>,
+    pub last_outcome: Option<BatchOutcome>,
+    pub landed: u64,
+    pub dropped: u64,
+}
+
+/// The treasury as fetched by the latest round.
+#[derive(Debug, Clone, Copy)]
+pub struct RoundInfo {
+    pub reward_rate: u64,
+    pub difficulty: Hash,
+    pub epoch_reset_at: Instant,
+}
+
+impl MiningStats {
+    /// Counts a task that sends or watches bundles until the returned guard is
+    /// dropped.
+    pub fn track(self: &Arc<Self>) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(self.clone())
+    }
+
+    /// Waits until every tracked task is done, returns false if some are still
+    /// running after `timeout`.
+    pub async fn wait_in_flight(&self, timeout: Duration) -> bool {
+        let start = Instant::now();
+
+        while self.in_flight.load(Ordering::Relaxed) > 0 {
+            if start.elapsed() >= timeout {
+                return false;
+            }
+
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+
+        true
+    }
+
+    pub fn record_round(&self, mining_duration: Duration) {
+        metrics::MINING_DURATION.observe(mining_duration.as_secs_f64());
+        self.rounds.fetch_add(1, Ordering::Relaxed);
+        self.mining_ms
+            .fetch_add(mining_duration.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_sent(&self) {
+        metrics::BUNDLES_SENT.inc();
+        self.bundles_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_dropped(&self) {
+        metrics::BUNDLES_DROPPED.inc();
+        self.bundles_dropped.fetch_add(1, Ordering::Relaxed);
+
+        let consecutive = self.consecutive_drops.fetch_add(1, Ordering::Relaxed) + 1;
+        notify::bundles_dropped(consecutive);
+    }
+
+    /// Not counted as dropped, a higher tip wouldn't have helped.
+    pub fn record_invalidated(&self) {
+        metrics::BUNDLES_INVALIDATED.inc();
+        self.bundles_invalidated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_failed(&self, txs: u64) {
+        metrics::TXS_FAILED.inc_by(txs);
+        self.txs_failed.fetch_add(txs, Ordering::Relaxed);
+    }
+
+    pub fn record_landed(&self, tip: u64, rewards: u64, confirm_duration: Duration) {
+        metrics::BUNDLES_LANDED.inc();
+        metrics::TIPS_PAID.inc_by(tip);
+        self.bundles_landed.fetch_add(1, Ordering::Relaxed);
+        self.consecutive_drops.store(0, Ordering::Relaxed);
+        self.tips_paid.fetch_add(tip, Ordering::Relaxed);
+        self.rewards.fetch_add(rewards, Ordering::Relaxed);
+        self.confirm_ms
+            .fetch_add(confirm_duration.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_round_info(&self, reward_rate: u64, difficulty: Hash, time_to_next_epoch: Duration) {
+        *self.round.lock().unwrap() = Some(RoundInfo {
+            reward_rate,
+            difficulty,
+            epoch_reset_at: Instant::now() + time_to_next_epoch,
+        });
+    }
+
+    pub fn round_info(&self) -> Option<RoundInfo> {
+        *self.round.lock().unwrap()
+    }
+
+    pub fn set_batch_state(&self, id: usize, state: BatchState) {
+        self.batches.lock().unwrap().entry(id).or_default().state = state;
+    }
+
+    /// The bundles of the batch are sent and watched.
+    pub fn record_batch_sent(&self, id: usize, tip: u64) {
+        let mut batches = self.batches.lock().unwrap();
+        let batch = batches.entry(id).or_default();
+
+        batch.state = BatchState::Watching;
+        batch.last_tip = Some(tip);
+    }
+
+    /// The batch is idle again, back in the rotation.
+    pub fn record_batch_outcome(&self, id: usize, outcome: BatchOutcome) {
+        let mut batches = self.batches.lock().unwrap();
+        let batch = batches.entry(id).or_default();
+
+        batch.state = BatchState::Idle;
+        batch.last_outcome = Some(outcome);
+
+        match outcome {
+            BatchOutcome::Landed { .. } => batch.landed += 1,
+            BatchOutcome::Dropped => batch.dropped += 1,
+            BatchOutcome::Failed | BatchOutcome::Invalidated | BatchOutcome::Released => {}
+        }
+    }
+
+    pub fn batches(&self) -> Vec<(usize, BatchStats)> {
+        self.batches
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, batch)| (*id, *batch))
+            .collect()
+    }
+
+    /// Logs the aggregate every `interval` until the process exits.
+    pub fn spawn_reporter(self: &Arc<Self>, interval: Duration) {
+        let stats = self.clone();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                stats.log("mining stats");
+            }
+        });
+    }
+
+    pub fn log_summary(&self) {
+        self.log("mining summary");
+    }
+
+    fn log(&self, message: &str) {
+        let rounds = self.rounds.load(Ordering::Relaxed);
+        let landed = self.bundles_landed.load(Ordering::Relaxed);
+        let dropped = self.bundles_dropped.load(Ordering::Relaxed);
+
+        let landing_rate = match landed + dropped {
+            0 => 0.0,
+            watched => landed as f64 / watched as f64 * 100.0,
+        };
+        let confirm = Duration::from_millis(self.confirm_ms.load(Ordering::Relaxed) / landed.max(1));
+        let mining = Duration::from_millis(self.mining_ms.load(Ordering::Relaxed) / rounds.max(1));
+
+        info!(
+            rounds,
+            bundles.sent = self.bundles_sent.load(Ordering::Relaxed),
+            bundles.landed = landed,
+            bundles.dropped = dropped,
+            bundles.invalidated = self.bundles_invalidated.load(Ordering::Relaxed),
+            txs.failed = self.txs_failed.load(Ordering::Relaxed),
+            landing_rate = format_args!("{landing_rate:.1}%"),
+            confirm.avg = format_duration!(confirm),
+            mining.avg = format_duration!(mining),
+            tips = self.tips_paid.load(Ordering::Relaxed),
+            rewards = format_reward!(self.rewards.load(Ordering::Relaxed)),
+            "{message}"
+        );
+    }
+}
+
+pub struct InFlight(Arc<MiningStats>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Stops a mining loop after a number of rounds, once it has run for long
+/// enough or when a stop is requested.
+#[derive(Debug, Clone)]
+pub struct RunLimit {
+    max_rounds: Option<u64>,
+    deadline: Option<Instant>,
+}
+
+impl RunLimit {
+    pub fn new(max_rounds: Option<u64>, run_for: Option<Duration>) -> Self {
+        Self {
+            max_rounds,
+            deadline: run_for.and_then(|run_for| Instant::now().checked_add(run_for)),
+        }
+    }
+
+    pub fn reached(&self, rounds: u64) -> bool {
+        stop_requested() ||
+            self.max_rounds.is_some_and(|max_rounds| rounds >= max_rounds) ||
+            self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use clap::Parser;
+use tracing::error;
+
+use crate::{format_duration, utils, Miner};
+
+#[derive(Parser, Debug, Clone)]
+pub struct StatusArgs {
+    #[arg(long, default_value = "false", help = "Print the status again every few seconds")]
+    pub watch: bool,
+
+    #[arg(long, default_value = "5", help = "Seconds between two refreshes with --watch")]
+    pub interval: u64,
+}
+
+impl Miner {
+    pub async fn status(&self, args: &StatusArgs) {
+        let client = Self::get_client_confirmed(&self.rpc);
+
+        loop {
+            match Self::get_system_accounts(&client).await {
+                Ok((treasury, clock, buses)) => {
+                    let difficulty: solana_sdk::keccak::Hash = treasury.difficulty.into();
+                    let reset_threshold = treasury.last_reset_at.saturating_add(ore::EPOCH_DURATION);
+
+                    // The reset waits for the first transaction past the
+                    // threshold.
+                    let time_to_next_epoch = match clock.unix_timestamp < reset_threshold {
+                        true => Self::get_time_to_next_epoch(&treasury, &clock, reset_threshold),
+                        false => Duration::ZERO,
+                    };
+
+                    println!("slot                {}", clock.slot);
+                    println!("difficulty          {difficulty}");
+                    println!(
+                        "reward rate         {} ({} ORE per mine)",
+                        treasury.reward_rate,
+                        utils::ore_ui_amount(treasury.reward_rate)
+                    );
+                    println!("next epoch reset in {}", format_duration!(time_to_next_epoch));
+
+                    for bus in &buses {
+                        println!("bus {}               {} ORE", bus.id, utils::ore_ui_amount(bus.rewards));
+                    }
+
+                    let total = buses.iter().map(|bus| bus.rewards).sum::<u64>();
+                    println!("total bus rewards   {} ORE", utils::ore_ui_amount(total));
+                }
+                Err(err) => error!("fail to get system accounts: {err:#}"),
+            }
+
+            if !args.watch {
+                break;
+            }
+
+            tokio::time::sleep(Duration::from_secs(args.interval)).await;
+            println!();
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use ore::state::{Bus, Treasury};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::clock::Clock;
+use tokio::sync::Mutex;
+use tracing::debug;
+
+use crate::Miner;
+
+/// Concurrent workers start their rounds around the same time, a fetch this
+/// recent is shared instead of fetching again.
+const MAX_AGE: Duration = Duration::from_secs(2);
+
+pub type SystemAccounts = (Treasury, Clock, [Bus; ore::BUS_COUNT]);
+
+/// Treasury, clock and buses shared by the mining workers, so that they agree
+/// on the difficulty and don't each fetch them every round.
+#[derive(Clone)]
+pub struct SystemAccountsCache {
+    client: Arc<RpcClient>,
+    latest: Arc<Mutex<Option<(SystemAccounts, Instant)>>>,
+}
+
+impl SystemAccountsCache {
+    pub fn new(client: Arc<RpcClient>) -> Self {
+        Self {
+            client,
+            latest: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Same as `Miner::get_system_accounts`, from the cache while it is fresh.
+    /// The lock is held while fetching, workers asking at the same time wait
+    /// for that fetch instead of issuing their own.
+    pub async fn get(&self) -> eyre::Result<SystemAccounts> {
+        let mut latest = self.latest.lock().await;
+
+        if let Some(((treasury, clock, buses), fetched_at)) = latest.as_ref() {
+            let age = fetched_at.elapsed();
+
+            if age < MAX_AGE {
+                debug!(age_ms = age.as_millis() as u64, "cached system accounts");
+
+                // The clock keeps going while cached, the time to the next
+                // epoch is computed from it.
+                let mut clock = clock.clone();
+                clock.unix_timestamp += age.as_secs() as i64;
+
+                return Ok((*treasury, clock, *buses));
+            }
+        }
+
+        let accounts = Miner::get_system_accounts(&self.client).await?;
+        *latest = Some((accounts.clone(), Instant::now()));
+
+        Ok(accounts)
+    }
+}
@@ -1,28 +1,262 @@
-use std::{collections::HashMap, env, path::PathBuf};
+use std::{
+    collections::HashMap,
+    env,
+    fs,
+    future::Future,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
+use bip39::{Language, Mnemonic};
 use cached::proc_macro::cached;
-use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
+use once_cell::sync::OnceCell;
+use rand::Rng;
+use solana_sdk::{
+    clock::Slot,
+    commitment_config::CommitmentConfig,
+    derivation_path::DerivationPath,
+    keccak,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    signer::keypair::{generate_seed_from_seed_phrase_and_passphrase, keypair_from_seed_and_derivation_path},
+    transaction::TransactionError,
+};
 use solana_transaction_status::TransactionStatus;
+use tracing::{debug, warn};
 
+use crate::{
+    cluster,
+    pda_cache::{self, Derivation},
+    AccountError,
+};
+
+/// Set from `--commitment`, every request and landing check then uses it.
+static COMMITMENT: OnceCell<CommitmentConfig> = OnceCell::new();
+
+pub fn set_commitment(commitment: CommitmentConfig) {
+    let _ = COMMITMENT.set(commitment);
+}
+
+/// The `--commitment` given on the command line, `default` without it.
+pub fn commitment_or(default: CommitmentConfig) -> CommitmentConfig {
+    COMMITMENT.get().copied().unwrap_or(default)
+}
+
+/// Keyed on the program id, which `--cluster` selects.
 #[cached]
-pub fn get_proof_pda(authority: Pubkey) -> Pubkey {
-    Pubkey::find_program_address(&[ore::PROOF, authority.as_ref()], &ore::ID).0
+fn find_proof_pda(program_id: Pubkey, authority: Pubkey) -> Pubkey {
+    pda_cache::get(Derivation::Proof { program_id, authority })
 }
 
 #[cached]
+fn find_ata(owner: Pubkey, mint: Pubkey) -> Pubkey {
+    spl_associated_token_account::get_associated_token_address(&owner, &mint)
+}
+
+pub fn get_proof_pda(authority: Pubkey) -> Pubkey {
+    find_proof_pda(cluster::program_id(), authority)
+}
+
+pub fn get_proof_pda_no_cache(authority: Pubkey) -> Pubkey {
+    find_proof_pda_no_cache(cluster::program_id(), authority)
+}
+
 pub fn get_treasury_ata() -> Pubkey {
-    spl_associated_token_account::get_associated_token_address(&ore::TREASURY_ADDRESS, &ore::MINT_ADDRESS)
+    find_ata(cluster::addresses().treasury, cluster::mint())
 }
 
-#[cached]
 pub fn get_ore_ata(owner: Pubkey) -> Pubkey {
-    spl_associated_token_account::get_associated_token_address(&owner, &ore::MINT_ADDRESS)
+    find_ata(owner, cluster::mint())
+}
+
+pub fn available_threads() -> usize {
+    std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4)
+}
+
+/// Parses `<n>[s|m|h]`, in seconds when there is no unit.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => value.split_at(i),
+        None => (value, "s"),
+    };
+
+    let number = number.parse::<u64>().map_err(|_| format!("invalid duration {value}"))?;
+
+    let secs = match unit {
+        "s" => number,
+        "m" => number.saturating_mul(60),
+        "h" => number.saturating_mul(60 * 60),
+        _ => return Err(format!("invalid duration unit {unit}, expected s, m or h")),
+    };
+
+    Ok(Duration::from_secs(secs))
+}
+
+/// Reads a keypair file holding a JSON byte array, a base58 secret key as
+/// exported by Phantom or a 12/24 word seed phrase. A seed phrase gives the
+/// first wallet of Phantom, `m/44'/501'/0'/0'`.
+pub fn read_keypair(path: impl AsRef<Path>) -> eyre::Result<Keypair> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path).map_err(|err| eyre::eyre!("fail to read {}: {err}", path.display()))?;
+    let content = content.trim();
+    let words = content.split_whitespace().collect::<Vec<_>>();
+
+    let (format, keypair) = if content.starts_with('[') {
+        let keypair = serde_json::from_str::<Vec<u8>>(content)
+            .map_err(|err| eyre::eyre!("{err}"))
+            .and_then(|bytes| Keypair::from_bytes(&bytes).map_err(|err| eyre::eyre!("{err}")));
+
+        ("JSON byte array", keypair)
+    } else if words.len() > 1 {
+        let keypair = match words.len() {
+            12 | 24 => seed_phrase(content).and_then(|phrase| {
+                let seed = generate_seed_from_seed_phrase_and_passphrase(&phrase, "");
+                let path = DerivationPath::new_bip44(Some(0), Some(0));
+
+                keypair_from_seed_and_derivation_path(&seed, Some(path)).map_err(|err| eyre::eyre!("{err}"))
+            }),
+            count => Err(eyre::eyre!("{count} words, expected 12 or 24")),
+        };
+
+        ("seed phrase", keypair)
+    } else {
+        let keypair = bs58::decode(content)
+            .into_vec()
+            .map_err(|err| eyre::eyre!("{err}"))
+            .and_then(|bytes| Keypair::from_bytes(&bytes).map_err(|err| eyre::eyre!("{err}")));
+
+        ("base58 secret key", keypair)
+    };
+
+    keypair.map_err(|err| eyre::eyre!("invalid keypair in {} read as {format}: {err}", path.display()))
+}
+
+/// The words of `phrase` joined by single spaces, once checked against the
+/// BIP39 English wordlist and checksum so that a mistyped phrase isn't read as
+/// another wallet.
+pub fn seed_phrase(phrase: &str) -> eyre::Result<String> {
+    let phrase = phrase.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    Mnemonic::from_phrase(&phrase, Language::English).map_err(|err| eyre::eyre!("{err}"))?;
+
+    Ok(phrase)
+}
+
+/// Exponential backoff with jitter for retrying RPC calls, so that a node that
+/// is down isn't hammered by every mining loop.
+#[derive(Debug, Clone, Copy)]
+pub struct BackoffPolicy {
+    pub base: Duration,
+    pub max_delay: Duration,
+    pub max_retries: u32,
+}
+
+impl BackoffPolicy {
+    pub const RPC: Self = Self {
+        base: Duration::from_millis(500),
+        max_delay: Duration::from_secs(30),
+        max_retries: 5,
+    };
+
+    /// Doubles from `base` on every retry up to `max_delay`, then takes a
+    /// random 50% to 100% of it so that the loops don't retry in lockstep.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let delay = self.base.saturating_mul(1 << retry.min(16)).min(self.max_delay);
+
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+/// Runs `f` until it succeeds or `policy.max_retries` retries failed, returning
+/// the last error then.
+pub async fn retry_with_backoff<T, F, Fut>(what: &str, policy: BackoffPolicy, mut f: F) -> eyre::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = eyre::Result<T>>,
+{
+    let mut retry = 0;
+
+    loop {
+        match f().await {
+            Ok(value) => {
+                if retry > 0 {
+                    debug!(retries = retry, "{what} succeeded after retrying");
+                }
+
+                return Ok(value);
+            }
+            // A missing or undecodable account stays so, only the RPC errors
+            // are retried.
+            Err(err)
+                if retry < policy.max_retries &&
+                    err.downcast_ref::<AccountError>()
+                        .map_or(true, AccountError::is_transient) =>
+            {
+                let delay = policy.delay(retry);
+                retry += 1;
+
+                warn!(retry, delay = ?delay, "fail to {what}, retrying: {err:#}");
+                tokio::time::sleep(delay).await;
+            }
+            Err(err) => return Err(err.wrap_err(format!("fail to {what} after {retry} retries"))),
+        }
+    }
+}
+
+/// Fetches `items` with requests of at most `limit` of them and merges the
+/// answers in the order of `items`, with the lowest slot the requests were
+/// answered at. An answer short of its chunk is padded with `None` so that the
+/// positions still match. Without items a single empty request is sent, it
+/// still answers the current slot.
+pub async fn fetch_chunked<'a, T, U, E, F, Fut>(
+    items: &'a [T],
+    limit: usize,
+    mut fetch: F,
+) -> Result<(Vec<Option<U>>, Slot), E>
+where
+    F: FnMut(&'a [T]) -> Fut,
+    Fut: Future<Output = Result<(Vec<Option<U>>, Slot), E>>,
+{
+    let chunks = match items.is_empty() {
+        true => vec![items],
+        false => items.chunks(limit).collect(),
+    };
+
+    let mut merged = Vec::with_capacity(items.len());
+    let mut slot = None;
+
+    for chunk in chunks {
+        let (mut values, chunk_slot) = fetch(chunk).await?;
+        values.resize_with(chunk.len(), || None);
+
+        merged.extend(values);
+        slot = Some(slot.map_or(chunk_slot, |slot: Slot| slot.min(chunk_slot)));
+    }
+
+    Ok((merged, slot.unwrap_or_default()))
 }
 
 pub fn ore_ui_amount(amount: u64) -> f64 {
     spl_token::amount_to_ui_amount(amount, ore::TOKEN_DECIMALS)
 }
 
+/// The pubsub websocket of `rpc`, served by the validator on the next port
+/// when the RPC has an explicit port.
+pub fn ws_url(rpc: &str) -> String {
+    let url = match rpc.split_once("://") {
+        Some(("https", rest)) => format!("wss://{rest}"),
+        Some(("http", rest)) => format!("ws://{rest}"),
+        _ => rpc.to_string(),
+    };
+
+    match url.rsplit_once(':') {
+        Some((host, port)) if !host.ends_with('/') && port.parse::<u16>().is_ok() => {
+            format!("{host}:{}", port.parse::<u16>().unwrap().saturating_add(1))
+        }
+        _ => url,
+    }
+}
+
 #[cached]
 pub fn get_gpu_nonce_worker_path() -> PathBuf {
     env::current_exe().unwrap().parent().unwrap().join("nonce-worker-gpu")
@@ -33,27 +267,127 @@ pub fn get_nonce_worker_path() -> PathBuf {
     env::current_exe().unwrap().parent().unwrap().join("nonce-worker")
 }
 
-pub fn find_landed_txs(signatures: &[Signature], statuses: Vec<Option<TransactionStatus>>) -> Vec<Signature> {
-    let landed_tx = statuses
-        .into_iter()
-        .zip(signatures.iter())
-        .filter_map(|(status, sig)| {
-            if status?.satisfies_commitment(CommitmentConfig::confirmed()) {
-                Some(*sig)
-            } else {
-                None
+/// Checks that `result` is keccak(hash ‖ pubkey ‖ nonce) and satisfies the
+/// difficulty, the same check the program runs on mine.
+pub fn verify_nonce(
+    difficulty: &keccak::Hash,
+    hash: &keccak::Hash,
+    pubkey: &Pubkey,
+    result: &keccak::Hash,
+    nonce: u64,
+) -> bool {
+    let expected = keccak::hashv(&[hash.as_ref(), pubkey.as_ref(), nonce.to_le_bytes().as_slice()]);
+
+    expected == *result && result.to_bytes() <= difficulty.to_bytes()
+}
+
+/// What became of a sent transaction. A transaction can be confirmed and
+/// still have failed, e.g. a mine instruction with a stale hash, it then
+/// earned nothing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxOutcome {
+    /// Not confirmed yet, or dropped once expired.
+    Pending,
+    Landed {
+        slot: Slot,
+    },
+    Failed {
+        slot: Slot,
+        err: TransactionError,
+    },
+}
+
+impl TxOutcome {
+    /// Confirmed, whether it succeeded or not.
+    pub fn is_confirmed(&self) -> bool {
+        !matches!(self, Self::Pending)
+    }
+}
+
+/// The outcome of each signature, in the order of `signatures`.
+pub fn find_tx_outcomes(
+    signatures: &[Signature],
+    statuses: Vec<Option<TransactionStatus>>,
+) -> Vec<(Signature, TxOutcome)> {
+    let commitment = commitment_or(CommitmentConfig::confirmed());
+
+    signatures
+        .iter()
+        .zip(statuses.into_iter().chain(std::iter::repeat(None)))
+        .map(|(sig, status)| {
+            let outcome = match status {
+                Some(status) if status.satisfies_commitment(commitment) => match status.err {
+                    None => TxOutcome::Landed { slot: status.slot },
+                    Some(err) => TxOutcome::Failed { slot: status.slot, err },
+                },
+                _ => TxOutcome::Pending,
+            };
+
+            (*sig, outcome)
+        })
+        .collect()
+}
+
+/// A mine transaction and its copies in the bundles sent to the other buses,
+/// at most one of them lands.
+#[derive(Debug, Clone)]
+pub struct BusTx {
+    /// Signature of the transaction in the bundle of each bus.
+    pub signatures: Vec<Signature>,
+    pub signers: Vec<Pubkey>,
+    /// Signature and priority fees paid once it lands.
+    pub fees: u64,
+    /// Tip paid once it lands, when it carries the bribe of the bundle.
+    pub tip: u64,
+}
+
+/// The outcome of each transaction over all the buses: landed when one copy
+/// landed, failed when one failed and none landed. The signature is the one of
+/// the copy that decided, the first one while pending. Over RPC the
+/// transactions of a bundle land one by one, each is settled on its own.
+pub fn bus_tx_outcomes(txs: &[BusTx], outcomes: &[(Signature, TxOutcome)]) -> Vec<(Signature, TxOutcome)> {
+    let outcomes = outcomes.iter().cloned().collect::<HashMap<_, _>>();
+
+    txs.iter()
+        .map(|tx| {
+            let mut settled = (tx.signatures[0], TxOutcome::Pending);
+
+            for signature in &tx.signatures {
+                match outcomes.get(signature) {
+                    Some(outcome @ TxOutcome::Landed { .. }) => return (*signature, outcome.clone()),
+                    Some(outcome @ TxOutcome::Failed { .. }) if !settled.1.is_confirmed() => {
+                        settled = (*signature, outcome.clone());
+                    }
+                    _ => {}
+                }
             }
+
+            settled
         })
-        .collect::<Vec<_>>();
+        .collect()
+}
 
-    landed_tx
+/// Slot of the first landed transaction of `outcomes`, 0 when none landed.
+pub fn landed_slot(outcomes: &[(Signature, TxOutcome)]) -> Slot {
+    outcomes
+        .iter()
+        .find_map(|(_, outcome)| match outcome {
+            TxOutcome::Landed { slot } => Some(*slot),
+            _ => None,
+        })
+        .unwrap_or_default()
 }
 
-pub fn pick_richest_account(account_balances: &HashMap<Pubkey, u64>, accounts: &[Pubkey]) -> Pubkey {
-    *accounts
+/// The account holding the most lamports, an account missing from the
+/// balances doesn't exist on chain and holds nothing. None when no account
+/// holds any.
+pub fn pick_richest_account(account_balances: &HashMap<Pubkey, u64>, accounts: &[Pubkey]) -> Option<Pubkey> {
+    accounts
         .iter()
-        .max_by_key(|pubkey| account_balances.get(pubkey).unwrap())
-        .expect("accounts should not be empty")
+        .map(|pubkey| (*pubkey, account_balances.get(pubkey).copied().unwrap_or_default()))
+        .filter(|(_, balance)| *balance > 0)
+        .max_by_key(|(_, balance)| *balance)
+        .map(|(pubkey, _)| pubkey)
 }
 
 #[macro_export]
@@ -90,3 +424,128 @@ macro_rules! wait_continue {
         continue;
     }};
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constant;
+
+    #[test]
+    fn seed_phrase_accepts_a_valid_phrase() {
+        let phrase = "abandon abandon abandon abandon abandon abandon\nabandon abandon abandon abandon abandon  about";
+
+        assert_eq!(
+            seed_phrase(phrase).unwrap(),
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about"
+        );
+    }
+
+    #[test]
+    fn seed_phrase_rejects_a_bad_checksum() {
+        assert!(seed_phrase(&["abandon"; 12].join(" ")).is_err());
+    }
+
+    #[test]
+    fn seed_phrase_rejects_words_outside_the_wordlist() {
+        assert!(seed_phrase("buy milk eggs bread and call the plumber about the sink tomorrow").is_err());
+    }
+
+    /// Answers every item of a chunk at `slot`, recording the chunk sizes.
+    async fn fetch_all<T: Copy>(items: &[T], limit: usize, slots: &[Slot]) -> (Vec<Option<T>>, Slot, Vec<usize>) {
+        let mut sizes = vec![];
+
+        let (merged, slot) = fetch_chunked(items, limit, |chunk| {
+            let slot = slots[sizes.len() % slots.len()];
+            sizes.push(chunk.len());
+
+            async move { Ok::<_, ()>((chunk.iter().copied().map(Some).collect(), slot)) }
+        })
+        .await
+        .unwrap();
+
+        (merged, slot, sizes)
+    }
+
+    #[tokio::test]
+    async fn fetch_chunked_keeps_the_order_of_101_pubkeys() {
+        let pubkeys = (0..101).map(|_| Pubkey::new_unique()).collect::<Vec<_>>();
+
+        let (merged, _, sizes) = fetch_all(&pubkeys, constant::FETCH_ACCOUNT_LIMIT, &[10]).await;
+
+        assert_eq!(sizes, vec![100, 1]);
+        assert_eq!(merged, pubkeys.into_iter().map(Some).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn fetch_chunked_keeps_the_order_of_257_signatures() {
+        let signatures = (0..257).map(|_| Signature::new_unique()).collect::<Vec<_>>();
+
+        let (merged, _, sizes) = fetch_all(&signatures, constant::SIGNATURE_STATUS_LIMIT, &[10]).await;
+
+        assert_eq!(sizes, vec![256, 1]);
+        assert_eq!(merged, signatures.into_iter().map(Some).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn fetch_chunked_answers_the_lowest_slot() {
+        let (_, slot, _) = fetch_all(&[1, 2, 3, 4, 5], 2, &[12, 10, 11]).await;
+
+        assert_eq!(slot, 10);
+    }
+
+    #[tokio::test]
+    async fn fetch_chunked_sends_one_request_without_items() {
+        let (merged, slot, sizes) = fetch_all::<u8>(&[], 100, &[10]).await;
+
+        assert!(merged.is_empty());
+        assert_eq!(slot, 10);
+        assert_eq!(sizes, vec![0]);
+    }
+
+    #[tokio::test]
+    async fn fetch_chunked_pads_short_answers() {
+        let (merged, _) = fetch_chunked(&[1, 2, 3, 4, 5], 2, |chunk| async move {
+            Ok::<_, ()>((chunk.iter().copied().take(1).map(Some).collect::<Vec<_>>(), 0))
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(merged, vec![Some(1), None, Some(3), None, Some(5)]);
+    }
+
+    #[test]
+    fn bus_tx_outcomes_settle_each_transaction_over_the_buses() {
+        let txs = (0..3)
+            .map(|_| BusTx {
+                signatures: vec![Signature::new_unique(), Signature::new_unique()],
+                signers: vec![Pubkey::new_unique()],
+                fees: 5000,
+                tip: 0,
+            })
+            .collect::<Vec<_>>();
+        let failed = TxOutcome::Failed {
+            slot: 5,
+            err: TransactionError::AccountNotFound,
+        };
+
+        // The first transaction failed on a bus and landed on the other, the
+        // second only failed, the third was dropped on both.
+        let outcomes = vec![
+            (txs[0].signatures[0], failed.clone()),
+            (txs[0].signatures[1], TxOutcome::Landed { slot: 6 }),
+            (txs[1].signatures[0], TxOutcome::Pending),
+            (txs[1].signatures[1], failed.clone()),
+            (txs[2].signatures[0], TxOutcome::Pending),
+            (txs[2].signatures[1], TxOutcome::Pending),
+        ];
+
+        assert_eq!(
+            bus_tx_outcomes(&txs, &outcomes),
+            vec![
+                (txs[0].signatures[1], TxOutcome::Landed { slot: 6 }),
+                (txs[1].signatures[1], failed),
+                (txs[2].signatures[0], TxOutcome::Pending),
+            ]
+        );
+    }
+}
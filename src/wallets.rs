@@ -0,0 +1,129 @@
+use clap::Parser;
+use ore::{state::Proof, utils::AccountDeserialize};
+use serde::Serialize;
+use solana_sdk::signature::Signer;
+use spl_token::{solana_program::program_pack::Pack, state::Account as TokenAccount};
+use tracing::error;
+
+use crate::{constant, utils, Miner};
+
+#[derive(Parser, Debug, Clone)]
+pub struct WalletsArgs {
+    #[arg(long, help = "The folder that contains the keys of the wallets")]
+    pub key_folder: String,
+
+    #[arg(long, default_value = "false", help = "Print the wallets and the totals as JSON")]
+    pub json: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct WalletBalances {
+    wallet: String,
+    /// Lamports.
+    sol: u64,
+    /// None when the token account doesn't exist.
+    ore: Option<u64>,
+    registered: bool,
+    claimable: u64,
+}
+
+impl Miner {
+    pub async fn wallets(&self, args: &WalletsArgs) {
+        let client = Self::get_client_confirmed(&self.rpc);
+        let accounts = match Self::read_keys(&args.key_folder, self.recursive) {
+            Ok(accounts) => accounts,
+            Err(err) => {
+                error!("{err:#}");
+                return;
+            }
+        };
+        let mut wallets = Vec::with_capacity(accounts.len());
+
+        for batch in accounts.chunks(constant::FETCH_ACCOUNT_LIMIT) {
+            let pubkeys = batch.iter().map(|key| key.pubkey()).collect::<Vec<_>>();
+            let atas = pubkeys
+                .iter()
+                .map(|pubkey| utils::get_ore_ata(*pubkey))
+                .collect::<Vec<_>>();
+            let proofs = pubkeys
+                .iter()
+                .map(|pubkey| utils::get_proof_pda(*pubkey))
+                .collect::<Vec<_>>();
+
+            let fetched = tokio::try_join!(
+                Self::get_balances(&client, &pubkeys),
+                async { Ok::<_, eyre::Report>(client.get_multiple_accounts(&atas).await?) },
+                async { Ok::<_, eyre::Report>(client.get_multiple_accounts(&proofs).await?) },
+            );
+
+            let (balances, ata_accounts, proof_accounts) = match fetched {
+                Ok(value) => value,
+                Err(err) => {
+                    error!("fail to get wallet accounts: {err:#}");
+                    return;
+                }
+            };
+
+            for ((pubkey, ata_account), proof_account) in pubkeys.iter().zip(ata_accounts).zip(proof_accounts) {
+                let proof = proof_account.and_then(|account| Proof::try_from_bytes(&account.data).ok().copied());
+
+                wallets.push(WalletBalances {
+                    wallet: pubkey.to_string(),
+                    sol: balances.get(pubkey).copied().unwrap_or_default(),
+                    ore: ata_account.and_then(|account| TokenAccount::unpack(&account.data).ok().map(|ata| ata.amount)),
+                    registered: proof.is_some(),
+                    claimable: proof.map(|proof| proof.claimable_rewards).unwrap_or_default(),
+                });
+            }
+        }
+
+        let registered = wallets.iter().filter(|wallet| wallet.registered).count();
+        let total_sol = wallets.iter().map(|wallet| wallet.sol).sum::<u64>();
+        let total_ore = wallets.iter().filter_map(|wallet| wallet.ore).sum::<u64>();
+        let total_claimable = wallets.iter().map(|wallet| wallet.claimable).sum::<u64>();
+
+        if args.json {
+            let output = serde_json::json!({
+                "wallets": wallets,
+                "total": {
+                    "wallets": wallets.len(),
+                    "registered": registered,
+                    "sol": total_sol,
+                    "ore": total_ore,
+                    "claimable": total_claimable,
+                },
+            });
+
+            println!("{}", serde_json::to_string_pretty(&output).unwrap());
+            return;
+        }
+
+        println!(
+            "{:<44} {:>14} {:>14} {:>10} {:>14}",
+            "wallet", "sol", "ore", "registered", "claimable"
+        );
+
+        for wallet in &wallets {
+            println!(
+                "{:<44} {:>14} {:>14} {:>10} {:>14}",
+                wallet.wallet,
+                spl_token::amount_to_ui_amount(wallet.sol, 9),
+                wallet
+                    .ore
+                    .map(|ore| utils::ore_ui_amount(ore).to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                wallet.registered,
+                utils::ore_ui_amount(wallet.claimable)
+            );
+        }
+
+        println!(
+            "{:<44} {:>14} {:>14} {:>10} {:>14}",
+            "total",
+            spl_token::amount_to_ui_amount(total_sol, 9),
+            utils::ore_ui_amount(total_ore),
+            registered,
+            utils::ore_ui_amount(total_claimable)
+        );
+    }
+}
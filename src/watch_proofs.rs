@@ -0,0 +1,243 @@
+use std::time::Duration;
+
+use clap::Parser;
+use futures_util::{stream::select_all, StreamExt};
+use ore::state::Proof;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::{
+    nonblocking::{pubsub_client::PubsubClient, rpc_client::RpcClient},
+    rpc_config::RpcAccountInfoConfig,
+};
+use solana_sdk::{account::Account, commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signer};
+use tracing::{error, info, warn};
+
+use crate::{constant, format_reward, notify, parse_account, utils, Miner};
+
+#[derive(Parser, Debug, Clone)]
+pub struct WatchProofsArgs {
+    #[arg(long, help = "The folder that contains the keys of the wallets to watch")]
+    pub key_folder: String,
+
+    #[arg(
+        long,
+        help = "Websocket of the RPC, derived from --rpc when not set, e.g. wss:// for https://"
+    )]
+    pub ws_url: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Poll the proofs instead of subscribing to them"
+    )]
+    pub poll: bool,
+
+    #[arg(
+        long,
+        default_value = "10",
+        help = "Seconds between two polls, with --poll or when the websocket is unreachable"
+    )]
+    pub interval: u64,
+}
+
+/// The proofs as last seen and what changed since the watch started.
+struct ProofWatch {
+    wallets: Vec<Pubkey>,
+    proofs: Vec<Option<Proof>>,
+    changes: u64,
+    credited: u64,
+    claimed: u64,
+}
+
+impl Miner {
+    /// Logs every change of the proofs of `--key-folder`, without mining.
+    pub async fn watch_proofs(&self, args: &WatchProofsArgs) {
+        let wallets = match Self::read_keys(&args.key_folder, self.recursive) {
+            Ok(keys) => keys.iter().map(|key| key.pubkey()).collect::<Vec<_>>(),
+            Err(err) => {
+                error!("{err:#}");
+                return;
+            }
+        };
+
+        let client = Self::get_client_confirmed(&self.rpc);
+        let proof_pda = wallets
+            .iter()
+            .map(|wallet| utils::get_proof_pda(*wallet))
+            .collect::<Vec<_>>();
+
+        let proofs = match fetch_proofs(&client, &proof_pda).await {
+            Ok(proofs) => proofs,
+            Err(err) => {
+                error!("fail to fetch proof accounts: {err:#}");
+                return;
+            }
+        };
+
+        let unregistered = proofs.iter().filter(|proof| proof.is_none()).count();
+        let claimable = proofs
+            .iter()
+            .flatten()
+            .map(|proof| proof.claimable_rewards)
+            .sum::<u64>();
+
+        info!(
+            wallets = wallets.len(),
+            unregistered,
+            claimable = format_reward!(claimable),
+            "watching proofs"
+        );
+
+        let mut watch = ProofWatch {
+            wallets,
+            proofs,
+            changes: 0,
+            credited: 0,
+            claimed: 0,
+        };
+
+        if !args.poll {
+            let ws_url = args.ws_url.clone().unwrap_or_else(|| utils::ws_url(&self.rpc));
+
+            loop {
+                match subscribe_proofs(&ws_url, &proof_pda, &mut watch).await {
+                    Ok(()) => warn!(ws_url, "proof subscriptions closed, subscribing again"),
+                    Err(err) => {
+                        warn!(ws_url, "fail to subscribe to the proofs, polling instead: {err:#}");
+                        break;
+                    }
+                }
+
+                // Catches up with the changes missed while disconnected.
+                if let Err(err) = poll_proofs(&client, &proof_pda, &mut watch).await {
+                    error!("fail to fetch proof accounts: {err:#}");
+                }
+            }
+        }
+
+        loop {
+            if let Err(err) = poll_proofs(&client, &proof_pda, &mut watch).await {
+                error!("fail to fetch proof accounts: {err:#}");
+            }
+
+            tokio::time::sleep(Duration::from_secs(args.interval)).await;
+        }
+    }
+}
+
+/// The proofs of `proof_pda`, `None` for the wallets without one.
+async fn fetch_proofs(client: &RpcClient, proof_pda: &[Pubkey]) -> eyre::Result<Vec<Option<Proof>>> {
+    let mut proofs = Vec::with_capacity(proof_pda.len());
+
+    for chunk in proof_pda.chunks(constant::FETCH_ACCOUNT_LIMIT) {
+        let accounts = client
+            .get_multiple_accounts_with_commitment(chunk, utils::commitment_or(CommitmentConfig::confirmed()))
+            .await?
+            .value;
+
+        proofs.extend(
+            chunk
+                .iter()
+                .zip(accounts)
+                .map(|(pubkey, account)| parse_account(pubkey, account).ok()),
+        );
+    }
+
+    Ok(proofs)
+}
+
+async fn poll_proofs(client: &RpcClient, proof_pda: &[Pubkey], watch: &mut ProofWatch) -> eyre::Result<()> {
+    for (i, proof) in fetch_proofs(client, proof_pda).await?.into_iter().enumerate() {
+        watch.observe(i, proof);
+    }
+
+    Ok(())
+}
+
+/// Follows the proofs until the websocket closes. Fails when the
+/// subscriptions can't be made.
+async fn subscribe_proofs(ws_url: &str, proof_pda: &[Pubkey], watch: &mut ProofWatch) -> eyre::Result<()> {
+    let pubsub = PubsubClient::new(ws_url).await?;
+    let config = RpcAccountInfoConfig {
+        encoding: Some(UiAccountEncoding::Base64),
+        commitment: Some(utils::commitment_or(CommitmentConfig::confirmed())),
+        ..RpcAccountInfoConfig::default()
+    };
+
+    let mut streams = Vec::with_capacity(proof_pda.len());
+
+    for (i, pubkey) in proof_pda.iter().enumerate() {
+        let (stream, _unsubscribe) = pubsub.account_subscribe(pubkey, Some(config.clone())).await?;
+        streams.push(stream.map(move |response| (i, response.value.decode::<Account>())));
+    }
+
+    let mut updates = select_all(streams);
+
+    while let Some((i, account)) = updates.next().await {
+        watch.observe(i, parse_account(&proof_pda[i], account).ok());
+    }
+
+    Ok(())
+}
+
+impl ProofWatch {
+    fn observe(&mut self, i: usize, proof: Option<Proof>) {
+        let wallet = self.wallets[i];
+        let before = std::mem::replace(&mut self.proofs[i], proof);
+
+        let (before, after) = match (before, proof) {
+            (Some(before), Some(after)) => (before, after),
+            (None, Some(_)) => {
+                info!(%wallet, "proof created");
+                return;
+            }
+            (Some(_), None) => {
+                warn!(%wallet, "proof closed");
+                notify::notify(
+                    &format!("proof_missing/{wallet}"),
+                    format!("proof of wallet {wallet} is gone"),
+                );
+                return;
+            }
+            (None, None) => return,
+        };
+
+        if before.hash.0 == after.hash.0 && before.claimable_rewards == after.claimable_rewards {
+            return;
+        }
+
+        let credited = after.total_rewards.saturating_sub(before.total_rewards);
+        let claimed = before
+            .claimable_rewards
+            .saturating_add(credited)
+            .saturating_sub(after.claimable_rewards);
+
+        self.changes += 1;
+        self.credited += credited;
+        self.claimed += claimed;
+
+        info!(
+            %wallet,
+            claimable.before = format_reward!(before.claimable_rewards),
+            claimable.after = format_reward!(after.claimable_rewards),
+            hash.before = %solana_sdk::keccak::Hash::new_from_array(before.hash.0),
+            hash.after = %solana_sdk::keccak::Hash::new_from_array(after.hash.0),
+            credited = format_reward!(credited),
+            claimed = format_reward!(claimed),
+            total.changes = self.changes,
+            total.credited = format_reward!(self.credited),
+            total.claimed = format_reward!(self.claimed),
+            "proof changed"
+        );
+
+        if claimed > 0 {
+            notify::notify(
+                &format!("proof_claimed/{wallet}"),
+                format!(
+                    "{} ORE claimed from the proof of wallet {wallet}, {} ORE left",
+                    utils::ore_ui_amount(claimed),
+                    utils::ore_ui_amount(after.claimable_rewards)
+                ),
+            );
+        }
+    }
+}
@@ -0,0 +1,408 @@
+use std::{collections::HashMap, fmt, path::PathBuf, process::Stdio, sync::OnceLock, time::Instant};
+
+use eyre::{bail, ContextCompat, WrapErr};
+use solana_sdk::{keccak::Hash, pubkey::Pubkey};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    process::{Child, ChildStderr, ChildStdin, ChildStdout, Command},
+    sync::{mpsc::UnboundedSender, Mutex},
+};
+use tracing::{debug, info, warn};
+
+use crate::utils;
+
+/// Idle long-lived workers, keyed by how they were spawned.
+static IDLE_WORKERS: OnceLock<Mutex<HashMap<WorkerSpec, Vec<PersistentWorker>>>> = OnceLock::new();
+
+/// Marks the end of a job in the streaming protocol.
+const END_OF_JOB: u32 = u32::MAX;
+
+/// Receives `(input index, (hash, nonce))` as soon as the worker returns them.
+pub type ResultSender = UnboundedSender<(usize, (Hash, u64))>;
+
+/// A nonce worker binary and how to run it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WorkerSpec {
+    pub path: PathBuf,
+    /// Whether this is the CUDA worker.
+    pub gpu: bool,
+    /// Whether the persistent worker streams each result as soon as it is
+    /// found.
+    pub stream: bool,
+    /// GPU device the worker is restricted to via `CUDA_VISIBLE_DEVICES`.
+    pub device: Option<usize>,
+}
+
+impl WorkerSpec {
+    pub fn cpu() -> Self {
+        Self {
+            path: utils::get_nonce_worker_path(),
+            gpu: false,
+            stream: true,
+            device: None,
+        }
+    }
+
+    pub fn gpu(device: Option<usize>, stream: bool) -> Self {
+        Self {
+            path: utils::get_gpu_nonce_worker_path(),
+            gpu: true,
+            stream,
+            device,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        if self.gpu {
+            "gpu"
+        } else {
+            "cpu"
+        }
+    }
+
+    fn command(&self, persistent: bool) -> Command {
+        let mut command = Command::new(&self.path);
+
+        if let Some(device) = self.device {
+            command.env("CUDA_VISIBLE_DEVICES", device.to_string());
+        }
+
+        if persistent {
+            command.arg("--persistent");
+
+            if self.stream {
+                command.arg("--stream");
+            }
+        }
+
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(cfg!(windows));
+
+        command
+    }
+}
+
+impl fmt::Display for WorkerSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.device {
+            Some(device) => write!(f, "{}@{device}", self.path.display()),
+            None => write!(f, "{}", self.path.display()),
+        }
+    }
+}
+
+/// Encodes the job header and inputs. The deadline is sent as the remaining
+/// milliseconds, 0 meaning no deadline.
+fn encode_job(
+    threads: usize,
+    deadline: Option<Instant>,
+    difficulty: &Hash,
+    hash_and_pubkey: &[(Hash, Pubkey)],
+) -> eyre::Result<Vec<u8>> {
+    let threads = match u16::try_from(threads) {
+        Ok(threads) => threads,
+        Err(_) => bail!("thread count {threads} is larger than {}", u16::MAX),
+    };
+
+    let deadline_ms = match deadline {
+        Some(deadline) => {
+            let remaining = deadline.saturating_duration_since(Instant::now()).as_millis();
+            u32::try_from(remaining).unwrap_or(u32::MAX).max(1)
+        }
+        None => 0,
+    };
+
+    let mut job = Vec::with_capacity(38 + hash_and_pubkey.len() * 64);
+
+    job.extend_from_slice(&threads.to_le_bytes());
+    job.extend_from_slice(&deadline_ms.to_le_bytes());
+    job.extend_from_slice(difficulty.as_ref());
+
+    for (hash, pubkey) in hash_and_pubkey {
+        job.extend_from_slice(hash.as_ref());
+        job.extend_from_slice(pubkey.as_ref());
+    }
+
+    Ok(job)
+}
+
+fn decode_result(item: &[u8]) -> (Hash, u64) {
+    let hash = Hash(item[..32].try_into().unwrap());
+    let nonce = u64::from_le_bytes(item[32..40].try_into().unwrap());
+
+    (hash, nonce)
+}
+
+/// Sends every `[u32 index][hash][nonce]` record, inputs left unsolved at the
+/// deadline have none.
+fn send_results(output: &[u8], results: &ResultSender) -> eyre::Result<()> {
+    if output.len() % 44 != 0 {
+        bail!("worker returned {} bytes, not a multiple of 44", output.len());
+    }
+
+    for record in output.chunks_exact(44) {
+        let index = u32::from_le_bytes(record[..4].try_into().unwrap());
+        let _ = results.send((index as usize, decode_result(&record[4..])));
+    }
+
+    Ok(())
+}
+
+/// Parses a `[done ]hashes=<n> elapsed_ms=<ms>` progress line written by the
+/// CPU worker.
+fn parse_progress(line: &str) -> Option<(bool, u64, u64)> {
+    let (done, line) = match line.strip_prefix("done ") {
+        Some(line) => (true, line),
+        None => (false, line),
+    };
+
+    let mut fields = line.split(' ');
+    let hashes = fields.next()?.strip_prefix("hashes=")?.parse().ok()?;
+    let elapsed_ms = fields.next()?.strip_prefix("elapsed_ms=")?.parse().ok()?;
+
+    Some((done, hashes, elapsed_ms))
+}
+
+/// Forwards the worker stderr into tracing, logging the hash rate when a job
+/// completes.
+fn forward_stderr(worker: &WorkerSpec, stderr: ChildStderr) {
+    let worker = worker.to_string();
+
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            match parse_progress(&line) {
+                Some((true, hashes, elapsed_ms)) => {
+                    let elapsed = elapsed_ms as f64 / 1000.0;
+                    let rate = if elapsed_ms > 0 {
+                        hashes as f64 / elapsed / 1e6
+                    } else {
+                        0.0
+                    };
+
+                    info!(worker, hashes, "{rate:.2} MH/s over {elapsed:.1}s");
+                }
+                Some((false, hashes, elapsed_ms)) => debug!(worker, hashes, elapsed_ms, "mining progress"),
+                None => warn!(worker, "{line}"),
+            }
+        }
+    });
+}
+
+/// Mines with a persistent worker, falling back to a one-shot worker if it
+/// dies. Results are sent as soon as they are read, a streaming worker returns
+/// each of them as it is found, others return all of them at the end. Inputs
+/// not solved by the deadline get no result.
+pub async fn mine(
+    worker: &WorkerSpec,
+    threads: usize,
+    deadline: Option<Instant>,
+    difficulty: &Hash,
+    hash_and_pubkey: &[(Hash, Pubkey)],
+    results: &ResultSender,
+) -> eyre::Result<()> {
+    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        return Ok(());
+    }
+
+    match mine_persistent(worker, threads, deadline, difficulty, hash_and_pubkey, results).await {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            warn!(%worker, "persistent worker failed, falling back to one-shot worker: {err:#}");
+
+            mine_oneshot(worker, threads, deadline, difficulty, hash_and_pubkey, results).await
+        }
+    }
+}
+
+/// Spawns a worker for a single job and waits for it to exit.
+pub async fn mine_oneshot(
+    worker: &WorkerSpec,
+    threads: usize,
+    deadline: Option<Instant>,
+    difficulty: &Hash,
+    hash_and_pubkey: &[(Hash, Pubkey)],
+    results: &ResultSender,
+) -> eyre::Result<()> {
+    let mut child = worker
+        .command(false)
+        .spawn()
+        .wrap_err_with(|| format!("fail to spawn {worker}"))?;
+
+    forward_stderr(worker, child.stderr.take().context("worker stderr is not piped")?);
+
+    {
+        let stdin = child.stdin.as_mut().context("worker stdin is not piped")?;
+
+        stdin
+            .write_all(&encode_job(threads, deadline, difficulty, hash_and_pubkey)?)
+            .await
+            .wrap_err("fail to write to worker")?;
+    }
+
+    let guard = TerminateOnDrop(child.id());
+    let output = child.wait_with_output().await.wrap_err("fail to wait for worker")?;
+    guard.disarm();
+
+    if !output.status.success() {
+        bail!("worker exited with {}", output.status);
+    }
+
+    send_results(&output.stdout, results)
+}
+
+/// Sends the job to an idle long-lived worker, spawning one if none is
+/// available. The worker is only put back to the idle pool if the job
+/// completes, a broken one is terminated on drop.
+pub async fn mine_persistent(
+    worker: &WorkerSpec,
+    threads: usize,
+    deadline: Option<Instant>,
+    difficulty: &Hash,
+    hash_and_pubkey: &[(Hash, Pubkey)],
+    results: &ResultSender,
+) -> eyre::Result<()> {
+    let mut persistent = PersistentWorker::acquire(worker).await?;
+    persistent
+        .mine(threads, deadline, difficulty, hash_and_pubkey, results)
+        .await?;
+    persistent.release().await;
+
+    Ok(())
+}
+
+pub struct PersistentWorker {
+    _child: Child,
+    _guard: TerminateOnDrop,
+    spec: WorkerSpec,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+}
+
+impl PersistentWorker {
+    fn spawn(spec: &WorkerSpec) -> eyre::Result<Self> {
+        let mut child = spec
+            .command(true)
+            .spawn()
+            .wrap_err_with(|| format!("fail to spawn {spec}"))?;
+
+        forward_stderr(spec, child.stderr.take().context("worker stderr is not piped")?);
+
+        let stdin = child.stdin.take().context("worker stdin is not piped")?;
+        let stdout = child.stdout.take().context("worker stdout is not piped")?;
+
+        Ok(Self {
+            _guard: TerminateOnDrop(child.id()),
+            _child: child,
+            spec: spec.clone(),
+            stdin,
+            stdout,
+        })
+    }
+
+    async fn acquire(spec: &WorkerSpec) -> eyre::Result<Self> {
+        let idle = IDLE_WORKERS
+            .get_or_init(Default::default)
+            .lock()
+            .await
+            .get_mut(spec)
+            .and_then(Vec::pop);
+
+        match idle {
+            Some(idle) => Ok(idle),
+            None => Self::spawn(spec),
+        }
+    }
+
+    async fn release(self) {
+        IDLE_WORKERS
+            .get_or_init(Default::default)
+            .lock()
+            .await
+            .entry(self.spec.clone())
+            .or_default()
+            .push(self);
+    }
+
+    async fn mine(
+        &mut self,
+        threads: usize,
+        deadline: Option<Instant>,
+        difficulty: &Hash,
+        hash_and_pubkey: &[(Hash, Pubkey)],
+        results: &ResultSender,
+    ) -> eyre::Result<()> {
+        let job = encode_job(threads, deadline, difficulty, hash_and_pubkey)?;
+
+        self.stdin
+            .write_u32_le(job.len() as u32)
+            .await
+            .wrap_err("fail to write to worker")?;
+        self.stdin.write_all(&job).await.wrap_err("fail to write to worker")?;
+        self.stdin.flush().await.wrap_err("fail to write to worker")?;
+
+        if !self.spec.stream {
+            let len = self.stdout.read_u32_le().await.wrap_err("fail to read from worker")?;
+
+            let mut output = vec![0u8; len as usize];
+            self.stdout
+                .read_exact(&mut output)
+                .await
+                .wrap_err("fail to read from worker")?;
+
+            return send_results(&output, results);
+        }
+
+        let mut item = [0u8; 40];
+
+        loop {
+            let index = self.stdout.read_u32_le().await.wrap_err("fail to read from worker")?;
+
+            if index == END_OF_JOB {
+                return Ok(());
+            }
+
+            self.stdout
+                .read_exact(&mut item)
+                .await
+                .wrap_err("fail to read from worker")?;
+
+            let _ = results.send((index as usize, decode_result(&item)));
+        }
+    }
+}
+
+/// Drops every idle persistent worker, terminating them.
+pub async fn shutdown() {
+    if let Some(idle) = IDLE_WORKERS.get() {
+        idle.lock().await.clear();
+    }
+}
+
+/// Sends SIGTERM to the worker process when dropped, so a worker whose task is
+/// cancelled flushes its results and exits instead of hashing forever. Windows
+/// has no signals, the worker is killed on drop instead.
+struct TerminateOnDrop(Option<u32>);
+
+impl TerminateOnDrop {
+    fn disarm(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for TerminateOnDrop {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(pid) = self.0 {
+            // SAFETY: kill has no memory safety requirements, the pid belongs
+            // to a child that has not been waited on yet.
+            unsafe {
+                libc::kill(pid as libc::pid_t, libc::SIGTERM);
+            }
+        }
+    }
+}
@@ -0,0 +1,485 @@
+//! Register, mine and claim, batch-transfer top-ups and collect sweeps against
+//! a local `solana-test-validator`, with the ore program and accounts cloned
+//! from mainnet and a mock block engine landing the bundles on the validator.
+//! The jito tip stream isn't mocked, its URL is fixed.
+//!
+//! Ignored by default, they need network access to clone the accounts and a
+//! few minutes to mine. Each test starts its own validator:
+//!
+//! ```text
+//! cargo test --test local_validator -- --ignored --nocapture --test-threads 1
+//! ```
+//!
+//! They fail when `solana-test-validator` isn't on the PATH.
+//! `ORE_TEST_CLONE_URL` is the cluster the accounts are cloned from,
+//! mainnet-beta by default.
+
+use std::{
+    env,
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{Child, Command, Output, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use ore::{state::Proof, utils::AccountDeserialize};
+use serde_json::{json, Value};
+use solana_client::{rpc_client::RpcClient, rpc_request::RpcRequest};
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    native_token::LAMPORTS_PER_SOL,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+
+/// Tip of the bundles, above the rent exemption so that the first tip to a
+/// tip account that doesn't exist on the validator lands.
+const TIP: &str = "1000000";
+
+const VALIDATOR_START_TIMEOUT: Duration = Duration::from_secs(120);
+const MINE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// A fresh folder of its own under the system temp dir.
+fn temp_folder(name: &str) -> PathBuf {
+    let folder = env::temp_dir().join(format!("ore-miner-it-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&folder);
+    fs::create_dir_all(&folder).unwrap();
+    folder
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+fn assert_validator_installed() {
+    let installed = Command::new("solana-test-validator")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+
+    assert!(installed, "solana-test-validator is not on the PATH");
+}
+
+/// A `solana-test-validator` killed on drop.
+struct TestValidator {
+    child: Child,
+    rpc_url: String,
+}
+
+impl TestValidator {
+    fn start(ledger: &Path) -> Self {
+        let rpc_port = free_port();
+        let clone_url = env::var("ORE_TEST_CLONE_URL").unwrap_or_else(|_| "mainnet-beta".to_string());
+        let treasury_ata =
+            spl_associated_token_account::get_associated_token_address(&ore::TREASURY_ADDRESS, &ore::MINT_ADDRESS);
+
+        let mut command = Command::new("solana-test-validator");
+        command
+            .arg("--reset")
+            .arg("--quiet")
+            .args(["--ledger", ledger.to_str().unwrap()])
+            .args(["--rpc-port", rpc_port.to_string().as_str()])
+            .args(["--faucet-port", free_port().to_string().as_str()])
+            .args(["--url", clone_url.as_str()])
+            .args(["--clone-upgradeable-program", ore::ID.to_string().as_str()]);
+
+        for account in [
+            ore::TREASURY_ADDRESS,
+            treasury_ata,
+            ore::MINT_ADDRESS,
+            ore::METADATA_ADDRESS,
+        ]
+        .into_iter()
+        .chain(ore::BUS_ADDRESSES)
+        {
+            command.args(["--clone", account.to_string().as_str()]);
+        }
+
+        let child = command
+            .stdout(Stdio::null())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .expect("fail to spawn solana-test-validator");
+
+        let validator = Self {
+            child,
+            rpc_url: format!("http://127.0.0.1:{rpc_port}"),
+        };
+
+        let client = validator.client();
+        let start = Instant::now();
+
+        while client.get_health().is_err() {
+            assert!(start.elapsed() < VALIDATOR_START_TIMEOUT, "validator didn't start");
+            thread::sleep(Duration::from_millis(500));
+        }
+
+        validator
+    }
+
+    fn client(&self) -> RpcClient {
+        RpcClient::new_with_commitment(self.rpc_url.clone(), CommitmentConfig::confirmed())
+    }
+
+    fn airdrop(&self, to: &Pubkey, lamports: u64) {
+        let client = self.client();
+        let signature = client.request_airdrop(to, lamports).unwrap();
+
+        while !client.confirm_transaction(&signature).unwrap() {
+            thread::sleep(Duration::from_millis(200));
+        }
+    }
+}
+
+impl Drop for TestValidator {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Stands in for the block engine `sendBundle` endpoint, the transactions of
+/// every bundle are sent to the validator one by one. Unlike jito, a bundle
+/// isn't atomic.
+struct MockBlockEngine {
+    url: String,
+    bundles: Arc<AtomicUsize>,
+}
+
+impl MockBlockEngine {
+    fn start(rpc_url: String) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let bundles = Arc::new(AtomicUsize::new(0));
+
+        thread::spawn({
+            let bundles = bundles.clone();
+
+            move || {
+                let client = RpcClient::new_with_commitment(rpc_url, CommitmentConfig::confirmed());
+
+                for stream in listener.incoming().flatten() {
+                    if let Err(err) = Self::serve(stream, &client, &bundles) {
+                        eprintln!("mock block engine: {err}");
+                    }
+                }
+            }
+        });
+
+        Self { url, bundles }
+    }
+
+    fn serve(mut stream: TcpStream, client: &RpcClient, bundles: &AtomicUsize) -> std::io::Result<()> {
+        let request = read_json_request(&mut stream)?;
+
+        let response = match request["method"].as_str() {
+            Some("sendBundle") => {
+                let txs = request["params"][0].as_array().cloned().unwrap_or_default();
+
+                for tx in &txs {
+                    let sent = client.send::<String>(
+                        RpcRequest::SendTransaction,
+                        json!([tx, { "encoding": "base58", "skipPreflight": true }]),
+                    );
+
+                    if let Err(err) = sent {
+                        eprintln!("mock block engine: fail to forward transaction: {err}");
+                    }
+                }
+
+                let id = bundles.fetch_add(1, Ordering::Relaxed);
+                json!({ "jsonrpc": "2.0", "id": request["id"], "result": format!("mock-bundle-{id}") })
+            }
+            method => json!({
+                "jsonrpc": "2.0",
+                "id": request["id"],
+                "error": { "code": -32601, "message": format!("method not found: {method:?}") },
+            }),
+        };
+
+        let body = response.to_string();
+
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n\
+             {body}",
+            body.len()
+        )
+    }
+
+    fn bundles(&self) -> usize {
+        self.bundles.load(Ordering::Relaxed)
+    }
+}
+
+/// The JSON body of an HTTP request.
+fn read_json_request(stream: &mut TcpStream) -> std::io::Result<Value> {
+    let mut reader = BufReader::new(stream);
+    let mut content_length = 0;
+
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+
+        let line = line.trim_end();
+
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or_default();
+            }
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    serde_json::from_slice(&body).map_err(std::io::Error::other)
+}
+
+/// `ore-miner` with the global options pointing at the validator and the mock
+/// block engine.
+fn ore_miner(validator: &TestValidator, block_engine: &MockBlockEngine) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_ore-miner"));
+    command.args([
+        "--rpc",
+        validator.rpc_url.as_str(),
+        "--jito-url",
+        block_engine.url.as_str(),
+        "--priority-fee",
+        TIP,
+    ]);
+    command
+}
+
+fn assert_success(what: &str, output: Output) {
+    assert!(
+        output.status.success(),
+        "{what} exited with {}\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+fn write_keypair(path: PathBuf, keypair: &Keypair) {
+    fs::write(path, serde_json::to_string(&keypair.to_bytes().to_vec()).unwrap()).unwrap();
+}
+
+fn proof(client: &RpcClient, wallet: &Pubkey) -> Option<Proof> {
+    let (pda, _) = Pubkey::find_program_address(&[ore::PROOF, wallet.as_ref()], &ore::ID);
+    let account = client.get_account(&pda).ok()?;
+
+    Proof::try_from_bytes(&account.data).ok().copied()
+}
+
+#[test]
+#[ignore = "needs solana-test-validator and network access to clone the ore accounts"]
+fn register_mine_claim() {
+    assert_validator_installed();
+
+    let folder = temp_folder("register-mine-claim");
+    let validator = TestValidator::start(&folder.join("ledger"));
+    let block_engine = MockBlockEngine::start(validator.rpc_url.clone());
+    let client = validator.client();
+
+    let key_folder = folder.join("keys");
+    fs::create_dir(&key_folder).unwrap();
+
+    let wallets = (0..2).map(|_| Keypair::new()).collect::<Vec<_>>();
+
+    for (i, wallet) in wallets.iter().enumerate() {
+        write_keypair(key_folder.join(format!("{i}.json")), wallet);
+        validator.airdrop(&wallet.pubkey(), LAMPORTS_PER_SOL);
+    }
+
+    // Register with plain transactions.
+    let output = ore_miner(&validator, &block_engine)
+        .args(["register", "--no-jito", "--key-folder", key_folder.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert_success("register", output);
+
+    for wallet in &wallets {
+        assert!(
+            proof(&client, &wallet.pubkey()).is_some(),
+            "{} not registered",
+            wallet.pubkey()
+        );
+    }
+
+    // Mine with the first wallet until it earns something.
+    let miner = wallets[0].pubkey();
+    let mut mining = ore_miner(&validator, &block_engine)
+        .args([
+            "mine",
+            "--threads",
+            "2",
+            "--keypair",
+            key_folder.join("0.json").to_str().unwrap(),
+        ])
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    let start = Instant::now();
+
+    let rewards = loop {
+        if let Some(proof) = proof(&client, &miner).filter(|proof| proof.claimable_rewards > 0) {
+            break proof.claimable_rewards;
+        }
+
+        if start.elapsed() >= MINE_TIMEOUT || mining.try_wait().unwrap().is_some() {
+            let _ = mining.kill();
+            panic!("no rewards mined");
+        }
+
+        thread::sleep(Duration::from_secs(2));
+    };
+
+    mining.kill().unwrap();
+    mining.wait().unwrap();
+
+    // Claim through the mock block engine.
+    let beneficiary = Keypair::new().pubkey();
+    let bundles_before = block_engine.bundles();
+
+    let output = ore_miner(&validator, &block_engine)
+        .args([
+            "claim",
+            "--beneficiary",
+            beneficiary.to_string().as_str(),
+            "--address",
+            miner.to_string().as_str(),
+            "--create-ata",
+            "--key-folder",
+            key_folder.to_str().unwrap(),
+        ])
+        .output()
+        .unwrap();
+    assert_success("claim", output);
+
+    assert!(
+        block_engine.bundles() > bundles_before,
+        "no bundle sent to the block engine"
+    );
+
+    let ata = spl_associated_token_account::get_associated_token_address(&beneficiary, &ore::MINT_ADDRESS);
+    let claimed = client.get_token_account_balance(&ata).unwrap();
+
+    // The miner may have earned more before it was stopped.
+    assert!(claimed.amount.parse::<u64>().unwrap() >= rewards);
+    assert_eq!(proof(&client, &miner).unwrap().claimable_rewards, 0);
+
+    drop(validator);
+    fs::remove_dir_all(folder).unwrap();
+}
+
+#[test]
+#[ignore = "needs solana-test-validator and network access to clone the ore accounts"]
+fn batch_transfer_top_up() {
+    assert_validator_installed();
+
+    let folder = temp_folder("batch-transfer-top-up");
+    let validator = TestValidator::start(&folder.join("ledger"));
+    let block_engine = MockBlockEngine::start(validator.rpc_url.clone());
+    let client = validator.client();
+
+    let funder = Keypair::new();
+    let funder_path = folder.join("funder.json");
+    write_keypair(funder_path.clone(), &funder);
+    validator.airdrop(&funder.pubkey(), 2 * LAMPORTS_PER_SOL);
+
+    let max_value = LAMPORTS_PER_SOL / 10;
+    let empty = Keypair::new().pubkey();
+    let below = Keypair::new().pubkey();
+    let above = Keypair::new().pubkey();
+
+    validator.airdrop(&below, max_value / 4);
+    validator.airdrop(&above, 2 * max_value);
+
+    let output = ore_miner(&validator, &block_engine)
+        .args([
+            "batch-transfer",
+            "--keypair",
+            funder_path.to_str().unwrap(),
+            "--max-value",
+            "0.1",
+            "--address",
+            format!("{empty},{below},{above}").as_str(),
+        ])
+        .output()
+        .unwrap();
+    assert_success("batch-transfer", output);
+
+    assert_eq!(client.get_balance(&empty).unwrap(), max_value);
+    assert_eq!(client.get_balance(&below).unwrap(), max_value);
+    assert_eq!(client.get_balance(&above).unwrap(), 2 * max_value);
+
+    drop(validator);
+    fs::remove_dir_all(folder).unwrap();
+}
+
+#[test]
+#[ignore = "needs solana-test-validator and network access to clone the ore accounts"]
+fn collect_sweep() {
+    assert_validator_installed();
+
+    let folder = temp_folder("collect-sweep");
+    let validator = TestValidator::start(&folder.join("ledger"));
+    let block_engine = MockBlockEngine::start(validator.rpc_url.clone());
+    let client = validator.client();
+
+    let key_folder = folder.join("keys");
+    fs::create_dir(&key_folder).unwrap();
+
+    let wallets = (0..3).map(|_| Keypair::new()).collect::<Vec<_>>();
+
+    for (i, wallet) in wallets.iter().enumerate() {
+        write_keypair(key_folder.join(format!("{i}.json")), wallet);
+        validator.airdrop(&wallet.pubkey(), LAMPORTS_PER_SOL);
+    }
+
+    let beneficiary = Keypair::new().pubkey();
+
+    let output = ore_miner(&validator, &block_engine)
+        .args([
+            "collect",
+            "--no-jito",
+            "--key-folder",
+            key_folder.to_str().unwrap(),
+            "--beneficiary",
+            beneficiary.to_string().as_str(),
+        ])
+        .output()
+        .unwrap();
+    assert_success("collect", output);
+
+    // Without --fee-payer the first wallet pays the fees, the others keep the
+    // rent exemption and the fee of their signature.
+    let reserve = client.get_minimum_balance_for_rent_exemption(0).unwrap() + 5_000;
+
+    for wallet in &wallets[1..] {
+        assert!(
+            client.get_balance(&wallet.pubkey()).unwrap() <= reserve,
+            "{} not swept",
+            wallet.pubkey()
+        );
+    }
+
+    assert!(client.get_balance(&beneficiary).unwrap() >= 2 * (LAMPORTS_PER_SOL - reserve));
+
+    drop(validator);
+    fs::remove_dir_all(folder).unwrap();
+}